@@ -0,0 +1,142 @@
+use anyhow::Result;
+use chrono::{DateTime, Duration, FixedOffset, Utc};
+use rand::Rng;
+use sea_orm::{ActiveModelTrait, ConnectionTrait, EntityTrait, QueryFilter, Set};
+use sha2::{Digest, Sha256};
+use workspace_entity::api_tokens;
+
+/// Prefix of personal access tokens (issued to an account)
+pub const PERSONAL_TOKEN_PREFIX: &str = "tvp_";
+/// Prefix of bot service tokens (issued to a `bots` row)
+pub const BOT_TOKEN_PREFIX: &str = "tvb_";
+
+/// Random characters after the prefix (~190 bits of entropy)
+const TOKEN_RANDOM_LEN: usize = 32;
+/// Characters of the random part kept in `token_prefix` for display
+const DISPLAY_PREFIX_LEN: usize = 8;
+const TOKEN_ALPHABET: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+/// `last_used_at` is only written when older than this, to avoid a write per request
+const LAST_USED_RESOLUTION_SECS: i64 = 60;
+
+// Scopes an API token can be limited to; JWT sessions are not scope-limited
+pub const SCOPE_ACCOUNT_READ: &str = "account:read";
+pub const SCOPE_REALMS_WRITE: &str = "realms:write";
+pub const SCOPE_BOTS_WRITE: &str = "bots:write";
+pub const SCOPE_AI_USAGE_WRITE: &str = "ai_usage:write";
+pub const SCOPE_TRIPS_READ: &str = "trips:read";
+pub const SCOPE_TRIPS_WRITE: &str = "trips:write";
+
+pub const SCOPES: &[&str] = &[
+    SCOPE_ACCOUNT_READ,
+    SCOPE_REALMS_WRITE,
+    SCOPE_BOTS_WRITE,
+    SCOPE_AI_USAGE_WRITE,
+    SCOPE_TRIPS_READ,
+    SCOPE_TRIPS_WRITE,
+];
+
+/// A freshly generated token; `token` is only ever shown to the caller once
+pub struct GeneratedToken {
+    pub token: String,
+    pub token_prefix: String,
+    pub token_hash: String,
+}
+
+pub fn generate_token(prefix: &str) -> GeneratedToken {
+    let mut rng = rand::thread_rng();
+    let random: String = (0..TOKEN_RANDOM_LEN)
+        .map(|_| TOKEN_ALPHABET[rng.gen_range(0..TOKEN_ALPHABET.len())] as char)
+        .collect();
+    let token = format!("{prefix}{random}");
+
+    GeneratedToken {
+        token_prefix: token[..prefix.len() + DISPLAY_PREFIX_LEN].to_owned(),
+        token_hash: hash_token(&token),
+        token,
+    }
+}
+
+/// Tokens are long and random, so a plain SHA-256 is enough to store them
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Whether a bearer value looks like an API token rather than a JWT
+pub fn is_api_token(token: &str) -> bool {
+    token.starts_with(PERSONAL_TOKEN_PREFIX) || token.starts_with(BOT_TOKEN_PREFIX)
+}
+
+/// Look up an active (not revoked, not expired) token and bump its `last_used_at`
+pub async fn find_active<C: ConnectionTrait>(
+    db: &C,
+    token: &str,
+) -> Result<Option<api_tokens::Model>> {
+    let now: DateTime<FixedOffset> = Utc::now().into();
+
+    let Some(api_token) = api_tokens::Entity::find()
+        .filter(api_tokens::COLUMN.token_hash.eq(hash_token(token)))
+        .one(db)
+        .await?
+    else {
+        return Ok(None);
+    };
+
+    if api_token.revoked_at.is_some() || api_token.expires_at.is_some_and(|at| at <= now) {
+        return Ok(None);
+    }
+
+    let stale = api_token
+        .last_used_at
+        .is_none_or(|at| now - at > Duration::seconds(LAST_USED_RESOLUTION_SECS));
+    if stale {
+        let mut active: api_tokens::ActiveModel = api_token.clone().into();
+        active.last_used_at = Set(Some(now));
+        active.update(db).await?;
+    }
+
+    Ok(Some(api_token))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_tokens_carry_their_prefix() {
+        for prefix in [PERSONAL_TOKEN_PREFIX, BOT_TOKEN_PREFIX] {
+            let generated = generate_token(prefix);
+            assert!(generated.token.starts_with(prefix));
+            assert_eq!(generated.token.len(), prefix.len() + TOKEN_RANDOM_LEN);
+            assert!(
+                generated.token[prefix.len()..]
+                    .bytes()
+                    .all(|b| TOKEN_ALPHABET.contains(&b))
+            );
+            assert_eq!(
+                generated.token_prefix.len(),
+                prefix.len() + DISPLAY_PREFIX_LEN
+            );
+            assert!(generated.token.starts_with(&generated.token_prefix));
+            assert!(is_api_token(&generated.token));
+        }
+    }
+
+    #[test]
+    fn tokens_are_found_by_their_hash() {
+        let generated = generate_token(PERSONAL_TOKEN_PREFIX);
+        assert_eq!(hash_token(&generated.token), generated.token_hash);
+        assert_eq!(generated.token_hash.len(), 64);
+
+        let other = generate_token(PERSONAL_TOKEN_PREFIX);
+        assert_ne!(other.token, generated.token);
+        assert_ne!(other.token_hash, generated.token_hash);
+        assert_ne!(hash_token(&generated.token_prefix), generated.token_hash);
+    }
+
+    #[test]
+    fn jwts_are_not_api_tokens() {
+        assert!(!is_api_token("eyJhbGciOiJFUzI1NiJ9.e30.c2ln"));
+        assert!(!is_api_token(""));
+        assert!(!is_api_token("tvx_abc"));
+    }
+}
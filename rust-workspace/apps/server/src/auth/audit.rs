@@ -0,0 +1,57 @@
+use crate::auth::service::ClientInfo;
+use anyhow::Result;
+use chrono::Utc;
+use sea_orm::{ActiveModelTrait, ConnectionTrait, Set};
+use uuid::Uuid;
+use workspace_entity::auth_audit_logs;
+
+// Event types written to auth_audit_logs.event_type
+pub const LOGIN_FAILED: &str = "login_failed";
+pub const LOGIN_LOCKED: &str = "login_locked";
+pub const MFA_FAILED: &str = "mfa_failed";
+pub const MFA_VERIFIED: &str = "mfa_verified";
+pub const MFA_ENROLLED: &str = "mfa_enrolled";
+pub const MFA_DISABLED: &str = "mfa_disabled";
+pub const RECOVERY_CODES_REGENERATED: &str = "recovery_codes_regenerated";
+pub const MFA_POLICY_CHANGED: &str = "mfa_policy_changed";
+pub const REALM_UPDATED: &str = "realm_updated";
+pub const REALM_DEACTIVATED: &str = "realm_deactivated";
+pub const REALM_REACTIVATED: &str = "realm_reactivated";
+pub const REALM_DELETED: &str = "realm_deleted";
+pub const REALM_RESTORED: &str = "realm_restored";
+pub const REALM_TRANSFERRED: &str = "realm_transferred";
+pub const API_TOKEN_CREATED: &str = "api_token_created";
+pub const API_TOKEN_REVOKED: &str = "api_token_revoked";
+pub const ACCOUNT_UPDATED: &str = "account_updated";
+pub const EMAIL_CHANGE_REQUESTED: &str = "email_change_requested";
+pub const EMAIL_CHANGED: &str = "email_changed";
+pub const PASSWORD_CHANGED: &str = "password_changed";
+pub const ACCOUNT_DEACTIVATED: &str = "account_deactivated";
+pub const ACCOUNT_DELETED: &str = "account_deleted";
+pub const DATA_EXPORT_REQUESTED: &str = "data_export_requested";
+pub const PROFILES_MERGED: &str = "profiles_merged";
+
+/// Append an entry to the auth audit log
+pub async fn record_event<C: ConnectionTrait>(
+    db: &C,
+    event_type: &str,
+    account_id: Option<Uuid>,
+    identifier: Option<&str>,
+    client: &ClientInfo,
+    metadata: Option<serde_json::Value>,
+) -> Result<()> {
+    auth_audit_logs::ActiveModel {
+        id: Set(Uuid::now_v7()),
+        account_id: Set(account_id),
+        event_type: Set(event_type.to_owned()),
+        identifier: Set(identifier.map(|s| s.to_owned())),
+        ip_address: Set(client.ip.clone()),
+        user_agent: Set(client.user_agent.clone()),
+        created_at: Set(Utc::now().into()),
+        metadata: Set(metadata),
+    }
+    .insert(db)
+    .await?;
+
+    Ok(())
+}
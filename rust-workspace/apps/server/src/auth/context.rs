@@ -0,0 +1,261 @@
+use crate::AppState;
+use crate::auth::{api_token, jwt, realm};
+use crate::error::Error;
+use axum::http::{self, HeaderMap};
+use axum_connect::error::{RpcError, RpcIntoError};
+use axum_connect::parts::RpcFromRequestParts;
+use prost::Message;
+use sea_orm::{EntityTrait, QueryFilter};
+use uuid::Uuid;
+use workspace_entity::{account_realm_roles, accounts, roles};
+
+/// Header selecting the realm a request operates in
+pub const REALM_ID_HEADER: &str = "x-realm-id";
+
+/// How the caller authenticated
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TokenKind {
+    /// Access token (JWT) from an interactive login
+    Session,
+    /// API token issued to an account
+    PersonalAccessToken,
+    /// API token issued to a bot
+    BotToken,
+}
+
+/// The authenticated caller, resolved from the `Authorization: Bearer` header
+/// (JWT or API token) and the optional `x-realm-id` header.
+///
+/// Use it as a handler argument; requests without valid credentials are rejected.
+#[allow(dead_code)] // Not every field is needed by the current handlers
+pub struct AuthContext {
+    pub token_kind: TokenKind,
+    /// Account the request acts for; `None` for bot tokens
+    pub account_id: Option<Uuid>,
+    /// Bot the request acts for; only set for bot tokens
+    pub bot_id: Option<Uuid>,
+    /// Set when authenticated with an API token
+    pub api_token_id: Option<Uuid>,
+    /// Realm from `x-realm-id`, else from the token; `None` if neither names one
+    pub realm_id: Option<Uuid>,
+    /// Role names the account holds in `realm_id`
+    pub roles: Vec<String>,
+    /// Scopes of an API token; `None` for sessions, which are not scope-limited
+    scopes: Option<Vec<String>>,
+    /// Realm an API token was issued for; such tokens can't act in other realms
+    bound_realm_id: Option<Uuid>,
+    /// When a session logged in (Unix seconds); `None` for API tokens
+    pub auth_time: Option<u64>,
+    /// Whether deactivated and deleted realms are accepted; see `RealmLifecycleContext`
+    any_realm_state: bool,
+}
+
+impl AuthContext {
+    pub async fn from_headers(state: &AppState, headers: &HeaderMap) -> Result<Self, Error> {
+        Self::resolve(state, headers, false).await
+    }
+
+    async fn resolve(
+        state: &AppState,
+        headers: &HeaderMap,
+        any_realm_state: bool,
+    ) -> Result<Self, Error> {
+        // Extract Authorization header
+        let auth_header = headers
+            .get(http::header::AUTHORIZATION)
+            .ok_or(Error::Unauthenticated)?
+            .to_str()
+            .map_err(|_| Error::Unauthenticated)?;
+
+        // Parse Bearer token
+        let token = auth_header
+            .strip_prefix("Bearer ")
+            .ok_or(Error::Unauthenticated)?;
+
+        let mut session_version = None;
+        let mut context = if api_token::is_api_token(token) {
+            let api_token = api_token::find_active(&state.conn, token)
+                .await
+                .map_err(Error::Anyhow)?
+                .ok_or(Error::Unauthenticated)?;
+
+            AuthContext {
+                token_kind: if api_token.bot_id.is_some() {
+                    TokenKind::BotToken
+                } else {
+                    TokenKind::PersonalAccessToken
+                },
+                account_id: api_token.account_id,
+                bot_id: api_token.bot_id,
+                api_token_id: Some(api_token.id),
+                realm_id: api_token.realm_id,
+                roles: Vec::new(),
+                scopes: Some(api_token.scopes),
+                bound_realm_id: api_token.realm_id,
+                auth_time: None,
+                any_realm_state,
+            }
+        } else {
+            let claims =
+                jwt::verify_token(token, &state.jwt_keys).map_err(|_| Error::Unauthenticated)?;
+
+            let account_id = Uuid::parse_str(&claims.sub).map_err(|_| Error::Unauthenticated)?;
+            session_version = Some(claims.session_version);
+            let realm_id = claims
+                .realm_id
+                .map(|r| Uuid::parse_str(&r))
+                .transpose()
+                .map_err(|_| Error::Unauthenticated)?;
+
+            AuthContext {
+                token_kind: TokenKind::Session,
+                account_id: Some(account_id),
+                bot_id: None,
+                api_token_id: None,
+                realm_id,
+                roles: Vec::new(),
+                scopes: None,
+                bound_realm_id: None,
+                auth_time: Some(claims.auth_time as u64),
+                any_realm_state,
+            }
+        };
+
+        // Deactivated accounts lose all access; sessions also end when the account
+        // bumps its session version (password change, deactivation)
+        if let Some(account_id) = context.account_id {
+            let account = accounts::Entity::find_by_id(account_id)
+                .one(&state.conn)
+                .await?
+                .filter(|a| a.is_active)
+                .ok_or(Error::Unauthenticated)?;
+            if session_version.is_some_and(|v| v != account.session_version) {
+                return Err(Error::Unauthenticated);
+            }
+        }
+
+        // An explicit realm header wins over the realm carried by the token
+        if let Some(header) = headers.get(REALM_ID_HEADER) {
+            let realm_id = header
+                .to_str()
+                .ok()
+                .and_then(|v| Uuid::parse_str(v.trim()).ok())
+                .ok_or_else(|| Error::invalid_field(REALM_ID_HEADER, "must be a UUID"))?;
+            context.realm_id = Some(realm_id);
+        }
+
+        if let Some(realm_id) = context.realm_id {
+            context.roles = context.roles_in(state, realm_id).await?;
+        }
+
+        Ok(context)
+    }
+
+    /// Role names of the caller in `realm_id`. Fails if the caller is not a member, if
+    /// its API token is bound to a different realm, or if the realm is deactivated or
+    /// scheduled for deletion.
+    pub async fn roles_in(&self, state: &AppState, realm_id: Uuid) -> Result<Vec<String>, Error> {
+        if self.bound_realm_id.is_some_and(|bound| bound != realm_id) {
+            return Err(Error::Forbidden);
+        }
+        if !self.any_realm_state {
+            realm::ensure_usable(&state.conn, realm_id).await?;
+        }
+
+        // Bot tokens are members of their bot's realm by construction
+        let Some(account_id) = self.account_id else {
+            return Ok(Vec::new());
+        };
+
+        let realm_roles = account_realm_roles::Entity::find()
+            .filter(account_realm_roles::COLUMN.account_id.eq(account_id))
+            .filter(account_realm_roles::COLUMN.realm_id.eq(realm_id))
+            .find_also_related(roles::Entity)
+            .all(&state.conn)
+            .await?;
+
+        if realm_roles.is_empty() {
+            return Err(Error::Forbidden);
+        }
+
+        Ok(realm_roles
+            .into_iter()
+            .filter_map(|(_, role)| role.map(|r| r.name))
+            .collect())
+    }
+
+    /// Reject API tokens that weren't issued with `scope`
+    pub fn require_scope(&self, scope: &str) -> Result<(), Error> {
+        match &self.scopes {
+            Some(scopes) if !scopes.iter().any(|s| s == scope) => Err(Error::Forbidden),
+            _ => Ok(()),
+        }
+    }
+
+    /// Account the request acts for; bot tokens are rejected
+    pub fn require_account(&self) -> Result<Uuid, Error> {
+        self.account_id.ok_or(Error::Forbidden)
+    }
+
+    /// Account of an interactive session; API tokens are rejected, e.g. so a
+    /// token can't mint further tokens or change security settings
+    pub fn require_session(&self) -> Result<Uuid, Error> {
+        if self.token_kind != TokenKind::Session {
+            return Err(Error::Forbidden);
+        }
+        self.require_account()
+    }
+
+    /// Realm the request operates in. There is no implicit default: callers pick one
+    /// via `x-realm-id`, a realm-scoped access token or a realm-bound API token.
+    pub fn require_realm(&self) -> Result<Uuid, Error> {
+        self.realm_id
+            .ok_or_else(|| Error::FailedPrecondition("no realm selected".to_string()))
+    }
+}
+
+#[async_trait::async_trait]
+impl<M> RpcFromRequestParts<M, AppState> for AuthContext
+where
+    M: Message,
+{
+    type Rejection = RpcError;
+
+    async fn rpc_from_request_parts(
+        parts: &mut http::request::Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        AuthContext::from_headers(state, &parts.headers)
+            .await
+            .map_err(|e| e.rpc_into_error())
+    }
+}
+
+/// `AuthContext` for the realm lifecycle RPCs (reactivate, restore, delete), the only
+/// ones that may act in a deactivated realm or one scheduled for deletion
+pub struct RealmLifecycleContext(pub AuthContext);
+
+impl std::ops::Deref for RealmLifecycleContext {
+    type Target = AuthContext;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+#[async_trait::async_trait]
+impl<M> RpcFromRequestParts<M, AppState> for RealmLifecycleContext
+where
+    M: Message,
+{
+    type Rejection = RpcError;
+
+    async fn rpc_from_request_parts(
+        parts: &mut http::request::Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        AuthContext::resolve(state, &parts.headers, true)
+            .await
+            .map(RealmLifecycleContext)
+            .map_err(|e| e.rpc_into_error())
+    }
+}
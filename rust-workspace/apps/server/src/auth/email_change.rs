@@ -0,0 +1,65 @@
+use crate::auth::api_token;
+use anyhow::Result;
+use chrono::{Duration, Utc};
+use sea_orm::{ActiveModelTrait, ConnectionTrait, EntityTrait, QueryFilter, Set};
+use uuid::Uuid;
+use workspace_entity::account_email_changes;
+
+/// Prefix of email change confirmation tokens
+pub const EMAIL_CHANGE_TOKEN_PREFIX: &str = "tve_";
+/// How long a confirmation token stays valid
+const CONFIRMATION_SECS: i64 = 86400;
+
+/// Record `new_email` as awaiting confirmation, replacing any earlier pending change,
+/// and return the confirmation token to mail to it
+pub async fn request<C: ConnectionTrait>(
+    db: &C,
+    account_id: Uuid,
+    new_email: &str,
+) -> Result<String> {
+    account_email_changes::Entity::delete_by_id(account_id)
+        .exec(db)
+        .await?;
+
+    let generated = api_token::generate_token(EMAIL_CHANGE_TOKEN_PREFIX);
+    account_email_changes::ActiveModel {
+        account_id: Set(account_id),
+        new_email: Set(new_email.to_owned()),
+        token_hash: Set(generated.token_hash),
+        expires_at: Set((Utc::now() + Duration::seconds(CONFIRMATION_SECS)).into()),
+        created_at: Set(Utc::now().into()),
+    }
+    .insert(db)
+    .await?;
+
+    Ok(generated.token)
+}
+
+/// The unexpired pending change a confirmation token belongs to
+pub async fn find_by_token<C: ConnectionTrait>(
+    db: &C,
+    token: &str,
+) -> Result<Option<account_email_changes::Model>> {
+    if !token.starts_with(EMAIL_CHANGE_TOKEN_PREFIX) {
+        return Ok(None);
+    }
+
+    Ok(account_email_changes::Entity::find()
+        .filter(
+            account_email_changes::COLUMN
+                .token_hash
+                .eq(api_token::hash_token(token)),
+        )
+        .filter(account_email_changes::COLUMN.expires_at.gt(Utc::now()))
+        .one(db)
+        .await?)
+}
+
+/// The account's unexpired pending email, if any
+pub async fn pending_email<C: ConnectionTrait>(db: &C, account_id: Uuid) -> Result<Option<String>> {
+    Ok(account_email_changes::Entity::find_by_id(account_id)
+        .filter(account_email_changes::COLUMN.expires_at.gt(Utc::now()))
+        .one(db)
+        .await?
+        .map(|change| change.new_email))
+}
@@ -0,0 +1,265 @@
+use anyhow::{Result, anyhow};
+use chrono::{Duration, Utc};
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, Condition, ConnectionTrait, DatabaseConnection, EntityTrait,
+    QueryFilter, QueryOrder, Set,
+};
+use serde_json::json;
+use uuid::Uuid;
+use workspace_entity::{
+    account_realm_roles, accounts, chat_participants, chats, data_exports, federated_identities,
+    identity_providers, messages, profiles, realms, roles, trip_card_rich_text, trip_card_votes,
+    trip_cards, trip_expense_shares, trip_expenses, trip_participants, trips,
+};
+
+// Values of data_exports.status
+pub const STATUS_PENDING: &str = "pending";
+pub const STATUS_RUNNING: &str = "running";
+pub const STATUS_READY: &str = "ready";
+pub const STATUS_FAILED: &str = "failed";
+
+/// How long a finished archive is kept for download
+pub const ARCHIVE_TTL_DAYS: i64 = 7;
+
+/// Run an export job in the background; failures are recorded on the row
+pub async fn run(conn: DatabaseConnection, export_id: Uuid) {
+    if let Err(err) = run_export(&conn, export_id).await {
+        println!("data export {export_id} failed: {err:#}");
+
+        let failed = data_exports::ActiveModel {
+            id: Set(export_id),
+            status: Set(STATUS_FAILED.to_owned()),
+            error: Set(Some("The export could not be generated".to_owned())),
+            completed_at: Set(Some(Utc::now().into())),
+            ..Default::default()
+        };
+        if let Err(err) = failed.update(&conn).await {
+            println!("data export {export_id}: could not record failure: {err}");
+        }
+    }
+}
+
+async fn run_export(conn: &DatabaseConnection, export_id: Uuid) -> Result<()> {
+    // Drop archives nobody can download anymore
+    data_exports::Entity::delete_many()
+        .filter(data_exports::COLUMN.expires_at.lt(Utc::now()))
+        .exec(conn)
+        .await?;
+
+    let export = data_exports::Entity::find_by_id(export_id)
+        .one(conn)
+        .await?
+        .ok_or_else(|| anyhow!("export not found"))?;
+
+    let mut running: data_exports::ActiveModel = export.clone().into();
+    running.status = Set(STATUS_RUNNING.to_owned());
+    running.update(conn).await?;
+
+    let archive = build_archive(conn, export.account_id).await?;
+
+    let now = Utc::now();
+    let mut ready: data_exports::ActiveModel = export.into();
+    ready.status = Set(STATUS_READY.to_owned());
+    ready.archive = Set(Some(serde_json::to_vec_pretty(&archive)?));
+    ready.completed_at = Set(Some(now.into()));
+    ready.expires_at = Set(Some((now + Duration::days(ARCHIVE_TTL_DAYS)).into()));
+    ready.update(conn).await?;
+
+    Ok(())
+}
+
+/// Collect everything stored about an account: the account itself, its identities and
+/// realm roles, and its profiles (linked, or matching its email or a federated identity) with
+/// their trips, cards, votes, rich-text edits and chats. Credentials are left out.
+pub async fn build_archive<C: ConnectionTrait>(
+    db: &C,
+    account_id: Uuid,
+) -> Result<serde_json::Value> {
+    let account = accounts::Entity::find_by_id(account_id)
+        .one(db)
+        .await?
+        .ok_or_else(|| anyhow!("account not found"))?;
+
+    let identities = federated_identities::Entity::find()
+        .filter(federated_identities::COLUMN.account_id.eq(account_id))
+        .find_also_related(identity_providers::Entity)
+        .all(db)
+        .await?;
+
+    let realm_roles = account_realm_roles::Entity::find()
+        .filter(account_realm_roles::COLUMN.account_id.eq(account_id))
+        .find_also_related(roles::Entity)
+        .all(db)
+        .await?;
+    let realm_ids: Vec<Uuid> = realm_roles.iter().map(|(r, _)| r.realm_id).collect();
+    let realm_names = realms::Entity::find()
+        .filter(realms::COLUMN.id.is_in(realm_ids.clone()))
+        .all(db)
+        .await?;
+    let realm_name = |id: Uuid| {
+        realm_names
+            .iter()
+            .find(|r| r.id == id)
+            .map(|r| r.name.clone())
+    };
+
+    // Profiles linked to the account, plus unlinked ones matching the external user ID
+    // of one of its federated identities. Anyone can put an address on a profile, so
+    // matching by email needs a verified one, and only in the account's realms.
+    let mut profile_match = Condition::any().add(profiles::Column::AccountId.eq(account_id));
+    for (identity, provider) in &identities {
+        if let Some(provider) = provider {
+            profile_match = profile_match.add(
+                Condition::all()
+                    .add(profiles::Column::AccountId.is_null())
+                    .add(profiles::COLUMN.realm_id.eq(provider.realm_id))
+                    .add(profiles::Column::ThirdProviderType.eq(&provider.provider_type))
+                    .add(profiles::Column::ThirdId.eq(&identity.external_user_id)),
+            );
+        }
+    }
+    if account.email_verified {
+        profile_match = profile_match.add(
+            Condition::all()
+                .add(profiles::Column::AccountId.is_null())
+                .add(profiles::COLUMN.realm_id.is_in(realm_ids))
+                .add(profiles::COLUMN.email.eq(&account.email)),
+        );
+    }
+    let profiles = profiles::Entity::find()
+        .filter(profile_match)
+        .all(db)
+        .await?;
+    let profile_ids: Vec<Uuid> = profiles.iter().map(|p| p.id).collect();
+
+    let trip_participations = trip_participants::Entity::find()
+        .filter(
+            trip_participants::COLUMN
+                .profile_id
+                .is_in(profile_ids.clone()),
+        )
+        .all(db)
+        .await?;
+    let trips = trips::Entity::find()
+        .filter(
+            Condition::any()
+                .add(
+                    trips::COLUMN
+                        .id
+                        .is_in(trip_participations.iter().map(|p| p.trip_id)),
+                )
+                .add(trips::COLUMN.created_by.is_in(profile_ids.clone())),
+        )
+        .order_by_asc(trips::Column::CreatedAt)
+        .all(db)
+        .await?;
+
+    let trip_cards = trip_cards::Entity::find()
+        .filter(trip_cards::COLUMN.created_by.is_in(profile_ids.clone()))
+        .order_by_asc(trip_cards::Column::CreatedAt)
+        .all(db)
+        .await?;
+    let trip_card_votes = trip_card_votes::Entity::find()
+        .filter(
+            trip_card_votes::COLUMN
+                .profile_id
+                .is_in(profile_ids.clone()),
+        )
+        .all(db)
+        .await?;
+    let rich_text_edits = trip_card_rich_text::Entity::find()
+        .filter(
+            trip_card_rich_text::COLUMN
+                .last_edited_by
+                .is_in(profile_ids.clone()),
+        )
+        .all(db)
+        .await?;
+
+    // Expenses the profiles paid, recorded or have a share in, with their own shares
+    let expense_shares = trip_expense_shares::Entity::find()
+        .filter(
+            trip_expense_shares::COLUMN
+                .profile_id
+                .is_in(profile_ids.clone()),
+        )
+        .all(db)
+        .await?;
+    let trip_expenses = trip_expenses::Entity::find()
+        .filter(
+            Condition::any()
+                .add(trip_expenses::COLUMN.paid_by.is_in(profile_ids.clone()))
+                .add(trip_expenses::COLUMN.created_by.is_in(profile_ids.clone()))
+                .add(
+                    trip_expenses::COLUMN
+                        .id
+                        .is_in(expense_shares.iter().map(|s| s.expense_id)),
+                ),
+        )
+        .order_by_asc(trip_expenses::Column::SpentAt)
+        .all(db)
+        .await?;
+
+    // Chats the profiles take part in, with their full history
+    let chat_participations = chat_participants::Entity::find()
+        .filter(chat_participants::COLUMN.profile_id.is_in(profile_ids))
+        .all(db)
+        .await?;
+    let chat_ids: Vec<Uuid> = chat_participations.iter().map(|p| p.chat_id).collect();
+    let chats = chats::Entity::find()
+        .filter(chats::COLUMN.id.is_in(chat_ids.clone()))
+        .all(db)
+        .await?;
+    let messages = messages::Entity::find()
+        .filter(messages::COLUMN.chat_id.is_in(chat_ids))
+        .order_by_asc(messages::Column::CreatedAt)
+        .all(db)
+        .await?;
+
+    Ok(json!({
+        "exportedAt": Utc::now().to_rfc3339(),
+        "account": {
+            "id": account.id,
+            "username": account.username,
+            "email": account.email,
+            "emailVerified": account.email_verified,
+            "isActive": account.is_active,
+            "createdAt": account.created_at,
+            "updatedAt": account.updated_at,
+            "lastLoginAt": account.last_login_at,
+            "metadata": account.metadata,
+        },
+        "federatedIdentities": identities
+            .iter()
+            .map(|(identity, provider)| json!({
+                "identityProviderId": identity.identity_provider_id,
+                "provider": provider.as_ref().map(|p| &p.alias),
+                "providerType": provider.as_ref().map(|p| &p.provider_type),
+                "externalUserId": identity.external_user_id,
+                "externalUsername": identity.external_username,
+                "firstLoginAt": identity.first_login_at,
+                "lastLoginAt": identity.last_login_at,
+            }))
+            .collect::<Vec<_>>(),
+        "realmRoles": realm_roles
+            .iter()
+            .map(|(realm_role, role)| json!({
+                "realmId": realm_role.realm_id,
+                "realm": realm_name(realm_role.realm_id),
+                "role": role.as_ref().map(|r| &r.name),
+                "grantedAt": realm_role.granted_at,
+            }))
+            .collect::<Vec<_>>(),
+        "profiles": profiles,
+        "tripParticipations": trip_participations,
+        "trips": trips,
+        "tripCards": trip_cards,
+        "tripCardVotes": trip_card_votes,
+        "tripCardRichTextEdits": rich_text_edits,
+        "tripExpenses": trip_expenses,
+        "tripExpenseShares": expense_shares,
+        "chatParticipations": chat_participations,
+        "chats": chats,
+        "messages": messages,
+    }))
+}
@@ -1,45 +1,192 @@
-use anyhow::Result;
-use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation, decode, encode};
+use crate::auth::keys::JwtKeys;
+use anyhow::{Result, anyhow};
+use jsonwebtoken::{Header, Validation, decode, decode_header, encode};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use std::time::{SystemTime, UNIX_EPOCH};
 
+/// Database role PostGraphile switches to for API requests; created and
+/// restricted by row-level security policies in the migrations
+pub const DB_ROLE: &str = "tripvota_user";
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: String,              // Subject (User ID)
     pub exp: usize,               // Expiration
     pub iat: usize,               // Issued At
+    pub iss: String,              // Issuer
+    pub aud: String,              // Audience
+    pub role: String,             // Database role (PostGraphile)
+    pub account_id: String,       // Account ID, same as `sub`
     pub realm_id: Option<String>, // Realm ID
+    #[serde(default)]
+    pub session_version: i32, // `accounts.session_version` at issue time
+    #[serde(default)]
+    pub auth_time: usize, // When the session logged in; kept when the token is refreshed
 }
 
 pub fn sign_token(
     user_id: &str,
-    secret: &str,
+    keys: &JwtKeys,
     duration_secs: u64,
     realm_id: Option<&str>,
+    session_version: i32,
+    auth_time: u64,
 ) -> Result<String> {
-    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let now = now_secs()?;
     let claims = Claims {
         sub: user_id.to_owned(),
         exp: (now + duration_secs) as usize,
         iat: now as usize,
+        iss: keys.issuer.clone(),
+        aud: keys.audience.clone(),
+        role: DB_ROLE.to_owned(),
+        account_id: user_id.to_owned(),
         realm_id: realm_id.map(|s| s.to_owned()),
+        session_version,
+        auth_time: auth_time as usize,
     };
 
-    let token = encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(secret.as_bytes()),
-    )?;
+    let mut header = Header::new(keys.signing.algorithm);
+    header.kid = Some(keys.signing.kid.clone());
+
+    let token = encode(&header, &claims, &keys.signing.encoding_key)?;
 
     Ok(token)
 }
 
-pub fn verify_token(token: &str, secret: &str) -> Result<Claims> {
-    let token_data = decode::<Claims>(
-        token,
-        &DecodingKey::from_secret(secret.as_bytes()),
-        &Validation::default(),
-    )?;
+pub fn verify_token(token: &str, keys: &JwtKeys) -> Result<Claims> {
+    decode_for_audience(token, keys, &keys.audience)
+}
+
+/// Check the signature, issuer and expiry of a token, and that it was issued for `audience`
+fn decode_for_audience<T: DeserializeOwned>(
+    token: &str,
+    keys: &JwtKeys,
+    audience: &str,
+) -> Result<T> {
+    // Pick the verification key by `kid`, so tokens signed before a rotation stay valid
+    let header = decode_header(token)?;
+    let kid = header.kid.ok_or_else(|| anyhow!("token has no kid"))?;
+    let key = keys
+        .verifying
+        .get(&kid)
+        .ok_or_else(|| anyhow!("unknown kid '{kid}'"))?;
+
+    let mut validation = Validation::new(key.algorithm);
+    validation.set_issuer(&[&keys.issuer]);
+    validation.set_audience(&[audience]);
+
+    let token_data = decode::<T>(token, &key.decoding_key, &validation)?;
 
     Ok(token_data.claims)
 }
+
+/// Claims of a single-purpose token: an MFA challenge, a download or calendar link. Each
+/// purpose has its own audience (`<audience>/<purpose>`), so its tokens can never be
+/// accepted where an access token or a token of another purpose is expected.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PurposeClaims<T> {
+    pub sub: String, // Subject
+    pub exp: usize,  // Expiration
+    pub iat: usize,  // Issued At
+    pub iss: String, // Issuer
+    pub aud: String, // Audience (`<audience>/<purpose>`)
+    #[serde(flatten)]
+    pub extra: T, // Claims specific to the purpose
+}
+
+/// For purposes without claims of their own
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NoExtraClaims {}
+
+fn purpose_audience(keys: &JwtKeys, purpose: &str) -> String {
+    format!("{}/{purpose}", keys.audience)
+}
+
+fn now_secs() -> Result<u64> {
+    Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs())
+}
+
+/// Sign a token for `purpose` about `sub`, valid until `expires_at` (Unix seconds)
+fn sign_purpose_token<T: Serialize>(
+    keys: &JwtKeys,
+    purpose: &str,
+    sub: &str,
+    expires_at: u64,
+    extra: T,
+) -> Result<String> {
+    let claims = PurposeClaims {
+        sub: sub.to_owned(),
+        exp: expires_at as usize,
+        iat: now_secs()? as usize,
+        iss: keys.issuer.clone(),
+        aud: purpose_audience(keys, purpose),
+        extra,
+    };
+
+    let mut header = Header::new(keys.signing.algorithm);
+    header.kid = Some(keys.signing.kid.clone());
+
+    Ok(encode(&header, &claims, &keys.signing.encoding_key)?)
+}
+
+fn verify_purpose_token<T: DeserializeOwned>(
+    token: &str,
+    keys: &JwtKeys,
+    purpose: &str,
+) -> Result<PurposeClaims<T>> {
+    decode_for_audience(token, keys, &purpose_audience(keys, purpose))
+}
+
+/// Lifetime of the token handed out between the password and the MFA step
+const MFA_CHALLENGE_SECS: u64 = 300;
+
+/// Token handed out between the password and the MFA step; `sub` is the account ID
+pub type MfaChallengeClaims = PurposeClaims<NoExtraClaims>;
+
+pub fn sign_mfa_challenge(user_id: &str, keys: &JwtKeys) -> Result<String> {
+    let expires_at = now_secs()? + MFA_CHALLENGE_SECS;
+    sign_purpose_token(keys, "mfa", user_id, expires_at, NoExtraClaims {})
+}
+
+pub fn verify_mfa_challenge(token: &str, keys: &JwtKeys) -> Result<MfaChallengeClaims> {
+    verify_purpose_token(token, keys, "mfa")
+}
+
+/// Signed download link; `sub` is the ID of the downloadable resource
+pub type DownloadClaims = PurposeClaims<NoExtraClaims>;
+
+pub fn sign_download_token(resource_id: &str, keys: &JwtKeys, expires_at: u64) -> Result<String> {
+    sign_purpose_token(keys, "download", resource_id, expires_at, NoExtraClaims {})
+}
+
+pub fn verify_download_token(token: &str, keys: &JwtKeys) -> Result<DownloadClaims> {
+    verify_purpose_token(token, keys, "download")
+}
+
+/// Calendar feed link; `sub` is the trip ID. The link is long-lived, so the feed checks
+/// on every fetch that `profile_id` still takes part in the trip.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CalendarExtraClaims {
+    pub profile_id: String, // Profile the feed was issued to
+}
+
+pub type CalendarClaims = PurposeClaims<CalendarExtraClaims>;
+
+pub fn sign_calendar_token(
+    trip_id: &str,
+    profile_id: &str,
+    keys: &JwtKeys,
+    duration_secs: u64,
+) -> Result<String> {
+    let expires_at = now_secs()? + duration_secs;
+    let extra = CalendarExtraClaims {
+        profile_id: profile_id.to_owned(),
+    };
+    sign_purpose_token(keys, "calendar", trip_id, expires_at, extra)
+}
+
+pub fn verify_calendar_token(token: &str, keys: &JwtKeys) -> Result<CalendarClaims> {
+    verify_purpose_token(token, keys, "calendar")
+}
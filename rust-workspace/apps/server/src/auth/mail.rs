@@ -0,0 +1,25 @@
+use anyhow::Result;
+
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Delivers account emails (e.g. email change confirmations). The default writes them
+/// to stdout; a real transport (e.g. SMTP) can be plugged in through `AppState`.
+#[async_trait::async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, email: &Email) -> Result<()>;
+}
+
+/// Prints emails instead of sending them, for development
+pub struct ConsoleMailer;
+
+#[async_trait::async_trait]
+impl Mailer for ConsoleMailer {
+    async fn send(&self, email: &Email) -> Result<()> {
+        println!("mail to {}: {}\n{}", email.to, email.subject, email.body);
+        Ok(())
+    }
+}
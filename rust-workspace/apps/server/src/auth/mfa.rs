@@ -0,0 +1,268 @@
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use anyhow::{Context, Result, anyhow, bail};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use rand::{Rng, RngCore};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::time::{SystemTime, UNIX_EPOCH};
+use totp_rs::{Algorithm, TOTP};
+use uuid::Uuid;
+
+/// TOTP parameters understood by every common authenticator app
+const TOTP_DIGITS: usize = 6;
+const TOTP_STEP_SECS: u64 = 30;
+/// Codes from one step before/after the current one are accepted to allow for clock drift
+const TOTP_SKEW_STEPS: i64 = 1;
+/// 160-bit secrets, as recommended by RFC 4226
+const TOTP_SECRET_LEN: usize = 20;
+const NONCE_LEN: usize = 12;
+
+/// Number of recovery codes handed out per enrollment/regeneration
+const RECOVERY_CODE_COUNT: usize = 10;
+/// Unambiguous alphabet for recovery codes (no 0/o, 1/l/i)
+const RECOVERY_CODE_ALPHABET: &[u8] = b"23456789abcdefghjkmnpqrstuvwxyz";
+
+/// MFA configuration, read from the `mfa` section of appsettings.json
+///
+/// `encryption_key` is a base64 encoded 32-byte key used to encrypt TOTP secrets at rest:
+///
+/// ```sh
+/// openssl rand -base64 32
+/// ```
+#[derive(Deserialize, Debug)]
+pub struct MfaConfiguration {
+    /// Issuer shown in authenticator apps
+    pub issuer: String,
+    #[serde(default)]
+    pub encryption_key: String,
+}
+
+pub struct MfaKeys {
+    pub issuer: String,
+    cipher: Aes256Gcm,
+}
+
+impl MfaKeys {
+    pub fn from_configuration(config: &MfaConfiguration) -> Result<Self> {
+        if config.issuer.contains(':') {
+            bail!("mfa.issuer must not contain ':'");
+        }
+
+        let key = if config.encryption_key.is_empty() {
+            println!(
+                "WARNING: no mfa.encryption_key configured, using an ephemeral key (enrolled authenticators won't survive a restart)"
+            );
+            let mut key = [0u8; 32];
+            rand::thread_rng().fill_bytes(&mut key);
+            key.to_vec()
+        } else {
+            STANDARD
+                .decode(config.encryption_key.trim())
+                .context("mfa.encryption_key is not valid base64")?
+        };
+
+        let cipher = Aes256Gcm::new_from_slice(&key)
+            .map_err(|_| anyhow!("mfa.encryption_key must be 32 bytes"))?;
+
+        Ok(Self {
+            issuer: config.issuer.clone(),
+            cipher,
+        })
+    }
+
+    /// Encrypt a TOTP secret, bound to the account so rows can't be swapped between accounts.
+    /// Returns `(ciphertext, nonce)`.
+    pub fn encrypt_secret(&self, account_id: Uuid, secret: &[u8]) -> Result<(Vec<u8>, Vec<u8>)> {
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: secret,
+                    aad: account_id.as_bytes(),
+                },
+            )
+            .map_err(|_| anyhow!("failed to encrypt TOTP secret"))?;
+
+        Ok((ciphertext, nonce.to_vec()))
+    }
+
+    pub fn decrypt_secret(
+        &self,
+        account_id: Uuid,
+        ciphertext: &[u8],
+        nonce: &[u8],
+    ) -> Result<Vec<u8>> {
+        if nonce.len() != NONCE_LEN {
+            bail!("invalid TOTP secret nonce");
+        }
+
+        self.cipher
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: account_id.as_bytes(),
+                },
+            )
+            .map_err(|_| anyhow!("failed to decrypt TOTP secret"))
+    }
+
+    /// Build the TOTP generator for an account; `account_name` is the label shown in the app
+    pub fn totp(&self, secret: Vec<u8>, account_name: &str) -> Result<TOTP> {
+        TOTP::new(
+            Algorithm::SHA1,
+            TOTP_DIGITS,
+            0, // Skew is handled in `match_totp_code` so we know which step matched
+            TOTP_STEP_SECS,
+            secret,
+            Some(self.issuer.clone()),
+            account_name.replace(':', ""),
+        )
+        .map_err(|e| anyhow!("invalid TOTP parameters: {e}"))
+    }
+}
+
+pub fn generate_totp_secret() -> Vec<u8> {
+    let mut secret = vec![0u8; TOTP_SECRET_LEN];
+    rand::thread_rng().fill_bytes(&mut secret);
+    secret
+}
+
+/// Returns the time step `code` is valid for, ignoring steps up to and including
+/// `last_used_step` so a code can only be used once
+pub fn match_totp_code(totp: &TOTP, code: &str, last_used_step: Option<i64>) -> Option<i64> {
+    let code = code.trim();
+    if code.len() != TOTP_DIGITS {
+        return None;
+    }
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+    let current_step = (now / TOTP_STEP_SECS) as i64;
+
+    (current_step - TOTP_SKEW_STEPS..=current_step + TOTP_SKEW_STEPS)
+        .filter(|step| last_used_step.is_none_or(|last| *step > last))
+        .find(|step| totp.check(code, *step as u64 * TOTP_STEP_SECS))
+}
+
+/// Generate a fresh set of recovery codes in `xxxxx-xxxxx` form
+pub fn generate_recovery_codes() -> Vec<String> {
+    let mut rng = rand::thread_rng();
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let chars: String = (0..10)
+                .map(|_| {
+                    RECOVERY_CODE_ALPHABET[rng.gen_range(0..RECOVERY_CODE_ALPHABET.len())] as char
+                })
+                .collect();
+            format!("{}-{}", &chars[..5], &chars[5..])
+        })
+        .collect()
+}
+
+/// Hash a recovery code for storage/lookup; case, spaces and dashes are ignored
+pub fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    hex::encode(Sha256::digest(normalized.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys() -> MfaKeys {
+        MfaKeys::from_configuration(&MfaConfiguration {
+            issuer: "Tripvota".to_owned(),
+            encryption_key: STANDARD.encode([7u8; 32]),
+        })
+        .unwrap()
+    }
+
+    fn current_step() -> i64 {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        (now.as_secs() / TOTP_STEP_SECS) as i64
+    }
+
+    fn code_at(totp: &TOTP, step: i64) -> String {
+        totp.generate(step as u64 * TOTP_STEP_SECS)
+    }
+
+    #[test]
+    fn codes_match_within_the_skew_window() {
+        let totp = keys().totp(generate_totp_secret(), "alice").unwrap();
+        let step = current_step();
+        for step in [step - 1, step, step + 1] {
+            assert_eq!(
+                match_totp_code(&totp, &code_at(&totp, step), None),
+                Some(step)
+            );
+        }
+        assert_eq!(
+            match_totp_code(&totp, &code_at(&totp, step - 3), None),
+            None
+        );
+        assert_eq!(
+            match_totp_code(&totp, &code_at(&totp, step + 3), None),
+            None
+        );
+    }
+
+    #[test]
+    fn codes_are_accepted_once() {
+        let totp = keys().totp(generate_totp_secret(), "alice").unwrap();
+        let step = current_step();
+        let code = code_at(&totp, step);
+        assert_eq!(match_totp_code(&totp, &code, Some(step)), None);
+        assert_eq!(match_totp_code(&totp, &code, Some(step + 1)), None);
+        assert_eq!(match_totp_code(&totp, &code, Some(step - 1)), Some(step));
+    }
+
+    #[test]
+    fn malformed_codes_are_rejected() {
+        let totp = keys().totp(generate_totp_secret(), "alice").unwrap();
+        assert_eq!(match_totp_code(&totp, "", None), None);
+        assert_eq!(match_totp_code(&totp, "12345", None), None);
+        assert_eq!(match_totp_code(&totp, "1234567", None), None);
+    }
+
+    #[test]
+    fn secrets_decrypt_only_for_their_account() {
+        let keys = keys();
+        let account_id = Uuid::now_v7();
+        let secret = generate_totp_secret();
+        let (ciphertext, nonce) = keys.encrypt_secret(account_id, &secret).unwrap();
+
+        assert_eq!(
+            keys.decrypt_secret(account_id, &ciphertext, &nonce)
+                .unwrap(),
+            secret
+        );
+        assert!(
+            keys.decrypt_secret(Uuid::now_v7(), &ciphertext, &nonce)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn recovery_codes_hash_ignoring_case_and_separators() {
+        let codes = generate_recovery_codes();
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+
+        let code = &codes[0];
+        assert_eq!(code.len(), 11);
+        let hash = hash_recovery_code(code);
+        assert_eq!(hash_recovery_code(&code.to_uppercase()), hash);
+        assert_eq!(hash_recovery_code(&code.replace('-', " ")), hash);
+        assert_eq!(hash_recovery_code(&code.replace('-', "")), hash);
+        assert_ne!(hash_recovery_code(&codes[1]), hash);
+    }
+}
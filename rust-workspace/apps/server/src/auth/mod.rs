@@ -1,3 +1,13 @@
+pub mod api_token;
+pub mod audit;
+pub mod context;
+pub mod email_change;
+pub mod export;
 pub mod jwt;
+pub mod keys;
+pub mod mail;
+pub mod mfa;
 pub mod password;
+pub mod realm;
 pub mod service;
+pub mod throttle;
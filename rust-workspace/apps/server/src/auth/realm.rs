@@ -0,0 +1,161 @@
+use anyhow::Result;
+use chrono::{Duration, Utc};
+use sea_orm::{
+    ColumnTrait, Condition, ConnectionTrait, DatabaseConnection, EntityTrait, QueryFilter,
+    TransactionTrait,
+};
+use uuid::Uuid;
+use workspace_entity::{bots, channel_bridge, chats, messages, profiles, realms, trips};
+
+/// Days a deleted realm can still be restored before it is purged
+pub const DELETION_GRACE_DAYS: i64 = 30;
+
+/// How often the purge job looks for realms past their grace period
+const PURGE_INTERVAL_SECS: u64 = 3600;
+
+/// Realm names are used in URLs: 3-63 lowercase letters, digits and hyphens,
+/// not starting or ending with a hyphen
+pub fn is_valid_name(name: &str) -> bool {
+    (3..=63).contains(&name.len())
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-')
+        && !name.starts_with('-')
+        && !name.ends_with('-')
+}
+
+/// Fails unless the realm is active and not scheduled for deletion. Nothing but the
+/// realm lifecycle RPCs may act in a realm that isn't.
+pub async fn ensure_usable<C: ConnectionTrait>(
+    db: &C,
+    realm_id: Uuid,
+) -> Result<(), crate::error::Error> {
+    let realm = realms::Entity::find_by_id(realm_id)
+        .one(db)
+        .await?
+        .ok_or(crate::error::Error::NotFound)?;
+
+    if realm.deleted_at.is_some() {
+        return Err(crate::error::Error::FailedPrecondition(
+            "realm is scheduled for deletion".to_string(),
+        ));
+    }
+    if !realm.is_active {
+        return Err(crate::error::Error::FailedPrecondition(
+            "realm is deactivated".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Periodically hard-delete realms whose grace period has passed
+pub async fn run_purge(conn: DatabaseConnection) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(PURGE_INTERVAL_SECS));
+    loop {
+        interval.tick().await;
+        if let Err(err) = purge_expired(&conn).await {
+            println!("realm purge failed: {err:#}");
+        }
+    }
+}
+
+async fn purge_expired(conn: &DatabaseConnection) -> Result<()> {
+    let cutoff = Utc::now() - Duration::days(DELETION_GRACE_DAYS);
+    let expired = realms::Entity::find()
+        .filter(realms::Column::DeletedAt.lt(cutoff))
+        .all(conn)
+        .await?;
+
+    for realm in expired {
+        let txn = conn.begin().await?;
+        purge_realm(&txn, realm.id).await?;
+        txn.commit().await?;
+        println!("purged realm {} ({})", realm.name, realm.id);
+    }
+
+    Ok(())
+}
+
+/// Delete a realm and everything in it. Most rows go through the CASCADE foreign keys of
+/// `realms`; chats, their messages and channel bridges are not covered by those and are
+/// removed first (or, for bridges, after their bots are gone).
+async fn purge_realm<C: ConnectionTrait>(db: &C, realm_id: Uuid) -> Result<()> {
+    let trip_ids: Vec<Uuid> = trips::Entity::find()
+        .filter(trips::COLUMN.realm_id.eq(realm_id))
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|t| t.id)
+        .collect();
+    let profile_ids: Vec<Uuid> = profiles::Entity::find()
+        .filter(profiles::COLUMN.realm_id.eq(realm_id))
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|p| p.id)
+        .collect();
+
+    // Trip chats, and chats without a trip started by the realm's profiles
+    let chat_ids: Vec<Uuid> = chats::Entity::find()
+        .filter(
+            Condition::any()
+                .add(chats::Column::TripId.is_in(trip_ids))
+                .add(chats::COLUMN.created_by.is_in(profile_ids)),
+        )
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|c| c.id)
+        .collect();
+
+    messages::Entity::delete_many()
+        .filter(messages::COLUMN.chat_id.is_in(chat_ids.clone()))
+        .exec(db)
+        .await?;
+    chats::Entity::delete_many()
+        .filter(chats::COLUMN.id.is_in(chat_ids))
+        .exec(db)
+        .await?;
+
+    let bridge_ids: Vec<Uuid> = bots::Entity::find()
+        .filter(bots::COLUMN.realm_id.eq(realm_id))
+        .all(db)
+        .await?
+        .into_iter()
+        .flat_map(|b| [b.api_channel_bridge_id, b.oauth_channel_bridge_id])
+        .flatten()
+        .collect();
+
+    realms::Entity::delete_by_id(realm_id).exec(db).await?;
+
+    // Bridges may be shared with bots or profiles of other realms; keep those
+    let still_used: Vec<Uuid> = bots::Entity::find()
+        .filter(
+            Condition::any()
+                .add(bots::Column::ApiChannelBridgeId.is_in(bridge_ids.clone()))
+                .add(bots::Column::OauthChannelBridgeId.is_in(bridge_ids.clone())),
+        )
+        .all(db)
+        .await?
+        .into_iter()
+        .flat_map(|b| [b.api_channel_bridge_id, b.oauth_channel_bridge_id])
+        .chain(
+            profiles::Entity::find()
+                .filter(profiles::Column::ChannelBridgeId.is_in(bridge_ids.clone()))
+                .all(db)
+                .await?
+                .into_iter()
+                .map(|p| p.channel_bridge_id),
+        )
+        .flatten()
+        .collect();
+
+    channel_bridge::Entity::delete_many()
+        .filter(channel_bridge::COLUMN.id.is_in(bridge_ids))
+        .filter(channel_bridge::COLUMN.id.is_not_in(still_used))
+        .exec(db)
+        .await?;
+
+    Ok(())
+}
@@ -1,13 +1,28 @@
+use crate::auth::api_token;
+use crate::auth::audit;
+use crate::auth::context::{AuthContext, RealmLifecycleContext};
+use crate::auth::email_change;
+use crate::auth::export;
 use crate::auth::jwt;
+use crate::auth::mail;
+use crate::auth::mfa;
 use crate::auth::password;
+use crate::auth::realm;
+use crate::auth::throttle::{self, ThrottleScope};
 use crate::proto::auth::*;
+use crate::quota;
 use anyhow::Result;
-use chrono::Utc;
+use chrono::{DateTime, FixedOffset, Utc};
+use sea_orm::sea_query::Expr;
 use sea_orm::{
-    ActiveModelTrait, ColumnTrait, EntityTrait, ExprTrait, QueryFilter, Set, TransactionTrait,
+    ActiveModelTrait, ColumnTrait, Condition, ConnectionTrait, EntityTrait, ExprTrait, QueryFilter,
+    QueryOrder, Set, TransactionTrait,
 };
 use uuid::Uuid;
-use workspace_entity::{account_realm_roles, accounts, realms, roles};
+use workspace_entity::{
+    account_email_changes, account_realm_roles, account_recovery_codes, account_totp_secrets,
+    accounts, api_tokens, bots, data_exports, profiles, realms, roles,
+};
 
 // This state should be injected in main.rs.
 // For now, we'll assume the handler has access to the connection.
@@ -16,10 +31,13 @@ use workspace_entity::{account_realm_roles, accounts, realms, roles};
 // However, axum-connect handlers can take State as an argument.
 
 use crate::AppState;
-use axum::extract::State;
+use axum::Json;
+use axum::extract::{ConnectInfo, Path, State};
 use axum::http::{self, HeaderMap};
+use axum::response::{IntoResponse, Response};
 use axum_connect::error::RpcError;
 use axum_connect::parts::RpcFromRequestParts;
+use jsonwebtoken::jwk::JwkSet;
 use prost::Message;
 
 // Wrapper type to allow HeaderMap as an extractor in axum-connect handlers
@@ -48,17 +66,72 @@ where
     }
 }
 
+// Caller details recorded for login throttling and auth audit entries
+pub struct ClientInfo {
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+#[async_trait::async_trait]
+impl<M> RpcFromRequestParts<M, crate::AppState> for ClientInfo
+where
+    M: Message,
+{
+    type Rejection = RpcError;
+
+    async fn rpc_from_request_parts(
+        parts: &mut http::request::Parts,
+        state: &crate::AppState,
+    ) -> Result<Self, Self::Rejection> {
+        // X-Forwarded-For can be set by anyone, so only honour it behind a trusted proxy
+        let forwarded_ip = if state.trust_proxy_headers {
+            parts
+                .headers
+                .get("x-forwarded-for")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.split(',').next())
+                .map(|ip| ip.trim().to_string())
+                .filter(|ip| !ip.is_empty())
+        } else {
+            None
+        };
+
+        let peer_ip = parts
+            .extensions
+            .get::<ConnectInfo<std::net::SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip().to_string());
+
+        let user_agent = parts
+            .headers
+            .get(http::header::USER_AGENT)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_string());
+
+        Ok(ClientInfo {
+            ip: forwarded_ip.or(peer_ip),
+            user_agent,
+        })
+    }
+}
+
 pub async fn register(
     State(state): State<AppState>,
     request: RegisterRequest,
 ) -> Result<RegisterResponse, crate::error::Error> {
     // Validate input
-    if request.email.is_empty() || request.password.is_empty() || request.username.is_empty() {
-        // In a real app we'd want specific error codes, but for now Internal/Anyhow will do or we extend Error enum
-        return Err(crate::error::Error::Anyhow(anyhow::anyhow!(
-            "Missing fields"
-        )));
+    crate::error::Error::require_fields(&[
+        ("email", &request.email),
+        ("password", &request.password),
+        ("username", &request.username),
+    ])?;
+
+    if !request.email.contains('@') {
+        return Err(crate::error::Error::invalid_field(
+            "email",
+            "must be an email address",
+        ));
     }
+    validate_username(&request.username)?;
 
     // Check if account exists
     let existing_account = accounts::Entity::find()
@@ -68,8 +141,7 @@ pub async fn register(
                 .or(accounts::Column::Username.eq(&request.username)),
         )
         .one(&state.conn)
-        .await
-        .map_err(|e| crate::error::Error::Anyhow(anyhow::Error::new(e)))?;
+        .await?;
 
     if existing_account.is_some() {
         return Ok(RegisterResponse {
@@ -95,10 +167,7 @@ pub async fn register(
         ..Default::default()
     };
 
-    new_account
-        .insert(&state.conn)
-        .await
-        .map_err(|e| crate::error::Error::Anyhow(anyhow::Error::new(e)))?;
+    new_account.insert(&state.conn).await?;
 
     Ok(RegisterResponse {
         success: true,
@@ -106,122 +175,372 @@ pub async fn register(
     })
 }
 
+/// Usernames can't contain `@`, so they never look like another account's email
+fn validate_username(username: &str) -> Result<(), crate::error::Error> {
+    if username.contains('@') {
+        return Err(crate::error::Error::invalid_field(
+            "username",
+            "must not contain '@'",
+        ));
+    }
+    Ok(())
+}
+
+/// Account by email address or username; only emails contain `@`
+async fn find_by_identifier<C: ConnectionTrait>(
+    db: &C,
+    identifier: &str,
+) -> Result<Option<accounts::Model>, crate::error::Error> {
+    let column = if identifier.contains('@') {
+        accounts::COLUMN.email
+    } else {
+        accounts::COLUMN.username
+    };
+    Ok(accounts::Entity::find()
+        .filter(column.eq(identifier))
+        .one(db)
+        .await?)
+}
+
 pub async fn login(
     State(state): State<AppState>,
+    client: ClientInfo,
     request: LoginRequest,
 ) -> Result<LoginResponse, crate::error::Error> {
-    // Find account by email
-    // Note: request field is named 'email' but logic says it can be username too?
-    // Let's support both if we want, or strict email. The proto comment says "Can be email or username".
+    // The `email` field accepts either an email address or a username
+    let identifier = request.email.trim();
+    crate::error::Error::require_fields(&[("email", identifier), ("password", &request.password)])?;
 
-    let account = accounts::Entity::find()
-        .filter(
-            accounts::Column::Email.eq(&request.email), // .or(accounts::Column::Username.eq(&request.email)) // Uncomment to allow username login
-        )
-        .one(&state.conn)
-        .await
-        .map_err(|e| crate::error::Error::Anyhow(anyhow::Error::new(e)))?;
+    // Reject early if this client IP is locked out
+    if let Some(ip) = &client.ip {
+        let locked = throttle::locked_until(&state.conn, ThrottleScope::Ip, ip)
+            .await
+            .map_err(crate::error::Error::Anyhow)?;
+        if let Some(until) = locked {
+            audit::record_event(
+                &state.conn,
+                audit::LOGIN_LOCKED,
+                None,
+                Some(identifier),
+                &client,
+                Some(serde_json::json!({ "scope": "ip", "locked_until": until.to_rfc3339() })),
+            )
+            .await
+            .map_err(crate::error::Error::Anyhow)?;
+            return Err(locked_out(until));
+        }
+    }
 
-    let account = match account {
-        Some(a) => a,
-        None => {
-            return Ok(LoginResponse {
-                success: false,
-                account: None,
-                access_token: "".to_string(),
-                refresh_token: "".to_string(),
-            });
-        } // Or return Error::Forbidden
-    };
+    let account = find_by_identifier(&state.conn, identifier).await?;
+
+    // Reject early if the account is locked out
+    if let Some(account) = &account {
+        let locked =
+            throttle::locked_until(&state.conn, ThrottleScope::Account, &account.id.to_string())
+                .await
+                .map_err(crate::error::Error::Anyhow)?;
+        if let Some(until) = locked {
+            audit::record_event(
+                &state.conn,
+                audit::LOGIN_LOCKED,
+                Some(account.id),
+                Some(identifier),
+                &client,
+                Some(serde_json::json!({ "scope": "account", "locked_until": until.to_rfc3339() })),
+            )
+            .await
+            .map_err(crate::error::Error::Anyhow)?;
+            return Err(locked_out(until));
+        }
+    }
 
     // Verify password
-    let valid = if let Some(hash) = &account.password_hash {
-        password::verify_password(&request.password, hash).map_err(crate::error::Error::Anyhow)?
-    } else {
-        false // No password hash (federated only?)
+    let valid = match account.as_ref().and_then(|a| a.password_hash.as_ref()) {
+        Some(hash) => password::verify_password(&request.password, hash)
+            .map_err(crate::error::Error::Anyhow)?,
+        None => false, // Unknown account or no password hash (federated only?)
+    };
+
+    let account = match account {
+        Some(account) if valid => account,
+        account => {
+            let account_id = account.as_ref().map(|a| a.id);
+            record_login_failure(&state, &client, account_id, identifier).await?;
+            return Err(crate::error::Error::Unauthenticated);
+        }
     };
 
-    if !valid {
+    // Only checked once the password is known to be right, so it doesn't reveal accounts
+    if !account.is_active {
+        return Err(crate::error::Error::FailedPrecondition(
+            "account is deactivated".to_string(),
+        ));
+    }
+
+    // A second factor is needed if TOTP is set up, or if a realm policy demands it.
+    // The throttle is only reset once the login is complete, so MFA guesses keep counting.
+    let totp_enabled = find_confirmed_totp(&state.conn, account.id)
+        .await?
+        .is_some();
+    let mfa_enrollment_required =
+        !totp_enabled && is_mfa_required_by_policy(&state.conn, account.id).await?;
+    if totp_enabled || mfa_enrollment_required {
+        let mfa_challenge_token = jwt::sign_mfa_challenge(&account.id.to_string(), &state.jwt_keys)
+            .map_err(crate::error::Error::Anyhow)?;
+
         return Ok(LoginResponse {
-            success: false,
+            success: true,
             account: None,
-            access_token: "".to_string(),
-            refresh_token: "".to_string(),
+            access_token: String::new(),
+            refresh_token: String::new(),
+            mfa_required: true,
+            mfa_challenge_token,
+            mfa_enrollment_required,
+            realm_id: String::new(),
         });
     }
 
-    // Generate tokens
-    let access_token = jwt::sign_token(&account.id.to_string(), &state.jwt_secret, 3600, None) // 1 hour
+    let session = complete_login(&state, account).await?;
+
+    Ok(LoginResponse {
+        success: true,
+        account: Some(session.account),
+        access_token: session.access_token,
+        refresh_token: session.refresh_token,
+        mfa_required: false,
+        mfa_challenge_token: String::new(),
+        mfa_enrollment_required: false,
+        realm_id: session.realm_id,
+    })
+}
+
+// Tokens handed out once all login factors have been checked
+struct Session {
+    account: Account,
+    access_token: String,
+    refresh_token: String,
+    /// Realm the tokens are for; empty if the account has no usable realm
+    realm_id: String,
+}
+
+/// Realm a login starts in: the one the account last selected, else the one it joined
+/// first. Realms it has left, deactivated realms and realms scheduled for deletion are
+/// skipped.
+async fn login_realm<C: ConnectionTrait>(
+    db: &C,
+    account: &accounts::Model,
+) -> Result<Option<Uuid>, crate::error::Error> {
+    let memberships = account_realm_roles::Entity::find()
+        .filter(account_realm_roles::COLUMN.account_id.eq(account.id))
+        .order_by_asc(account_realm_roles::Column::GrantedAt)
+        .all(db)
+        .await?;
+    let usable: Vec<Uuid> = realms::Entity::find()
+        .filter(
+            realms::COLUMN
+                .id
+                .is_in(memberships.iter().map(|m| m.realm_id)),
+        )
+        .filter(realms::COLUMN.is_active.eq(true))
+        .filter(realms::Column::DeletedAt.is_null())
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|realm| realm.id)
+        .collect();
+
+    if let Some(last) = account.last_realm_id.filter(|id| usable.contains(id)) {
+        return Ok(Some(last));
+    }
+    Ok(memberships
+        .into_iter()
+        .map(|m| m.realm_id)
+        .find(|id| usable.contains(id)))
+}
+
+/// Issue tokens for a fully authenticated account, reset its failure counter and
+/// record the login time. The tokens carry the realm from `login_realm`, so
+/// row-level security applies from the first request.
+async fn complete_login(
+    state: &AppState,
+    account: accounts::Model,
+) -> Result<Session, crate::error::Error> {
+    throttle::reset(&state.conn, ThrottleScope::Account, &account.id.to_string())
+        .await
         .map_err(crate::error::Error::Anyhow)?;
-    let refresh_token =
-        jwt::sign_token(&account.id.to_string(), &state.jwt_secret, 86400 * 7, None) // 7 days
-            .map_err(crate::error::Error::Anyhow)?;
+
+    // Link the account to a profile in each of its realms
+    crate::profile::ensure_for_member_realms(&state.conn, &account).await?;
+
+    // Generate tokens
+    let realm_id = login_realm(&state.conn, &account)
+        .await?
+        .map(|id| id.to_string());
+    let auth_time = Utc::now().timestamp() as u64;
+    let (access_token, refresh_token) =
+        sign_session_tokens(state, &account, realm_id.as_deref(), auth_time)?;
 
     // Update last login
     let mut active_account: accounts::ActiveModel = account.clone().into();
     active_account.last_login_at = Set(Some(Utc::now().into()));
-    active_account
-        .update(&state.conn)
-        .await
-        .map_err(|e| crate::error::Error::Anyhow(anyhow::Error::new(e)))?;
+    active_account.update(&state.conn).await?;
 
-    Ok(LoginResponse {
-        success: true,
-        account: Some(Account {
-            id: account.id.to_string(),
-            email: account.email,
-            username: account.username,
-            created_at: account.created_at.to_rfc3339(),
-        }),
+    Ok(Session {
+        account: account_to_proto(account),
         access_token,
         refresh_token,
+        realm_id: realm_id.unwrap_or_default(),
     })
 }
 
+fn account_to_proto(account: accounts::Model) -> Account {
+    Account {
+        id: account.id.to_string(),
+        email: account.email,
+        username: account.username,
+        created_at: account.created_at.to_rfc3339(),
+    }
+}
+
+/// Sign an access token (1 hour) and a refresh token (7 days) for the account, for a
+/// session that logged in at `auth_time` (Unix seconds)
+fn sign_session_tokens(
+    state: &AppState,
+    account: &accounts::Model,
+    realm_id: Option<&str>,
+    auth_time: u64,
+) -> Result<(String, String), crate::error::Error> {
+    let account_id = account.id.to_string();
+    let access_token = jwt::sign_token(
+        &account_id,
+        &state.jwt_keys,
+        3600,
+        realm_id,
+        account.session_version,
+        auth_time,
+    )
+    .map_err(crate::error::Error::Anyhow)?;
+    let refresh_token = jwt::sign_token(
+        &account_id,
+        &state.jwt_keys,
+        86400 * 7,
+        realm_id,
+        account.session_version,
+        auth_time,
+    )
+    .map_err(crate::error::Error::Anyhow)?;
+
+    Ok((access_token, refresh_token))
+}
+
+/// Count a failed login against the client IP and the account, and write an audit entry
+async fn record_login_failure(
+    state: &AppState,
+    client: &ClientInfo,
+    account_id: Option<Uuid>,
+    identifier: &str,
+) -> Result<(), crate::error::Error> {
+    let mut locked_until = None;
+
+    if let Some(ip) = &client.ip {
+        locked_until = throttle::record_failure(&state.conn, ThrottleScope::Ip, ip)
+            .await
+            .map_err(crate::error::Error::Anyhow)?;
+    }
+
+    if let Some(account_id) = account_id {
+        let account_locked_until =
+            throttle::record_failure(&state.conn, ThrottleScope::Account, &account_id.to_string())
+                .await
+                .map_err(crate::error::Error::Anyhow)?;
+        locked_until = locked_until.max(account_locked_until);
+    }
+
+    audit::record_event(
+        &state.conn,
+        audit::LOGIN_FAILED,
+        account_id,
+        Some(identifier),
+        client,
+        Some(serde_json::json!({
+            "reason": if account_id.is_some() { "invalid_password" } else { "unknown_account" },
+            "locked_until": locked_until.map(|t| t.to_rfc3339()),
+        })),
+    )
+    .await
+    .map_err(crate::error::Error::Anyhow)?;
+
+    Ok(())
+}
+
+/// Error for a login attempt while locked out; clients can retry once the lock expires
+fn locked_out(until: DateTime<FixedOffset>) -> crate::error::Error {
+    crate::error::Error::ResourceExhausted {
+        message: "Too many failed login attempts".to_string(),
+        retry_after: until.signed_duration_since(Utc::now()).to_std().ok(),
+    }
+}
+
 pub async fn refresh_token(
     State(state): State<AppState>,
     request: RefreshTokenRequest,
 ) -> Result<RefreshTokenResponse, crate::error::Error> {
     // Verify refresh token
-    let claims = jwt::verify_token(&request.refresh_token, &state.jwt_secret)
-        .map_err(|_| crate::error::Error::Forbidden)?; // Use proper error for invalid token
+    let claims = jwt::verify_token(&request.refresh_token, &state.jwt_keys)
+        .map_err(|_| crate::error::Error::Unauthenticated)?;
+
+    // The account must still be active, and the session not revoked since the token was issued
+    let account_id =
+        Uuid::parse_str(&claims.sub).map_err(|_| crate::error::Error::Unauthenticated)?;
+    let account = accounts::Entity::find_by_id(account_id)
+        .one(&state.conn)
+        .await?
+        .filter(|a| a.is_active && a.session_version == claims.session_version)
+        .ok_or(crate::error::Error::Unauthenticated)?;
 
-    // In a real app, we should check if the account still exists and is active.
     // We should also support token rotation (invalidating the old refresh token).
 
-    // Extract and validate realm_id if provided
-    let realm_id_str = if !request.realm_id.is_empty() {
+    // The tokens stay in the realm they were issued for unless the request selects
+    // another. Either way the account must still be a member, and the realm usable: a
+    // token for a realm since left, deactivated or deleted can't be refreshed.
+    let token_realm_id = claims
+        .realm_id
+        .as_deref()
+        .map(Uuid::parse_str)
+        .transpose()
+        .map_err(|_| crate::error::Error::Unauthenticated)?;
+    let realm_id = if !request.realm_id.is_empty() {
         let realm_id = Uuid::parse_str(&request.realm_id)
-            .map_err(|_| crate::error::Error::Anyhow(anyhow::anyhow!("Invalid realm_id format")))?;
-
-        // Validate that user has access to this realm
-        let account_id = Uuid::parse_str(&claims.sub).map_err(|_| {
-            crate::error::Error::Anyhow(anyhow::anyhow!("Invalid account ID in token"))
-        })?;
-
-        let has_access = account_realm_roles::Entity::find()
-            .filter(account_realm_roles::COLUMN.account_id.eq(account_id))
-            .filter(account_realm_roles::COLUMN.realm_id.eq(realm_id))
-            .one(&state.conn)
-            .await
-            .map_err(|e| crate::error::Error::Anyhow(anyhow::Error::new(e)))?;
+            .map_err(|_| crate::error::Error::invalid_field("realm_id", "must be a UUID"))?;
+        ensure_usable_membership(&state.conn, account_id, realm_id).await?;
+        crate::profile::ensure_for_account(&state.conn, realm_id, &account).await?;
 
-        if has_access.is_none() {
-            return Err(crate::error::Error::Forbidden);
+        // Remembered for the next login
+        if account.last_realm_id != Some(realm_id) {
+            accounts::Entity::update_many()
+                .col_expr(accounts::Column::LastRealmId, Expr::value(realm_id))
+                .filter(accounts::COLUMN.id.eq(account.id))
+                .exec(&state.conn)
+                .await?;
         }
 
-        Some(request.realm_id.as_str())
+        Some(realm_id)
+    } else if let Some(realm_id) = token_realm_id {
+        ensure_usable_membership(&state.conn, account_id, realm_id).await?;
+        Some(realm_id)
     } else {
         None
     };
-
-    let access_token = jwt::sign_token(&claims.sub, &state.jwt_secret, 3600, realm_id_str)
-        .map_err(crate::error::Error::Anyhow)?;
+    let realm_id = realm_id.map(|id| id.to_string());
 
     // Optionally rotate refresh token
-    let new_refresh_token =
-        jwt::sign_token(&claims.sub, &state.jwt_secret, 86400 * 7, realm_id_str)
-            .map_err(crate::error::Error::Anyhow)?;
+    // The session keeps its login time, so refreshing doesn't make it look fresh
+    let (access_token, new_refresh_token) = sign_session_tokens(
+        &state,
+        &account,
+        realm_id.as_deref(),
+        claims.auth_time as u64,
+    )?;
 
     Ok(RefreshTokenResponse {
         success: true,
@@ -230,6 +549,24 @@ pub async fn refresh_token(
     })
 }
 
+/// Fails unless the account is a member of the realm and the realm is usable
+async fn ensure_usable_membership<C: ConnectionTrait>(
+    db: &C,
+    account_id: Uuid,
+    realm_id: Uuid,
+) -> Result<(), crate::error::Error> {
+    let membership = account_realm_roles::Entity::find()
+        .filter(account_realm_roles::COLUMN.account_id.eq(account_id))
+        .filter(account_realm_roles::COLUMN.realm_id.eq(realm_id))
+        .one(db)
+        .await?;
+    if membership.is_none() {
+        return Err(crate::error::Error::Forbidden);
+    }
+
+    realm::ensure_usable(db, realm_id).await
+}
+
 pub async fn logout(
     State(_state): State<AppState>,
     _request: LogoutRequest,
@@ -239,51 +576,21 @@ pub async fn logout(
     Ok(LogoutResponse { success: true })
 }
 
-fn extract_account_id_from_headers(
-    headers: &Headers,
-    jwt_secret: &str,
-) -> Result<Uuid, crate::error::Error> {
-    // Extract Authorization header
-    let auth_header = headers
-        .get("authorization")
-        .ok_or(crate::error::Error::Forbidden)?
-        .to_str()
-        .map_err(|_| crate::error::Error::Forbidden)?;
-
-    // Parse Bearer token
-    let token = auth_header
-        .strip_prefix("Bearer ")
-        .ok_or(crate::error::Error::Forbidden)?;
-
-    // Verify token and extract account ID
-    let claims =
-        jwt::verify_token(token, jwt_secret).map_err(|_| crate::error::Error::Forbidden)?;
-
-    let account_id = Uuid::parse_str(&claims.sub).map_err(|_| crate::error::Error::Forbidden)?;
-
-    Ok(account_id)
-}
-
 pub async fn me(
     State(state): State<AppState>,
-    headers: Headers,
+    ctx: AuthContext,
     _request: MeRequest,
 ) -> Result<MeResponse, crate::error::Error> {
-    let account_id = extract_account_id_from_headers(&headers, &state.jwt_secret)?;
+    ctx.require_scope(api_token::SCOPE_ACCOUNT_READ)?;
+    let account_id = ctx.require_account()?;
 
     let account = accounts::Entity::find_by_id(account_id)
         .one(&state.conn)
-        .await
-        .map_err(|e| crate::error::Error::Anyhow(anyhow::Error::new(e)))?;
+        .await?;
 
     if let Some(account) = account {
         Ok(MeResponse {
-            account: Some(Account {
-                id: account.id.to_string(),
-                email: account.email,
-                username: account.username,
-                created_at: account.created_at.to_rfc3339(),
-            }),
+            account: Some(account_to_proto(account)),
         })
     } else {
         Err(crate::error::Error::NotFound)
@@ -292,32 +599,42 @@ pub async fn me(
 
 pub async fn list_realms(
     State(state): State<AppState>,
-    headers: Headers,
+    ctx: AuthContext,
     _request: ListRealmsRequest,
 ) -> Result<ListRealmsResponse, crate::error::Error> {
-    let account_id = extract_account_id_from_headers(&headers, &state.jwt_secret)?;
+    ctx.require_scope(api_token::SCOPE_ACCOUNT_READ)?;
+    let account_id = ctx.require_account()?;
 
     // Query account_realm_roles to get user's realms
     let realm_roles = account_realm_roles::Entity::find()
         .filter(account_realm_roles::COLUMN.account_id.eq(account_id))
         .find_also_related(realms::Entity)
         .all(&state.conn)
-        .await
-        .map_err(|e| crate::error::Error::Anyhow(anyhow::Error::new(e)))?;
+        .await?;
+
+    // Admins also see their deactivated and deleted realms, so they can bring them back
+    let admin_role_ids: Vec<Uuid> = roles::Entity::find()
+        .filter(
+            roles::COLUMN
+                .id
+                .is_in(realm_roles.iter().map(|(r, _)| r.role_id)),
+        )
+        .filter(roles::COLUMN.name.eq("admin"))
+        .all(&state.conn)
+        .await?
+        .into_iter()
+        .map(|r| r.id)
+        .collect();
 
     // Filter to active realms and map to proto messages
     let realms_list: Vec<Realm> = realm_roles
         .into_iter()
-        .filter_map(|(_, realm)| realm)
-        .filter(|r| r.is_active)
-        .map(|r| Realm {
-            id: r.id.to_string(),
-            name: r.name,
-            display_name: r.display_name,
-            description: r.description.unwrap_or_default(),
-            is_active: r.is_active,
-            created_at: r.created_at.to_rfc3339(),
+        .filter_map(|(realm_role, realm)| realm.map(|realm| (realm_role, realm)))
+        .filter(|(realm_role, realm)| {
+            (realm.is_active && realm.deleted_at.is_none())
+                || admin_role_ids.contains(&realm_role.role_id)
         })
+        .map(|(_, realm)| realm_to_proto(realm))
         .collect();
 
     Ok(ListRealmsResponse {
@@ -327,25 +644,26 @@ pub async fn list_realms(
 
 pub async fn create_realm(
     State(state): State<AppState>,
-    headers: Headers,
+    ctx: AuthContext,
     request: CreateRealmRequest,
 ) -> Result<CreateRealmResponse, crate::error::Error> {
-    // Extract account_id from JWT headers
-    let account_id = extract_account_id_from_headers(&headers, &state.jwt_secret)?;
+    ctx.require_scope(api_token::SCOPE_REALMS_WRITE)?;
+    let account_id = ctx.require_account()?;
 
     // Validate input
-    if request.name.is_empty() || request.display_name.is_empty() {
-        return Err(crate::error::Error::Anyhow(anyhow::anyhow!(
-            "Name and display_name are required"
-        )));
+    crate::error::Error::require_fields(&[
+        ("name", &request.name),
+        ("display_name", &request.display_name),
+    ])?;
+    if !realm::is_valid_name(&request.name) {
+        return Err(invalid_realm_name());
     }
 
     // Check if realm name already exists (unique constraint)
     let existing_realm = realms::Entity::find()
         .filter(realms::COLUMN.name.eq(&request.name))
         .one(&state.conn)
-        .await
-        .map_err(|e| crate::error::Error::Anyhow(anyhow::Error::new(e)))?;
+        .await?;
 
     if existing_realm.is_some() {
         return Ok(CreateRealmResponse {
@@ -356,11 +674,7 @@ pub async fn create_realm(
     }
 
     // Start transaction for atomic realm, role, and account_realm_role creation
-    let txn = state
-        .conn
-        .begin()
-        .await
-        .map_err(|e| crate::error::Error::Anyhow(anyhow::Error::new(e)))?;
+    let txn = state.conn.begin().await?;
 
     // Create realm
     let new_realm = realms::ActiveModel {
@@ -376,12 +690,13 @@ pub async fn create_realm(
         created_at: Set(Utc::now().into()),
         updated_at: Set(Utc::now().into()),
         metadata: Set(None),
+        require_admin_mfa: Set(false),
+        deleted_at: Set(None),
     };
 
     let created_realm = realms::Entity::insert(new_realm)
         .exec_with_returning(&txn)
-        .await
-        .map_err(|e| crate::error::Error::Anyhow(anyhow::Error::new(e)))?;
+        .await?;
 
     let realm_id = created_realm.id;
 
@@ -397,10 +712,7 @@ pub async fn create_realm(
         created_at: Set(Utc::now().into()),
     };
 
-    roles::Entity::insert(admin_role)
-        .exec(&txn)
-        .await
-        .map_err(|e| crate::error::Error::Anyhow(anyhow::Error::new(e)))?;
+    roles::Entity::insert(admin_role).exec(&txn).await?;
 
     // Add creator to account_realm_roles with admin role
     let account_realm_role = account_realm_roles::ActiveModel {
@@ -413,24 +725,1745 @@ pub async fn create_realm(
 
     account_realm_roles::Entity::insert(account_realm_role)
         .exec(&txn)
-        .await
-        .map_err(|e| crate::error::Error::Anyhow(anyhow::Error::new(e)))?;
+        .await?;
+
+    let account = accounts::Entity::find_by_id(account_id)
+        .one(&txn)
+        .await?
+        .ok_or(crate::error::Error::Unauthenticated)?;
+    crate::profile::ensure_for_account(&txn, realm_id, &account).await?;
 
     // Commit transaction
-    txn.commit()
-        .await
-        .map_err(|e| crate::error::Error::Anyhow(anyhow::Error::new(e)))?;
+    txn.commit().await?;
 
     Ok(CreateRealmResponse {
         success: true,
         message: "Realm created successfully".to_string(),
-        realm: Some(Realm {
-            id: created_realm.id.to_string(),
-            name: created_realm.name,
-            display_name: created_realm.display_name,
-            description: created_realm.description.unwrap_or_default(),
-            is_active: created_realm.is_active,
-            created_at: created_realm.created_at.to_rfc3339(),
-        }),
+        realm: Some(realm_to_proto(created_realm)),
+    })
+}
+
+fn realm_to_proto(realm: realms::Model) -> Realm {
+    Realm {
+        id: realm.id.to_string(),
+        name: realm.name,
+        display_name: realm.display_name,
+        description: realm.description.unwrap_or_default(),
+        is_active: realm.is_active,
+        created_at: realm.created_at.to_rfc3339(),
+        require_admin_mfa: realm.require_admin_mfa,
+        deleted_at: realm.deleted_at.map(|t| t.to_rfc3339()).unwrap_or_default(),
+        metadata: realm.metadata.map(|m| m.to_string()).unwrap_or_default(),
+    }
+}
+
+fn invalid_realm_name() -> crate::error::Error {
+    crate::error::Error::invalid_field(
+        "name",
+        "must be 3-63 lowercase letters, digits or hyphens, not starting or ending with a hyphen",
+    )
+}
+
+/// Realm by the `realm_id` of a request, if the caller is an admin there
+async fn find_admin_realm(
+    state: &AppState,
+    ctx: &AuthContext,
+    realm_id: &str,
+) -> Result<realms::Model, crate::error::Error> {
+    let realm_id = Uuid::parse_str(realm_id)
+        .map_err(|_| crate::error::Error::invalid_field("realm_id", "must be a UUID"))?;
+
+    let roles = ctx.roles_in(state, realm_id).await?;
+    if !roles.iter().any(|r| r == "admin") {
+        return Err(crate::error::Error::Forbidden);
+    }
+
+    realms::Entity::find_by_id(realm_id)
+        .one(&state.conn)
+        .await?
+        .ok_or(crate::error::Error::NotFound)
+}
+
+/// Serve the public verification keys as a JWKS document
+pub async fn jwks(State(state): State<AppState>) -> impl IntoResponse {
+    let jwks: JwkSet = state.jwt_keys.jwks();
+    (
+        [(http::header::CACHE_CONTROL, "public, max-age=300")],
+        Json(jwks),
+    )
+}
+
+// ============================================================================
+// Multi-Factor Authentication
+// ============================================================================
+
+async fn find_confirmed_totp<C: ConnectionTrait>(
+    db: &C,
+    account_id: Uuid,
+) -> Result<Option<account_totp_secrets::Model>, crate::error::Error> {
+    let totp = account_totp_secrets::Entity::find_by_id(account_id)
+        .one(db)
+        .await?;
+
+    Ok(totp.filter(|t| t.confirmed_at.is_some()))
+}
+
+/// Realm IDs in which the account holds the `admin` role
+async fn find_admin_realm_ids<C: ConnectionTrait>(
+    db: &C,
+    account_id: Uuid,
+) -> Result<Vec<Uuid>, crate::error::Error> {
+    let realm_roles = account_realm_roles::Entity::find()
+        .filter(account_realm_roles::COLUMN.account_id.eq(account_id))
+        .find_also_related(roles::Entity)
+        .all(db)
+        .await?;
+
+    Ok(realm_roles
+        .into_iter()
+        .filter(|(_, role)| role.as_ref().is_some_and(|r| r.name == "admin"))
+        .map(|(realm_role, _)| realm_role.realm_id)
+        .collect())
+}
+
+/// Whether an active realm requires MFA from this account because it is an admin there
+async fn is_mfa_required_by_policy<C: ConnectionTrait>(
+    db: &C,
+    account_id: Uuid,
+) -> Result<bool, crate::error::Error> {
+    let admin_realm_ids = find_admin_realm_ids(db, account_id).await?;
+    if admin_realm_ids.is_empty() {
+        return Ok(false);
+    }
+
+    let enforcing_realm = realms::Entity::find()
+        .filter(realms::COLUMN.id.is_in(admin_realm_ids))
+        .filter(realms::COLUMN.require_admin_mfa.eq(true))
+        .filter(realms::COLUMN.is_active.eq(true))
+        .one(db)
+        .await?;
+
+    Ok(enforcing_realm.is_some())
+}
+
+/// Check a TOTP code (and, if allowed, a recovery code) for an account with confirmed TOTP.
+/// Returns the factor that matched; accepted codes are consumed so they can't be replayed.
+async fn verify_second_factor(
+    state: &AppState,
+    account: &accounts::Model,
+    code: &str,
+    allow_recovery_code: bool,
+) -> Result<Option<&'static str>, crate::error::Error> {
+    let Some(totp_secret) = find_confirmed_totp(&state.conn, account.id).await? else {
+        return Ok(None);
+    };
+
+    let secret = state
+        .mfa_keys
+        .decrypt_secret(
+            account.id,
+            &totp_secret.secret_ciphertext,
+            &totp_secret.secret_nonce,
+        )
+        .map_err(crate::error::Error::Anyhow)?;
+    let totp = state
+        .mfa_keys
+        .totp(secret, &account.email)
+        .map_err(crate::error::Error::Anyhow)?;
+
+    if let Some(step) = mfa::match_totp_code(&totp, code, totp_secret.last_used_step) {
+        // Conditional update, so two concurrent requests can't both use the same code
+        let updated = account_totp_secrets::Entity::update_many()
+            .col_expr(account_totp_secrets::Column::LastUsedStep, step.into())
+            .filter(account_totp_secrets::COLUMN.account_id.eq(account.id))
+            .filter(
+                account_totp_secrets::Column::LastUsedStep
+                    .is_null()
+                    .or(account_totp_secrets::Column::LastUsedStep.lt(step)),
+            )
+            .exec(&state.conn)
+            .await?;
+
+        return Ok((updated.rows_affected == 1).then_some("totp"));
+    }
+
+    if allow_recovery_code {
+        let consumed = account_recovery_codes::Entity::update_many()
+            .col_expr(
+                account_recovery_codes::Column::UsedAt,
+                chrono::DateTime::<chrono::FixedOffset>::from(Utc::now()).into(),
+            )
+            .filter(account_recovery_codes::COLUMN.account_id.eq(account.id))
+            .filter(
+                account_recovery_codes::COLUMN
+                    .code_hash
+                    .eq(mfa::hash_recovery_code(code)),
+            )
+            .filter(account_recovery_codes::Column::UsedAt.is_null())
+            .exec(&state.conn)
+            .await?;
+
+        if consumed.rows_affected == 1 {
+            return Ok(Some("recovery_code"));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Reject while the account is locked out by too many failed attempts
+async fn ensure_account_not_locked(
+    state: &AppState,
+    client: &ClientInfo,
+    account: &accounts::Model,
+) -> Result<(), crate::error::Error> {
+    let locked =
+        throttle::locked_until(&state.conn, ThrottleScope::Account, &account.id.to_string())
+            .await
+            .map_err(crate::error::Error::Anyhow)?;
+
+    if let Some(until) = locked {
+        audit::record_event(
+            &state.conn,
+            audit::LOGIN_LOCKED,
+            Some(account.id),
+            Some(&account.email),
+            client,
+            Some(serde_json::json!({ "scope": "account", "locked_until": until.to_rfc3339() })),
+        )
+        .await
+        .map_err(crate::error::Error::Anyhow)?;
+        return Err(locked_out(until));
+    }
+
+    Ok(())
+}
+
+/// Count a wrong MFA code against the account and write an audit entry
+async fn record_mfa_failure(
+    state: &AppState,
+    client: &ClientInfo,
+    account: &accounts::Model,
+) -> Result<(), crate::error::Error> {
+    let locked_until =
+        throttle::record_failure(&state.conn, ThrottleScope::Account, &account.id.to_string())
+            .await
+            .map_err(crate::error::Error::Anyhow)?;
+
+    audit::record_event(
+        &state.conn,
+        audit::MFA_FAILED,
+        Some(account.id),
+        Some(&account.email),
+        client,
+        Some(serde_json::json!({ "locked_until": locked_until.map(|t| t.to_rfc3339()) })),
+    )
+    .await
+    .map_err(crate::error::Error::Anyhow)?;
+
+    Ok(())
+}
+
+/// Resolve the account for the MFA RPCs: from the login challenge token when one is given,
+/// otherwise from the Authorization header. The flag tells whether a challenge was used.
+async fn find_mfa_account(
+    state: &AppState,
+    headers: &Headers,
+    mfa_challenge_token: &str,
+) -> Result<(accounts::Model, bool), crate::error::Error> {
+    let (account_id, via_challenge) = if mfa_challenge_token.is_empty() {
+        (
+            AuthContext::from_headers(state, headers)
+                .await?
+                .require_session()?,
+            false,
+        )
+    } else {
+        let claims = jwt::verify_mfa_challenge(mfa_challenge_token, &state.jwt_keys)
+            .map_err(|_| crate::error::Error::Unauthenticated)?;
+        let account_id =
+            Uuid::parse_str(&claims.sub).map_err(|_| crate::error::Error::Unauthenticated)?;
+        (account_id, true)
+    };
+
+    let account = accounts::Entity::find_by_id(account_id)
+        .one(&state.conn)
+        .await?
+        .filter(|a| a.is_active)
+        .ok_or(crate::error::Error::Unauthenticated)?;
+
+    Ok((account, via_challenge))
+}
+
+/// Replace the account's recovery codes and return the new plain-text codes
+async fn replace_recovery_codes<C: ConnectionTrait>(
+    db: &C,
+    account_id: Uuid,
+) -> Result<Vec<String>, crate::error::Error> {
+    account_recovery_codes::Entity::delete_many()
+        .filter(account_recovery_codes::COLUMN.account_id.eq(account_id))
+        .exec(db)
+        .await?;
+
+    let codes = mfa::generate_recovery_codes();
+    let models = codes
+        .iter()
+        .map(|code| account_recovery_codes::ActiveModel {
+            id: Set(Uuid::now_v7()),
+            account_id: Set(account_id),
+            code_hash: Set(mfa::hash_recovery_code(code)),
+            used_at: Set(None),
+            created_at: Set(Utc::now().into()),
+        });
+
+    account_recovery_codes::Entity::insert_many(models)
+        .exec(db)
+        .await?;
+
+    Ok(codes)
+}
+
+pub async fn verify_mfa(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    request: VerifyMfaRequest,
+) -> Result<VerifyMfaResponse, crate::error::Error> {
+    let claims = jwt::verify_mfa_challenge(&request.mfa_challenge_token, &state.jwt_keys)
+        .map_err(|_| crate::error::Error::Unauthenticated)?;
+    let account_id =
+        Uuid::parse_str(&claims.sub).map_err(|_| crate::error::Error::Unauthenticated)?;
+
+    let account = accounts::Entity::find_by_id(account_id)
+        .one(&state.conn)
+        .await?
+        .filter(|a| a.is_active)
+        .ok_or(crate::error::Error::Unauthenticated)?;
+
+    ensure_account_not_locked(&state, &client, &account).await?;
+
+    let Some(method) = verify_second_factor(&state, &account, &request.code, true).await? else {
+        record_mfa_failure(&state, &client, &account).await?;
+        return Err(crate::error::Error::Unauthenticated);
+    };
+
+    audit::record_event(
+        &state.conn,
+        audit::MFA_VERIFIED,
+        Some(account.id),
+        Some(&account.email),
+        &client,
+        Some(serde_json::json!({ "method": method })),
+    )
+    .await
+    .map_err(crate::error::Error::Anyhow)?;
+
+    let session = complete_login(&state, account).await?;
+
+    Ok(VerifyMfaResponse {
+        success: true,
+        account: Some(session.account),
+        access_token: session.access_token,
+        refresh_token: session.refresh_token,
+        realm_id: session.realm_id,
+    })
+}
+
+pub async fn begin_totp_enrollment(
+    State(state): State<AppState>,
+    headers: Headers,
+    request: BeginTotpEnrollmentRequest,
+) -> Result<BeginTotpEnrollmentResponse, crate::error::Error> {
+    let (account, _) = find_mfa_account(&state, &headers, &request.mfa_challenge_token).await?;
+
+    // An active authenticator has to be disabled (with a valid code) before a new one is set up
+    if find_confirmed_totp(&state.conn, account.id)
+        .await?
+        .is_some()
+    {
+        return Err(crate::error::Error::FailedPrecondition(
+            "TOTP is already enabled".to_string(),
+        ));
+    }
+
+    let secret = mfa::generate_totp_secret();
+    let (secret_ciphertext, secret_nonce) = state
+        .mfa_keys
+        .encrypt_secret(account.id, &secret)
+        .map_err(crate::error::Error::Anyhow)?;
+
+    // Restarting enrollment replaces any pending secret
+    account_totp_secrets::Entity::delete_by_id(account.id)
+        .exec(&state.conn)
+        .await?;
+
+    account_totp_secrets::ActiveModel {
+        account_id: Set(account.id),
+        secret_ciphertext: Set(secret_ciphertext),
+        secret_nonce: Set(secret_nonce),
+        confirmed_at: Set(None),
+        last_used_step: Set(None),
+        created_at: Set(Utc::now().into()),
+    }
+    .insert(&state.conn)
+    .await?;
+
+    let totp = state
+        .mfa_keys
+        .totp(secret, &account.email)
+        .map_err(crate::error::Error::Anyhow)?;
+
+    Ok(BeginTotpEnrollmentResponse {
+        secret: totp.get_secret_base32(),
+        otpauth_uri: totp.get_url(),
     })
 }
+
+pub async fn confirm_totp_enrollment(
+    State(state): State<AppState>,
+    headers: Headers,
+    client: ClientInfo,
+    request: ConfirmTotpEnrollmentRequest,
+) -> Result<ConfirmTotpEnrollmentResponse, crate::error::Error> {
+    let (account, via_challenge) =
+        find_mfa_account(&state, &headers, &request.mfa_challenge_token).await?;
+
+    ensure_account_not_locked(&state, &client, &account).await?;
+
+    let pending = account_totp_secrets::Entity::find_by_id(account.id)
+        .one(&state.conn)
+        .await?
+        .filter(|t| t.confirmed_at.is_none())
+        .ok_or_else(|| {
+            crate::error::Error::FailedPrecondition("no TOTP enrollment in progress".to_string())
+        })?;
+
+    let secret = state
+        .mfa_keys
+        .decrypt_secret(
+            account.id,
+            &pending.secret_ciphertext,
+            &pending.secret_nonce,
+        )
+        .map_err(crate::error::Error::Anyhow)?;
+    let totp = state
+        .mfa_keys
+        .totp(secret, &account.email)
+        .map_err(crate::error::Error::Anyhow)?;
+
+    let Some(step) = mfa::match_totp_code(&totp, &request.code, None) else {
+        record_mfa_failure(&state, &client, &account).await?;
+        return Err(crate::error::Error::Unauthenticated);
+    };
+
+    let txn = state.conn.begin().await?;
+
+    let mut active: account_totp_secrets::ActiveModel = pending.into();
+    active.confirmed_at = Set(Some(Utc::now().into()));
+    active.last_used_step = Set(Some(step));
+    active.update(&txn).await?;
+
+    let recovery_codes = replace_recovery_codes(&txn, account.id).await?;
+
+    txn.commit().await?;
+
+    audit::record_event(
+        &state.conn,
+        audit::MFA_ENROLLED,
+        Some(account.id),
+        Some(&account.email),
+        &client,
+        None,
+    )
+    .await
+    .map_err(crate::error::Error::Anyhow)?;
+
+    // Enrolling with a login challenge token finishes that login
+    let session = if via_challenge {
+        Some(complete_login(&state, account).await?)
+    } else {
+        None
+    };
+
+    Ok(ConfirmTotpEnrollmentResponse {
+        success: true,
+        recovery_codes,
+        account: session.as_ref().map(|s| s.account.clone()),
+        access_token: session
+            .as_ref()
+            .map(|s| s.access_token.clone())
+            .unwrap_or_default(),
+        refresh_token: session
+            .as_ref()
+            .map(|s| s.refresh_token.clone())
+            .unwrap_or_default(),
+        realm_id: session.map(|s| s.realm_id).unwrap_or_default(),
+    })
+}
+
+pub async fn disable_totp(
+    State(state): State<AppState>,
+    headers: Headers,
+    client: ClientInfo,
+    request: DisableTotpRequest,
+) -> Result<DisableTotpResponse, crate::error::Error> {
+    let (account, _) = find_mfa_account(&state, &headers, "").await?;
+
+    // Realm policy wins over the user's choice
+    if is_mfa_required_by_policy(&state.conn, account.id).await? {
+        return Err(crate::error::Error::FailedPrecondition(
+            "MFA is required by a realm policy".to_string(),
+        ));
+    }
+
+    ensure_account_not_locked(&state, &client, &account).await?;
+
+    if verify_second_factor(&state, &account, &request.code, true)
+        .await?
+        .is_none()
+    {
+        record_mfa_failure(&state, &client, &account).await?;
+        return Err(crate::error::Error::Unauthenticated);
+    }
+
+    let txn = state.conn.begin().await?;
+
+    account_totp_secrets::Entity::delete_by_id(account.id)
+        .exec(&txn)
+        .await?;
+
+    account_recovery_codes::Entity::delete_many()
+        .filter(account_recovery_codes::COLUMN.account_id.eq(account.id))
+        .exec(&txn)
+        .await?;
+
+    txn.commit().await?;
+
+    audit::record_event(
+        &state.conn,
+        audit::MFA_DISABLED,
+        Some(account.id),
+        Some(&account.email),
+        &client,
+        None,
+    )
+    .await
+    .map_err(crate::error::Error::Anyhow)?;
+
+    Ok(DisableTotpResponse { success: true })
+}
+
+pub async fn regenerate_recovery_codes(
+    State(state): State<AppState>,
+    headers: Headers,
+    client: ClientInfo,
+    request: RegenerateRecoveryCodesRequest,
+) -> Result<RegenerateRecoveryCodesResponse, crate::error::Error> {
+    let (account, _) = find_mfa_account(&state, &headers, "").await?;
+
+    ensure_account_not_locked(&state, &client, &account).await?;
+
+    // Only a TOTP code is accepted here, a recovery code can't mint new ones
+    if verify_second_factor(&state, &account, &request.code, false)
+        .await?
+        .is_none()
+    {
+        record_mfa_failure(&state, &client, &account).await?;
+        return Err(crate::error::Error::Unauthenticated);
+    }
+
+    let recovery_codes = replace_recovery_codes(&state.conn, account.id).await?;
+
+    audit::record_event(
+        &state.conn,
+        audit::RECOVERY_CODES_REGENERATED,
+        Some(account.id),
+        Some(&account.email),
+        &client,
+        None,
+    )
+    .await
+    .map_err(crate::error::Error::Anyhow)?;
+
+    Ok(RegenerateRecoveryCodesResponse { recovery_codes })
+}
+
+pub async fn set_realm_mfa_policy(
+    State(state): State<AppState>,
+    ctx: AuthContext,
+    client: ClientInfo,
+    request: SetRealmMfaPolicyRequest,
+) -> Result<SetRealmMfaPolicyResponse, crate::error::Error> {
+    let account_id = ctx.require_session()?;
+    let realm = find_admin_realm(&state, &ctx, &request.realm_id).await?;
+
+    let mut active_realm: realms::ActiveModel = realm.into();
+    active_realm.require_admin_mfa = Set(request.require_admin_mfa);
+    active_realm.updated_at = Set(Utc::now().into());
+    let realm = active_realm.update(&state.conn).await?;
+
+    audit::record_event(
+        &state.conn,
+        audit::MFA_POLICY_CHANGED,
+        Some(account_id),
+        None,
+        &client,
+        Some(serde_json::json!({
+            "realm_id": realm.id,
+            "require_admin_mfa": realm.require_admin_mfa,
+        })),
+    )
+    .await
+    .map_err(crate::error::Error::Anyhow)?;
+
+    Ok(SetRealmMfaPolicyResponse {
+        success: true,
+        realm: Some(realm_to_proto(realm)),
+    })
+}
+
+// ============================================================================
+// Realm Lifecycle
+// ============================================================================
+
+pub async fn update_realm(
+    State(state): State<AppState>,
+    ctx: AuthContext,
+    client: ClientInfo,
+    request: UpdateRealmRequest,
+) -> Result<UpdateRealmResponse, crate::error::Error> {
+    ctx.require_scope(api_token::SCOPE_REALMS_WRITE)?;
+    let account_id = ctx.require_account()?;
+    let realm = find_admin_realm(&state, &ctx, &request.realm_id).await?;
+
+    // Quotas are managed by operators, not by the realm's admins
+    let quotas = realm
+        .metadata
+        .as_ref()
+        .and_then(|m| m.get(crate::quota::METADATA_KEY))
+        .cloned();
+
+    let mut changed = Vec::new();
+    let mut active_realm: realms::ActiveModel = realm.into();
+    if let Some(name) = request.name {
+        if !realm::is_valid_name(&name) {
+            return Err(invalid_realm_name());
+        }
+        active_realm.name = Set(name);
+        changed.push("name");
+    }
+    if let Some(display_name) = request.display_name {
+        crate::error::Error::require_fields(&[("display_name", &display_name)])?;
+        active_realm.display_name = Set(display_name);
+        changed.push("display_name");
+    }
+    if let Some(description) = request.description {
+        active_realm.description = Set((!description.is_empty()).then_some(description));
+        changed.push("description");
+    }
+    if let Some(metadata) = request.metadata {
+        let mut metadata = if metadata.is_empty() {
+            serde_json::Map::new()
+        } else {
+            serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(&metadata).map_err(
+                |_| crate::error::Error::invalid_field("metadata", "must be a JSON object"),
+            )?
+        };
+        metadata.remove(crate::quota::METADATA_KEY);
+        if let Some(quotas) = quotas {
+            metadata.insert(crate::quota::METADATA_KEY.to_owned(), quotas);
+        }
+        active_realm.metadata =
+            Set((!metadata.is_empty()).then_some(serde_json::Value::Object(metadata)));
+        changed.push("metadata");
+    }
+
+    // A taken name surfaces as ALREADY_EXISTS from the unique constraint
+    active_realm.updated_at = Set(Utc::now().into());
+    let realm = active_realm.update(&state.conn).await?;
+
+    audit::record_event(
+        &state.conn,
+        audit::REALM_UPDATED,
+        Some(account_id),
+        None,
+        &client,
+        Some(serde_json::json!({ "realm_id": realm.id, "fields": changed })),
+    )
+    .await
+    .map_err(crate::error::Error::Anyhow)?;
+
+    Ok(UpdateRealmResponse {
+        realm: Some(realm_to_proto(realm)),
+    })
+}
+
+/// Shared by DeactivateRealm and ReactivateRealm
+async fn set_realm_active(
+    state: &AppState,
+    ctx: &AuthContext,
+    client: &ClientInfo,
+    realm_id: &str,
+    is_active: bool,
+) -> Result<realms::Model, crate::error::Error> {
+    ctx.require_scope(api_token::SCOPE_REALMS_WRITE)?;
+    let account_id = ctx.require_account()?;
+    let realm = find_admin_realm(state, ctx, realm_id).await?;
+
+    let mut active_realm: realms::ActiveModel = realm.into();
+    active_realm.is_active = Set(is_active);
+    active_realm.updated_at = Set(Utc::now().into());
+    let realm = active_realm.update(&state.conn).await?;
+
+    audit::record_event(
+        &state.conn,
+        if is_active {
+            audit::REALM_REACTIVATED
+        } else {
+            audit::REALM_DEACTIVATED
+        },
+        Some(account_id),
+        None,
+        client,
+        Some(serde_json::json!({ "realm_id": realm.id })),
+    )
+    .await
+    .map_err(crate::error::Error::Anyhow)?;
+
+    Ok(realm)
+}
+
+pub async fn deactivate_realm(
+    State(state): State<AppState>,
+    ctx: AuthContext,
+    client: ClientInfo,
+    request: DeactivateRealmRequest,
+) -> Result<DeactivateRealmResponse, crate::error::Error> {
+    let realm = set_realm_active(&state, &ctx, &client, &request.realm_id, false).await?;
+
+    Ok(DeactivateRealmResponse {
+        realm: Some(realm_to_proto(realm)),
+    })
+}
+
+pub async fn reactivate_realm(
+    State(state): State<AppState>,
+    ctx: RealmLifecycleContext,
+    client: ClientInfo,
+    request: ReactivateRealmRequest,
+) -> Result<ReactivateRealmResponse, crate::error::Error> {
+    let realm = set_realm_active(&state, &ctx, &client, &request.realm_id, true).await?;
+
+    Ok(ReactivateRealmResponse {
+        realm: Some(realm_to_proto(realm)),
+    })
+}
+
+pub async fn delete_realm(
+    State(state): State<AppState>,
+    ctx: RealmLifecycleContext,
+    client: ClientInfo,
+    request: DeleteRealmRequest,
+) -> Result<DeleteRealmResponse, crate::error::Error> {
+    let account_id = ctx.require_session()?;
+    let realm = find_admin_realm(&state, &ctx, &request.realm_id).await?;
+
+    if request.confirm_name != realm.name {
+        return Err(crate::error::Error::invalid_field(
+            "confirm_name",
+            "must match the realm name",
+        ));
+    }
+    if realm.deleted_at.is_some() {
+        return Err(crate::error::Error::FailedPrecondition(
+            "realm is already scheduled for deletion".to_string(),
+        ));
+    }
+
+    // The realm is soft-deleted here; the purge job removes it after the grace period
+    let deleted_at = Utc::now();
+    let mut active_realm: realms::ActiveModel = realm.into();
+    active_realm.deleted_at = Set(Some(deleted_at.into()));
+    active_realm.updated_at = Set(deleted_at.into());
+    let realm = active_realm.update(&state.conn).await?;
+
+    let purge_after = deleted_at + chrono::Duration::days(realm::DELETION_GRACE_DAYS);
+
+    audit::record_event(
+        &state.conn,
+        audit::REALM_DELETED,
+        Some(account_id),
+        None,
+        &client,
+        Some(serde_json::json!({
+            "realm_id": realm.id,
+            "purge_after": purge_after.to_rfc3339(),
+        })),
+    )
+    .await
+    .map_err(crate::error::Error::Anyhow)?;
+
+    Ok(DeleteRealmResponse {
+        realm: Some(realm_to_proto(realm)),
+        purge_after: purge_after.to_rfc3339(),
+    })
+}
+
+pub async fn restore_realm(
+    State(state): State<AppState>,
+    ctx: RealmLifecycleContext,
+    client: ClientInfo,
+    request: RestoreRealmRequest,
+) -> Result<RestoreRealmResponse, crate::error::Error> {
+    let account_id = ctx.require_session()?;
+    let realm = find_admin_realm(&state, &ctx, &request.realm_id).await?;
+
+    if realm.deleted_at.is_none() {
+        return Err(crate::error::Error::FailedPrecondition(
+            "realm is not scheduled for deletion".to_string(),
+        ));
+    }
+
+    let mut active_realm: realms::ActiveModel = realm.into();
+    active_realm.deleted_at = Set(None);
+    active_realm.updated_at = Set(Utc::now().into());
+    let realm = active_realm.update(&state.conn).await?;
+
+    audit::record_event(
+        &state.conn,
+        audit::REALM_RESTORED,
+        Some(account_id),
+        None,
+        &client,
+        Some(serde_json::json!({ "realm_id": realm.id })),
+    )
+    .await
+    .map_err(crate::error::Error::Anyhow)?;
+
+    Ok(RestoreRealmResponse {
+        realm: Some(realm_to_proto(realm)),
+    })
+}
+
+pub async fn transfer_realm(
+    State(state): State<AppState>,
+    ctx: AuthContext,
+    client: ClientInfo,
+    request: TransferRealmRequest,
+) -> Result<TransferRealmResponse, crate::error::Error> {
+    let account_id = ctx.require_session()?;
+    let realm = find_admin_realm(&state, &ctx, &request.realm_id).await?;
+
+    let new_admin = request.new_admin.trim();
+    crate::error::Error::require_fields(&[("new_admin", new_admin)])?;
+    let new_admin = find_by_identifier(&state.conn, new_admin)
+        .await?
+        .filter(|a| a.is_active)
+        .ok_or_else(|| crate::error::Error::invalid_field("new_admin", "no such account"))?;
+    if new_admin.id == account_id {
+        return Err(crate::error::Error::invalid_field(
+            "new_admin",
+            "must be another account",
+        ));
+    }
+
+    let admin_role = roles::Entity::find()
+        .filter(roles::COLUMN.realm_id.eq(realm.id))
+        .filter(roles::COLUMN.name.eq("admin"))
+        .one(&state.conn)
+        .await?
+        .ok_or_else(|| {
+            crate::error::Error::FailedPrecondition("realm has no admin role".to_string())
+        })?;
+
+    let txn = state.conn.begin().await?;
+
+    let already_admin =
+        account_realm_roles::Entity::find_by_id((new_admin.id, realm.id, admin_role.id))
+            .one(&txn)
+            .await?
+            .is_some();
+    if !already_admin {
+        account_realm_roles::ActiveModel {
+            account_id: Set(new_admin.id),
+            realm_id: Set(realm.id),
+            role_id: Set(admin_role.id),
+            granted_at: Set(Utc::now().into()),
+            granted_by: Set(Some(account_id)),
+        }
+        .insert(&txn)
+        .await?;
+    }
+    crate::profile::ensure_for_account(&txn, realm.id, &new_admin).await?;
+
+    if !request.keep_admin {
+        account_realm_roles::Entity::delete_by_id((account_id, realm.id, admin_role.id))
+            .exec(&txn)
+            .await?;
+    }
+
+    audit::record_event(
+        &txn,
+        audit::REALM_TRANSFERRED,
+        Some(account_id),
+        None,
+        &client,
+        Some(serde_json::json!({
+            "realm_id": realm.id,
+            "new_admin_account_id": new_admin.id,
+            "keep_admin": request.keep_admin,
+        })),
+    )
+    .await
+    .map_err(crate::error::Error::Anyhow)?;
+
+    txn.commit().await?;
+
+    Ok(TransferRealmResponse { success: true })
+}
+
+pub async fn get_realm_usage(
+    State(state): State<AppState>,
+    ctx: AuthContext,
+    request: GetRealmUsageRequest,
+) -> Result<GetRealmUsageResponse, crate::error::Error> {
+    let realm_id = Uuid::parse_str(&request.realm_id)
+        .map_err(|_| crate::error::Error::invalid_field("realm_id", "must be a UUID"))?;
+    ctx.roles_in(&state, realm_id).await?;
+
+    let quotas = quota::load(&state.conn, realm_id).await?;
+    let usage = [
+        (
+            quota::MAX_BOTS,
+            quota::count_bots(&state.conn, realm_id).await?,
+            quotas.max_bots,
+        ),
+        (
+            quota::MAX_TRIPS,
+            quota::count_trips(&state.conn, realm_id).await?,
+            quotas.max_trips,
+        ),
+        (
+            quota::MAX_CARDS_PER_TRIP,
+            quota::largest_trip_cards(&state.conn, realm_id).await?,
+            quotas.max_cards_per_trip,
+        ),
+        (
+            quota::AI_TOKENS_PER_MONTH,
+            quota::ai_tokens_used(&state.conn, realm_id).await?,
+            quotas.ai_tokens_per_month,
+        ),
+    ];
+
+    Ok(GetRealmUsageResponse {
+        quotas: usage
+            .into_iter()
+            .map(|(name, used, limit)| QuotaUsage {
+                name: name.to_string(),
+                used,
+                limit,
+            })
+            .collect(),
+    })
+}
+
+// ============================================================================
+// API Tokens
+// ============================================================================
+
+fn api_token_to_proto(api_token: api_tokens::Model) -> ApiToken {
+    let format_id = |id: Option<Uuid>| id.map(|id| id.to_string()).unwrap_or_default();
+    let format_time = |at: Option<chrono::DateTime<chrono::FixedOffset>>| {
+        at.map(|at| at.to_rfc3339()).unwrap_or_default()
+    };
+
+    ApiToken {
+        id: api_token.id.to_string(),
+        name: api_token.name,
+        token_prefix: api_token.token_prefix,
+        scopes: api_token.scopes,
+        account_id: format_id(api_token.account_id),
+        bot_id: format_id(api_token.bot_id),
+        realm_id: format_id(api_token.realm_id),
+        expires_at: format_time(api_token.expires_at),
+        last_used_at: format_time(api_token.last_used_at),
+        revoked_at: format_time(api_token.revoked_at),
+        created_at: api_token.created_at.to_rfc3339(),
+    }
+}
+
+/// Load a bot the account may manage tokens for (it must be an admin of the bot's realm)
+async fn find_managed_bot(
+    state: &AppState,
+    account_id: Uuid,
+    bot_id: &str,
+) -> Result<bots::Model, crate::error::Error> {
+    let bot_id = Uuid::parse_str(bot_id)
+        .map_err(|_| crate::error::Error::invalid_field("bot_id", "must be a UUID"))?;
+
+    let bot = bots::Entity::find_by_id(bot_id)
+        .one(&state.conn)
+        .await?
+        .ok_or(crate::error::Error::NotFound)?;
+
+    if !find_admin_realm_ids(&state.conn, account_id)
+        .await?
+        .contains(&bot.realm_id)
+    {
+        return Err(crate::error::Error::Forbidden);
+    }
+
+    Ok(bot)
+}
+
+pub async fn create_api_token(
+    State(state): State<AppState>,
+    ctx: AuthContext,
+    client: ClientInfo,
+    request: CreateApiTokenRequest,
+) -> Result<CreateApiTokenResponse, crate::error::Error> {
+    let account_id = ctx.require_session()?;
+
+    // Validate input
+    let name = request.name.trim();
+    crate::error::Error::require_fields(&[("name", name)])?;
+    if request.scopes.is_empty() {
+        return Err(crate::error::Error::invalid_field(
+            "scopes",
+            "at least one scope is required",
+        ));
+    }
+    if let Some(unknown) = request
+        .scopes
+        .iter()
+        .find(|s| !api_token::SCOPES.contains(&s.as_str()))
+    {
+        return Err(crate::error::Error::invalid_field(
+            "scopes",
+            format!("unknown scope '{unknown}'"),
+        ));
+    }
+
+    let mut scopes = request.scopes.clone();
+    scopes.sort();
+    scopes.dedup();
+
+    // Bot tokens belong to the bot and are limited to its realm; personal tokens
+    // may optionally be limited to one realm the account belongs to
+    let (owner_account_id, bot_id, realm_id, prefix) = if !request.bot_id.is_empty() {
+        let bot = find_managed_bot(&state, account_id, &request.bot_id).await?;
+        (
+            None,
+            Some(bot.id),
+            Some(bot.realm_id),
+            api_token::BOT_TOKEN_PREFIX,
+        )
+    } else if !request.realm_id.is_empty() {
+        let realm_id = Uuid::parse_str(&request.realm_id)
+            .map_err(|_| crate::error::Error::invalid_field("realm_id", "must be a UUID"))?;
+
+        // Membership check; also rejects deactivated and deleted realms
+        ctx.roles_in(&state, realm_id).await?;
+
+        (
+            Some(account_id),
+            None,
+            Some(realm_id),
+            api_token::PERSONAL_TOKEN_PREFIX,
+        )
+    } else {
+        (
+            Some(account_id),
+            None,
+            None,
+            api_token::PERSONAL_TOKEN_PREFIX,
+        )
+    };
+
+    let generated = api_token::generate_token(prefix);
+    let expires_at = (request.expires_in_days > 0)
+        .then(|| Utc::now() + chrono::Duration::days(request.expires_in_days.into()));
+
+    let created = api_tokens::ActiveModel {
+        id: Set(Uuid::now_v7()),
+        name: Set(name.to_owned()),
+        token_prefix: Set(generated.token_prefix),
+        token_hash: Set(generated.token_hash),
+        account_id: Set(owner_account_id),
+        bot_id: Set(bot_id),
+        realm_id: Set(realm_id),
+        scopes: Set(scopes),
+        created_by: Set(Some(account_id)),
+        expires_at: Set(expires_at.map(Into::into)),
+        last_used_at: Set(None),
+        revoked_at: Set(None),
+        created_at: Set(Utc::now().into()),
+    }
+    .insert(&state.conn)
+    .await?;
+
+    audit::record_event(
+        &state.conn,
+        audit::API_TOKEN_CREATED,
+        Some(account_id),
+        None,
+        &client,
+        Some(serde_json::json!({
+            "api_token_id": created.id,
+            "bot_id": created.bot_id,
+            "scopes": created.scopes,
+        })),
+    )
+    .await
+    .map_err(crate::error::Error::Anyhow)?;
+
+    Ok(CreateApiTokenResponse {
+        token: generated.token,
+        api_token: Some(api_token_to_proto(created)),
+    })
+}
+
+pub async fn list_api_tokens(
+    State(state): State<AppState>,
+    ctx: AuthContext,
+    request: ListApiTokensRequest,
+) -> Result<ListApiTokensResponse, crate::error::Error> {
+    let account_id = ctx.require_session()?;
+
+    let query = if request.bot_id.is_empty() {
+        api_tokens::Entity::find().filter(api_tokens::COLUMN.account_id.eq(account_id))
+    } else {
+        let bot = find_managed_bot(&state, account_id, &request.bot_id).await?;
+        api_tokens::Entity::find().filter(api_tokens::COLUMN.bot_id.eq(bot.id))
+    };
+
+    let api_tokens = query.all(&state.conn).await?;
+
+    Ok(ListApiTokensResponse {
+        api_tokens: api_tokens.into_iter().map(api_token_to_proto).collect(),
+    })
+}
+
+pub async fn revoke_api_token(
+    State(state): State<AppState>,
+    ctx: AuthContext,
+    client: ClientInfo,
+    request: RevokeApiTokenRequest,
+) -> Result<RevokeApiTokenResponse, crate::error::Error> {
+    let account_id = ctx.require_session()?;
+
+    let token_id = Uuid::parse_str(&request.id)
+        .map_err(|_| crate::error::Error::invalid_field("id", "must be a UUID"))?;
+
+    let api_token = api_tokens::Entity::find_by_id(token_id)
+        .one(&state.conn)
+        .await?
+        .ok_or(crate::error::Error::NotFound)?;
+
+    // Own personal tokens, or tokens of bots in realms the caller administers
+    match (api_token.account_id, api_token.bot_id) {
+        (Some(owner), _) if owner == account_id => {}
+        (_, Some(bot_id)) => {
+            find_managed_bot(&state, account_id, &bot_id.to_string()).await?;
+        }
+        _ => return Err(crate::error::Error::NotFound),
+    }
+
+    if api_token.revoked_at.is_none() {
+        let mut active: api_tokens::ActiveModel = api_token.into();
+        active.revoked_at = Set(Some(Utc::now().into()));
+        active.update(&state.conn).await?;
+
+        audit::record_event(
+            &state.conn,
+            audit::API_TOKEN_REVOKED,
+            Some(account_id),
+            None,
+            &client,
+            Some(serde_json::json!({ "api_token_id": token_id })),
+        )
+        .await
+        .map_err(crate::error::Error::Anyhow)?;
+    }
+
+    Ok(RevokeApiTokenResponse { success: true })
+}
+
+// ============================================================================
+// Account Self-Service
+// ============================================================================
+
+/// Account of the calling interactive session
+async fn find_session_account(
+    state: &AppState,
+    ctx: &AuthContext,
+) -> Result<accounts::Model, crate::error::Error> {
+    let account_id = ctx.require_session()?;
+
+    accounts::Entity::find_by_id(account_id)
+        .one(&state.conn)
+        .await?
+        .ok_or(crate::error::Error::NotFound)
+}
+
+/// How recently a session must have logged in to confirm a sensitive change of an
+/// account that has neither a password nor TOTP to re-enter
+const REAUTH_MAX_SESSION_AGE_SECS: i64 = 300;
+
+/// Re-verify the password, and a second factor if TOTP is enabled, before a sensitive
+/// change. Failures count towards the account lockout like failed logins.
+///
+/// Accounts created through a federated login have no password: they prove themselves
+/// with a second factor if TOTP is enabled, and otherwise only a session that logged in
+/// within the last few minutes is accepted.
+async fn reauthenticate(
+    state: &AppState,
+    ctx: &AuthContext,
+    client: &ClientInfo,
+    account: &accounts::Model,
+    password_field: &str,
+    password: &str,
+    code: &str,
+) -> Result<(), crate::error::Error> {
+    ensure_account_not_locked(state, client, account).await?;
+
+    let totp_enabled = find_confirmed_totp(&state.conn, account.id)
+        .await?
+        .is_some();
+
+    match &account.password_hash {
+        Some(hash) => {
+            let valid =
+                password::verify_password(password, hash).map_err(crate::error::Error::Anyhow)?;
+            if !valid {
+                record_login_failure(state, client, Some(account.id), &account.email).await?;
+                return Err(crate::error::Error::invalid_field(
+                    password_field,
+                    "is incorrect",
+                ));
+            }
+        }
+        None if !totp_enabled => {
+            let fresh = ctx.auth_time.is_some_and(|auth_time| {
+                Utc::now().timestamp() - (auth_time as i64) <= REAUTH_MAX_SESSION_AGE_SECS
+            });
+            if !fresh {
+                return Err(crate::error::Error::FailedPrecondition(
+                    "log in again to confirm this change".to_string(),
+                ));
+            }
+        }
+        None => {}
+    }
+
+    if totp_enabled
+        && verify_second_factor(state, account, code, true)
+            .await?
+            .is_none()
+    {
+        record_mfa_failure(state, client, account).await?;
+        return Err(crate::error::Error::invalid_field("code", "is incorrect"));
+    }
+
+    Ok(())
+}
+
+pub async fn update_account(
+    State(state): State<AppState>,
+    ctx: AuthContext,
+    client: ClientInfo,
+    request: UpdateAccountRequest,
+) -> Result<UpdateAccountResponse, crate::error::Error> {
+    let account = find_session_account(&state, &ctx).await?;
+
+    let username = request.username.trim();
+    validate_username(username)?;
+    let email = request.email.trim();
+    if !email.is_empty() && !email.contains('@') {
+        return Err(crate::error::Error::invalid_field(
+            "email",
+            "must be an email address",
+        ));
+    }
+
+    // A new email could take over the account (e.g. through a password reset), so it
+    // needs the password and only applies once confirmed from the new address
+    let new_email = (!email.is_empty() && email != account.email).then_some(email);
+    if let Some(email) = new_email {
+        reauthenticate(
+            &state,
+            &ctx,
+            &client,
+            &account,
+            "password",
+            &request.password,
+            &request.code,
+        )
+        .await?;
+
+        let taken = accounts::Entity::find()
+            .filter(accounts::COLUMN.email.eq(email))
+            .one(&state.conn)
+            .await?;
+        if taken.is_some() {
+            return Err(crate::error::Error::AlreadyExists(
+                "account with this email".to_string(),
+            ));
+        }
+    }
+
+    let mut updated = account.clone();
+    if !username.is_empty() && username != account.username {
+        // Taken usernames surface as ALREADY_EXISTS from the unique constraint
+        let mut active_account: accounts::ActiveModel = account.clone().into();
+        active_account.username = Set(username.to_owned());
+        active_account.updated_at = Set(Utc::now().into());
+        updated = active_account.update(&state.conn).await?;
+
+        audit::record_event(
+            &state.conn,
+            audit::ACCOUNT_UPDATED,
+            Some(updated.id),
+            None,
+            &client,
+            Some(serde_json::json!({ "fields": ["username"] })),
+        )
+        .await
+        .map_err(crate::error::Error::Anyhow)?;
+    }
+
+    if let Some(email) = new_email {
+        let token = email_change::request(&state.conn, account.id, email)
+            .await
+            .map_err(crate::error::Error::Anyhow)?;
+        state
+            .mailer
+            .send(&mail::Email {
+                to: email.to_owned(),
+                subject: "Confirm your new email address".to_string(),
+                body: format!(
+                    "Confirm the change of the email of {} with this token within 24 hours:\n{token}",
+                    updated.username
+                ),
+            })
+            .await
+            .map_err(crate::error::Error::Anyhow)?;
+
+        // The entry goes to the account, so no address is logged
+        audit::record_event(
+            &state.conn,
+            audit::EMAIL_CHANGE_REQUESTED,
+            Some(account.id),
+            None,
+            &client,
+            None,
+        )
+        .await
+        .map_err(crate::error::Error::Anyhow)?;
+    }
+
+    let pending_email = email_change::pending_email(&state.conn, account.id)
+        .await
+        .map_err(crate::error::Error::Anyhow)?;
+
+    Ok(UpdateAccountResponse {
+        account: Some(account_to_proto(updated)),
+        pending_email: pending_email.unwrap_or_default(),
+    })
+}
+
+pub async fn confirm_email_change(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    request: ConfirmEmailChangeRequest,
+) -> Result<ConfirmEmailChangeResponse, crate::error::Error> {
+    crate::error::Error::require_fields(&[("token", &request.token)])?;
+
+    let change = email_change::find_by_token(&state.conn, request.token.trim())
+        .await
+        .map_err(crate::error::Error::Anyhow)?
+        .ok_or_else(|| crate::error::Error::invalid_field("token", "is invalid or expired"))?;
+    let account = accounts::Entity::find_by_id(change.account_id)
+        .one(&state.conn)
+        .await?
+        .filter(|a| a.is_active)
+        .ok_or(crate::error::Error::NotFound)?;
+
+    // Taken emails surface as ALREADY_EXISTS from the unique constraint
+    let txn = state.conn.begin().await?;
+    let mut active_account: accounts::ActiveModel = account.clone().into();
+    active_account.email = Set(change.new_email.clone());
+    active_account.email_verified = Set(true);
+    active_account.updated_at = Set(Utc::now().into());
+    let updated = active_account.update(&txn).await?;
+    account_email_changes::Entity::delete_by_id(account.id)
+        .exec(&txn)
+        .await?;
+    txn.commit().await?;
+
+    audit::record_event(
+        &state.conn,
+        audit::EMAIL_CHANGED,
+        Some(updated.id),
+        None,
+        &client,
+        None,
+    )
+    .await
+    .map_err(crate::error::Error::Anyhow)?;
+
+    Ok(ConfirmEmailChangeResponse {
+        account: Some(account_to_proto(updated)),
+    })
+}
+
+pub async fn change_password(
+    State(state): State<AppState>,
+    ctx: AuthContext,
+    client: ClientInfo,
+    request: ChangePasswordRequest,
+) -> Result<ChangePasswordResponse, crate::error::Error> {
+    let account = find_session_account(&state, &ctx).await?;
+
+    crate::error::Error::require_fields(&[("new_password", &request.new_password)])?;
+    reauthenticate(
+        &state,
+        &ctx,
+        &client,
+        &account,
+        "current_password",
+        &request.current_password,
+        &request.code,
+    )
+    .await?;
+
+    let password_hash =
+        password::hash_password(&request.new_password).map_err(crate::error::Error::Anyhow)?;
+
+    // Bumping the session version signs out every other session
+    let mut active_account: accounts::ActiveModel = account.clone().into();
+    active_account.password_hash = Set(Some(password_hash));
+    active_account.session_version = Set(account.session_version + 1);
+    active_account.updated_at = Set(Utc::now().into());
+    let updated = active_account.update(&state.conn).await?;
+
+    audit::record_event(
+        &state.conn,
+        audit::PASSWORD_CHANGED,
+        Some(updated.id),
+        None,
+        &client,
+        None,
+    )
+    .await
+    .map_err(crate::error::Error::Anyhow)?;
+
+    // Keep the caller signed in, in the realm it was using
+    let realm_id = ctx.realm_id.map(|id| id.to_string());
+    let (access_token, refresh_token) = sign_session_tokens(
+        &state,
+        &updated,
+        realm_id.as_deref(),
+        ctx.auth_time.unwrap_or_default(),
+    )?;
+
+    Ok(ChangePasswordResponse {
+        access_token,
+        refresh_token,
+    })
+}
+
+pub async fn deactivate_account(
+    State(state): State<AppState>,
+    ctx: AuthContext,
+    client: ClientInfo,
+    request: DeactivateAccountRequest,
+) -> Result<DeactivateAccountResponse, crate::error::Error> {
+    let account = find_session_account(&state, &ctx).await?;
+
+    reauthenticate(
+        &state,
+        &ctx,
+        &client,
+        &account,
+        "password",
+        &request.password,
+        &request.code,
+    )
+    .await?;
+
+    // Inactive accounts are rejected at login and by every authenticated request,
+    // which also covers their API tokens
+    let mut active_account: accounts::ActiveModel = account.clone().into();
+    active_account.is_active = Set(false);
+    active_account.deactivated_at = Set(Some(Utc::now().into()));
+    active_account.session_version = Set(account.session_version + 1);
+    active_account.updated_at = Set(Utc::now().into());
+    active_account.update(&state.conn).await?;
+
+    audit::record_event(
+        &state.conn,
+        audit::ACCOUNT_DEACTIVATED,
+        Some(account.id),
+        None,
+        &client,
+        None,
+    )
+    .await
+    .map_err(crate::error::Error::Anyhow)?;
+
+    Ok(DeactivateAccountResponse { success: true })
+}
+
+pub async fn delete_account(
+    State(state): State<AppState>,
+    ctx: AuthContext,
+    client: ClientInfo,
+    request: DeleteAccountRequest,
+) -> Result<DeleteAccountResponse, crate::error::Error> {
+    let account = find_session_account(&state, &ctx).await?;
+
+    reauthenticate(
+        &state,
+        &ctx,
+        &client,
+        &account,
+        "password",
+        &request.password,
+        &request.code,
+    )
+    .await?;
+
+    // A realm must not be left without an admin
+    for realm_id in find_admin_realm_ids(&state.conn, account.id).await? {
+        let other_admins = account_realm_roles::Entity::find()
+            .filter(account_realm_roles::COLUMN.realm_id.eq(realm_id))
+            .filter(account_realm_roles::COLUMN.account_id.ne(account.id))
+            .find_also_related(roles::Entity)
+            .all(&state.conn)
+            .await?;
+
+        if !other_admins
+            .iter()
+            .any(|(_, role)| role.as_ref().is_some_and(|r| r.name == "admin"))
+        {
+            return Err(crate::error::Error::FailedPrecondition(format!(
+                "account is the only admin of realm {realm_id}"
+            )));
+        }
+    }
+
+    let member_realm_ids: Vec<Uuid> = account_realm_roles::Entity::find()
+        .filter(account_realm_roles::COLUMN.account_id.eq(account.id))
+        .all(&state.conn)
+        .await?
+        .into_iter()
+        .map(|realm_role| realm_role.realm_id)
+        .collect();
+
+    let txn = state.conn.begin().await?;
+
+    // Profiles are referenced by trips, cards, votes and rich-text edits (without cascade),
+    // so the account's profiles are stripped of personal data instead of deleted. Unlinked
+    // ones only count as the account's by a verified email.
+    let mut account_profiles = Condition::any().add(profiles::Column::AccountId.eq(account.id));
+    if account.email_verified {
+        account_profiles = account_profiles.add(
+            profiles::COLUMN
+                .realm_id
+                .is_in(member_realm_ids)
+                .and(profiles::Column::AccountId.is_null())
+                .and(profiles::COLUMN.email.eq(&account.email)),
+        );
+    }
+    profiles::Entity::update_many()
+        .col_expr(profiles::Column::Username, Expr::value("deleted-user"))
+        .col_expr(profiles::Column::Email, Expr::value(""))
+        .col_expr(profiles::Column::Phone, Expr::value(""))
+        .col_expr(profiles::Column::ThirdId, Expr::cust("NULL"))
+        .col_expr(profiles::Column::ThirdProviderType, Expr::cust("NULL"))
+        .col_expr(profiles::Column::Metadata, Expr::cust("NULL"))
+        .filter(account_profiles)
+        .exec(&txn)
+        .await?;
+
+    // The entry outlives the account (its account_id is set to NULL); no email is kept
+    audit::record_event(
+        &txn,
+        audit::ACCOUNT_DELETED,
+        Some(account.id),
+        None,
+        &client,
+        Some(serde_json::json!({ "account_id": account.id })),
+    )
+    .await
+    .map_err(crate::error::Error::Anyhow)?;
+
+    // Memberships, identities, MFA settings and API tokens cascade
+    accounts::Entity::delete_by_id(account.id)
+        .exec(&txn)
+        .await?;
+
+    txn.commit().await?;
+
+    Ok(DeleteAccountResponse { success: true })
+}
+
+// ============================================================================
+// Personal Data Export
+// ============================================================================
+
+/// Lifetime of a download link handed out for a ready export
+const EXPORT_DOWNLOAD_LINK_SECS: i64 = 3600;
+/// Unfinished exports older than this are assumed lost (e.g. to a restart)
+const EXPORT_STALE_SECS: i64 = 3600;
+
+fn data_export_to_proto(
+    state: &AppState,
+    export: data_exports::Model,
+) -> Result<DataExport, crate::error::Error> {
+    let now = Utc::now();
+
+    // Links are signed rather than stored, and never outlive the archive
+    let download_url = match export.expires_at {
+        Some(expires_at) if export.status == export::STATUS_READY && expires_at > now => {
+            let link_expires_at = expires_at
+                .timestamp()
+                .min(now.timestamp() + EXPORT_DOWNLOAD_LINK_SECS);
+            let token = jwt::sign_download_token(
+                &export.id.to_string(),
+                &state.jwt_keys,
+                link_expires_at as u64,
+            )
+            .map_err(crate::error::Error::Anyhow)?;
+            format!("/exports/{token}")
+        }
+        _ => String::new(),
+    };
+
+    Ok(DataExport {
+        id: export.id.to_string(),
+        status: export.status,
+        download_url,
+        created_at: export.created_at.to_rfc3339(),
+        completed_at: export
+            .completed_at
+            .map(|t| t.to_rfc3339())
+            .unwrap_or_default(),
+        expires_at: export
+            .expires_at
+            .map(|t| t.to_rfc3339())
+            .unwrap_or_default(),
+        error: export.error.unwrap_or_default(),
+    })
+}
+
+pub async fn export_my_data(
+    State(state): State<AppState>,
+    ctx: AuthContext,
+    client: ClientInfo,
+    _request: ExportMyDataRequest,
+) -> Result<ExportMyDataResponse, crate::error::Error> {
+    let account_id = ctx.require_session()?;
+
+    // One export at a time
+    let in_progress = data_exports::Entity::find()
+        .filter(data_exports::COLUMN.account_id.eq(account_id))
+        .filter(
+            data_exports::COLUMN
+                .status
+                .is_in([export::STATUS_PENDING, export::STATUS_RUNNING]),
+        )
+        .filter(
+            data_exports::COLUMN
+                .created_at
+                .gt(Utc::now() - chrono::Duration::seconds(EXPORT_STALE_SECS)),
+        )
+        .one(&state.conn)
+        .await?;
+    if let Some(export) = in_progress {
+        return Ok(ExportMyDataResponse {
+            export: Some(data_export_to_proto(&state, export)?),
+        });
+    }
+
+    let created = data_exports::ActiveModel {
+        id: Set(Uuid::now_v7()),
+        account_id: Set(account_id),
+        status: Set(export::STATUS_PENDING.to_owned()),
+        archive: Set(None),
+        error: Set(None),
+        created_at: Set(Utc::now().into()),
+        completed_at: Set(None),
+        expires_at: Set(None),
+    }
+    .insert(&state.conn)
+    .await?;
+
+    tokio::spawn(export::run(state.conn.clone(), created.id));
+
+    audit::record_event(
+        &state.conn,
+        audit::DATA_EXPORT_REQUESTED,
+        Some(account_id),
+        None,
+        &client,
+        Some(serde_json::json!({ "data_export_id": created.id })),
+    )
+    .await
+    .map_err(crate::error::Error::Anyhow)?;
+
+    Ok(ExportMyDataResponse {
+        export: Some(data_export_to_proto(&state, created)?),
+    })
+}
+
+pub async fn get_data_export(
+    State(state): State<AppState>,
+    ctx: AuthContext,
+    request: GetDataExportRequest,
+) -> Result<GetDataExportResponse, crate::error::Error> {
+    let account_id = ctx.require_session()?;
+
+    let export_id = Uuid::parse_str(&request.id)
+        .map_err(|_| crate::error::Error::invalid_field("id", "must be a UUID"))?;
+
+    let export = data_exports::Entity::find_by_id(export_id)
+        .one(&state.conn)
+        .await?
+        .filter(|e| e.account_id == account_id)
+        .ok_or(crate::error::Error::NotFound)?;
+
+    Ok(GetDataExportResponse {
+        export: Some(data_export_to_proto(&state, export)?),
+    })
+}
+
+/// `GET /exports/{token}`: the signed link from `DataExport.download_url`
+pub async fn download_data_export(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+) -> Result<Response, crate::error::Error> {
+    let claims = jwt::verify_download_token(&token, &state.jwt_keys)
+        .map_err(|_| crate::error::Error::Unauthenticated)?;
+    let export_id = Uuid::parse_str(&claims.sub).map_err(|_| crate::error::Error::NotFound)?;
+
+    let archive = data_exports::Entity::find_by_id(export_id)
+        .one(&state.conn)
+        .await?
+        .filter(|e| e.expires_at.is_some_and(|at| at > Utc::now()))
+        .and_then(|e| e.archive)
+        .ok_or(crate::error::Error::NotFound)?;
+
+    Ok((
+        [
+            (http::header::CONTENT_TYPE, "application/json".to_owned()),
+            (
+                http::header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"tripvota-export-{export_id}.json\""),
+            ),
+            (http::header::CACHE_CONTROL, "no-store".to_owned()),
+        ],
+        archive,
+    )
+        .into_response())
+}
@@ -0,0 +1,185 @@
+use anyhow::Result;
+use chrono::{DateTime, Duration, FixedOffset, Utc};
+use sea_orm::sea_query::{Expr, OnConflict};
+use sea_orm::{ColumnTrait, ConnectionTrait, EntityTrait, ExprTrait, QueryFilter, Set};
+use workspace_entity::login_throttles;
+
+/// Failed logins allowed per account before it gets locked
+const ACCOUNT_MAX_FAILURES: i32 = 5;
+/// Failed logins allowed per client IP before it gets locked (higher because of shared NATs)
+const IP_MAX_FAILURES: i32 = 20;
+/// Wrong passwords allowed per share link before it gets locked
+const SHARE_LINK_MAX_FAILURES: i32 = 10;
+/// First lockout duration, doubled on every further failure
+const BASE_LOCKOUT_SECS: i64 = 30;
+/// Upper bound for a single lockout
+const MAX_LOCKOUT_SECS: i64 = 3600;
+/// Counters start over once no failure happened for this long
+const FAILURE_WINDOW_SECS: i64 = 3600;
+
+#[derive(Clone, Copy, Debug)]
+pub enum ThrottleScope {
+    Account,
+    Ip,
+    /// Passwords of a trip share link, keyed by the link's id
+    ShareLink,
+}
+
+impl ThrottleScope {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ThrottleScope::Account => "account",
+            ThrottleScope::Ip => "ip",
+            ThrottleScope::ShareLink => "share_link",
+        }
+    }
+
+    fn max_failures(&self) -> i32 {
+        match self {
+            ThrottleScope::Account => ACCOUNT_MAX_FAILURES,
+            ThrottleScope::Ip => IP_MAX_FAILURES,
+            ThrottleScope::ShareLink => SHARE_LINK_MAX_FAILURES,
+        }
+    }
+}
+
+/// Exponential lockout: 30s, 60s, 120s, ... capped at `MAX_LOCKOUT_SECS`
+fn lockout_duration(scope: ThrottleScope, failed_count: i32) -> Option<Duration> {
+    let over = failed_count - scope.max_failures();
+    if over < 0 {
+        return None;
+    }
+
+    let secs = BASE_LOCKOUT_SECS
+        .checked_shl(over.min(32) as u32)
+        .unwrap_or(MAX_LOCKOUT_SECS)
+        .min(MAX_LOCKOUT_SECS);
+    Some(Duration::seconds(secs))
+}
+
+/// Returns the lock expiry if `key` is currently locked out
+pub async fn locked_until<C: ConnectionTrait>(
+    db: &C,
+    scope: ThrottleScope,
+    key: &str,
+) -> Result<Option<DateTime<FixedOffset>>> {
+    let throttle = login_throttles::Entity::find_by_id((scope.as_str().to_owned(), key.to_owned()))
+        .one(db)
+        .await?;
+
+    Ok(throttle
+        .and_then(|t| t.locked_until)
+        .filter(|until| *until > Utc::now()))
+}
+
+/// Counts a failed attempt and returns the new lock expiry if the threshold was crossed.
+/// The counter is bumped in a single upsert, so concurrent failures are all counted.
+pub async fn record_failure<C: ConnectionTrait>(
+    db: &C,
+    scope: ThrottleScope,
+    key: &str,
+) -> Result<Option<DateTime<FixedOffset>>> {
+    let now: DateTime<FixedOffset> = Utc::now().into();
+    let window_start = now - Duration::seconds(FAILURE_WINDOW_SECS);
+
+    let throttle = login_throttles::Entity::insert(login_throttles::ActiveModel {
+        scope: Set(scope.as_str().to_owned()),
+        key: Set(key.to_owned()),
+        failed_count: Set(1),
+        last_failed_at: Set(Some(now)),
+        locked_until: Set(None),
+    })
+    .on_conflict(
+        OnConflict::columns([login_throttles::Column::Scope, login_throttles::Column::Key])
+            .value(
+                login_throttles::Column::FailedCount,
+                Expr::case(
+                    Expr::col((
+                        login_throttles::Entity,
+                        login_throttles::Column::LastFailedAt,
+                    ))
+                    .gt(window_start),
+                    Expr::col((
+                        login_throttles::Entity,
+                        login_throttles::Column::FailedCount,
+                    ))
+                    .add(1),
+                )
+                .finally(1),
+            )
+            .update_column(login_throttles::Column::LastFailedAt)
+            .to_owned(),
+    )
+    .exec_with_returning(db)
+    .await?;
+
+    let Some(lockout) = lockout_duration(scope, throttle.failed_count) else {
+        return Ok(None);
+    };
+    let locked_until = now + lockout;
+
+    // Only ever extends the lock, whichever of two concurrent failures writes last
+    login_throttles::Entity::update_many()
+        .col_expr(
+            login_throttles::Column::LockedUntil,
+            Expr::value(locked_until),
+        )
+        .filter(login_throttles::COLUMN.scope.eq(scope.as_str()))
+        .filter(login_throttles::COLUMN.key.eq(key))
+        .filter(
+            login_throttles::Column::LockedUntil
+                .is_null()
+                .or(login_throttles::Column::LockedUntil.lt(locked_until)),
+        )
+        .exec(db)
+        .await?;
+
+    Ok(Some(locked_until))
+}
+
+/// Clears the counter after a successful login
+pub async fn reset<C: ConnectionTrait>(db: &C, scope: ThrottleScope, key: &str) -> Result<()> {
+    login_throttles::Entity::delete_by_id((scope.as_str().to_owned(), key.to_owned()))
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_lockout_below_the_threshold() {
+        assert_eq!(lockout_duration(ThrottleScope::Account, 1), None);
+        assert_eq!(
+            lockout_duration(ThrottleScope::Account, ACCOUNT_MAX_FAILURES - 1),
+            None
+        );
+        assert_eq!(
+            lockout_duration(ThrottleScope::Ip, IP_MAX_FAILURES - 1),
+            None
+        );
+    }
+
+    #[test]
+    fn lockout_starts_at_30s_and_doubles() {
+        let secs = |failed_count| {
+            lockout_duration(ThrottleScope::Account, failed_count).map(|d| d.num_seconds())
+        };
+        assert_eq!(secs(ACCOUNT_MAX_FAILURES), Some(30));
+        assert_eq!(secs(ACCOUNT_MAX_FAILURES + 1), Some(60));
+        assert_eq!(secs(ACCOUNT_MAX_FAILURES + 2), Some(120));
+        assert_eq!(secs(ACCOUNT_MAX_FAILURES + 6), Some(1920));
+    }
+
+    #[test]
+    fn lockout_is_capped_at_an_hour() {
+        let secs = |failed_count| {
+            lockout_duration(ThrottleScope::ShareLink, failed_count).map(|d| d.num_seconds())
+        };
+        assert_eq!(secs(SHARE_LINK_MAX_FAILURES + 7), Some(MAX_LOCKOUT_SECS));
+        assert_eq!(secs(SHARE_LINK_MAX_FAILURES + 40), Some(MAX_LOCKOUT_SECS));
+        assert_eq!(secs(i32::MAX), Some(MAX_LOCKOUT_SECS));
+    }
+}
@@ -1,89 +1,26 @@
 use crate::AppState;
-use crate::auth::jwt;
-use crate::auth::service::Headers;
+use crate::auth::api_token::{SCOPE_AI_USAGE_WRITE, SCOPE_BOTS_WRITE};
+use crate::auth::context::AuthContext;
 use crate::error::Error;
+use crate::quota;
 use axum::extract::State;
 use chrono::Utc;
-use sea_orm::{ActiveModelTrait, EntityTrait, QueryFilter, Set, TransactionTrait};
+use sea_orm::{ActiveModelTrait, EntityTrait, QueryFilter, QuerySelect, Set, TransactionTrait};
 use uuid::Uuid;
-use workspace_entity::{account_realm_roles, bots, channel_bridge};
+use workspace_entity::{bots, channel_bridge};
 
 use crate::proto::bot::*;
 
-/// Extract account_id and realm_id from JWT token in request headers
-fn extract_account_and_realm_from_headers(
-    headers: &Headers,
-    jwt_secret: &str,
-) -> Result<(Uuid, Option<Uuid>), Error> {
-    // Extract Authorization header
-    let auth_header = headers
-        .get("authorization")
-        .ok_or(Error::Forbidden)?
-        .to_str()
-        .map_err(|_| Error::Forbidden)?;
-
-    // Parse Bearer token
-    let token = auth_header
-        .strip_prefix("Bearer ")
-        .ok_or(Error::Forbidden)?;
-
-    // Verify token and extract account ID and realm_id
-    let claims = jwt::verify_token(token, jwt_secret).map_err(|_| Error::Forbidden)?;
-
-    let account_id = Uuid::parse_str(&claims.sub).map_err(|_| Error::Forbidden)?;
-
-    let realm_id = claims
-        .realm_id
-        .map(|r| Uuid::parse_str(&r))
-        .transpose()
-        .map_err(|_| Error::Forbidden)?;
-
-    Ok((account_id, realm_id))
-}
-
-/// Extract account_id from JWT token in request headers (for backward compatibility)
-fn extract_account_id_from_headers(headers: &Headers, jwt_secret: &str) -> Result<Uuid, Error> {
-    let (account_id, _) = extract_account_and_realm_from_headers(headers, jwt_secret)?;
-    Ok(account_id)
-}
-
-/// Extract realm_id from JWT token in request headers
-/// Falls back to database lookup if not in JWT (for backward compatibility)
-async fn extract_realm_id_from_headers(
-    headers: &Headers,
-    jwt_secret: &str,
-    account_id: Uuid,
-    db: &sea_orm::DatabaseConnection,
-) -> Result<Uuid, Error> {
-    // Try to get realm_id from JWT first
-    let (_, realm_id) = extract_account_and_realm_from_headers(headers, jwt_secret)?;
-
-    if let Some(realm_id) = realm_id {
-        return Ok(realm_id);
-    }
-
-    // Fallback to database lookup (for backward compatibility with old tokens)
-    let realm_role = account_realm_roles::Entity::find()
-        .filter(account_realm_roles::COLUMN.account_id.eq(account_id))
-        .one(db)
-        .await
-        .map_err(|e| Error::Anyhow(anyhow::Error::new(e)))?;
-
-    match realm_role {
-        Some(rr) => Ok(rr.realm_id),
-        None => Err(Error::Forbidden), // User has no realm access
-    }
-}
-
 /// Convert protobuf ChannelBridgeInput to channel_bridge::ActiveModel
 fn channel_bridge_input_to_active_model(
     input: &ChannelBridgeInput,
 ) -> Result<channel_bridge::ActiveModel, Error> {
     // Validate bridge_type
     if input.bridge_type != "oauth" && input.bridge_type != "api" {
-        return Err(Error::Anyhow(anyhow::anyhow!(
-            "Invalid bridge_type: must be 'oauth' or 'api'"
-        )));
+        return Err(Error::invalid_field(
+            "bridge_type",
+            "must be 'oauth' or 'api'",
+        ));
     }
 
     let mut active_model = channel_bridge::ActiveModel {
@@ -151,45 +88,44 @@ fn bot_to_proto(bot: &bots::Model) -> Bot {
         capabilities: bot.capabilities.clone().unwrap_or_default(),
         created_at: bot.created_at.to_rfc3339(),
         updated_at: bot.updated_at.to_rfc3339(),
+        version: bot.version,
     }
 }
 
 /// Create Bot handler
 pub async fn create_bot(
     State(state): State<AppState>,
-    headers: Headers,
+    ctx: AuthContext,
     request: CreateBotRequest,
 ) -> Result<CreateBotResponse, Error> {
-    // Extract account_id and realm_id from JWT
-    let account_id = extract_account_id_from_headers(&headers, &state.jwt_secret)?;
+    // Bot management acts on behalf of an account, so bot tokens are rejected
+    ctx.require_scope(SCOPE_BOTS_WRITE)?;
+    ctx.require_account()?;
     let realm_id = if request.realm_id.is_empty() {
-        // Extract from JWT token
-        extract_realm_id_from_headers(&headers, &state.jwt_secret, account_id, &state.conn).await?
+        ctx.require_realm()?
     } else {
         // Validate that provided realm_id belongs to user
         let provided_realm_id = Uuid::parse_str(&request.realm_id)
-            .map_err(|_| Error::Anyhow(anyhow::anyhow!("Invalid realm_id format")))?;
+            .map_err(|_| Error::invalid_field("realm_id", "must be a UUID"))?;
 
         // Verify user has access to this realm
-        let has_access = account_realm_roles::Entity::find()
-            .filter(account_realm_roles::COLUMN.account_id.eq(account_id))
-            .filter(account_realm_roles::COLUMN.realm_id.eq(provided_realm_id))
-            .one(&state.conn)
-            .await
-            .map_err(|e| Error::Anyhow(anyhow::Error::new(e)))?;
-
-        if has_access.is_none() {
-            return Err(Error::Forbidden);
-        }
+        ctx.roles_in(&state, provided_realm_id).await?;
 
         provided_realm_id
     };
 
     // Validate: at least one channel bridge must be provided
     if request.api_channel_bridge.is_none() && request.oauth_channel_bridge.is_none() {
-        return Err(Error::Anyhow(anyhow::anyhow!(
-            "At least one channel bridge (API or OAuth) is required"
-        )));
+        return Err(Error::InvalidArgument(
+            ["api_channel_bridge", "oauth_channel_bridge"]
+                .into_iter()
+                .map(|field| crate::error::FieldViolation {
+                    field: field.to_string(),
+                    description: "at least one channel bridge (API or OAuth) is required"
+                        .to_string(),
+                })
+                .collect(),
+        ));
     }
 
     // Validate: bot name uniqueness within realm
@@ -197,22 +133,19 @@ pub async fn create_bot(
         .filter(bots::COLUMN.realm_id.eq(realm_id))
         .filter(bots::COLUMN.name.eq(&request.name))
         .one(&state.conn)
-        .await
-        .map_err(|e| Error::Anyhow(anyhow::Error::new(e)))?;
+        .await?;
 
     if existing_bot.is_some() {
-        return Err(Error::Anyhow(anyhow::anyhow!(
-            "Bot name '{}' already exists in this realm",
+        return Err(Error::AlreadyExists(format!(
+            "bot name '{}' in this realm",
             request.name
         )));
     }
 
     // Start transaction for atomic bot and bridge creation
-    let txn = state
-        .conn
-        .begin()
-        .await
-        .map_err(|e| Error::Anyhow(anyhow::Error::new(e)))?;
+    let txn = state.conn.begin().await?;
+
+    quota::ensure_bot_available(&txn, realm_id).await?;
 
     // Create channel bridges if provided
     let mut api_bridge_id: Option<Uuid> = None;
@@ -222,8 +155,7 @@ pub async fn create_bot(
         let api_bridge_active = channel_bridge_input_to_active_model(api_bridge_input)?;
         let api_bridge_res = channel_bridge::Entity::insert(api_bridge_active)
             .exec(&txn)
-            .await
-            .map_err(|e| Error::Anyhow(anyhow::Error::new(e)))?;
+            .await?;
         api_bridge_id = Some(api_bridge_res.last_insert_id);
     }
 
@@ -231,8 +163,7 @@ pub async fn create_bot(
         let oauth_bridge_active = channel_bridge_input_to_active_model(oauth_bridge_input)?;
         let oauth_bridge_res = channel_bridge::Entity::insert(oauth_bridge_active)
             .exec(&txn)
-            .await
-            .map_err(|e| Error::Anyhow(anyhow::Error::new(e)))?;
+            .await?;
         oauth_bridge_id = Some(oauth_bridge_res.last_insert_id);
     }
 
@@ -262,13 +193,10 @@ pub async fn create_bot(
 
     let created_bot = bots::Entity::insert(new_bot)
         .exec_with_returning(&txn)
-        .await
-        .map_err(|e| Error::Anyhow(anyhow::Error::new(e)))?;
+        .await?;
 
     // Commit transaction
-    txn.commit()
-        .await
-        .map_err(|e| Error::Anyhow(anyhow::Error::new(e)))?;
+    txn.commit().await?;
 
     Ok(CreateBotResponse {
         success: true,
@@ -280,23 +208,26 @@ pub async fn create_bot(
 /// Update Bot handler
 pub async fn update_bot(
     State(state): State<AppState>,
-    headers: Headers,
+    ctx: AuthContext,
     request: UpdateBotRequest,
 ) -> Result<UpdateBotResponse, Error> {
-    // Extract account_id and realm_id from JWT
-    let account_id = extract_account_id_from_headers(&headers, &state.jwt_secret)?;
-    let realm_id =
-        extract_realm_id_from_headers(&headers, &state.jwt_secret, account_id, &state.conn).await?;
+    // Bot management acts on behalf of an account, so bot tokens are rejected
+    ctx.require_scope(SCOPE_BOTS_WRITE)?;
+    ctx.require_account()?;
+    let realm_id = ctx.require_realm()?;
 
     // Parse bot ID
-    let bot_id = Uuid::parse_str(&request.id)
-        .map_err(|_| Error::Anyhow(anyhow::anyhow!("Invalid bot ID format")))?;
+    let bot_id =
+        Uuid::parse_str(&request.id).map_err(|_| Error::invalid_field("id", "must be a UUID"))?;
 
-    // Validate bot exists and belongs to user's realm
+    let txn = state.conn.begin().await?;
+
+    // Validate bot exists and belongs to user's realm; the row stays locked until the
+    // update, so the version check can't race another update
     let existing_bot = bots::Entity::find_by_id(bot_id)
-        .one(&state.conn)
-        .await
-        .map_err(|e| Error::Anyhow(anyhow::Error::new(e)))?;
+        .lock_exclusive()
+        .one(&txn)
+        .await?;
 
     let bot = existing_bot.ok_or(Error::NotFound)?;
 
@@ -304,6 +235,17 @@ pub async fn update_bot(
         return Err(Error::Forbidden);
     }
 
+    if let Some(expected_version) = request.expected_version
+        && expected_version != bot.version
+    {
+        return Err(Error::version_mismatch(
+            expected_version,
+            bot.version,
+            "bot.Bot",
+            &bot_to_proto(&bot),
+        ));
+    }
+
     // Validate: bot name uniqueness within realm (excluding current bot)
     if !request.name.is_empty() && request.name != bot.name {
         let duplicate = bots::Entity::find()
@@ -311,12 +253,11 @@ pub async fn update_bot(
             .filter(bots::COLUMN.name.eq(&request.name))
             .filter(bots::COLUMN.id.ne(bot_id))
             .one(&state.conn)
-            .await
-            .map_err(|e| Error::Anyhow(anyhow::Error::new(e)))?;
+            .await?;
 
         if duplicate.is_some() {
-            return Err(Error::Anyhow(anyhow::anyhow!(
-                "Bot name '{}' already exists in this realm",
+            return Err(Error::AlreadyExists(format!(
+                "bot name '{}' in this realm",
                 request.name
             )));
         }
@@ -326,7 +267,7 @@ pub async fn update_bot(
     let final_api_bridge_id = if !request.api_channel_bridge_id.is_empty() {
         Some(
             Uuid::parse_str(&request.api_channel_bridge_id)
-                .map_err(|_| Error::Anyhow(anyhow::anyhow!("Invalid API bridge ID format")))?,
+                .map_err(|_| Error::invalid_field("api_channel_bridge_id", "must be a UUID"))?,
         )
     } else {
         bot.api_channel_bridge_id
@@ -335,44 +276,45 @@ pub async fn update_bot(
     let final_oauth_bridge_id = if !request.oauth_channel_bridge_id.is_empty() {
         Some(
             Uuid::parse_str(&request.oauth_channel_bridge_id)
-                .map_err(|_| Error::Anyhow(anyhow::anyhow!("Invalid OAuth bridge ID format")))?,
+                .map_err(|_| Error::invalid_field("oauth_channel_bridge_id", "must be a UUID"))?,
         )
     } else {
         bot.oauth_channel_bridge_id
     };
 
     if final_api_bridge_id.is_none() && final_oauth_bridge_id.is_none() {
-        return Err(Error::Anyhow(anyhow::anyhow!(
-            "At least one channel bridge (API or OAuth) must remain"
-        )));
+        return Err(Error::FailedPrecondition(
+            "at least one channel bridge (API or OAuth) must remain".to_string(),
+        ));
     }
 
     // Validate bridge IDs exist and belong to realm (if provided)
     if let Some(api_bridge_id) = final_api_bridge_id {
         let bridge = channel_bridge::Entity::find_by_id(api_bridge_id)
             .one(&state.conn)
-            .await
-            .map_err(|e| Error::Anyhow(anyhow::Error::new(e)))?;
+            .await?;
         if bridge.is_none() {
-            return Err(Error::Anyhow(anyhow::anyhow!(
-                "API channel bridge not found"
-            )));
+            return Err(Error::invalid_field(
+                "api_channel_bridge_id",
+                "channel bridge not found",
+            ));
         }
     }
 
     if let Some(oauth_bridge_id) = final_oauth_bridge_id {
         let bridge = channel_bridge::Entity::find_by_id(oauth_bridge_id)
             .one(&state.conn)
-            .await
-            .map_err(|e| Error::Anyhow(anyhow::Error::new(e)))?;
+            .await?;
         if bridge.is_none() {
-            return Err(Error::Anyhow(anyhow::anyhow!(
-                "OAuth channel bridge not found"
-            )));
+            return Err(Error::invalid_field(
+                "oauth_channel_bridge_id",
+                "channel bridge not found",
+            ));
         }
     }
 
     // Convert Model to ActiveModel and update fields
+    let version = bot.version;
     let mut bot_active: bots::ActiveModel = bot.into();
 
     if !request.name.is_empty() {
@@ -400,12 +342,12 @@ pub async fn update_bot(
     bot_active.api_channel_bridge_id = Set(final_api_bridge_id);
     bot_active.oauth_channel_bridge_id = Set(final_oauth_bridge_id);
     bot_active.updated_at = Set(Utc::now().into());
+    bot_active.version = Set(version + 1);
 
     // Update bot
-    let updated_bot = bot_active
-        .update(&state.conn)
-        .await
-        .map_err(|e| Error::Anyhow(anyhow::Error::new(e)))?;
+    let updated_bot = bot_active.update(&txn).await?;
+
+    txn.commit().await?;
 
     Ok(UpdateBotResponse {
         success: true,
@@ -417,23 +359,20 @@ pub async fn update_bot(
 /// Delete Bot handler
 pub async fn delete_bot(
     State(state): State<AppState>,
-    headers: Headers,
+    ctx: AuthContext,
     request: DeleteBotRequest,
 ) -> Result<DeleteBotResponse, Error> {
-    // Extract account_id and realm_id from JWT
-    let account_id = extract_account_id_from_headers(&headers, &state.jwt_secret)?;
-    let realm_id =
-        extract_realm_id_from_headers(&headers, &state.jwt_secret, account_id, &state.conn).await?;
+    // Bot management acts on behalf of an account, so bot tokens are rejected
+    ctx.require_scope(SCOPE_BOTS_WRITE)?;
+    ctx.require_account()?;
+    let realm_id = ctx.require_realm()?;
 
     // Parse bot ID
-    let bot_id = Uuid::parse_str(&request.id)
-        .map_err(|_| Error::Anyhow(anyhow::anyhow!("Invalid bot ID format")))?;
+    let bot_id =
+        Uuid::parse_str(&request.id).map_err(|_| Error::invalid_field("id", "must be a UUID"))?;
 
     // Validate bot exists and belongs to user's realm
-    let existing_bot = bots::Entity::find_by_id(bot_id)
-        .one(&state.conn)
-        .await
-        .map_err(|e| Error::Anyhow(anyhow::Error::new(e)))?;
+    let existing_bot = bots::Entity::find_by_id(bot_id).one(&state.conn).await?;
 
     let bot = existing_bot.ok_or(Error::NotFound)?;
 
@@ -446,13 +385,33 @@ pub async fn delete_bot(
 
     // Delete bot
     let bot_active: bots::ActiveModel = bot.into();
-    bot_active
-        .delete(&state.conn)
-        .await
-        .map_err(|e| Error::Anyhow(anyhow::Error::new(e)))?;
+    bot_active.delete(&state.conn).await?;
 
     Ok(DeleteBotResponse {
         success: true,
         message: "Bot deleted successfully".to_string(),
     })
 }
+
+/// Report AI tokens consumed by a bot, counted against its realm's monthly budget
+pub async fn report_ai_usage(
+    State(state): State<AppState>,
+    ctx: AuthContext,
+    request: ReportAiUsageRequest,
+) -> Result<ReportAiUsageResponse, Error> {
+    // Usage is reported by the bots that call the AI provider
+    ctx.require_scope(SCOPE_AI_USAGE_WRITE)?;
+    let bot_id = ctx.bot_id.ok_or(Error::Forbidden)?;
+
+    let bot = bots::Entity::find_by_id(bot_id)
+        .one(&state.conn)
+        .await?
+        .ok_or(Error::NotFound)?;
+
+    let (used, limit) = quota::record_ai_tokens(&state.conn, bot.realm_id, request.tokens).await?;
+
+    Ok(ReportAiUsageResponse {
+        tokens_used: used,
+        tokens_limit: limit,
+    })
+}
@@ -2,13 +2,25 @@ use axum::{
     http::StatusCode,
     response::{IntoResponse, Response},
 };
-use axum_connect::error::{RpcError, RpcErrorCode, RpcIntoError};
+use axum_connect::error::{RpcError, RpcErrorCode, RpcErrorDetail, RpcIntoError};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD_NO_PAD;
+use sea_orm::{DbErr, SqlErr};
+use std::collections::HashMap;
 
 // This is an example Error type, to demo impls needed for `axum-connect`. It uses `thiserror` to
 // wrap various error types as syntactic sugar, but you could just as easily write this out by hand.
 #[allow(dead_code)]
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
+    /// Returns `400 Bad Request`, with a `google.rpc.BadRequest` detail listing the fields
+    #[error("invalid argument")]
+    InvalidArgument(Vec<FieldViolation>),
+
+    /// Returns `401 Unauthorized`
+    #[error("authentication failed")]
+    Unauthenticated,
+
     /// Returns `403 Forbidden`
     #[error("user may not perform that action")]
     Forbidden,
@@ -17,11 +29,157 @@ pub enum Error {
     #[error("request path not found")]
     NotFound,
 
+    /// Returns `409 Conflict`
+    #[error("already exists: {0}")]
+    AlreadyExists(String),
+
+    /// Returns `412 Precondition Failed`; the system is not in a state the request needs
+    #[error("failed precondition: {0}")]
+    FailedPrecondition(String),
+
+    /// Returns `409 Conflict`; the record changed since the version the request was based on.
+    /// Carries the current record as a detail, so clients can merge and retry.
+    #[error("aborted: {message}")]
+    Aborted {
+        message: String,
+        /// Message type name and protobuf encoding of the current record
+        current: Option<(String, Vec<u8>)>,
+    },
+
+    /// Returns `429 Too Many Requests`, with a `google.rpc.RetryInfo` detail when known
+    #[error("resource exhausted: {message}")]
+    ResourceExhausted {
+        message: String,
+        retry_after: Option<std::time::Duration>,
+    },
+
     /// Returns `500 Internal Server Error`
     #[error("an internal server error occurred")]
     Anyhow(#[from] anyhow::Error),
 }
 
+impl Error {
+    /// Shorthand for an `InvalidArgument` error about a single field
+    pub fn invalid_field(field: &str, description: impl Into<String>) -> Self {
+        Self::InvalidArgument(vec![FieldViolation {
+            field: field.to_owned(),
+            description: description.into(),
+        }])
+    }
+
+    /// An `Aborted` error for an update based on `expected` while the record is at the
+    /// version of `current` (a message named `type_name`, e.g. `trip.Trip`)
+    pub fn version_mismatch<M: prost::Message>(
+        expected: i64,
+        actual: i64,
+        type_name: &str,
+        current: &M,
+    ) -> Self {
+        Self::Aborted {
+            message: format!("expected version {expected}, but the record is at version {actual}"),
+            current: Some((type_name.to_string(), current.encode_to_vec())),
+        }
+    }
+
+    /// Fails with an `InvalidArgument` listing every empty field of `fields` (name, value)
+    pub fn require_fields(fields: &[(&str, &str)]) -> Result<(), Self> {
+        let violations: Vec<_> = fields
+            .iter()
+            .filter(|(_, value)| value.is_empty())
+            .map(|(field, _)| FieldViolation {
+                field: field.to_string(),
+                description: "is required".to_string(),
+            })
+            .collect();
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(Self::InvalidArgument(violations))
+        }
+    }
+}
+
+/// Database errors are internal, except constraint violations caused by the request
+impl From<DbErr> for Error {
+    fn from(err: DbErr) -> Self {
+        match err.sql_err() {
+            Some(SqlErr::UniqueConstraintViolation(message)) => {
+                Self::AlreadyExists(unique_violation_subject(&message))
+            }
+            Some(SqlErr::ForeignKeyConstraintViolation(_)) => {
+                Self::FailedPrecondition("referenced record does not exist".to_string())
+            }
+            _ => Self::Anyhow(anyhow::Error::new(err)),
+        }
+    }
+}
+
+/// Turn `duplicate key value violates unique constraint "uq_bots_realm_name"` into a
+/// client-facing description, without leaking the rest of the driver message
+fn unique_violation_subject(message: &str) -> String {
+    message
+        .split('"')
+        .nth(1)
+        .map(|constraint| format!("a record violating {constraint}"))
+        .unwrap_or_else(|| "a record with these values".to_string())
+}
+
+/// `google.rpc.BadRequest.FieldViolation`
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct FieldViolation {
+    /// Request field, e.g. `name` or `api_channel_bridge.third_id`
+    #[prost(string, tag = "1")]
+    pub field: String,
+    #[prost(string, tag = "2")]
+    pub description: String,
+}
+
+/// `google.rpc.BadRequest`
+#[derive(Clone, PartialEq, prost::Message)]
+struct BadRequest {
+    #[prost(message, repeated, tag = "1")]
+    field_violations: Vec<FieldViolation>,
+}
+
+/// `google.rpc.ErrorInfo`
+#[derive(Clone, PartialEq, prost::Message)]
+struct ErrorInfo {
+    #[prost(string, tag = "1")]
+    reason: String,
+    #[prost(string, tag = "2")]
+    domain: String,
+    #[prost(map = "string, string", tag = "3")]
+    metadata: HashMap<String, String>,
+}
+
+/// `google.protobuf.Duration`
+#[derive(Clone, PartialEq, prost::Message)]
+struct ProtoDuration {
+    #[prost(int64, tag = "1")]
+    seconds: i64,
+    #[prost(int32, tag = "2")]
+    nanos: i32,
+}
+
+/// `google.rpc.RetryInfo`
+#[derive(Clone, PartialEq, prost::Message)]
+struct RetryInfo {
+    #[prost(message, optional, tag = "1")]
+    retry_delay: Option<ProtoDuration>,
+}
+
+/// Domain reported in `google.rpc.ErrorInfo`
+const ERROR_DOMAIN: &str = "tripvota";
+
+/// Encode a message as a Connect error detail (unpadded base64 of the protobuf bytes)
+fn error_detail<M: prost::Message>(type_name: &str, message: &M) -> RpcErrorDetail {
+    RpcErrorDetail {
+        proto_type: type_name.to_string(),
+        proto_b62_value: STANDARD_NO_PAD.encode(message.encode_to_vec()),
+    }
+}
+
 /// Allows the error type to be returned from RPC handlers.
 ///
 /// This trait is distinct from `IntoResponse` because RPCs cannot return arbitrary HTML responses.
@@ -34,10 +192,80 @@ impl RpcIntoError for Error {
         // Each response is a tuple of well-defined (per the Connect-Web) codes, along with a
         // message.
         match self {
+            Self::InvalidArgument(field_violations) => {
+                let message = field_violations
+                    .iter()
+                    .map(|v| format!("{}: {}", v.field, v.description))
+                    .collect::<Vec<_>>()
+                    .join("; ");
+                let mut error = RpcError::new(RpcErrorCode::InvalidArgument, message);
+                error.details.push(error_detail(
+                    "google.rpc.BadRequest",
+                    &BadRequest { field_violations },
+                ));
+                error
+            }
+            Self::Unauthenticated => {
+                RpcError::new(RpcErrorCode::Unauthenticated, "Unauthenticated".to_string())
+            }
             Self::Forbidden => {
                 RpcError::new(RpcErrorCode::PermissionDenied, "Forbidden".to_string())
             }
             Self::NotFound => RpcError::new(RpcErrorCode::NotFound, "Not Found".to_string()),
+            Self::AlreadyExists(subject) => {
+                let mut error = RpcError::new(
+                    RpcErrorCode::AlreadyExists,
+                    format!("Already exists: {subject}"),
+                );
+                error.details.push(error_detail(
+                    "google.rpc.ErrorInfo",
+                    &ErrorInfo {
+                        reason: "ALREADY_EXISTS".to_string(),
+                        domain: ERROR_DOMAIN.to_string(),
+                        metadata: HashMap::from([("subject".to_string(), subject)]),
+                    },
+                ));
+                error
+            }
+            Self::FailedPrecondition(message) => {
+                RpcError::new(RpcErrorCode::FailedPrecondition, message)
+            }
+            Self::Aborted { message, current } => {
+                let mut error = RpcError::new(RpcErrorCode::Aborted, message);
+                error.details.push(error_detail(
+                    "google.rpc.ErrorInfo",
+                    &ErrorInfo {
+                        reason: "VERSION_MISMATCH".to_string(),
+                        domain: ERROR_DOMAIN.to_string(),
+                        metadata: HashMap::new(),
+                    },
+                ));
+                if let Some((proto_type, value)) = current {
+                    error.details.push(RpcErrorDetail {
+                        proto_type,
+                        proto_b62_value: STANDARD_NO_PAD.encode(value),
+                    });
+                }
+                error
+            }
+            Self::ResourceExhausted {
+                message,
+                retry_after,
+            } => {
+                let mut error = RpcError::new(RpcErrorCode::ResourceExhausted, message);
+                if let Some(retry_after) = retry_after {
+                    error.details.push(error_detail(
+                        "google.rpc.RetryInfo",
+                        &RetryInfo {
+                            retry_delay: Some(ProtoDuration {
+                                seconds: retry_after.as_secs() as i64,
+                                nanos: retry_after.subsec_nanos() as i32,
+                            }),
+                        },
+                    ));
+                }
+                error
+            }
             Self::Anyhow(_) => {
                 RpcError::new(RpcErrorCode::Internal, "Internal Server Error".to_string())
             }
@@ -51,8 +279,18 @@ impl IntoResponse for Error {
     fn into_response(self) -> Response {
         println!("{:#?}", self);
         match self {
+            Self::InvalidArgument(_) => (StatusCode::BAD_REQUEST, "Bad Request").into_response(),
+            Self::Unauthenticated => (StatusCode::UNAUTHORIZED, "Unauthorized").into_response(),
             Self::Forbidden => (StatusCode::FORBIDDEN, "Forbidden").into_response(),
             Self::NotFound => (StatusCode::NOT_FOUND, "Not Found").into_response(),
+            Self::AlreadyExists(_) => (StatusCode::CONFLICT, "Conflict").into_response(),
+            Self::FailedPrecondition(_) => {
+                (StatusCode::PRECONDITION_FAILED, "Precondition Failed").into_response()
+            }
+            Self::Aborted { .. } => (StatusCode::CONFLICT, "Conflict").into_response(),
+            Self::ResourceExhausted { .. } => {
+                (StatusCode::TOO_MANY_REQUESTS, "Too Many Requests").into_response()
+            }
             Self::Anyhow(_) => {
                 (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error").into_response()
             }
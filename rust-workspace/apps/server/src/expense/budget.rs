@@ -0,0 +1,167 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use workspace_entity::{trip_cards, trip_expenses, trips};
+
+/// Trip metadata key holding the budget
+pub const METADATA_BUDGET: &str = "budget";
+/// Card metadata key holding what the card is expected to cost the whole group, as
+/// `{ "amount": 4500, "currency": "EUR" }` in minor units
+pub const METADATA_COST_ESTIMATE: &str = "costEstimate";
+/// Category of repayments between participants; they don't count towards the budget
+pub const CATEGORY_SETTLEMENT: &str = "settlement";
+/// Cards whose cost estimates count towards the budget
+pub const ESTIMATED_CARD_STATUSES: [&str; 2] = ["scheduled", "completed"];
+
+/// A trip's budget, in minor units of one currency
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Budget {
+    pub currency: String,
+    /// 0 means no overall limit
+    #[serde(default)]
+    pub total: i64,
+    #[serde(default)]
+    pub categories: BTreeMap<String, i64>,
+}
+
+#[derive(Deserialize)]
+struct CostEstimate {
+    amount: i64,
+    currency: String,
+}
+
+/// The trip's budget, if it has one; a malformed one is reported rather than ignored
+pub fn of_trip(trip: &trips::Model) -> Result<Option<Budget>, String> {
+    trip.metadata
+        .as_ref()
+        .and_then(|m| m.get(METADATA_BUDGET))
+        .map(|budget| {
+            serde_json::from_value(budget.clone())
+                .map_err(|e| format!("the trip's {METADATA_BUDGET} metadata is invalid: {e}"))
+        })
+        .transpose()
+}
+
+/// The trip's metadata with `budget` set, or removed if `None`
+pub fn set(
+    metadata: Option<serde_json::Value>,
+    budget: Option<&Budget>,
+) -> Option<serde_json::Value> {
+    let mut object = match metadata {
+        Some(serde_json::Value::Object(object)) => object,
+        _ => serde_json::Map::new(),
+    };
+    match budget {
+        Some(budget) => {
+            object.insert(
+                METADATA_BUDGET.to_owned(),
+                serde_json::to_value(budget).unwrap_or_default(),
+            );
+        }
+        None => {
+            object.remove(METADATA_BUDGET);
+        }
+    }
+    (!object.is_empty()).then_some(serde_json::Value::Object(object))
+}
+
+/// A card's cost estimate; cards without a well-formed one have none
+fn cost_estimate(card: &trip_cards::Model) -> Option<CostEstimate> {
+    card.metadata
+        .as_ref()
+        .and_then(|m| m.get(METADATA_COST_ESTIMATE))
+        .and_then(|estimate| serde_json::from_value(estimate.clone()).ok())
+        .filter(|estimate: &CostEstimate| estimate.amount >= 0)
+}
+
+/// Totals of one category in one currency
+#[derive(Default)]
+pub struct Line {
+    pub category: String,
+    pub currency: String,
+    pub budget: Option<i64>,
+    pub estimated: i64,
+    pub spent: i64,
+}
+
+pub struct Report {
+    /// By currency, then category
+    pub categories: Vec<Line>,
+    /// Per currency, with an empty category
+    pub totals: Vec<Line>,
+}
+
+type Lines = BTreeMap<(String, String), Line>;
+
+fn line<'a>(lines: &'a mut Lines, currency: &str, category: &str) -> &'a mut Line {
+    lines
+        .entry((currency.to_owned(), category.to_owned()))
+        .or_insert_with(|| Line {
+            category: category.to_owned(),
+            currency: currency.to_owned(),
+            ..Default::default()
+        })
+}
+
+/// Compare the budget with the cost estimates of `cards` and with `expenses`, per
+/// currency and category. Expenses without a category count as uncategorized
+/// (the empty category), as do cards; settlements are left out.
+pub fn report(
+    budget: Option<&Budget>,
+    cards: &[trip_cards::Model],
+    expenses: &[trip_expenses::Model],
+) -> Report {
+    let mut categories = Lines::new();
+    let mut totals: BTreeMap<String, Line> = BTreeMap::new();
+    if let Some(budget) = budget {
+        for (category, amount) in &budget.categories {
+            line(&mut categories, &budget.currency, category).budget = Some(*amount);
+        }
+    }
+    for card in cards {
+        if let Some(estimate) = cost_estimate(card) {
+            line(
+                &mut categories,
+                &estimate.currency,
+                card.category.as_deref().unwrap_or_default(),
+            )
+            .estimated += estimate.amount;
+        }
+    }
+    for expense in expenses
+        .iter()
+        .filter(|e| e.category.as_deref() != Some(CATEGORY_SETTLEMENT))
+    {
+        line(
+            &mut categories,
+            &expense.currency,
+            expense.category.as_deref().unwrap_or_default(),
+        )
+        .spent += expense.amount;
+    }
+
+    for category in categories.values() {
+        let total = totals
+            .entry(category.currency.clone())
+            .or_insert_with(|| Line {
+                currency: category.currency.clone(),
+                ..Default::default()
+            });
+        total.estimated += category.estimated;
+        total.spent += category.spent;
+    }
+    if let Some(budget) = budget.filter(|b| b.total > 0) {
+        totals
+            .entry(budget.currency.clone())
+            .or_insert_with(|| Line {
+                currency: budget.currency.clone(),
+                ..Default::default()
+            })
+            .budget = Some(budget.total);
+    }
+
+    Report {
+        categories: categories.into_values().collect(),
+        totals: totals.into_values().collect(),
+    }
+}
@@ -0,0 +1,4 @@
+pub mod budget;
+pub mod service;
+pub mod settlement;
+pub mod split;
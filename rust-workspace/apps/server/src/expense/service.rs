@@ -0,0 +1,620 @@
+use crate::AppState;
+use crate::auth::api_token::{SCOPE_TRIPS_READ, SCOPE_TRIPS_WRITE};
+use crate::auth::context::AuthContext;
+use crate::error::Error;
+use crate::expense::{budget, settlement, split};
+use crate::trip::activity;
+use crate::trip::service::{TripAccess, caller_profile, parse_uuid, trip_access, trip_to_proto};
+use axum::extract::State;
+use chrono::{DateTime, FixedOffset, Utc};
+use sea_orm::{
+    ActiveModelTrait, ConnectionTrait, EntityTrait, QueryFilter, QueryOrder, QuerySelect, Set,
+    TransactionTrait,
+};
+use std::collections::HashMap;
+use uuid::Uuid;
+use workspace_entity::{trip_cards, trip_expense_shares, trip_expenses, trip_participants, trips};
+
+use crate::proto::expense::*;
+
+/// Largest amount of a single expense or budget, in minor units
+const MAX_AMOUNT: i64 = 1_000_000_000_000_000;
+
+fn expense_to_proto(
+    expense: &trip_expenses::Model,
+    shares: &[trip_expense_shares::Model],
+) -> Expense {
+    Expense {
+        id: expense.id.to_string(),
+        trip_id: expense.trip_id.to_string(),
+        trip_card_id: expense
+            .trip_card_id
+            .map(|id| id.to_string())
+            .unwrap_or_default(),
+        paid_by: expense.paid_by.to_string(),
+        created_by: expense.created_by.to_string(),
+        description: expense.description.clone(),
+        category: expense.category.clone().unwrap_or_default(),
+        amount: expense.amount,
+        currency: expense.currency.clone(),
+        spent_at: expense.spent_at.to_rfc3339(),
+        shares: shares
+            .iter()
+            .map(|share| ExpenseShare {
+                profile_id: share.profile_id.to_string(),
+                amount: share.amount,
+            })
+            .collect(),
+        created_at: expense.created_at.to_rfc3339(),
+        updated_at: expense.updated_at.to_rfc3339(),
+        version: expense.version,
+    }
+}
+
+fn budget_to_proto(budget: budget::Budget) -> Budget {
+    Budget {
+        currency: budget.currency,
+        total: budget.total,
+        categories: budget.categories.into_iter().collect(),
+    }
+}
+
+fn budget_line_to_proto(line: budget::Line) -> BudgetLine {
+    BudgetLine {
+        category: line.category,
+        currency: line.currency,
+        budget: line.budget,
+        estimated: line.estimated,
+        spent: line.spent,
+    }
+}
+
+fn validate_currency(field: &str, currency: &str) -> Result<(), Error> {
+    if currency.len() != 3 || !currency.bytes().all(|b| b.is_ascii_uppercase()) {
+        return Err(Error::invalid_field(
+            field,
+            "must be an ISO 4217 code, e.g. 'EUR'",
+        ));
+    }
+    Ok(())
+}
+
+fn validate_amount(field: &str, amount: i64, allow_zero: bool) -> Result<(), Error> {
+    if amount < 0 || (amount == 0 && !allow_zero) || amount > MAX_AMOUNT {
+        return Err(Error::invalid_field(
+            field,
+            format!(
+                "must be {} and at most {MAX_AMOUNT}",
+                if allow_zero { "0 or more" } else { "positive" }
+            ),
+        ));
+    }
+    Ok(())
+}
+
+fn validate_description(description: &str) -> Result<(), Error> {
+    if description.trim().is_empty() {
+        return Err(Error::invalid_field("description", "must not be empty"));
+    }
+    Ok(())
+}
+
+fn parse_spent_at(spent_at: &str) -> Result<DateTime<FixedOffset>, Error> {
+    DateTime::parse_from_rfc3339(spent_at)
+        .map_err(|_| Error::invalid_field("spent_at", "must be an ISO 8601 timestamp"))
+}
+
+/// The caller may change an expense they recorded, or any if they edit the trip
+fn can_change(access: &TripAccess, expense: &trip_expenses::Model) -> bool {
+    access.can_edit() || access.profile_id() == Some(expense.created_by)
+}
+
+/// Profiles taking part in a trip, in the order they joined
+async fn participant_ids<C: ConnectionTrait>(db: &C, trip_id: Uuid) -> Result<Vec<Uuid>, Error> {
+    Ok(trip_participants::Entity::find()
+        .filter(trip_participants::COLUMN.trip_id.eq(trip_id))
+        .order_by_asc(trip_participants::Column::JoinedAt)
+        .order_by_asc(trip_participants::Column::ProfileId)
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|p| p.profile_id)
+        .collect())
+}
+
+fn require_participant(participants: &[Uuid], field: &str, profile_id: Uuid) -> Result<(), Error> {
+    if !participants.contains(&profile_id) {
+        return Err(Error::invalid_field(
+            field,
+            "must be a participant of the trip",
+        ));
+    }
+    Ok(())
+}
+
+/// A card of the trip, to link an expense to
+async fn find_card<C: ConnectionTrait>(
+    db: &C,
+    trip_id: Uuid,
+    trip_card_id: &str,
+) -> Result<trip_cards::Model, Error> {
+    trip_cards::Entity::find_by_id(parse_uuid("trip_card_id", trip_card_id)?)
+        .one(db)
+        .await?
+        .filter(|card| card.trip_id == trip_id)
+        .ok_or_else(|| Error::invalid_field("trip_card_id", "no such card in this trip"))
+}
+
+/// Shares of `amount` as requested; without a split, equal shares of all participants
+fn resolve_split(
+    split: Option<&ExpenseSplit>,
+    amount: i64,
+    participants: &[Uuid],
+) -> Result<Vec<(Uuid, i64)>, Error> {
+    let requested = match split {
+        Some(split) => split
+            .shares
+            .iter()
+            .map(|share| {
+                let profile_id = parse_uuid("split.shares.profile_id", &share.profile_id)?;
+                require_participant(participants, "split.shares.profile_id", profile_id)?;
+                Ok(split::Requested {
+                    profile_id,
+                    amount: share.amount,
+                    weight: share.weight,
+                })
+            })
+            .collect::<Result<Vec<_>, Error>>()?,
+        None => participants
+            .iter()
+            .map(|profile_id| split::Requested {
+                profile_id: *profile_id,
+                amount: 0,
+                weight: 1,
+            })
+            .collect(),
+    };
+    split::split(amount, &requested).map_err(|e| Error::invalid_field("split", e))
+}
+
+async fn insert_shares<C: ConnectionTrait>(
+    db: &C,
+    expense_id: Uuid,
+    mut shares: Vec<(Uuid, i64)>,
+) -> Result<Vec<trip_expense_shares::Model>, Error> {
+    // In the order they are read back in
+    shares.sort_by_key(|(profile_id, _)| *profile_id);
+    let mut inserted = Vec::with_capacity(shares.len());
+    for (profile_id, amount) in shares {
+        inserted.push(
+            trip_expense_shares::ActiveModel {
+                expense_id: Set(expense_id),
+                profile_id: Set(profile_id),
+                amount: Set(amount),
+            }
+            .insert(db)
+            .await?,
+        );
+    }
+    Ok(inserted)
+}
+
+async fn shares_of<C: ConnectionTrait>(
+    db: &C,
+    expense_ids: impl IntoIterator<Item = Uuid>,
+) -> Result<Vec<trip_expense_shares::Model>, Error> {
+    Ok(trip_expense_shares::Entity::find()
+        .filter(trip_expense_shares::COLUMN.expense_id.is_in(expense_ids))
+        .order_by_asc(trip_expense_shares::Column::ProfileId)
+        .all(db)
+        .await?)
+}
+
+/// A trip the caller takes part in
+async fn participant_access<C: ConnectionTrait>(
+    db: &C,
+    ctx: &AuthContext,
+    trip_id: &str,
+) -> Result<TripAccess, Error> {
+    let access = trip_access(db, ctx, parse_uuid("trip_id", trip_id)?, false).await?;
+    if access.role.is_none() {
+        return Err(Error::Forbidden);
+    }
+    Ok(access)
+}
+
+/// An expense the caller may change with their access to its trip, locked for the rest
+/// of the transaction
+async fn expense_access<C: ConnectionTrait>(
+    db: &C,
+    ctx: &AuthContext,
+    expense_id: &str,
+) -> Result<(trip_expenses::Model, TripAccess), Error> {
+    let expense = trip_expenses::Entity::find_by_id(parse_uuid("expense_id", expense_id)?)
+        .lock_exclusive()
+        .one(db)
+        .await?
+        .ok_or(Error::NotFound)?;
+    let access = trip_access(db, ctx, expense.trip_id, false).await?;
+    if access.role.is_none() || !can_change(&access, &expense) {
+        return Err(Error::Forbidden);
+    }
+    Ok((expense, access))
+}
+
+/// Create Expense handler
+pub async fn create_expense(
+    State(state): State<AppState>,
+    ctx: AuthContext,
+    request: CreateExpenseRequest,
+) -> Result<CreateExpenseResponse, Error> {
+    ctx.require_scope(SCOPE_TRIPS_WRITE)?;
+    Error::require_fields(&[
+        ("trip_id", &request.trip_id),
+        ("description", &request.description),
+        ("currency", &request.currency),
+    ])?;
+    validate_description(&request.description)?;
+    validate_amount("amount", request.amount, false)?;
+    validate_currency("currency", &request.currency)?;
+    let spent_at = match request.spent_at.as_str() {
+        "" => Utc::now().into(),
+        spent_at => parse_spent_at(spent_at)?,
+    };
+
+    let txn = state.conn.begin().await?;
+
+    let access = participant_access(&txn, &ctx, &request.trip_id).await?;
+    let trip_id = access.trip.id;
+    let participants = participant_ids(&txn, trip_id).await?;
+    let creator = caller_profile(&txn, &ctx, access.trip.realm_id).await?;
+    let paid_by = match request.paid_by.as_str() {
+        "" => creator.id,
+        paid_by => parse_uuid("paid_by", paid_by)?,
+    };
+    require_participant(&participants, "paid_by", paid_by)?;
+
+    let card = match request.trip_card_id.as_str() {
+        "" => None,
+        trip_card_id => Some(find_card(&txn, trip_id, trip_card_id).await?),
+    };
+    let category = match request.category.as_str() {
+        "" => card.as_ref().and_then(|c| c.category.clone()),
+        category => Some(category.to_owned()),
+    };
+    let shares = resolve_split(request.split.as_ref(), request.amount, &participants)?;
+
+    let now = Utc::now();
+    let expense = trip_expenses::ActiveModel {
+        id: Set(Uuid::now_v7()),
+        trip_id: Set(trip_id),
+        trip_card_id: Set(card.map(|c| c.id)),
+        paid_by: Set(paid_by),
+        created_by: Set(creator.id),
+        description: Set(request.description),
+        category: Set(category),
+        amount: Set(request.amount),
+        currency: Set(request.currency),
+        spent_at: Set(spent_at),
+        created_at: Set(now.into()),
+        updated_at: Set(now.into()),
+        version: Set(1),
+    }
+    .insert(&txn)
+    .await?;
+    let shares = insert_shares(&txn, expense.id, shares).await?;
+
+    txn.commit().await?;
+
+    Ok(CreateExpenseResponse {
+        expense: Some(expense_to_proto(&expense, &shares)),
+    })
+}
+
+/// Update Expense handler
+pub async fn update_expense(
+    State(state): State<AppState>,
+    ctx: AuthContext,
+    request: UpdateExpenseRequest,
+) -> Result<UpdateExpenseResponse, Error> {
+    ctx.require_scope(SCOPE_TRIPS_WRITE)?;
+    if let Some(description) = &request.description {
+        validate_description(description)?;
+    }
+    if let Some(amount) = request.amount {
+        validate_amount("amount", amount, false)?;
+    }
+    if let Some(currency) = &request.currency {
+        validate_currency("currency", currency)?;
+    }
+    let spent_at = request
+        .spent_at
+        .as_deref()
+        .map(parse_spent_at)
+        .transpose()?;
+
+    let txn = state.conn.begin().await?;
+
+    let (expense, _) = expense_access(&txn, &ctx, &request.expense_id).await?;
+    let current_shares = shares_of(&txn, [expense.id]).await?;
+    if let Some(expected_version) = request.expected_version
+        && expected_version != expense.version
+    {
+        return Err(Error::version_mismatch(
+            expected_version,
+            expense.version,
+            "expense.Expense",
+            &expense_to_proto(&expense, &current_shares),
+        ));
+    }
+
+    let participants = participant_ids(&txn, expense.trip_id).await?;
+    let amount = request.amount.unwrap_or(expense.amount);
+    let shares = match (&request.split, request.amount) {
+        (Some(split), _) => Some(resolve_split(Some(split), amount, &participants)?),
+        (None, Some(amount)) if amount != expense.amount => Some(split::scale(
+            amount,
+            &current_shares
+                .iter()
+                .map(|share| (share.profile_id, share.amount))
+                .collect::<Vec<_>>(),
+        )),
+        _ => None,
+    };
+
+    let mut active: trip_expenses::ActiveModel = expense.clone().into();
+    if let Some(description) = request.description {
+        active.description = Set(description);
+    }
+    active.amount = Set(amount);
+    if let Some(currency) = request.currency {
+        active.currency = Set(currency);
+    }
+    if let Some(paid_by) = request.paid_by {
+        let paid_by = parse_uuid("paid_by", &paid_by)?;
+        if paid_by != expense.paid_by {
+            require_participant(&participants, "paid_by", paid_by)?;
+        }
+        active.paid_by = Set(paid_by);
+    }
+    if let Some(category) = request.category {
+        active.category = Set(Some(category).filter(|c| !c.is_empty()));
+    }
+    if let Some(trip_card_id) = request.trip_card_id {
+        let card = match trip_card_id.as_str() {
+            "" => None,
+            trip_card_id => Some(find_card(&txn, expense.trip_id, trip_card_id).await?),
+        };
+        active.trip_card_id = Set(card.map(|c| c.id));
+    }
+    if let Some(spent_at) = spent_at {
+        active.spent_at = Set(spent_at);
+    }
+    active.updated_at = Set(Utc::now().into());
+    active.version = Set(expense.version + 1);
+    let updated = active.update(&txn).await?;
+
+    let shares = match shares {
+        Some(shares) => {
+            trip_expense_shares::Entity::delete_many()
+                .filter(trip_expense_shares::COLUMN.expense_id.eq(expense.id))
+                .exec(&txn)
+                .await?;
+            insert_shares(&txn, expense.id, shares).await?
+        }
+        None => current_shares,
+    };
+
+    txn.commit().await?;
+
+    Ok(UpdateExpenseResponse {
+        expense: Some(expense_to_proto(&updated, &shares)),
+    })
+}
+
+/// Delete Expense handler
+pub async fn delete_expense(
+    State(state): State<AppState>,
+    ctx: AuthContext,
+    request: DeleteExpenseRequest,
+) -> Result<DeleteExpenseResponse, Error> {
+    ctx.require_scope(SCOPE_TRIPS_WRITE)?;
+
+    let txn = state.conn.begin().await?;
+    let (expense, _) = expense_access(&txn, &ctx, &request.expense_id).await?;
+    trip_expenses::Entity::delete_by_id(expense.id)
+        .exec(&txn)
+        .await?;
+    txn.commit().await?;
+
+    Ok(DeleteExpenseResponse { success: true })
+}
+
+/// A trip's expenses, most recently spent first
+async fn expenses_of_trip<C: ConnectionTrait>(
+    db: &C,
+    trip_id: Uuid,
+) -> Result<Vec<trip_expenses::Model>, Error> {
+    Ok(trip_expenses::Entity::find()
+        .filter(trip_expenses::COLUMN.trip_id.eq(trip_id))
+        .order_by_desc(trip_expenses::Column::SpentAt)
+        .order_by_desc(trip_expenses::Column::Id)
+        .all(db)
+        .await?)
+}
+
+/// List Expenses handler
+pub async fn list_expenses(
+    State(state): State<AppState>,
+    ctx: AuthContext,
+    request: ListExpensesRequest,
+) -> Result<ListExpensesResponse, Error> {
+    ctx.require_scope(SCOPE_TRIPS_READ)?;
+    let access = participant_access(&state.conn, &ctx, &request.trip_id).await?;
+
+    let expenses = expenses_of_trip(&state.conn, access.trip.id).await?;
+    let mut shares: HashMap<Uuid, Vec<trip_expense_shares::Model>> = HashMap::new();
+    for share in shares_of(&state.conn, expenses.iter().map(|e| e.id)).await? {
+        shares.entry(share.expense_id).or_default().push(share);
+    }
+
+    Ok(ListExpensesResponse {
+        expenses: expenses
+            .iter()
+            .map(|expense| {
+                expense_to_proto(
+                    expense,
+                    shares.get(&expense.id).map_or(&[], |s| s.as_slice()),
+                )
+            })
+            .collect(),
+    })
+}
+
+/// Set Trip Budget handler
+pub async fn set_trip_budget(
+    State(state): State<AppState>,
+    ctx: AuthContext,
+    request: SetTripBudgetRequest,
+) -> Result<SetTripBudgetResponse, Error> {
+    ctx.require_scope(SCOPE_TRIPS_WRITE)?;
+    let new_budget = request
+        .budget
+        .map(|budget| {
+            validate_currency("budget.currency", &budget.currency)?;
+            validate_amount("budget.total", budget.total, true)?;
+            for (category, amount) in &budget.categories {
+                if category.trim().is_empty() {
+                    return Err(Error::invalid_field(
+                        "budget.categories",
+                        "names must not be empty",
+                    ));
+                }
+                validate_amount("budget.categories", *amount, true)?;
+            }
+            Ok(budget::Budget {
+                currency: budget.currency,
+                total: budget.total,
+                categories: budget.categories.into_iter().collect(),
+            })
+        })
+        .transpose()?;
+
+    let txn = state.conn.begin().await?;
+
+    // Locks the trip, so the version check can't race another update
+    let access = trip_access(&txn, &ctx, parse_uuid("trip_id", &request.trip_id)?, true).await?;
+    if !access.can_edit() {
+        return Err(Error::Forbidden);
+    }
+    let profile_id = access.profile_id();
+    let trip = access.trip;
+
+    if let Some(expected_version) = request.expected_version
+        && expected_version != trip.version
+    {
+        return Err(Error::version_mismatch(
+            expected_version,
+            trip.version,
+            "trip.Trip",
+            &trip_to_proto(&trip),
+        ));
+    }
+
+    let mut active: trips::ActiveModel = trip.clone().into();
+    active.metadata = Set(budget::set(trip.metadata.clone(), new_budget.as_ref()));
+    active.updated_at = Set(Utc::now().into());
+    active.version = Set(trip.version + 1);
+    let updated = active.update(&txn).await?;
+    activity::record_update(
+        &txn,
+        trip.id,
+        profile_id,
+        activity::ENTITY_TRIP,
+        trip.id,
+        &trip,
+        &updated,
+    )
+    .await?;
+
+    txn.commit().await?;
+
+    Ok(SetTripBudgetResponse {
+        budget: new_budget.map(budget_to_proto),
+        trip_version: updated.version,
+    })
+}
+
+/// Get Trip Budget handler
+pub async fn get_trip_budget(
+    State(state): State<AppState>,
+    ctx: AuthContext,
+    request: GetTripBudgetRequest,
+) -> Result<GetTripBudgetResponse, Error> {
+    ctx.require_scope(SCOPE_TRIPS_READ)?;
+    let access = participant_access(&state.conn, &ctx, &request.trip_id).await?;
+    let trip_budget = budget::of_trip(&access.trip).map_err(Error::FailedPrecondition)?;
+
+    let cards = trip_cards::Entity::find()
+        .filter(trip_cards::COLUMN.trip_id.eq(access.trip.id))
+        .filter(
+            trip_cards::COLUMN
+                .status
+                .is_in(budget::ESTIMATED_CARD_STATUSES),
+        )
+        .all(&state.conn)
+        .await?;
+    let expenses = expenses_of_trip(&state.conn, access.trip.id).await?;
+    let report = budget::report(trip_budget.as_ref(), &cards, &expenses);
+
+    Ok(GetTripBudgetResponse {
+        budget: trip_budget.map(budget_to_proto),
+        categories: report
+            .categories
+            .into_iter()
+            .map(budget_line_to_proto)
+            .collect(),
+        totals: report
+            .totals
+            .into_iter()
+            .map(budget_line_to_proto)
+            .collect(),
+    })
+}
+
+/// Get Settlement handler
+pub async fn get_settlement(
+    State(state): State<AppState>,
+    ctx: AuthContext,
+    request: GetSettlementRequest,
+) -> Result<GetSettlementResponse, Error> {
+    ctx.require_scope(SCOPE_TRIPS_READ)?;
+    let access = participant_access(&state.conn, &ctx, &request.trip_id).await?;
+
+    let expenses = expenses_of_trip(&state.conn, access.trip.id).await?;
+    let shares = shares_of(&state.conn, expenses.iter().map(|e| e.id)).await?;
+    let balances = settlement::balances(&expenses, &shares);
+    let transfers = settlement::transfers(&balances);
+
+    Ok(GetSettlementResponse {
+        balances: balances
+            .iter()
+            .map(|balance| Balance {
+                profile_id: balance.profile_id.to_string(),
+                currency: balance.currency.clone(),
+                paid: balance.paid,
+                owed: balance.owed,
+                net: balance.net(),
+            })
+            .collect(),
+        transfers: transfers
+            .into_iter()
+            .map(|transfer| Transfer {
+                from_profile_id: transfer.from.to_string(),
+                to_profile_id: transfer.to.to_string(),
+                currency: transfer.currency,
+                amount: transfer.amount,
+            })
+            .collect(),
+    })
+}
@@ -0,0 +1,289 @@
+use std::collections::BTreeMap;
+use uuid::Uuid;
+use workspace_entity::{trip_expense_shares, trip_expenses};
+
+/// Up to this many profiles with an open balance (per currency), the transfers are
+/// minimized exactly; the search takes 2^n steps. Beyond, a greedy plan is used.
+const MAX_EXACT_PROFILES: usize = 16;
+
+pub struct Balance {
+    pub profile_id: Uuid,
+    pub currency: String,
+    pub paid: i64,
+    pub owed: i64,
+}
+
+impl Balance {
+    /// Positive if others owe the profile
+    pub fn net(&self) -> i64 {
+        self.paid - self.owed
+    }
+}
+
+pub struct Transfer {
+    pub from: Uuid,
+    pub to: Uuid,
+    pub currency: String,
+    pub amount: i64,
+}
+
+type Balances = BTreeMap<(String, Uuid), Balance>;
+
+fn entry<'a>(balances: &'a mut Balances, currency: &str, profile_id: Uuid) -> &'a mut Balance {
+    balances
+        .entry((currency.to_owned(), profile_id))
+        .or_insert_with(|| Balance {
+            profile_id,
+            currency: currency.to_owned(),
+            paid: 0,
+            owed: 0,
+        })
+}
+
+/// What each profile paid and owes, per currency, ordered by currency and profile
+pub fn balances(
+    expenses: &[trip_expenses::Model],
+    shares: &[trip_expense_shares::Model],
+) -> Vec<Balance> {
+    let mut balances = Balances::new();
+    let currencies: BTreeMap<Uuid, &str> = expenses
+        .iter()
+        .map(|expense| (expense.id, expense.currency.as_str()))
+        .collect();
+    for expense in expenses {
+        entry(&mut balances, &expense.currency, expense.paid_by).paid += expense.amount;
+    }
+    for share in shares {
+        if let Some(currency) = currencies.get(&share.expense_id) {
+            entry(&mut balances, currency, share.profile_id).owed += share.amount;
+        }
+    }
+
+    balances.into_values().collect()
+}
+
+/// The fewest transfers that bring every balance to zero, per currency.
+///
+/// Profiles whose balances cancel out among themselves can settle within their group, and
+/// a group of k profiles needs k - 1 transfers. So the number of transfers is lowest with
+/// the most groups: those are searched for exactly, then settled largest debt first.
+pub fn transfers(balances: &[Balance]) -> Vec<Transfer> {
+    let mut by_currency: BTreeMap<&str, Vec<(Uuid, i64)>> = BTreeMap::new();
+    for balance in balances.iter().filter(|b| b.net() != 0) {
+        by_currency
+            .entry(&balance.currency)
+            .or_default()
+            .push((balance.profile_id, balance.net()));
+    }
+
+    let mut transfers = Vec::new();
+    for (currency, nets) in by_currency {
+        for group in zero_sum_groups(&nets) {
+            settle_group(currency, group, &mut transfers);
+        }
+    }
+    transfers
+}
+
+/// Split the balances into the most groups that each sum to zero
+fn zero_sum_groups(nets: &[(Uuid, i64)]) -> Vec<Vec<(Uuid, i64)>> {
+    let n = nets.len();
+    if n == 0 {
+        return Vec::new();
+    }
+    if n > MAX_EXACT_PROFILES {
+        return vec![nets.to_vec()];
+    }
+
+    // best[mask]: the most zero-sum groups the profiles in mask can be split into, when
+    // adding them one by one and closing a group whenever the sum so far is zero
+    let full = (1usize << n) - 1;
+    let mut sums = vec![0i64; full + 1];
+    let mut best = vec![0u32; full + 1];
+    for mask in 1..=full {
+        let lowest = mask.trailing_zeros() as usize;
+        sums[mask] = sums[mask & (mask - 1)] + nets[lowest].1;
+        let most = (0..n)
+            .filter(|i| mask & (1 << i) != 0)
+            .map(|i| best[mask ^ (1 << i)])
+            .max()
+            .unwrap_or(0);
+        best[mask] = most + u32::from(sums[mask] == 0);
+    }
+
+    // Take profiles off in an order that achieves best[full]; added back in reverse,
+    // the sum returns to zero at the end of each group
+    let mut order = Vec::with_capacity(n);
+    let mut mask = full;
+    while mask != 0 {
+        let closes = u32::from(sums[mask] == 0);
+        let Some(i) = (0..n)
+            .filter(|i| mask & (1 << i) != 0)
+            .find(|i| best[mask ^ (1 << i)] + closes == best[mask])
+        else {
+            break;
+        };
+        order.push(i);
+        mask ^= 1 << i;
+    }
+
+    let mut groups = Vec::new();
+    let mut group = Vec::new();
+    let mut sum = 0;
+    for i in order.into_iter().rev() {
+        group.push(nets[i]);
+        sum += nets[i].1;
+        if sum == 0 {
+            groups.push(std::mem::take(&mut group));
+        }
+    }
+    if !group.is_empty() {
+        groups.push(group);
+    }
+    groups
+}
+
+/// Pay the largest creditor from the largest debtor until everyone is settled; each
+/// transfer settles at least one of them
+fn settle_group(currency: &str, group: Vec<(Uuid, i64)>, transfers: &mut Vec<Transfer>) {
+    let (mut creditors, mut debtors): (Vec<_>, Vec<_>) =
+        group.into_iter().partition(|(_, net)| *net > 0);
+    for (_, net) in &mut debtors {
+        *net = -*net;
+    }
+
+    while let (Some(c), Some(d)) = (largest(&creditors), largest(&debtors)) {
+        let amount = creditors[c].1.min(debtors[d].1);
+        transfers.push(Transfer {
+            from: debtors[d].0,
+            to: creditors[c].0,
+            currency: currency.to_owned(),
+            amount,
+        });
+        creditors[c].1 -= amount;
+        debtors[d].1 -= amount;
+        creditors.retain(|(_, net)| *net > 0);
+        debtors.retain(|(_, net)| *net > 0);
+    }
+}
+
+/// Index of the largest amount, the lowest profile id on ties
+fn largest(amounts: &[(Uuid, i64)]) -> Option<usize> {
+    (0..amounts.len()).max_by(|&a, &b| {
+        amounts[a]
+            .1
+            .cmp(&amounts[b].1)
+            .then(amounts[b].0.cmp(&amounts[a].0))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(n: u128) -> Uuid {
+        Uuid::from_u128(n)
+    }
+
+    fn balances(nets: &[i64]) -> Vec<Balance> {
+        nets.iter()
+            .enumerate()
+            .map(|(i, net)| Balance {
+                profile_id: id(i as u128 + 1),
+                currency: "EUR".to_owned(),
+                paid: net.max(&0).to_owned(),
+                owed: (-net).max(0),
+            })
+            .collect()
+    }
+
+    /// The transfers bring every balance to zero
+    fn assert_settles(balances: &[Balance], transfers: &[Transfer]) {
+        let mut nets: BTreeMap<Uuid, i64> =
+            balances.iter().map(|b| (b.profile_id, b.net())).collect();
+        for transfer in transfers {
+            assert!(transfer.amount > 0);
+            *nets.get_mut(&transfer.from).unwrap() += transfer.amount;
+            *nets.get_mut(&transfer.to).unwrap() -= transfer.amount;
+        }
+        assert!(nets.values().all(|net| *net == 0), "{nets:?}");
+    }
+
+    #[test]
+    fn transfers_are_minimal() {
+        // Paying largest first would take 5 transfers; splitting into {4, -4} and
+        // {1, 5, -3, -3} takes 4
+        let balances = balances(&[1, 4, 5, -3, -3, -4]);
+        let transfers = transfers(&balances);
+        assert_settles(&balances, &transfers);
+        assert_eq!(transfers.len(), 4);
+    }
+
+    #[test]
+    fn settled_profiles_need_no_transfers() {
+        assert!(transfers(&balances(&[0, 0])).is_empty());
+
+        let balances = balances(&[10, 0, -10]);
+        let transfers = transfers(&balances);
+        assert_eq!(transfers.len(), 1);
+        assert_eq!((transfers[0].from, transfers[0].to), (id(3), id(1)));
+        assert_eq!(transfers[0].amount, 10);
+    }
+
+    #[test]
+    fn currencies_are_settled_separately() {
+        let mut balances = balances(&[5, -5]);
+        balances.extend([
+            Balance {
+                profile_id: id(1),
+                currency: "JPY".to_owned(),
+                paid: 0,
+                owed: 700,
+            },
+            Balance {
+                profile_id: id(2),
+                currency: "JPY".to_owned(),
+                paid: 700,
+                owed: 0,
+            },
+        ]);
+        let transfers = transfers(&balances);
+        assert_eq!(transfers.len(), 2);
+        assert_eq!(transfers[0].currency, "EUR");
+        assert_eq!((transfers[1].from, transfers[1].to), (id(1), id(2)));
+        assert_eq!(transfers[1].amount, 700);
+    }
+
+    #[test]
+    fn many_profiles_fall_back_to_one_greedy_group() {
+        // Eight pairs that cancel out, and one more profile than is searched exactly
+        let mut nets: Vec<i64> = (1..=8).flat_map(|n| [n, -n]).collect();
+        nets.push(0);
+        nets.extend([3, -3]);
+        nets.retain(|net| *net != 0);
+        assert!(nets.len() > MAX_EXACT_PROFILES);
+
+        let with_ids: Vec<(Uuid, i64)> = nets
+            .iter()
+            .enumerate()
+            .map(|(i, net)| (id(i as u128 + 1), *net))
+            .collect();
+        assert_eq!(zero_sum_groups(&with_ids).len(), 1);
+
+        let balances = balances(&nets);
+        let transfers = transfers(&balances);
+        assert_settles(&balances, &transfers);
+        assert!(transfers.len() < nets.len());
+    }
+
+    #[test]
+    fn groups_are_searched_exactly_up_to_the_limit() {
+        let nets: Vec<(Uuid, i64)> = (1..=8)
+            .flat_map(|n| [n, -n])
+            .enumerate()
+            .map(|(i, net)| (id(i as u128 + 1), net))
+            .collect();
+        assert_eq!(nets.len(), MAX_EXACT_PROFILES);
+        assert_eq!(zero_sum_groups(&nets).len(), 8);
+    }
+}
@@ -0,0 +1,195 @@
+use uuid::Uuid;
+
+/// A share as requested; see `ExpenseSplit` in expense.proto
+pub struct Requested {
+    pub profile_id: Uuid,
+    pub amount: i64,
+    pub weight: u32,
+}
+
+/// Split `total` as requested: by the given amounts if any is set, else by weight (0
+/// counting as 1). Shares come back in the requested order.
+pub fn split(total: i64, requested: &[Requested]) -> Result<Vec<(Uuid, i64)>, String> {
+    if requested.is_empty() {
+        return Err("must name at least one participant".to_string());
+    }
+    for (i, share) in requested.iter().enumerate() {
+        if requested[..i]
+            .iter()
+            .any(|other| other.profile_id == share.profile_id)
+        {
+            return Err(format!("names {} more than once", share.profile_id));
+        }
+    }
+
+    if requested.iter().all(|share| share.amount == 0) {
+        let weights: Vec<(Uuid, i128)> = requested
+            .iter()
+            .map(|share| (share.profile_id, i128::from(share.weight.max(1))))
+            .collect();
+        return Ok(by_weights(total, &weights));
+    }
+
+    if requested.iter().any(|share| share.amount < 0) {
+        return Err("amounts must not be negative".to_string());
+    }
+    let sum: i128 = requested.iter().map(|share| i128::from(share.amount)).sum();
+    if sum != i128::from(total) {
+        return Err(format!("amounts add up to {sum} instead of {total}"));
+    }
+    Ok(requested
+        .iter()
+        .map(|share| (share.profile_id, share.amount))
+        .collect())
+}
+
+/// Split `total` in proportion to the current shares, e.g. after the amount changed. If
+/// every share is 0, it is split equally.
+pub fn scale(total: i64, shares: &[(Uuid, i64)]) -> Vec<(Uuid, i64)> {
+    let weights: Vec<(Uuid, i128)> = if shares.iter().all(|(_, amount)| *amount == 0) {
+        shares.iter().map(|(id, _)| (*id, 1)).collect()
+    } else {
+        shares
+            .iter()
+            .map(|(id, amount)| (*id, i128::from(*amount)))
+            .collect()
+    };
+    by_weights(total, &weights)
+}
+
+/// Each share rounded down, with the rest handed out a unit at a time from the first
+fn by_weights(total: i64, weights: &[(Uuid, i128)]) -> Vec<(Uuid, i64)> {
+    let sum: i128 = weights.iter().map(|(_, weight)| weight).sum();
+    if sum == 0 {
+        return Vec::new();
+    }
+    let mut shares: Vec<(Uuid, i64)> = weights
+        .iter()
+        .map(|(id, weight)| (*id, (i128::from(total) * weight / sum) as i64))
+        .collect();
+    let mut rest = total - shares.iter().map(|(_, amount)| amount).sum::<i64>();
+    for ((_, amount), (_, weight)) in shares.iter_mut().zip(weights) {
+        if rest == 0 {
+            break;
+        }
+        if *weight > 0 {
+            *amount += 1;
+            rest -= 1;
+        }
+    }
+    shares
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(n: u128) -> Uuid {
+        Uuid::from_u128(n)
+    }
+
+    fn by_weight(weights: &[u32]) -> Vec<Requested> {
+        weights
+            .iter()
+            .enumerate()
+            .map(|(i, weight)| Requested {
+                profile_id: id(i as u128 + 1),
+                amount: 0,
+                weight: *weight,
+            })
+            .collect()
+    }
+
+    fn by_amount(amounts: &[i64]) -> Vec<Requested> {
+        amounts
+            .iter()
+            .enumerate()
+            .map(|(i, amount)| Requested {
+                profile_id: id(i as u128 + 1),
+                amount: *amount,
+                weight: 0,
+            })
+            .collect()
+    }
+
+    fn amounts(shares: &[(Uuid, i64)]) -> Vec<i64> {
+        shares.iter().map(|(_, amount)| *amount).collect()
+    }
+
+    #[test]
+    fn remainder_goes_to_the_first_shares() {
+        assert_eq!(
+            amounts(&split(100, &by_weight(&[0, 0, 0])).unwrap()),
+            [34, 33, 33]
+        );
+        assert_eq!(
+            amounts(&split(101, &by_weight(&[1, 1, 1])).unwrap()),
+            [34, 34, 33]
+        );
+        assert_eq!(amounts(&split(10, &by_weight(&[1, 2])).unwrap()), [4, 6]);
+    }
+
+    #[test]
+    fn shares_always_add_up_to_the_total() {
+        let weightings: [&[u32]; 5] = [
+            &[1],
+            &[1, 1, 1],
+            &[3, 1, 0],
+            &[7, 11, 13, 17],
+            &[u32::MAX, 1],
+        ];
+        for weights in weightings {
+            for total in (0..500).chain([i64::MAX / 2, i64::MAX]) {
+                let shares = split(total, &by_weight(weights)).unwrap();
+                assert_eq!(shares.len(), weights.len());
+                assert_eq!(
+                    shares.iter().map(|(_, a)| i128::from(*a)).sum::<i128>(),
+                    i128::from(total)
+                );
+                assert!(shares.iter().all(|(_, amount)| *amount >= 0));
+            }
+        }
+    }
+
+    #[test]
+    fn amounts_are_taken_as_given() {
+        let shares = split(100, &by_amount(&[70, 0, 30])).unwrap();
+        assert_eq!(amounts(&shares), [70, 0, 30]);
+        assert_eq!(shares[2].0, id(3));
+    }
+
+    #[test]
+    fn rejects_invalid_splits() {
+        assert!(split(100, &[]).is_err());
+
+        let mut duplicate = by_weight(&[1, 1]);
+        duplicate[1].profile_id = duplicate[0].profile_id;
+        assert!(
+            split(100, &duplicate)
+                .unwrap_err()
+                .contains("more than once")
+        );
+
+        assert!(
+            split(100, &by_amount(&[-5, 105]))
+                .unwrap_err()
+                .contains("negative")
+        );
+        assert!(
+            split(100, &by_amount(&[50, 40]))
+                .unwrap_err()
+                .contains("instead of 100")
+        );
+    }
+
+    #[test]
+    fn scaling_keeps_proportions_and_the_total() {
+        let shares = [(id(1), 30), (id(2), 10)];
+        assert_eq!(amounts(&scale(80, &shares)), [60, 20]);
+        assert_eq!(amounts(&scale(7, &shares)), [6, 1]);
+
+        // Zero shares stay zero, unless all are zero
+        assert_eq!(amounts(&scale(5, &[(id(1), 0), (id(2), 3)])), [0, 5]);
+        assert_eq!(amounts(&scale(5, &[(id(1), 0), (id(2), 0)])), [3, 2]);
+    }
+}
@@ -1,31 +1,56 @@
 use async_stream::stream;
+use auth::keys::{JwtConfiguration, JwtKeys};
+use auth::mfa::{MfaConfiguration, MfaKeys};
 use auth::service::*; // Import auth service handlers
-use axum::Router;
+use axum::{Router, routing::get};
 use axum_connect::{futures::Stream, prelude::*};
 use bot::service::*; // Import bot service handlers
 use error::Error;
+use expense::service::*; // Import expense service handlers
 use proto::auth::*; // Import auth proto
 use proto::bot::*; // Import bot proto
+use proto::expense::*; // Import expense proto
 use proto::hello::*;
+use proto::trip::*; // Import trip proto
 use sea_orm::{Database, DatabaseConnection};
 use serde::Deserialize;
+use std::net::SocketAddr;
+use std::sync::Arc;
 use tower_http::cors::CorsLayer;
+use trip::itinerary::{ItineraryConfiguration, StraightLineRouter};
+use trip::service::*; // Import trip service handlers
 
 // Take a peak at error.rs to see how errors work in axum-connect.
 mod auth;
 mod bot;
 mod error; // Register auth module
+mod expense;
+mod profile;
+mod quota;
+mod trip;
 
 #[derive(Clone)]
 struct AppState {
     conn: DatabaseConnection,
-    jwt_secret: String,
+    jwt_keys: Arc<JwtKeys>,
+    mfa_keys: Arc<MfaKeys>,
+    trust_proxy_headers: bool,
+    rich_text_hub: Arc<trip::rich_text::Hub>,
+    presence_hub: Arc<trip::presence::Hub>,
+    routing_provider: Arc<dyn trip::itinerary::RoutingProvider>,
+    mailer: Arc<dyn auth::mail::Mailer>,
 }
 
 #[derive(Deserialize, Debug)]
 struct AppConfiguration {
     database_url: String,
-    jwt_secret: String,
+    jwt: JwtConfiguration,
+    mfa: MfaConfiguration,
+    /// Take the client IP from `X-Forwarded-For` (only enable behind a reverse proxy)
+    #[serde(default)]
+    trust_proxy_headers: bool,
+    #[serde(default)]
+    itinerary: ItineraryConfiguration,
 }
 
 mod proto {
@@ -39,6 +64,12 @@ mod proto {
     pub mod bot {
         include!(concat!(env!("OUT_DIR"), "/bot.rs"));
     }
+    pub mod trip {
+        include!(concat!(env!("OUT_DIR"), "/trip.rs"));
+    }
+    pub mod expense {
+        include!(concat!(env!("OUT_DIR"), "/expense.rs"));
+    }
 }
 
 #[tokio::main]
@@ -51,9 +82,27 @@ async fn main() {
         .await
         .expect("Database connection failed");
 
+    let jwt_keys = JwtKeys::from_configuration(&config.jwt).expect("Failed to load JWT keys");
+    let mfa_keys = MfaKeys::from_configuration(&config.mfa).expect("Failed to load MFA keys");
+    let routing_provider =
+        StraightLineRouter::new(config.itinerary.clone()).expect("Invalid itinerary configuration");
+
+    // Hard-delete realms whose deletion grace period has passed
+    tokio::spawn(auth::realm::run_purge(conn.clone()));
+
+    // Drop trip presence sessions that stopped heartbeating
+    let presence_hub = Arc::new(trip::presence::Hub::default());
+    tokio::spawn(trip::presence::run_expiry(presence_hub.clone()));
+
     let state = AppState {
         conn,
-        jwt_secret: config.jwt_secret,
+        jwt_keys: Arc::new(jwt_keys),
+        mfa_keys: Arc::new(mfa_keys),
+        trust_proxy_headers: config.trust_proxy_headers,
+        rich_text_hub: Arc::default(),
+        presence_hub,
+        routing_provider: Arc::new(routing_provider),
+        mailer: Arc::new(auth::mail::ConsoleMailer),
     };
 
     // Build our application with a route. Note the `rpc` method which was added by `axum-connect`.
@@ -70,18 +119,99 @@ async fn main() {
         .rpc(AuthService::me(me))
         .rpc(AuthService::list_realms(list_realms))
         .rpc(AuthService::create_realm(create_realm))
+        .rpc(AuthService::verify_mfa(verify_mfa))
+        .rpc(AuthService::begin_totp_enrollment(begin_totp_enrollment))
+        .rpc(AuthService::confirm_totp_enrollment(
+            confirm_totp_enrollment,
+        ))
+        .rpc(AuthService::disable_totp(disable_totp))
+        .rpc(AuthService::regenerate_recovery_codes(
+            regenerate_recovery_codes,
+        ))
+        .rpc(AuthService::set_realm_mfa_policy(set_realm_mfa_policy))
+        .rpc(AuthService::update_realm(update_realm))
+        .rpc(AuthService::deactivate_realm(deactivate_realm))
+        .rpc(AuthService::reactivate_realm(reactivate_realm))
+        .rpc(AuthService::delete_realm(delete_realm))
+        .rpc(AuthService::restore_realm(restore_realm))
+        .rpc(AuthService::transfer_realm(transfer_realm))
+        .rpc(AuthService::get_realm_usage(get_realm_usage))
+        .rpc(AuthService::create_api_token(create_api_token))
+        .rpc(AuthService::list_api_tokens(list_api_tokens))
+        .rpc(AuthService::revoke_api_token(revoke_api_token))
+        .rpc(AuthService::update_account(update_account))
+        .rpc(AuthService::confirm_email_change(confirm_email_change))
+        .rpc(AuthService::change_password(change_password))
+        .rpc(AuthService::deactivate_account(deactivate_account))
+        .rpc(AuthService::delete_account(delete_account))
+        .rpc(AuthService::export_my_data(export_my_data))
+        .rpc(AuthService::get_data_export(get_data_export))
         // Bot Service
         .rpc(BotService::create_bot(create_bot))
         .rpc(BotService::update_bot(update_bot))
         .rpc(BotService::delete_bot(delete_bot))
+        .rpc(BotService::report_ai_usage(report_ai_usage))
+        // Trip Service
+        .rpc(TripService::get_my_profile(get_my_profile))
+        .rpc(TripService::merge_profiles(merge_profiles))
+        .rpc(TripService::add_participant(add_participant))
+        .rpc(TripService::remove_participant(remove_participant))
+        .rpc(TripService::change_participant_role(
+            change_participant_role,
+        ))
+        .rpc(TripService::list_participants(list_participants))
+        .rpc(TripService::update_trip(update_trip))
+        .rpc(TripService::update_trip_card(update_trip_card))
+        .rpc(TripService::clone_trip(clone_trip))
+        .rpc(TripService::save_trip_as_template(save_trip_as_template))
+        .rpc(TripService::list_trip_templates(list_trip_templates))
+        .rpc(TripService::create_trip_from_template(
+            create_trip_from_template,
+        ))
+        .rpc(TripService::delete_trip_template(delete_trip_template))
+        .rpc(TripService::search_cards_nearby(search_cards_nearby))
+        .rpc(TripService::search_cards_in_bounds(search_cards_in_bounds))
+        .rpc(TripService::validate_itinerary(validate_itinerary))
+        .rpc(TripService::optimize_itinerary(optimize_itinerary))
+        .rpc(TripService::apply_itinerary_plan(apply_itinerary_plan))
+        .rpc(TripService::export_trip_ics(export_trip_ics))
+        .rpc(TripService::import_trip_ics(import_trip_ics))
+        .rpc(TripService::create_share_link(create_share_link))
+        .rpc(TripService::list_share_links(list_share_links))
+        .rpc(TripService::revoke_share_link(revoke_share_link))
+        .rpc(TripService::get_shared_trip(get_shared_trip))
+        .rpc(TripService::list_trip_activity(list_trip_activity))
+        .rpc(TripService::undo_activity(undo_activity))
+        .rpc(TripService::get_rich_text(get_rich_text))
+        .rpc(TripService::push_rich_text_update(push_rich_text_update))
+        .rpc(TripService::subscribe_rich_text(subscribe_rich_text))
+        .rpc(TripService::update_presence(update_presence))
+        .rpc(TripService::leave_presence(leave_presence))
+        .rpc(TripService::subscribe_presence(subscribe_presence))
+        // Expense Service
+        .rpc(ExpenseService::create_expense(create_expense))
+        .rpc(ExpenseService::update_expense(update_expense))
+        .rpc(ExpenseService::delete_expense(delete_expense))
+        .rpc(ExpenseService::list_expenses(list_expenses))
+        .rpc(ExpenseService::set_trip_budget(set_trip_budget))
+        .rpc(ExpenseService::get_trip_budget(get_trip_budget))
+        .rpc(ExpenseService::get_settlement(get_settlement))
+        // Public keys for verifying access tokens (PostGraphile, other services)
+        .route("/.well-known/jwks.json", get(jwks))
+        .route("/exports/{token}", get(download_data_export))
+        .route("/trips/{id}/calendar.ics", get(trip_calendar_feed))
         .with_state(state);
 
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3030").await.unwrap();
     println!("listening on http://{:?}", listener.local_addr().unwrap());
-    axum::serve(listener, app.layer(CorsLayer::very_permissive()))
-        .with_graceful_shutdown(shutdown_signal())
-        .await
-        .unwrap();
+    axum::serve(
+        listener,
+        app.layer(CorsLayer::very_permissive())
+            .into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown_signal())
+    .await
+    .unwrap();
 }
 
 /// Handle graceful shutdown signals (SIGTERM, SIGINT, etc.)
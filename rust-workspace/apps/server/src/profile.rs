@@ -0,0 +1,385 @@
+use crate::error::Error;
+use crate::trip::{activity, participant};
+use chrono::Utc;
+use sea_orm::sea_query::Expr;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, ConnectionTrait, DbErr, EntityTrait, ExprTrait, QueryFilter, Set,
+};
+use std::collections::HashSet;
+use uuid::Uuid;
+use workspace_entity::{
+    account_realm_roles, accounts, activity_log, chat_participants, chats, profiles,
+    trip_card_rich_text, trip_card_rich_text_updates, trip_card_votes, trip_cards,
+    trip_expense_shares, trip_expenses, trip_participants, trip_share_links, trips,
+};
+
+/// The account's profile in a realm
+pub async fn find_for_account<C: ConnectionTrait>(
+    db: &C,
+    realm_id: Uuid,
+    account_id: Uuid,
+) -> Result<Option<profiles::Model>, DbErr> {
+    profiles::Entity::find()
+        .filter(profiles::COLUMN.realm_id.eq(realm_id))
+        .filter(profiles::Column::AccountId.eq(account_id))
+        .one(db)
+        .await
+}
+
+/// The account's profile in a realm. If the account's email is verified, an unlinked
+/// profile with that email (e.g. one created before the account joined) is claimed;
+/// otherwise a profile is created from the account, and an admin can merge the two
+/// with `merge_profiles`. Callers must have checked that the account is a member of
+/// the realm.
+pub async fn ensure_for_account<C: ConnectionTrait>(
+    db: &C,
+    realm_id: Uuid,
+    account: &accounts::Model,
+) -> Result<profiles::Model, DbErr> {
+    if let Some(profile) = find_for_account(db, realm_id, account.id).await? {
+        return Ok(profile);
+    }
+
+    let unlinked = if account.email_verified {
+        profiles::Entity::find()
+            .filter(profiles::COLUMN.realm_id.eq(realm_id))
+            .filter(profiles::COLUMN.email.eq(&account.email))
+            .filter(profiles::Column::AccountId.is_null())
+            .one(db)
+            .await?
+    } else {
+        None
+    };
+    if let Some(profile) = unlinked {
+        let mut active: profiles::ActiveModel = profile.into();
+        active.account_id = Set(Some(account.id));
+        return active.update(db).await;
+    }
+
+    profiles::ActiveModel {
+        id: Set(Uuid::now_v7()),
+        realm_id: Set(realm_id),
+        username: Set(account.username.clone()),
+        email: Set(account.email.clone()),
+        phone: Set(String::new()),
+        created_at: Set(Utc::now().into()),
+        account_id: Set(Some(account.id)),
+        ..Default::default()
+    }
+    .insert(db)
+    .await
+}
+
+/// Make sure the account has a profile in every realm it is a member of
+pub async fn ensure_for_member_realms<C: ConnectionTrait>(
+    db: &C,
+    account: &accounts::Model,
+) -> Result<(), DbErr> {
+    let member_realms: HashSet<Uuid> = account_realm_roles::Entity::find()
+        .filter(account_realm_roles::COLUMN.account_id.eq(account.id))
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|r| r.realm_id)
+        .collect();
+    let linked: HashSet<Uuid> = profiles::Entity::find()
+        .filter(profiles::Column::AccountId.eq(account.id))
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|p| p.realm_id)
+        .collect();
+
+    for realm_id in member_realms.difference(&linked) {
+        ensure_for_account(db, *realm_id, account).await?;
+    }
+
+    Ok(())
+}
+
+/// Rank of a trip role when two participations are merged; the higher one is kept
+fn trip_role_rank(role: &str) -> u8 {
+    match role {
+        participant::ROLE_OWNER => 2,
+        participant::ROLE_EDITOR => 1,
+        _ => 0,
+    }
+}
+
+/// Merge `source` into `target` (same realm): everything referring to `source` is moved to
+/// `target`, and `source` is deleted. Where both took part in the same trip or chat, the higher
+/// role is kept; where both voted on the same card, `target`'s vote is kept; where both share an
+/// expense, their shares are added up. Fields `target` lacks (phone, channel login, account,
+/// metadata keys) are taken from `source`. Moved trip participations are logged as changes by
+/// `actor`, moved votes by the vote trigger. Run it in a transaction.
+pub async fn merge<C: ConnectionTrait>(
+    db: &C,
+    source: profiles::Model,
+    target: profiles::Model,
+    actor: Option<Uuid>,
+) -> Result<profiles::Model, Error> {
+    // Trip participations
+    let target_trips = trip_participants::Entity::find()
+        .filter(trip_participants::COLUMN.profile_id.eq(target.id))
+        .all(db)
+        .await?;
+    for participation in trip_participants::Entity::find()
+        .filter(trip_participants::COLUMN.profile_id.eq(source.id))
+        .all(db)
+        .await?
+    {
+        let trip_id = participation.trip_id;
+        match target_trips.iter().find(|t| t.trip_id == trip_id) {
+            Some(existing) => {
+                if trip_role_rank(&participation.role) > trip_role_rank(&existing.role) {
+                    let mut active: trip_participants::ActiveModel = existing.clone().into();
+                    active.role = Set(participation.role.clone());
+                    let updated = active.update(db).await?;
+                    activity::record_update(
+                        db,
+                        trip_id,
+                        actor,
+                        activity::ENTITY_TRIP_PARTICIPANT,
+                        target.id,
+                        existing,
+                        &updated,
+                    )
+                    .await?;
+                }
+                trip_participants::Entity::delete_by_id((trip_id, source.id))
+                    .exec(db)
+                    .await?;
+            }
+            None => {
+                trip_participants::Entity::update_many()
+                    .col_expr(trip_participants::Column::ProfileId, Expr::value(target.id))
+                    .filter(trip_participants::COLUMN.trip_id.eq(trip_id))
+                    .filter(trip_participants::COLUMN.profile_id.eq(source.id))
+                    .exec(db)
+                    .await?;
+                let moved = trip_participants::Model {
+                    profile_id: target.id,
+                    ..participation.clone()
+                };
+                activity::record(
+                    db,
+                    trip_id,
+                    actor,
+                    activity::ENTITY_TRIP_PARTICIPANT,
+                    target.id,
+                    activity::ACTION_CREATE,
+                    None,
+                    Some(activity::snapshot(&moved)?),
+                )
+                .await?;
+            }
+        }
+        activity::record(
+            db,
+            trip_id,
+            actor,
+            activity::ENTITY_TRIP_PARTICIPANT,
+            source.id,
+            activity::ACTION_DELETE,
+            Some(activity::snapshot(&participation)?),
+            None,
+        )
+        .await?;
+    }
+
+    // Chat participations
+    let target_chats = chat_participants::Entity::find()
+        .filter(chat_participants::COLUMN.profile_id.eq(target.id))
+        .all(db)
+        .await?;
+    for participation in chat_participants::Entity::find()
+        .filter(chat_participants::COLUMN.profile_id.eq(source.id))
+        .all(db)
+        .await?
+    {
+        match target_chats
+            .iter()
+            .find(|t| t.chat_id == participation.chat_id)
+        {
+            Some(existing) => {
+                if participation.role == participant::CHAT_ROLE_OWNER
+                    && existing.role != participant::CHAT_ROLE_OWNER
+                {
+                    let mut active: chat_participants::ActiveModel = existing.clone().into();
+                    active.role = Set(participation.role.clone());
+                    active.update(db).await?;
+                }
+                chat_participants::Entity::delete_by_id((participation.chat_id, source.id))
+                    .exec(db)
+                    .await?;
+            }
+            None => {
+                chat_participants::Entity::update_many()
+                    .col_expr(chat_participants::Column::ProfileId, Expr::value(target.id))
+                    .filter(chat_participants::COLUMN.chat_id.eq(participation.chat_id))
+                    .filter(chat_participants::COLUMN.profile_id.eq(source.id))
+                    .exec(db)
+                    .await?;
+            }
+        }
+    }
+
+    // Card votes; a duplicate vote is dropped and no longer counted
+    let target_votes: HashSet<Uuid> = trip_card_votes::Entity::find()
+        .filter(trip_card_votes::COLUMN.profile_id.eq(target.id))
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|v| v.trip_card_id)
+        .collect();
+    for vote in trip_card_votes::Entity::find()
+        .filter(trip_card_votes::COLUMN.profile_id.eq(source.id))
+        .all(db)
+        .await?
+    {
+        if target_votes.contains(&vote.trip_card_id) {
+            trip_card_votes::Entity::delete_by_id((vote.trip_card_id, source.id))
+                .exec(db)
+                .await?;
+            trip_cards::Entity::update_many()
+                .col_expr(
+                    trip_cards::Column::VoteCount,
+                    Expr::col(trip_cards::Column::VoteCount).sub(1),
+                )
+                .filter(trip_cards::COLUMN.id.eq(vote.trip_card_id))
+                .exec(db)
+                .await?;
+        } else {
+            trip_card_votes::Entity::update_many()
+                .col_expr(trip_card_votes::Column::ProfileId, Expr::value(target.id))
+                .filter(trip_card_votes::COLUMN.trip_card_id.eq(vote.trip_card_id))
+                .filter(trip_card_votes::COLUMN.profile_id.eq(source.id))
+                .exec(db)
+                .await?;
+        }
+    }
+
+    // Expense shares; where both share an expense, the shares are added up
+    let target_shares: HashSet<Uuid> = trip_expense_shares::Entity::find()
+        .filter(trip_expense_shares::COLUMN.profile_id.eq(target.id))
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|s| s.expense_id)
+        .collect();
+    for share in trip_expense_shares::Entity::find()
+        .filter(trip_expense_shares::COLUMN.profile_id.eq(source.id))
+        .all(db)
+        .await?
+    {
+        if target_shares.contains(&share.expense_id) {
+            trip_expense_shares::Entity::update_many()
+                .col_expr(
+                    trip_expense_shares::Column::Amount,
+                    Expr::col(trip_expense_shares::Column::Amount).add(share.amount),
+                )
+                .filter(trip_expense_shares::COLUMN.expense_id.eq(share.expense_id))
+                .filter(trip_expense_shares::COLUMN.profile_id.eq(target.id))
+                .exec(db)
+                .await?;
+            trip_expense_shares::Entity::delete_by_id((share.expense_id, source.id))
+                .exec(db)
+                .await?;
+        } else {
+            trip_expense_shares::Entity::update_many()
+                .col_expr(
+                    trip_expense_shares::Column::ProfileId,
+                    Expr::value(target.id),
+                )
+                .filter(trip_expense_shares::COLUMN.expense_id.eq(share.expense_id))
+                .filter(trip_expense_shares::COLUMN.profile_id.eq(source.id))
+                .exec(db)
+                .await?;
+        }
+    }
+    trip_expenses::Entity::update_many()
+        .col_expr(trip_expenses::Column::PaidBy, Expr::value(target.id))
+        .filter(trip_expenses::COLUMN.paid_by.eq(source.id))
+        .exec(db)
+        .await?;
+
+    // Authorship
+    trips::Entity::update_many()
+        .col_expr(trips::Column::CreatedBy, Expr::value(target.id))
+        .filter(trips::COLUMN.created_by.eq(source.id))
+        .exec(db)
+        .await?;
+    trip_cards::Entity::update_many()
+        .col_expr(trip_cards::Column::CreatedBy, Expr::value(target.id))
+        .filter(trip_cards::COLUMN.created_by.eq(source.id))
+        .exec(db)
+        .await?;
+    trip_card_rich_text::Entity::update_many()
+        .col_expr(
+            trip_card_rich_text::Column::LastEditedBy,
+            Expr::value(target.id),
+        )
+        .filter(trip_card_rich_text::Column::LastEditedBy.eq(source.id))
+        .exec(db)
+        .await?;
+    trip_card_rich_text_updates::Entity::update_many()
+        .col_expr(
+            trip_card_rich_text_updates::Column::ProfileId,
+            Expr::value(target.id),
+        )
+        .filter(trip_card_rich_text_updates::Column::ProfileId.eq(source.id))
+        .exec(db)
+        .await?;
+    activity_log::Entity::update_many()
+        .col_expr(activity_log::Column::ProfileId, Expr::value(target.id))
+        .filter(activity_log::Column::ProfileId.eq(source.id))
+        .exec(db)
+        .await?;
+    chats::Entity::update_many()
+        .col_expr(chats::Column::CreatedBy, Expr::value(target.id))
+        .filter(chats::COLUMN.created_by.eq(source.id))
+        .exec(db)
+        .await?;
+    trip_expenses::Entity::update_many()
+        .col_expr(trip_expenses::Column::CreatedBy, Expr::value(target.id))
+        .filter(trip_expenses::COLUMN.created_by.eq(source.id))
+        .exec(db)
+        .await?;
+    trip_share_links::Entity::update_many()
+        .col_expr(trip_share_links::Column::CreatedBy, Expr::value(target.id))
+        .filter(trip_share_links::COLUMN.created_by.eq(source.id))
+        .exec(db)
+        .await?;
+
+    // The source goes first, so its channel login can move without hitting the
+    // (realm_id, third_provider_type, third_id) and (realm_id, account_id) unique indexes
+    profiles::Entity::delete_by_id(source.id).exec(db).await?;
+
+    let metadata = match (target.metadata.clone(), source.metadata) {
+        (Some(serde_json::Value::Object(mut target)), Some(serde_json::Value::Object(source))) => {
+            for (key, value) in source {
+                target.entry(key).or_insert(value);
+            }
+            Some(serde_json::Value::Object(target))
+        }
+        (None, source) => source,
+        (target, _) => target,
+    };
+
+    let mut active: profiles::ActiveModel = target.clone().into();
+    if target.phone.is_empty() {
+        active.phone = Set(source.phone);
+    }
+    if target.third_id.is_none() && target.third_provider_type.is_none() {
+        active.third_id = Set(source.third_id);
+        active.third_provider_type = Set(source.third_provider_type);
+    }
+    if target.channel_bridge_id.is_none() {
+        active.channel_bridge_id = Set(source.channel_bridge_id);
+    }
+    if target.account_id.is_none() {
+        active.account_id = Set(source.account_id);
+    }
+    active.metadata = Set(metadata);
+    Ok(active.update(db).await?)
+}
@@ -0,0 +1,234 @@
+use crate::error::Error;
+use chrono::{Datelike, NaiveDate, TimeZone, Utc};
+use sea_orm::sea_query::{Expr, OnConflict};
+use sea_orm::{
+    ConnectionTrait, EntityTrait, ExprTrait, PaginatorTrait, QueryFilter, QueryOrder, QuerySelect,
+    RelationTrait, Set,
+};
+use serde::Deserialize;
+use uuid::Uuid;
+use workspace_entity::{bots, realm_ai_usage, realms, trip_cards, trips};
+
+// Quota names, as used in `realms.metadata.quotas` and in errors
+pub const MAX_BOTS: &str = "max_bots";
+pub const MAX_TRIPS: &str = "max_trips";
+pub const MAX_CARDS_PER_TRIP: &str = "max_cards_per_trip";
+pub const AI_TOKENS_PER_MONTH: &str = "ai_tokens_per_month";
+
+/// Key of the quota definitions in `realms.metadata`. Quotas are set by operators,
+/// realm admins can't change them through UpdateRealm.
+pub const METADATA_KEY: &str = "quotas";
+
+/// Limits of a realm; a missing limit means unlimited
+#[derive(Debug, Default, Deserialize)]
+pub struct RealmQuotas {
+    pub max_bots: Option<u64>,
+    pub max_trips: Option<u64>,
+    pub max_cards_per_trip: Option<u64>,
+    pub ai_tokens_per_month: Option<u64>,
+}
+
+impl RealmQuotas {
+    pub fn from_metadata(metadata: Option<&serde_json::Value>) -> Self {
+        metadata
+            .and_then(|m| m.get(METADATA_KEY))
+            .and_then(|q| serde_json::from_value(q.clone()).ok())
+            .unwrap_or_default()
+    }
+}
+
+pub async fn load<C: ConnectionTrait>(db: &C, realm_id: Uuid) -> Result<RealmQuotas, Error> {
+    let realm = realms::Entity::find_by_id(realm_id)
+        .one(db)
+        .await?
+        .ok_or(Error::NotFound)?;
+
+    Ok(RealmQuotas::from_metadata(realm.metadata.as_ref()))
+}
+
+/// Like `load`, but locks the realm row until the end of the transaction, so that
+/// concurrent requests check a quota and create what it limits one after another
+async fn load_locked<C: ConnectionTrait>(db: &C, realm_id: Uuid) -> Result<RealmQuotas, Error> {
+    let realm = realms::Entity::find_by_id(realm_id)
+        .lock_exclusive()
+        .one(db)
+        .await?
+        .ok_or(Error::NotFound)?;
+
+    Ok(RealmQuotas::from_metadata(realm.metadata.as_ref()))
+}
+
+/// Fails with `ResourceExhausted` if `adding` more would take `used` over `limit`
+pub fn ensure_within(quota: &str, limit: Option<u64>, used: u64, adding: u64) -> Result<(), Error> {
+    match limit {
+        Some(limit) if used + adding > limit => Err(Error::ResourceExhausted {
+            message: format!("Realm quota {quota} ({limit}) exceeded"),
+            retry_after: None,
+        }),
+        _ => Ok(()),
+    }
+}
+
+pub async fn count_bots<C: ConnectionTrait>(db: &C, realm_id: Uuid) -> Result<u64, Error> {
+    Ok(bots::Entity::find()
+        .filter(bots::COLUMN.realm_id.eq(realm_id))
+        .count(db)
+        .await?)
+}
+
+pub async fn count_trips<C: ConnectionTrait>(db: &C, realm_id: Uuid) -> Result<u64, Error> {
+    Ok(trips::Entity::find()
+        .filter(trips::COLUMN.realm_id.eq(realm_id))
+        .count(db)
+        .await?)
+}
+
+/// Card count of the realm's largest trip, i.e. the usage of `max_cards_per_trip`
+pub async fn largest_trip_cards<C: ConnectionTrait>(db: &C, realm_id: Uuid) -> Result<u64, Error> {
+    let cards = trip_cards::Entity::find()
+        .select_only()
+        .column_as(Expr::col(trip_cards::Column::Id).count(), "cards")
+        .join(
+            sea_orm::JoinType::InnerJoin,
+            trip_cards::Relation::Trips.def(),
+        )
+        .filter(trips::COLUMN.realm_id.eq(realm_id))
+        .group_by(trip_cards::Column::TripId)
+        .order_by_desc(Expr::col(trip_cards::Column::Id).count())
+        .into_tuple::<i64>()
+        .one(db)
+        .await?;
+
+    Ok(cards.unwrap_or(0) as u64)
+}
+
+/// Check the bot quota before creating a bot in the realm. Call it in the transaction
+/// that creates the bot; the realm stays locked until it ends.
+pub async fn ensure_bot_available<C: ConnectionTrait>(db: &C, realm_id: Uuid) -> Result<(), Error> {
+    let quotas = load_locked(db, realm_id).await?;
+    if quotas.max_bots.is_none() {
+        return Ok(());
+    }
+
+    ensure_within(
+        MAX_BOTS,
+        quotas.max_bots,
+        count_bots(db, realm_id).await?,
+        1,
+    )
+}
+
+/// Check the trip quota, and the card quota for a trip of `cards` cards, before
+/// creating a trip in the realm. Call it in the transaction that creates the trip; the
+/// realm stays locked until it ends.
+pub async fn ensure_trip_available<C: ConnectionTrait>(
+    db: &C,
+    realm_id: Uuid,
+    cards: u64,
+) -> Result<(), Error> {
+    let quotas = load_locked(db, realm_id).await?;
+    ensure_within(MAX_CARDS_PER_TRIP, quotas.max_cards_per_trip, 0, cards)?;
+    if quotas.max_trips.is_none() {
+        return Ok(());
+    }
+
+    ensure_within(
+        MAX_TRIPS,
+        quotas.max_trips,
+        count_trips(db, realm_id).await?,
+        1,
+    )
+}
+
+/// Check the card quota before adding `adding` cards to a trip. Call it in the
+/// transaction that adds the cards; the realm stays locked until it ends.
+pub async fn ensure_cards_available<C: ConnectionTrait>(
+    db: &C,
+    realm_id: Uuid,
+    trip_id: Uuid,
+    adding: u64,
+) -> Result<(), Error> {
+    let quotas = load_locked(db, realm_id).await?;
+    if quotas.max_cards_per_trip.is_none() {
+        return Ok(());
+    }
+
+    let cards = trip_cards::Entity::find()
+        .filter(trip_cards::COLUMN.trip_id.eq(trip_id))
+        .count(db)
+        .await?;
+    ensure_within(MAX_CARDS_PER_TRIP, quotas.max_cards_per_trip, cards, adding)
+}
+
+/// First day of the current month (UTC), the key of `realm_ai_usage`
+fn current_period() -> NaiveDate {
+    Utc::now().date_naive().with_day(1).expect("day 1 exists")
+}
+
+/// Tokens the realm has used this month
+pub async fn ai_tokens_used<C: ConnectionTrait>(db: &C, realm_id: Uuid) -> Result<u64, Error> {
+    let usage = realm_ai_usage::Entity::find_by_id((realm_id, current_period()))
+        .one(db)
+        .await?;
+
+    Ok(usage.map_or(0, |u| u.tokens_used.max(0) as u64))
+}
+
+/// Add `tokens` to this month's AI usage. Fails with `ResourceExhausted` (retryable
+/// next month) once the monthly budget is used up; the usage that pushes the realm
+/// over the budget is still recorded, as it has already happened.
+///
+/// Returns the tokens used this month and the monthly limit.
+pub async fn record_ai_tokens<C: ConnectionTrait>(
+    db: &C,
+    realm_id: Uuid,
+    tokens: u64,
+) -> Result<(u64, Option<u64>), Error> {
+    let quotas = load(db, realm_id).await?;
+    let used = ai_tokens_used(db, realm_id).await?;
+    if let Some(limit) = quotas.ai_tokens_per_month
+        && used >= limit
+    {
+        let next_period = current_period()
+            .checked_add_months(chrono::Months::new(1))
+            .expect("next month exists");
+        let retry_after = Utc
+            .from_utc_datetime(&next_period.and_hms_opt(0, 0, 0).expect("midnight exists"))
+            .signed_duration_since(Utc::now())
+            .to_std()
+            .ok();
+        return Err(Error::ResourceExhausted {
+            message: format!("Realm quota {AI_TOKENS_PER_MONTH} ({limit}) exceeded"),
+            retry_after,
+        });
+    }
+
+    if tokens == 0 {
+        return Ok((used, quotas.ai_tokens_per_month));
+    }
+
+    let tokens =
+        i64::try_from(tokens).map_err(|_| Error::invalid_field("tokens", "is out of range"))?;
+    realm_ai_usage::Entity::insert(realm_ai_usage::ActiveModel {
+        realm_id: Set(realm_id),
+        period_start: Set(current_period()),
+        tokens_used: Set(tokens),
+        updated_at: Set(Utc::now().into()),
+    })
+    .on_conflict(
+        OnConflict::columns([
+            realm_ai_usage::Column::RealmId,
+            realm_ai_usage::Column::PeriodStart,
+        ])
+        .value(
+            realm_ai_usage::Column::TokensUsed,
+            Expr::col((realm_ai_usage::Entity, realm_ai_usage::Column::TokensUsed)).add(tokens),
+        )
+        .update_column(realm_ai_usage::Column::UpdatedAt)
+        .to_owned(),
+    )
+    .exec(db)
+    .await?;
+
+    Ok((used + tokens as u64, quotas.ai_tokens_per_month))
+}
@@ -0,0 +1,443 @@
+use crate::error::Error;
+use crate::quota;
+use chrono::Utc;
+use sea_orm::{ActiveModelTrait, ConnectionTrait, DbErr, EntityTrait, QuerySelect, Set};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+use uuid::Uuid;
+use workspace_entity::{activity_log, trip_cards, trips};
+
+pub const ENTITY_TRIP: &str = "trip";
+pub const ENTITY_TRIP_CARD: &str = "trip_card";
+pub const ENTITY_TRIP_PARTICIPANT: &str = "trip_participant";
+/// Votes are logged against their card, by a database trigger
+pub const ENTITY_TRIP_CARD_VOTE: &str = "trip_card_vote";
+
+pub const ACTION_CREATE: &str = "create";
+pub const ACTION_UPDATE: &str = "update";
+pub const ACTION_DELETE: &str = "delete";
+
+/// Bookkeeping fields that change with (nearly) every write and are left out of diffs
+const UNTRACKED_FIELDS: [&str; 4] = ["updatedAt", "version", "voteCount", "voteData"];
+
+fn to_object<M: Serialize>(model: &M) -> Result<Map<String, Value>, Error> {
+    match serde_json::to_value(model).map_err(anyhow::Error::from)? {
+        Value::Object(object) => Ok(object),
+        _ => Err(anyhow::anyhow!("model did not serialize to an object").into()),
+    }
+}
+
+/// The whole record, for creations and deletions
+pub fn snapshot<M: Serialize>(model: &M) -> Result<Value, Error> {
+    Ok(Value::Object(to_object(model)?))
+}
+
+/// The tracked fields that differ between `before` and `after`, as (before, after)
+/// objects; `None` if nothing tracked changed
+pub fn diff<M: Serialize>(before: &M, after: &M) -> Result<Option<(Value, Value)>, Error> {
+    let before = to_object(before)?;
+    let mut after = to_object(after)?;
+
+    let mut old = Map::new();
+    let mut new = Map::new();
+    for (field, value) in before {
+        if UNTRACKED_FIELDS.contains(&field.as_str()) {
+            continue;
+        }
+        let changed = after.remove(&field).filter(|v| *v != value);
+        if let Some(changed) = changed {
+            old.insert(field.clone(), value);
+            new.insert(field, changed);
+        }
+    }
+
+    if new.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some((Value::Object(old), Value::Object(new))))
+}
+
+/// Append an entry to a trip's activity log
+#[allow(clippy::too_many_arguments)]
+pub async fn record<C: ConnectionTrait>(
+    db: &C,
+    trip_id: Uuid,
+    profile_id: Option<Uuid>,
+    entity_type: &str,
+    entity_id: Uuid,
+    action: &str,
+    before: Option<Value>,
+    after: Option<Value>,
+) -> Result<activity_log::Model, DbErr> {
+    insert(
+        db,
+        trip_id,
+        profile_id,
+        entity_type,
+        entity_id,
+        action,
+        before,
+        after,
+        None,
+    )
+    .await
+}
+
+/// Card deletions and votes are logged by database triggers, as they mostly happen through
+/// GraphQL. Call this in transactions that log such changes themselves, so they aren't
+/// logged twice.
+pub async fn mark_recorded<C: ConnectionTrait>(db: &C) -> Result<(), DbErr> {
+    db.execute_unprepared("SELECT set_config('tripvota.activity_recorded', 'on', true)")
+        .await?;
+    Ok(())
+}
+
+/// Record an update of `entity`, unless no tracked field changed
+pub async fn record_update<C: ConnectionTrait, M: Serialize>(
+    db: &C,
+    trip_id: Uuid,
+    profile_id: Option<Uuid>,
+    entity_type: &str,
+    entity_id: Uuid,
+    before: &M,
+    after: &M,
+) -> Result<(), Error> {
+    if let Some((before, after)) = diff(before, after)? {
+        record(
+            db,
+            trip_id,
+            profile_id,
+            entity_type,
+            entity_id,
+            ACTION_UPDATE,
+            Some(before),
+            Some(after),
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn insert<C: ConnectionTrait>(
+    db: &C,
+    trip_id: Uuid,
+    profile_id: Option<Uuid>,
+    entity_type: &str,
+    entity_id: Uuid,
+    action: &str,
+    before: Option<Value>,
+    after: Option<Value>,
+    undoes_id: Option<Uuid>,
+) -> Result<activity_log::Model, DbErr> {
+    activity_log::ActiveModel {
+        id: Set(Uuid::now_v7()),
+        trip_id: Set(trip_id),
+        profile_id: Set(profile_id),
+        entity_type: Set(entity_type.to_owned()),
+        entity_id: Set(entity_id),
+        action: Set(action.to_owned()),
+        before: Set(before),
+        after: Set(after),
+        undoes_id: Set(undoes_id),
+        undone_at: Set(None),
+        created_at: Set(Utc::now().into()),
+    }
+    .insert(db)
+    .await
+}
+
+/// Fails unless every field of `fields` still has the recorded value, i.e. no later
+/// change touched what the activity changed
+fn ensure_unchanged<M: Serialize>(current: &M, fields: Option<&Value>) -> Result<(), Error> {
+    let current = to_object(current)?;
+    let conflicting = fields
+        .and_then(Value::as_object)
+        .into_iter()
+        .flatten()
+        .filter(|(field, _)| !UNTRACKED_FIELDS.contains(&field.as_str()))
+        .any(|(field, value)| current.get(field) != Some(value));
+    if conflicting {
+        return Err(Error::FailedPrecondition(
+            "the change conflicts with later changes".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// `current` with `fields` put back
+fn restore<M: Serialize + DeserializeOwned>(
+    current: &M,
+    fields: Option<&Value>,
+) -> Result<M, Error> {
+    let mut object = to_object(current)?;
+    for (field, value) in fields.and_then(Value::as_object).into_iter().flatten() {
+        object.insert(field.clone(), value.clone());
+    }
+    Ok(serde_json::from_value(Value::Object(object)).map_err(anyhow::Error::from)?)
+}
+
+/// Revert an activity, recording the revert as a new activity of `profile_id`. Updates
+/// are reverted only if the changed fields still hold the values they were changed to,
+/// created cards are deleted only if unchanged since, and deleted cards are re-created
+/// (without their votes). Run it in a transaction.
+pub async fn undo<C: ConnectionTrait>(
+    db: &C,
+    activity: activity_log::Model,
+    profile_id: Option<Uuid>,
+) -> Result<activity_log::Model, Error> {
+    if activity.undone_at.is_some() {
+        return Err(Error::FailedPrecondition(
+            "the change was already undone".to_string(),
+        ));
+    }
+
+    let (action, before, after) = match (activity.entity_type.as_str(), activity.action.as_str()) {
+        (ENTITY_TRIP, ACTION_UPDATE) => {
+            let trip = trips::Entity::find_by_id(activity.entity_id)
+                .lock_exclusive()
+                .one(db)
+                .await?
+                .ok_or_else(|| {
+                    Error::FailedPrecondition("the trip no longer exists".to_string())
+                })?;
+            ensure_unchanged(&trip, activity.after.as_ref())?;
+
+            let mut restored = restore(&trip, activity.before.as_ref())?;
+            restored.updated_at = Utc::now().into();
+            restored.version = trip.version + 1;
+            let active = trips::ActiveModel::from(restored).reset_all();
+            let updated = active.update(db).await?;
+
+            let (before, after) = diff(&trip, &updated)?.unzip();
+            (ACTION_UPDATE, before, after)
+        }
+        (ENTITY_TRIP_CARD, ACTION_UPDATE) => {
+            let card = trip_cards::Entity::find_by_id(activity.entity_id)
+                .lock_exclusive()
+                .one(db)
+                .await?
+                .ok_or_else(|| {
+                    Error::FailedPrecondition("the card no longer exists".to_string())
+                })?;
+            ensure_unchanged(&card, activity.after.as_ref())?;
+
+            let mut restored = restore(&card, activity.before.as_ref())?;
+            restored.updated_at = Utc::now().into();
+            restored.version = card.version + 1;
+            let active = trip_cards::ActiveModel::from(restored).reset_all();
+            let updated = active.update(db).await?;
+
+            let (before, after) = diff(&card, &updated)?.unzip();
+            (ACTION_UPDATE, before, after)
+        }
+        (ENTITY_TRIP_CARD, ACTION_CREATE) => {
+            let card = trip_cards::Entity::find_by_id(activity.entity_id)
+                .lock_exclusive()
+                .one(db)
+                .await?
+                .ok_or_else(|| {
+                    Error::FailedPrecondition("the card was already deleted".to_string())
+                })?;
+            ensure_unchanged(&card, activity.after.as_ref())?;
+
+            mark_recorded(db).await?;
+            trip_cards::Entity::delete_by_id(card.id).exec(db).await?;
+            (ACTION_DELETE, Some(snapshot(&card)?), None)
+        }
+        (ENTITY_TRIP_CARD, ACTION_DELETE) => {
+            let exists = trip_cards::Entity::find_by_id(activity.entity_id)
+                .one(db)
+                .await?
+                .is_some();
+            let deleted = activity
+                .before
+                .clone()
+                .filter(|_| !exists)
+                .ok_or_else(|| Error::FailedPrecondition("the card exists".to_string()))?;
+
+            let trip = trips::Entity::find_by_id(activity.trip_id)
+                .one(db)
+                .await?
+                .ok_or(Error::NotFound)?;
+            quota::ensure_cards_available(db, trip.realm_id, trip.id, 1).await?;
+
+            let mut card: trip_cards::Model =
+                serde_json::from_value(deleted).map_err(anyhow::Error::from)?;
+            card.vote_count = 0;
+            card.vote_data = None;
+            card.updated_at = Utc::now().into();
+            card.version += 1;
+            let active = trip_cards::ActiveModel::from(card).reset_all();
+            let created = active.insert(db).await?;
+            (ACTION_CREATE, None, Some(snapshot(&created)?))
+        }
+        (ENTITY_TRIP_CARD_VOTE, _) => {
+            return Err(Error::FailedPrecondition(
+                "votes can't be undone, only changed by voting again".to_string(),
+            ));
+        }
+        _ => {
+            return Err(Error::FailedPrecondition(format!(
+                "{} changes can't be undone",
+                activity.entity_type
+            )));
+        }
+    };
+
+    let undo = insert(
+        db,
+        activity.trip_id,
+        profile_id,
+        &activity.entity_type,
+        activity.entity_id,
+        action,
+        before,
+        after,
+        Some(activity.id),
+    )
+    .await?;
+
+    let mut active: activity_log::ActiveModel = activity.into();
+    active.undone_at = Set(Some(undo.created_at));
+    active.update(db).await?;
+
+    Ok(undo)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use workspace_entity::geo_point::GeoPoint;
+
+    fn card() -> trip_cards::Model {
+        let now = Utc::now().into();
+        trip_cards::Model {
+            id: Uuid::now_v7(),
+            trip_id: Uuid::nil(),
+            created_by: Uuid::nil(),
+            title: "Museum".to_owned(),
+            description: None,
+            category: Some("sightseeing".to_owned()),
+            start_time: None,
+            end_time: None,
+            status: "draft".to_owned(),
+            display_order: Some(1),
+            vote_count: 0,
+            vote_data: None,
+            created_at: now,
+            updated_at: now,
+            metadata: None,
+            version: 1,
+            position: None,
+        }
+    }
+
+    #[test]
+    fn diff_holds_only_changed_tracked_fields() {
+        let before = card();
+        let after = trip_cards::Model {
+            title: "Art museum".to_owned(),
+            description: Some("Closed on Mondays".to_owned()),
+            vote_count: 3,
+            version: 2,
+            ..before.clone()
+        };
+
+        let (old, new) = diff(&before, &after).unwrap().unwrap();
+        assert_eq!(old, json!({ "title": "Museum", "description": null }));
+        assert_eq!(
+            new,
+            json!({ "title": "Art museum", "description": "Closed on Mondays" })
+        );
+
+        let bookkeeping = trip_cards::Model {
+            vote_count: 5,
+            version: 3,
+            ..before.clone()
+        };
+        assert_eq!(diff(&before, &bookkeeping).unwrap(), None);
+    }
+
+    #[test]
+    fn restoring_a_diff_reverts_the_change() {
+        let before = card();
+        let after = trip_cards::Model {
+            title: "Art museum".to_owned(),
+            category: None,
+            position: GeoPoint::new(2.3376, 48.8606),
+            version: 2,
+            ..before.clone()
+        };
+        let (old, new) = diff(&before, &after).unwrap().unwrap();
+
+        let reverted = restore(&after, Some(&old)).unwrap();
+        assert_eq!(diff(&before, &reverted).unwrap(), None);
+        assert_eq!(reverted.version, after.version);
+
+        let redone = restore(&reverted, Some(&new)).unwrap();
+        assert_eq!(diff(&after, &redone).unwrap(), None);
+    }
+
+    #[test]
+    fn later_changes_block_an_undo() {
+        let before = card();
+        let after = trip_cards::Model {
+            title: "Art museum".to_owned(),
+            version: 2,
+            ..before.clone()
+        };
+        let (_, new) = diff(&before, &after).unwrap().unwrap();
+        assert!(ensure_unchanged(&after, Some(&new)).is_ok());
+
+        // Other fields and bookkeeping may change in between
+        let unrelated = trip_cards::Model {
+            status: "scheduled".to_owned(),
+            vote_count: 2,
+            version: 4,
+            ..after.clone()
+        };
+        assert!(ensure_unchanged(&unrelated, Some(&new)).is_ok());
+
+        let conflicting = trip_cards::Model {
+            title: "Louvre".to_owned(),
+            ..after.clone()
+        };
+        assert!(matches!(
+            ensure_unchanged(&conflicting, Some(&new)),
+            Err(Error::FailedPrecondition(_))
+        ));
+    }
+
+    #[test]
+    fn cards_logged_by_the_delete_trigger_can_be_restored() {
+        // As built by `log_trip_card_delete()`
+        let id = Uuid::now_v7();
+        let logged = json!({
+            "id": id,
+            "tripId": Uuid::nil(),
+            "createdBy": Uuid::nil(),
+            "title": "Museum",
+            "description": null,
+            "category": "sightseeing",
+            "startTime": "2025-12-13T10:00:00+01:00",
+            "endTime": null,
+            "status": "scheduled",
+            "displayOrder": 2,
+            "voteCount": 1,
+            "voteData": { "up": 1 },
+            "createdAt": "2025-12-01T08:30:00.123456+00:00",
+            "updatedAt": "2025-12-02T08:30:00+00:00",
+            "metadata": null,
+            "version": 3,
+            "position": { "longitude": 2.3376, "latitude": 48.8606 }
+        });
+
+        let card: trip_cards::Model = serde_json::from_value(logged).unwrap();
+        assert_eq!(card.id, id);
+        assert_eq!(card.position, GeoPoint::new(2.3376, 48.8606));
+        assert_eq!(card.display_order, Some(2));
+    }
+}
@@ -0,0 +1,178 @@
+use crate::error::Error;
+use crate::quota;
+use crate::trip::{activity, participant};
+use chrono::{Duration, NaiveDate, Utc};
+use sea_orm::{ActiveModelTrait, ColumnTrait, ConnectionTrait, EntityTrait, QueryFilter, Set};
+use std::collections::HashMap;
+use uuid::Uuid;
+use workspace_entity::{
+    chats, trip_card_rich_text, trip_card_rich_text_updates, trip_cards, trip_participants, trips,
+};
+
+/// Status of trips that are realm templates rather than actual trips. Templates have no
+/// participants and are only reachable through the template RPCs.
+pub const STATUS_TEMPLATE: &str = "template";
+/// Status of trips created from a template or as a copy
+pub const STATUS_PLANNING: &str = "planning";
+
+pub struct Options {
+    pub title: String,
+    pub status: &'static str,
+    /// Profile recorded as the creator of the copy
+    pub created_by: Uuid,
+    /// New first day; the dates of the trip and the times of its cards move by the
+    /// difference to the source's first day. `None` keeps them.
+    pub start_date: Option<NaiveDate>,
+    /// Participants of the copy with their roles
+    pub participants: Vec<(Uuid, String)>,
+}
+
+/// Copy a trip with its cards and their rich text into a new trip of the same realm. Votes
+/// are not copied and completed cards become scheduled again. The copy gets a main chat
+/// (without messages) if the source has one and the copy has participants. Run it in a
+/// transaction.
+pub async fn copy_trip<C: ConnectionTrait>(
+    db: &C,
+    source: &trips::Model,
+    options: Options,
+) -> Result<trips::Model, Error> {
+    let shift = match (options.start_date, source.start_date) {
+        (Some(new), Some(old)) => Duration::days((new - old).num_days()),
+        (Some(_), None) => {
+            return Err(Error::FailedPrecondition(
+                "the trip has no start date to move from".to_string(),
+            ));
+        }
+        (None, _) => Duration::zero(),
+    };
+
+    let cards = trip_cards::Entity::find()
+        .filter(trip_cards::COLUMN.trip_id.eq(source.id))
+        .all(db)
+        .await?;
+    quota::ensure_trip_available(db, source.realm_id, cards.len() as u64).await?;
+
+    let now = Utc::now();
+    let trip = trips::ActiveModel {
+        id: Set(Uuid::now_v7()),
+        realm_id: Set(source.realm_id),
+        created_by: Set(options.created_by),
+        title: Set(options.title),
+        description: Set(source.description.clone()),
+        destination: Set(source.destination.clone()),
+        start_date: Set(source.start_date.map(|d| d + shift)),
+        end_date: Set(source.end_date.map(|d| d + shift)),
+        status: Set(options.status.to_owned()),
+        created_at: Set(now.into()),
+        updated_at: Set(now.into()),
+        metadata: Set(source.metadata.clone()),
+        version: Set(1),
+    }
+    .insert(db)
+    .await?;
+
+    let mut card_ids = HashMap::with_capacity(cards.len());
+    for card in cards {
+        let copy = trip_cards::Model {
+            id: Uuid::now_v7(),
+            trip_id: trip.id,
+            start_time: card.start_time.map(|t| t + shift),
+            end_time: card.end_time.map(|t| t + shift),
+            status: if card.status == "completed" {
+                "scheduled".to_owned()
+            } else {
+                card.status.clone()
+            },
+            vote_count: 0,
+            vote_data: None,
+            created_at: now.into(),
+            updated_at: now.into(),
+            version: 1,
+            ..card.clone()
+        };
+        card_ids.insert(card.id, copy.id);
+        trip_cards::ActiveModel::from(copy)
+            .reset_all()
+            .insert(db)
+            .await?;
+    }
+
+    // Documents are copied with their pending updates, so the copies replay to the same state
+    let documents = trip_card_rich_text::Entity::find()
+        .filter(trip_card_rich_text::Column::TripCardId.is_in(card_ids.keys().copied()))
+        .all(db)
+        .await?;
+    let updates = trip_card_rich_text_updates::Entity::find()
+        .filter(trip_card_rich_text_updates::Column::TripCardId.is_in(card_ids.keys().copied()))
+        .all(db)
+        .await?;
+    for document in documents {
+        let copy = trip_card_rich_text::Model {
+            trip_card_id: card_ids[&document.trip_card_id],
+            created_at: now.into(),
+            updated_at: now.into(),
+            ..document
+        };
+        trip_card_rich_text::ActiveModel::from(copy)
+            .reset_all()
+            .insert(db)
+            .await?;
+    }
+    for update in updates {
+        let copy = trip_card_rich_text_updates::Model {
+            trip_card_id: card_ids[&update.trip_card_id],
+            ..update
+        };
+        trip_card_rich_text_updates::ActiveModel::from(copy)
+            .reset_all()
+            .insert(db)
+            .await?;
+    }
+
+    for (profile_id, role) in &options.participants {
+        trip_participants::ActiveModel {
+            trip_id: Set(trip.id),
+            profile_id: Set(*profile_id),
+            role: Set(role.clone()),
+            joined_at: Set(now.into()),
+        }
+        .insert(db)
+        .await?;
+    }
+
+    let main_chat = chats::Entity::find()
+        .filter(chats::Column::TripId.eq(source.id))
+        .filter(chats::COLUMN.is_main.eq(true))
+        .one(db)
+        .await?;
+    if let Some(main_chat) = main_chat.filter(|_| !options.participants.is_empty()) {
+        chats::ActiveModel {
+            id: Set(Uuid::now_v7()),
+            trip_id: Set(Some(trip.id)),
+            created_by: Set(options.created_by),
+            title: Set(main_chat.title),
+            is_main: Set(true),
+            created_at: Set(now.into()),
+            metadata: Set(None),
+        }
+        .insert(db)
+        .await?;
+        for (profile_id, role) in &options.participants {
+            participant::sync_main_chat(db, trip.id, *profile_id, role).await?;
+        }
+    }
+
+    activity::record(
+        db,
+        trip.id,
+        Some(options.created_by),
+        activity::ENTITY_TRIP,
+        trip.id,
+        activity::ACTION_CREATE,
+        None,
+        Some(activity::snapshot(&trip)?),
+    )
+    .await?;
+
+    Ok(trip)
+}
@@ -0,0 +1,97 @@
+use sea_orm::sea_query::{Expr, Order};
+use sea_orm::{
+    ColumnTrait, ConnectionTrait, DbErr, EntityTrait, QueryFilter, QueryOrder, QuerySelect,
+};
+use std::collections::HashMap;
+use uuid::Uuid;
+use workspace_entity::geo_point::GeoPoint;
+use workspace_entity::trip_cards;
+
+/// Largest search radius
+pub const MAX_RADIUS_METERS: f64 = 1_000_000.0;
+pub const DEFAULT_LIMIT: u64 = 50;
+pub const MAX_LIMIT: u64 = 200;
+
+/// Geodesic distance in meters from `point` to a card's position
+fn distance_from(point: GeoPoint) -> Expr {
+    Expr::cust_with_values(
+        "ST_Distance(trip_cards.position, ST_SetSRID(ST_MakePoint($1, $2), 4326)::geography)",
+        [point.longitude, point.latitude],
+    )
+}
+
+/// Cards of `trip_ids` matching `condition`, nearest to `origin` first, with their distance
+async fn cards_by_distance<C: ConnectionTrait>(
+    db: &C,
+    trip_ids: &[Uuid],
+    condition: Expr,
+    origin: GeoPoint,
+    limit: u64,
+) -> Result<Vec<(trip_cards::Model, f64)>, DbErr> {
+    let nearest: Vec<(Uuid, f64)> = trip_cards::Entity::find()
+        .select_only()
+        .column(trip_cards::Column::Id)
+        .expr_as(distance_from(origin), "distance")
+        .filter(trip_cards::Column::TripId.is_in(trip_ids.iter().copied()))
+        .filter(trip_cards::Column::Position.is_not_null())
+        .filter(condition)
+        .order_by(distance_from(origin), Order::Asc)
+        .limit(limit)
+        .into_tuple()
+        .all(db)
+        .await?;
+
+    let mut cards: HashMap<Uuid, trip_cards::Model> = trip_cards::Entity::find()
+        .filter(trip_cards::Column::Id.is_in(nearest.iter().map(|(id, _)| *id)))
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|card| (card.id, card))
+        .collect();
+
+    Ok(nearest
+        .into_iter()
+        .filter_map(|(id, distance)| cards.remove(&id).map(|card| (card, distance)))
+        .collect())
+}
+
+/// Cards within `radius_meters` of `center`, nearest first. Uses the GIST index on
+/// `trip_cards.position` through `ST_DWithin`.
+pub async fn cards_nearby<C: ConnectionTrait>(
+    db: &C,
+    trip_ids: &[Uuid],
+    center: GeoPoint,
+    radius_meters: f64,
+    limit: u64,
+) -> Result<Vec<(trip_cards::Model, f64)>, DbErr> {
+    let within = Expr::cust_with_values(
+        "ST_DWithin(trip_cards.position, ST_SetSRID(ST_MakePoint($1, $2), 4326)::geography, $3)",
+        [center.longitude, center.latitude, radius_meters],
+    );
+    cards_by_distance(db, trip_ids, within, center, limit).await
+}
+
+/// Cards inside the longitude/latitude rectangle from `south_west` to `north_east` (as
+/// drawn on a map, so edges follow meridians and parallels), nearest to its center first
+pub async fn cards_in_bounds<C: ConnectionTrait>(
+    db: &C,
+    trip_ids: &[Uuid],
+    south_west: GeoPoint,
+    north_east: GeoPoint,
+    limit: u64,
+) -> Result<Vec<(trip_cards::Model, f64)>, DbErr> {
+    let inside = Expr::cust_with_values(
+        "ST_Covers(ST_MakeEnvelope($1, $2, $3, $4, 4326), trip_cards.position::geometry)",
+        [
+            south_west.longitude,
+            south_west.latitude,
+            north_east.longitude,
+            north_east.latitude,
+        ],
+    );
+    let center = GeoPoint {
+        longitude: (south_west.longitude + north_east.longitude) / 2.0,
+        latitude: (south_west.latitude + north_east.latitude) / 2.0,
+    };
+    cards_by_distance(db, trip_ids, inside, center, limit).await
+}
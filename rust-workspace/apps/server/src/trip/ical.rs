@@ -0,0 +1,627 @@
+//! iCalendar (RFC 5545) rendering of a trip's scheduled cards, and parsing of events to
+//! import as cards
+
+use chrono::{DateTime, Datelike, Duration, FixedOffset, NaiveDate, NaiveDateTime, Utc};
+use uuid::Uuid;
+use workspace_entity::geo_point::GeoPoint;
+use workspace_entity::{trip_cards, trips};
+
+pub const CONTENT_TYPE: &str = "text/calendar; charset=utf-8";
+
+const PRODID: &str = "-//TripVota//Trip Calendar//EN";
+/// Domain part of event UIDs, so they stay unique next to other calendars' events
+const UID_DOMAIN: &str = "tripvota";
+/// Content lines longer than this many octets are folded
+const MAX_LINE_OCTETS: usize = 75;
+
+/// Escape a TEXT value
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ';' => escaped.push_str("\\;"),
+            ',' => escaped.push_str("\\,"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// UTC date-time form, e.g. `20250102T030405Z`
+fn date_time(time: DateTime<FixedOffset>) -> String {
+    time.with_timezone(&Utc)
+        .format("%Y%m%dT%H%M%SZ")
+        .to_string()
+}
+
+/// Append a content line, folded after every 75 octets without splitting characters
+fn push_line(ics: &mut String, line: &str) {
+    let mut octets = 0;
+    for c in line.chars() {
+        if octets + c.len_utf8() > MAX_LINE_OCTETS {
+            ics.push_str("\r\n ");
+            // The leading space counts towards the continuation line
+            octets = 1;
+        }
+        ics.push(c);
+        octets += c.len_utf8();
+    }
+    ics.push_str("\r\n");
+}
+
+/// A VCALENDAR with a VEVENT per card. Event UIDs are derived from card IDs and
+/// SEQUENCE from card versions, so subscribed calendars move events rather than
+/// duplicating them when cards change.
+pub fn render(trip: &trips::Model, cards: &[trip_cards::Model]) -> String {
+    let mut ics = String::new();
+    push_line(&mut ics, "BEGIN:VCALENDAR");
+    push_line(&mut ics, "VERSION:2.0");
+    push_line(&mut ics, &format!("PRODID:{PRODID}"));
+    push_line(&mut ics, "CALSCALE:GREGORIAN");
+    push_line(&mut ics, "METHOD:PUBLISH");
+    push_line(&mut ics, &format!("X-WR-CALNAME:{}", escape(&trip.title)));
+    if let Some(description) = &trip.description {
+        push_line(&mut ics, &format!("X-WR-CALDESC:{}", escape(description)));
+    }
+
+    for card in cards {
+        let Some(start) = card.start_time else {
+            continue;
+        };
+        push_line(&mut ics, "BEGIN:VEVENT");
+        push_line(&mut ics, &format!("UID:{}@{UID_DOMAIN}", card.id));
+        push_line(&mut ics, &format!("DTSTAMP:{}", date_time(card.updated_at)));
+        push_line(
+            &mut ics,
+            &format!("LAST-MODIFIED:{}", date_time(card.updated_at)),
+        );
+        push_line(&mut ics, &format!("SEQUENCE:{}", card.version));
+        push_line(&mut ics, &format!("DTSTART:{}", date_time(start)));
+        if let Some(end) = card.end_time.filter(|end| *end > start) {
+            push_line(&mut ics, &format!("DTEND:{}", date_time(end)));
+        }
+        push_line(&mut ics, &format!("SUMMARY:{}", escape(&card.title)));
+        if let Some(description) = &card.description {
+            push_line(&mut ics, &format!("DESCRIPTION:{}", escape(description)));
+        }
+        if let Some(category) = &card.category {
+            push_line(&mut ics, &format!("CATEGORIES:{}", escape(category)));
+        }
+        if let Some(position) = card.position {
+            push_line(
+                &mut ics,
+                &format!("GEO:{};{}", position.latitude, position.longitude),
+            );
+        }
+        push_line(&mut ics, "STATUS:CONFIRMED");
+        push_line(&mut ics, "END:VEVENT");
+    }
+
+    push_line(&mut ics, "END:VCALENDAR");
+    ics
+}
+
+/// Card metadata key holding the UID of the event a card was imported from
+pub const METADATA_UID: &str = "icsUid";
+/// Card metadata key holding the LOCATION of an imported event
+pub const METADATA_LOCATION: &str = "location";
+
+/// Most events taken from one file
+pub const MAX_IMPORT_EVENTS: usize = 500;
+
+/// DTSTART/DTEND value of an event
+#[derive(Clone, Copy, Debug)]
+pub enum EventTime {
+    /// A date-time in UTC (`Z` suffix)
+    Utc(DateTime<Utc>),
+    /// A date-time in local or TZID time; zones aren't resolved, so these are taken to
+    /// be in the offset given to the import
+    Local(NaiveDateTime),
+    /// An all-day date (`VALUE=DATE`)
+    Date(NaiveDate),
+}
+
+impl EventTime {
+    /// The date-time, or `None` for all-day dates
+    pub fn at(&self, offset: FixedOffset) -> Option<DateTime<FixedOffset>> {
+        match self {
+            EventTime::Utc(time) => Some(time.with_timezone(&offset)),
+            EventTime::Local(time) => time
+                .checked_sub_signed(Duration::seconds(offset.local_minus_utc().into()))
+                .map(|utc| DateTime::from_naive_utc_and_offset(utc, offset)),
+            EventTime::Date(_) => None,
+        }
+    }
+
+    /// The local date
+    pub fn date(&self, offset: FixedOffset) -> NaiveDate {
+        match self {
+            EventTime::Date(date) => *date,
+            time => time.at(offset).map(|t| t.date_naive()).unwrap_or_default(),
+        }
+    }
+}
+
+/// The parts of a VEVENT that are imported
+#[derive(Debug, Default)]
+pub struct Event {
+    pub uid: Option<String>,
+    pub summary: Option<String>,
+    pub description: Option<String>,
+    pub location: Option<String>,
+    pub categories: Option<String>,
+    pub status: Option<String>,
+    pub start: Option<EventTime>,
+    pub end: Option<EventTime>,
+    pub duration: Option<Duration>,
+    pub geo: Option<GeoPoint>,
+    pub recurring: bool,
+    /// Properties that couldn't be read, e.g. `DTSTART`
+    pub invalid: Vec<String>,
+}
+
+/// Undo `escape`
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n' | 'N') => unescaped.push('\n'),
+            Some(c) => unescaped.push(c),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+/// Parameters of a content line, with upper-cased names
+type Params = Vec<(String, String)>;
+
+/// Split a content line into its upper-cased name, its parameters and its value
+fn split_line(line: &str) -> Option<(String, Params, &str)> {
+    // The value starts at the first colon outside a quoted parameter value
+    let mut quoted = false;
+    let colon = line.char_indices().find_map(|(i, c)| match c {
+        '"' => {
+            quoted = !quoted;
+            None
+        }
+        ':' if !quoted => Some(i),
+        _ => None,
+    })?;
+    let (head, value) = (&line[..colon], &line[colon + 1..]);
+
+    let mut parts = Vec::new();
+    let mut quoted = false;
+    let mut start = 0;
+    for (i, c) in head.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' if !quoted => {
+                parts.push(&head[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&head[start..]);
+
+    let name = parts[0].to_ascii_uppercase();
+    let params = parts[1..]
+        .iter()
+        .filter_map(|p| p.split_once('='))
+        .map(|(k, v)| (k.to_ascii_uppercase(), v.trim_matches('"').to_owned()))
+        .collect();
+    Some((name, params, value))
+}
+
+/// Years an event may fall in; keeps date arithmetic on imported times in range
+const EVENT_YEARS: std::ops::RangeInclusive<i32> = 1..=9999;
+
+fn parse_time(params: &[(String, String)], value: &str) -> Option<EventTime> {
+    let is_date = params
+        .iter()
+        .any(|(k, v)| k == "VALUE" && v.eq_ignore_ascii_case("DATE"));
+    if is_date || value.len() == 8 {
+        return NaiveDate::parse_from_str(value, "%Y%m%d")
+            .ok()
+            .filter(|date| EVENT_YEARS.contains(&date.year()))
+            .map(EventTime::Date);
+    }
+    let (value, utc) = match value.strip_suffix('Z') {
+        Some(value) => (value, true),
+        None => (value, false),
+    };
+    let time = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S")
+        .ok()
+        .filter(|time| EVENT_YEARS.contains(&time.year()))?;
+    Some(if utc {
+        EventTime::Utc(time.and_utc())
+    } else {
+        EventTime::Local(time)
+    })
+}
+
+/// Parse a DURATION value such as `PT1H30M`, `P1D` or `P2W`; `None` if it is malformed
+/// or too long to represent
+fn parse_duration(value: &str) -> Option<Duration> {
+    let (negative, value) = match value.as_bytes().first()? {
+        b'-' => (true, &value[1..]),
+        b'+' => (false, &value[1..]),
+        _ => (false, value),
+    };
+    let mut rest = value.strip_prefix('P')?;
+    let mut total = Duration::zero();
+    let mut in_time = false;
+    while !rest.is_empty() {
+        if let Some(r) = rest.strip_prefix('T') {
+            in_time = true;
+            rest = r;
+            continue;
+        }
+        let digits = rest.find(|c: char| !c.is_ascii_digit())?;
+        let amount: i64 = rest[..digits].parse().ok()?;
+        let part = match (rest.as_bytes()[digits], in_time) {
+            (b'W', false) => Duration::try_weeks(amount),
+            (b'D', false) => Duration::try_days(amount),
+            (b'H', true) => Duration::try_hours(amount),
+            (b'M', true) => Duration::try_minutes(amount),
+            (b'S', true) => Duration::try_seconds(amount),
+            _ => return None,
+        }?;
+        total = total.checked_add(&part)?;
+        rest = &rest[digits + 1..];
+    }
+    Some(if negative { -total } else { total })
+}
+
+/// `GEO:<latitude>;<longitude>`
+fn parse_geo(value: &str) -> Option<GeoPoint> {
+    let (latitude, longitude) = value.split_once(';')?;
+    GeoPoint::new(
+        longitude.trim().parse().ok()?,
+        latitude.trim().parse().ok()?,
+    )
+}
+
+/// The VEVENTs of an iCalendar file, in file order. Properties of nested components
+/// (such as VALARM) are ignored. Fails if the text is not a VCALENDAR.
+pub fn parse(ics: &str) -> Result<Vec<Event>, String> {
+    // Unfold: a line starting with a space or tab continues the previous one
+    let mut lines: Vec<String> = Vec::new();
+    for line in ics.split('\n') {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(continued), Some(last)) => last.push_str(continued),
+            _ => lines.push(line.to_owned()),
+        }
+    }
+
+    let mut calendar = false;
+    let mut events = Vec::new();
+    let mut event: Option<Event> = None;
+    // Components open inside the current event
+    let mut nested = 0;
+    for line in lines.iter().filter(|l| !l.trim().is_empty()) {
+        let Some((name, params, value)) = split_line(line) else {
+            continue;
+        };
+        match (name.as_str(), event.as_mut()) {
+            ("BEGIN", _) if value.eq_ignore_ascii_case("VCALENDAR") => calendar = true,
+            ("BEGIN", None) if value.eq_ignore_ascii_case("VEVENT") => {
+                if events.len() == MAX_IMPORT_EVENTS {
+                    return Err(format!("more than {MAX_IMPORT_EVENTS} events"));
+                }
+                event = Some(Event::default());
+                nested = 0;
+            }
+            ("BEGIN", Some(_)) => nested += 1,
+            ("END", Some(_)) if nested > 0 => nested -= 1,
+            ("END", Some(_)) if value.eq_ignore_ascii_case("VEVENT") => {
+                events.extend(event.take());
+            }
+            (_, Some(event)) if nested == 0 => {
+                let text = || Some(unescape(value)).filter(|t| !t.trim().is_empty());
+                match name.as_str() {
+                    "UID" => event.uid = text(),
+                    "SUMMARY" => event.summary = text(),
+                    "DESCRIPTION" => event.description = text(),
+                    "LOCATION" => event.location = text(),
+                    "CATEGORIES" => event.categories = text(),
+                    "STATUS" => event.status = Some(value.trim().to_ascii_uppercase()),
+                    "RRULE" | "RDATE" => event.recurring = true,
+                    "DTSTART" | "DTEND" | "DURATION" | "GEO" => {
+                        let parsed = match name.as_str() {
+                            "DTSTART" => parse_time(&params, value).map(|t| event.start = Some(t)),
+                            "DTEND" => parse_time(&params, value).map(|t| event.end = Some(t)),
+                            "DURATION" => parse_duration(value).map(|d| event.duration = Some(d)),
+                            _ => parse_geo(value).map(|g| event.geo = Some(g)),
+                        };
+                        if parsed.is_none() {
+                            event.invalid.push(name);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if !calendar {
+        return Err("not an iCalendar file".to_owned());
+    }
+    Ok(events)
+}
+
+/// The card to create for an event, or why the event is skipped. Timed events become
+/// scheduled cards, all-day and undated ones drafts. Dated events must start within the
+/// trip's dates.
+pub fn card_from_event(
+    event: &Event,
+    trip: &trips::Model,
+    created_by: Uuid,
+    utc_offset: FixedOffset,
+) -> Result<trip_cards::Model, String> {
+    if event.status.as_deref() == Some("CANCELLED") {
+        return Err("the event is cancelled".to_owned());
+    }
+    if event.recurring {
+        return Err("recurring events aren't supported".to_owned());
+    }
+    if !event.invalid.is_empty() {
+        return Err(format!("invalid {}", event.invalid.join(", ")));
+    }
+    if let Some(start) = event.start {
+        let date = start.date(utc_offset);
+        if trip.start_date.is_some_and(|first| date < first)
+            || trip.end_date.is_some_and(|last| date > last)
+        {
+            return Err("the event is outside the trip's dates".to_owned());
+        }
+    }
+
+    let start_time = event.start.and_then(|start| start.at(utc_offset));
+    let end_time = match start_time {
+        Some(start) => {
+            let end = match (event.end.and_then(|end| end.at(utc_offset)), event.duration) {
+                (Some(end), _) => Some(end),
+                (None, Some(duration)) => Some(
+                    start
+                        .checked_add_signed(duration)
+                        .ok_or_else(|| "the event's DURATION is out of range".to_owned())?,
+                ),
+                (None, None) => None,
+            };
+            end.filter(|end| *end >= start)
+        }
+        None => None,
+    };
+
+    let mut metadata = serde_json::Map::new();
+    if let Some(uid) = &event.uid {
+        metadata.insert(METADATA_UID.to_owned(), uid.clone().into());
+    }
+    if let Some(location) = &event.location {
+        metadata.insert(METADATA_LOCATION.to_owned(), location.clone().into());
+    }
+
+    let now = Utc::now().into();
+    Ok(trip_cards::Model {
+        id: Uuid::now_v7(),
+        trip_id: trip.id,
+        created_by,
+        title: event
+            .summary
+            .clone()
+            .unwrap_or_else(|| "Untitled event".to_owned()),
+        description: event.description.clone(),
+        category: event.categories.clone(),
+        start_time,
+        end_time,
+        status: if start_time.is_some() {
+            "scheduled"
+        } else {
+            "draft"
+        }
+        .to_owned(),
+        display_order: Some(0),
+        vote_count: 0,
+        vote_data: None,
+        created_at: now,
+        updated_at: now,
+        metadata: (!metadata.is_empty()).then_some(serde_json::Value::Object(metadata)),
+        version: 1,
+        position: event.geo,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(pairs: &[(&str, &str)]) -> Params {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("PT1H30M"), Some(Duration::minutes(90)));
+        assert_eq!(parse_duration("P1D"), Some(Duration::days(1)));
+        assert_eq!(parse_duration("P2W"), Some(Duration::weeks(2)));
+        assert_eq!(
+            parse_duration("P1DT2H3M4S"),
+            Some(
+                Duration::days(1)
+                    + Duration::hours(2)
+                    + Duration::minutes(3)
+                    + Duration::seconds(4)
+            )
+        );
+        assert_eq!(parse_duration("-PT15M"), Some(Duration::minutes(-15)));
+        assert_eq!(parse_duration("+PT15M"), Some(Duration::minutes(15)));
+    }
+
+    #[test]
+    fn rejects_malformed_durations() {
+        assert_eq!(parse_duration(""), None);
+        assert_eq!(parse_duration("1H"), None);
+        assert_eq!(parse_duration("P1H"), None);
+        assert_eq!(parse_duration("PT1D"), None);
+        assert_eq!(parse_duration("PT1"), None);
+        assert_eq!(parse_duration("PTH"), None);
+    }
+
+    #[test]
+    fn rejects_durations_out_of_range() {
+        assert_eq!(parse_duration("P99999999999999W"), None);
+        assert_eq!(parse_duration("P1000000000000D"), None);
+        assert_eq!(parse_duration("PT99999999999999999999S"), None);
+        // Each part fits, their sum doesn't
+        assert_eq!(parse_duration("P15000000000000DT9223372036854S"), None);
+    }
+
+    #[test]
+    fn parses_times() {
+        let utc = parse_time(&[], "20250102T030405Z");
+        assert!(
+            matches!(utc, Some(EventTime::Utc(t)) if t.to_rfc3339() == "2025-01-02T03:04:05+00:00")
+        );
+
+        let local = parse_time(&params(&[("TZID", "Europe/Paris")]), "20250102T030405");
+        assert!(
+            matches!(local, Some(EventTime::Local(t)) if t.to_string() == "2025-01-02 03:04:05")
+        );
+
+        let date = NaiveDate::from_ymd_opt(2025, 1, 2);
+        assert!(matches!(parse_time(&[], "20250102"), Some(EventTime::Date(d)) if Some(d) == date));
+        assert!(matches!(
+            parse_time(&params(&[("VALUE", "date")]), "20250102"),
+            Some(EventTime::Date(d)) if Some(d) == date
+        ));
+    }
+
+    #[test]
+    fn rejects_malformed_times() {
+        assert!(parse_time(&[], "2025-01-02").is_none());
+        assert!(parse_time(&[], "20251302").is_none());
+        assert!(parse_time(&[], "20250102T250000Z").is_none());
+        assert!(parse_time(&params(&[("VALUE", "DATE")]), "20250102T030405").is_none());
+        assert!(parse_time(&[], "+2620000102T030405").is_none());
+    }
+
+    #[test]
+    fn local_times_take_the_given_offset() {
+        let time = parse_time(&[], "20250102T090000").unwrap();
+        let offset = FixedOffset::east_opt(9 * 3600).unwrap();
+        assert_eq!(
+            time.at(offset).unwrap().to_rfc3339(),
+            "2025-01-02T09:00:00+09:00"
+        );
+        assert_eq!(
+            time.date(offset),
+            NaiveDate::from_ymd_opt(2025, 1, 2).unwrap()
+        );
+    }
+
+    #[test]
+    fn parses_events() {
+        let ics = "BEGIN:VCALENDAR\r\n\
+                   VERSION:2.0\r\n\
+                   BEGIN:VEVENT\r\n\
+                   UID:one@example.com\r\n\
+                   SUMMARY:Museum\\, then lunch\r\n\
+                   DESCRIPTION:Tickets at the\r\n\
+                   \x20\x20desk\r\n\
+                   DTSTART:20250102T090000Z\r\n\
+                   DURATION:PT2H\r\n\
+                   GEO:48.85;2.35\r\n\
+                   BEGIN:VALARM\r\n\
+                   SUMMARY:Reminder\r\n\
+                   END:VALARM\r\n\
+                   END:VEVENT\r\n\
+                   BEGIN:VEVENT\r\n\
+                   DTSTART;VALUE=DATE:20250103\r\n\
+                   DTEND:nonsense\r\n\
+                   RRULE:FREQ=DAILY\r\n\
+                   END:VEVENT\r\n\
+                   END:VCALENDAR\r\n";
+        let events = parse(ics).unwrap();
+        assert_eq!(events.len(), 2);
+
+        let first = &events[0];
+        assert_eq!(first.uid.as_deref(), Some("one@example.com"));
+        assert_eq!(first.summary.as_deref(), Some("Museum, then lunch"));
+        assert_eq!(first.description.as_deref(), Some("Tickets at the desk"));
+        assert!(matches!(first.start, Some(EventTime::Utc(_))));
+        assert_eq!(first.duration, Some(Duration::hours(2)));
+        assert!(first.geo.is_some());
+        assert!(first.invalid.is_empty());
+
+        let second = &events[1];
+        assert!(matches!(second.start, Some(EventTime::Date(_))));
+        assert!(second.recurring);
+        assert_eq!(second.invalid, vec!["DTEND".to_string()]);
+    }
+
+    #[test]
+    fn flags_durations_out_of_range() {
+        let ics = "BEGIN:VCALENDAR\nBEGIN:VEVENT\nDTSTART:20250102T090000Z\n\
+                   DURATION:P99999999999999W\nEND:VEVENT\nEND:VCALENDAR\n";
+        let events = parse(ics).unwrap();
+        assert_eq!(events[0].duration, None);
+        assert_eq!(events[0].invalid, vec!["DURATION".to_string()]);
+    }
+
+    #[test]
+    fn skips_events_ending_out_of_range() {
+        let now = Utc::now().into();
+        let trip = trips::Model {
+            id: Uuid::now_v7(),
+            realm_id: Uuid::now_v7(),
+            created_by: Uuid::now_v7(),
+            title: "Trip".to_owned(),
+            description: None,
+            destination: None,
+            start_date: None,
+            end_date: None,
+            status: "planning".to_owned(),
+            created_at: now,
+            updated_at: now,
+            metadata: None,
+            version: 1,
+        };
+        let ics = "BEGIN:VCALENDAR\nBEGIN:VEVENT\nDTSTART:20250102T090000Z\n\
+                   DURATION:P1000000000D\nEND:VEVENT\nEND:VCALENDAR\n";
+        let events = parse(ics).unwrap();
+        assert!(events[0].duration.is_some());
+        let offset = FixedOffset::east_opt(0).unwrap();
+        assert!(card_from_event(&events[0], &trip, Uuid::now_v7(), offset).is_err());
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert!(parse("BEGIN:VEVENT\nEND:VEVENT\n").is_err());
+        assert!(parse("").is_err());
+    }
+
+    #[test]
+    fn limits_the_number_of_events() {
+        let mut ics = String::from("BEGIN:VCALENDAR\n");
+        for _ in 0..=MAX_IMPORT_EVENTS {
+            ics.push_str("BEGIN:VEVENT\nEND:VEVENT\n");
+        }
+        ics.push_str("END:VCALENDAR\n");
+        assert!(parse(&ics).is_err());
+    }
+}
@@ -0,0 +1,318 @@
+use anyhow::Result;
+use chrono::{DateTime, Duration, FixedOffset};
+use sea_orm::sea_query::Expr;
+use sea_orm::{
+    ColumnTrait, ConnectionTrait, DbErr, EntityTrait, QueryFilter, QueryOrder, QuerySelect,
+};
+use serde::Deserialize;
+use std::collections::HashMap;
+use uuid::Uuid;
+use workspace_entity::geo_point::GeoPoint;
+use workspace_entity::trip_cards;
+
+/// Speeds used to estimate travel times from straight-line distances
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ItineraryConfiguration {
+    /// Average speeds in km/h, door to door
+    pub walking_kmh: f64,
+    pub transit_kmh: f64,
+    pub driving_kmh: f64,
+    /// Straight-line distances are multiplied by this to allow for the road network
+    pub detour_factor: f64,
+}
+
+impl Default for ItineraryConfiguration {
+    fn default() -> Self {
+        ItineraryConfiguration {
+            walking_kmh: 4.5,
+            transit_kmh: 20.0,
+            driving_kmh: 35.0,
+            detour_factor: 1.3,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TravelMode {
+    Walking,
+    Transit,
+    Driving,
+}
+
+impl TravelMode {
+    pub fn parse(mode: &str) -> Option<Self> {
+        match mode {
+            "walking" => Some(TravelMode::Walking),
+            "transit" => Some(TravelMode::Transit),
+            "driving" => Some(TravelMode::Driving),
+            _ => None,
+        }
+    }
+}
+
+/// A move between the positions of two consecutive cards
+#[allow(dead_code)] // The endpoints are for routers that look up actual routes
+pub struct Leg {
+    pub from: GeoPoint,
+    pub to: GeoPoint,
+    /// Geodesic distance (PostGIS `ST_Distance`)
+    pub distance_meters: f64,
+    pub mode: TravelMode,
+}
+
+/// Estimates how long a leg takes. The default estimates from the distance; a real
+/// router (e.g. one calling a directions API) can be plugged in through `AppState`.
+#[async_trait::async_trait]
+pub trait RoutingProvider: Send + Sync {
+    async fn travel_time(&self, leg: &Leg) -> Result<Duration>;
+}
+
+/// Distance times a detour factor, at the configured speed of the mode
+pub struct StraightLineRouter {
+    config: ItineraryConfiguration,
+}
+
+impl StraightLineRouter {
+    pub fn new(config: ItineraryConfiguration) -> Result<Self> {
+        let speeds = [config.walking_kmh, config.transit_kmh, config.driving_kmh];
+        if !speeds.iter().all(|s| s.is_finite() && *s > 0.0) {
+            anyhow::bail!("itinerary speeds must be positive");
+        }
+        if !config.detour_factor.is_finite() || config.detour_factor < 1.0 {
+            anyhow::bail!("itinerary.detour_factor must be at least 1");
+        }
+        Ok(StraightLineRouter { config })
+    }
+}
+
+#[async_trait::async_trait]
+impl RoutingProvider for StraightLineRouter {
+    async fn travel_time(&self, leg: &Leg) -> Result<Duration> {
+        let kmh = match leg.mode {
+            TravelMode::Walking => self.config.walking_kmh,
+            TravelMode::Transit => self.config.transit_kmh,
+            TravelMode::Driving => self.config.driving_kmh,
+        };
+        let meters = leg.distance_meters * self.config.detour_factor;
+        let seconds = meters / (kmh * 1000.0 / 3600.0);
+        Ok(Duration::seconds(seconds.ceil() as i64))
+    }
+}
+
+/// Going from one card to the next
+pub struct Transition {
+    pub from: Uuid,
+    pub to: Uuid,
+    /// `None` if either card has no position
+    pub distance_meters: Option<f64>,
+    pub travel: Duration,
+    /// From the end of `from` to the start of `to`; negative if they overlap
+    pub available: Duration,
+    /// Whether `available` covers the travel time and the buffer
+    pub feasible: bool,
+}
+
+/// New times for a card so the itinerary works out
+pub struct Suggestion {
+    pub trip_card_id: Uuid,
+    pub start_time: DateTime<FixedOffset>,
+    pub end_time: Option<DateTime<FixedOffset>>,
+}
+
+pub struct Report {
+    pub transitions: Vec<Transition>,
+    pub suggestions: Vec<Suggestion>,
+}
+
+/// A trip's scheduled cards in `start_time` order, each with its distance from the
+/// previous one (`None` for the first and where a position is missing)
+async fn scheduled_cards<C: ConnectionTrait>(
+    db: &C,
+    trip_id: Uuid,
+) -> Result<Vec<(trip_cards::Model, Option<f64>)>, DbErr> {
+    let ordered: Vec<(Uuid, Option<f64>)> = trip_cards::Entity::find()
+        .select_only()
+        .column(trip_cards::Column::Id)
+        .expr_as(
+            Expr::cust(
+                "ST_Distance(trip_cards.position, LAG(trip_cards.position) OVER (ORDER BY trip_cards.start_time, trip_cards.id))",
+            ),
+            "distance",
+        )
+        .filter(trip_cards::COLUMN.trip_id.eq(trip_id))
+        .filter(trip_cards::COLUMN.status.eq("scheduled"))
+        .filter(trip_cards::Column::StartTime.is_not_null())
+        .order_by_asc(trip_cards::Column::StartTime)
+        .order_by_asc(trip_cards::Column::Id)
+        .into_tuple()
+        .all(db)
+        .await?;
+
+    let mut cards: HashMap<Uuid, trip_cards::Model> = trip_cards::Entity::find()
+        .filter(trip_cards::Column::Id.is_in(ordered.iter().map(|(id, _)| *id)))
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|card| (card.id, card))
+        .collect();
+
+    Ok(ordered
+        .into_iter()
+        .filter_map(|(id, distance)| cards.remove(&id).map(|card| (card, distance)))
+        .collect())
+}
+
+/// Walk a trip's scheduled cards and check that each can be reached from the previous
+/// one in time, leaving at least `buffer` besides the travel time. Late cards are
+/// suggested to start once the previous one (at its suggested time) ends and the trip
+/// there is done, keeping their duration; later cards move along as needed.
+pub async fn validate<C: ConnectionTrait>(
+    db: &C,
+    router: &dyn RoutingProvider,
+    trip_id: Uuid,
+    mode: TravelMode,
+    buffer: Duration,
+) -> Result<Report> {
+    let cards = scheduled_cards(db, trip_id).await?;
+
+    let mut transitions = Vec::new();
+    let mut suggestions = Vec::new();
+    // End of the previous card, as suggested
+    let mut previous: Option<(&trip_cards::Model, DateTime<FixedOffset>)> = None;
+
+    for (card, distance) in &cards {
+        let Some(start) = card.start_time else {
+            continue;
+        };
+        let end = card.end_time.filter(|end| *end >= start);
+
+        if let Some((from, ready_from)) = previous {
+            let travel = match (from.position, card.position, distance) {
+                (Some(from), Some(to), Some(distance_meters)) => {
+                    router
+                        .travel_time(&Leg {
+                            from,
+                            to,
+                            distance_meters: *distance_meters,
+                            mode,
+                        })
+                        .await?
+                }
+                _ => Duration::zero(),
+            };
+            let from_end = from.end_time.or(from.start_time).unwrap_or(start);
+            let available = start - from_end;
+            transitions.push(Transition {
+                from: from.id,
+                to: card.id,
+                distance_meters: *distance,
+                travel,
+                available,
+                feasible: available >= travel + buffer,
+            });
+
+            let earliest = ready_from + travel + buffer;
+            if start < earliest {
+                let shift = earliest - start;
+                suggestions.push(Suggestion {
+                    trip_card_id: card.id,
+                    start_time: earliest,
+                    end_time: end.map(|end| end + shift),
+                });
+                previous = Some((card, end.map_or(earliest, |end| end + shift)));
+                continue;
+            }
+        }
+
+        previous = Some((card, end.unwrap_or(start)));
+    }
+
+    Ok(Report {
+        transitions,
+        suggestions,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leg(distance_meters: f64, mode: TravelMode) -> Leg {
+        let point = GeoPoint::new(2.3376, 48.8606).unwrap();
+        Leg {
+            from: point,
+            to: point,
+            distance_meters,
+            mode,
+        }
+    }
+
+    async fn seconds(router: &StraightLineRouter, leg: Leg) -> i64 {
+        router.travel_time(&leg).await.unwrap().num_seconds()
+    }
+
+    #[tokio::test]
+    async fn travel_times_follow_the_mode_speed_and_detour() {
+        let router = StraightLineRouter::new(ItineraryConfiguration::default()).unwrap();
+        // 1 km is 1.3 km with the detour
+        assert_eq!(
+            seconds(&router, leg(1000.0, TravelMode::Walking)).await,
+            1040
+        );
+        assert_eq!(
+            seconds(&router, leg(1000.0, TravelMode::Transit)).await,
+            234
+        );
+        // 133.7s, rounded up
+        assert_eq!(
+            seconds(&router, leg(1000.0, TravelMode::Driving)).await,
+            134
+        );
+        assert_eq!(seconds(&router, leg(0.0, TravelMode::Walking)).await, 0);
+    }
+
+    #[tokio::test]
+    async fn configured_speeds_are_used() {
+        let router = StraightLineRouter::new(ItineraryConfiguration {
+            walking_kmh: 3.6,
+            detour_factor: 1.0,
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(seconds(&router, leg(500.0, TravelMode::Walking)).await, 500);
+    }
+
+    #[test]
+    fn invalid_configurations_are_rejected() {
+        let invalid = [
+            ItineraryConfiguration {
+                walking_kmh: 0.0,
+                ..Default::default()
+            },
+            ItineraryConfiguration {
+                driving_kmh: f64::NAN,
+                ..Default::default()
+            },
+            ItineraryConfiguration {
+                transit_kmh: -20.0,
+                ..Default::default()
+            },
+            ItineraryConfiguration {
+                detour_factor: 0.9,
+                ..Default::default()
+            },
+        ];
+        for config in invalid {
+            assert!(StraightLineRouter::new(config).is_err());
+        }
+    }
+
+    #[test]
+    fn travel_modes_parse() {
+        assert_eq!(TravelMode::parse("walking"), Some(TravelMode::Walking));
+        assert_eq!(TravelMode::parse("transit"), Some(TravelMode::Transit));
+        assert_eq!(TravelMode::parse("driving"), Some(TravelMode::Driving));
+        assert_eq!(TravelMode::parse("cycling"), None);
+    }
+}
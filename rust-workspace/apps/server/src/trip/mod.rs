@@ -0,0 +1,11 @@
+pub mod activity;
+pub mod copy;
+pub mod geo;
+pub mod ical;
+pub mod itinerary;
+pub mod optimizer;
+pub mod participant;
+pub mod presence;
+pub mod rich_text;
+pub mod service;
+pub mod share;
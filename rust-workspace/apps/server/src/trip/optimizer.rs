@@ -0,0 +1,673 @@
+use crate::error::Error;
+use crate::trip::itinerary::{Leg, RoutingProvider, TravelMode};
+use chrono::{DateTime, Datelike, Duration, FixedOffset, NaiveDate, NaiveTime};
+use sea_orm::{
+    ColumnTrait, ConnectionTrait, DbBackend, EntityTrait, QueryFilter, QueryOrder, Statement,
+};
+use serde::Deserialize;
+use std::collections::HashMap;
+use uuid::Uuid;
+use workspace_entity::geo_point::GeoPoint;
+use workspace_entity::{trip_cards, trips};
+
+/// Card metadata key holding how long the activity takes, in minutes
+pub const METADATA_DURATION: &str = "durationMinutes";
+/// Card metadata key holding opening hours, as
+/// `[{ "days": [1, 2, 3, 4, 5], "open": "09:00", "close": "17:00" }]` with ISO weekdays
+/// (1 is Monday; no `days` means every day). Cards without it are always open.
+pub const METADATA_OPENING_HOURS: &str = "openingHours";
+
+/// Used when a card has neither a duration in its metadata nor start and end times
+/// (same as the web timeline)
+pub const DEFAULT_DURATION_MINUTES: i64 = 90;
+/// Proposed start times are rounded up to this (same as the web timeline)
+pub const SLOT_MINUTES: i64 = 15;
+/// Longest trip that is planned
+pub const MAX_DAYS: i64 = 60;
+
+#[derive(Deserialize)]
+struct OpeningHours {
+    #[serde(default)]
+    days: Vec<u32>,
+    open: String,
+    close: String,
+}
+
+pub struct Options {
+    pub mode: TravelMode,
+    /// Slack to leave between cards besides the travel time
+    pub buffer: Duration,
+    /// Offset of the trip's local time, which the day window and opening hours are in
+    pub utc_offset: FixedOffset,
+    pub day_start: NaiveTime,
+    pub day_end: NaiveTime,
+}
+
+/// A draft card with proposed times
+pub struct Placement {
+    pub card: trip_cards::Model,
+    pub start_time: DateTime<FixedOffset>,
+    pub end_time: DateTime<FixedOffset>,
+}
+
+pub struct Unplaced {
+    pub card: trip_cards::Model,
+    pub reason: String,
+}
+
+pub struct Plan {
+    /// In start time order
+    pub placements: Vec<Placement>,
+    pub unplaced: Vec<Unplaced>,
+    /// Travel time the placements add to the itinerary
+    pub added_travel: Duration,
+}
+
+/// Open (from, to) times per ISO weekday; index 0 is Monday
+type Week = [Vec<(NaiveTime, NaiveTime)>; 7];
+
+/// A busy stretch of the itinerary
+struct Slot {
+    id: Uuid,
+    start: DateTime<FixedOffset>,
+    end: DateTime<FixedOffset>,
+}
+
+/// Travel times between cards, asking the router once per pair
+struct TravelTimes<'a> {
+    router: &'a dyn RoutingProvider,
+    mode: TravelMode,
+    positions: HashMap<Uuid, GeoPoint>,
+    distances: HashMap<(Uuid, Uuid), f64>,
+    cache: HashMap<(Uuid, Uuid), Duration>,
+}
+
+impl TravelTimes<'_> {
+    /// Zero if either card has no position
+    async fn between(&mut self, from: Uuid, to: Uuid) -> anyhow::Result<Duration> {
+        if let Some(travel) = self.cache.get(&(from, to)) {
+            return Ok(*travel);
+        }
+        let leg = match (
+            self.positions.get(&from),
+            self.positions.get(&to),
+            self.distances.get(&(from, to)),
+        ) {
+            (Some(from), Some(to), Some(distance_meters)) => Leg {
+                from: *from,
+                to: *to,
+                distance_meters: *distance_meters,
+                mode: self.mode,
+            },
+            _ => return Ok(Duration::zero()),
+        };
+        let travel = self.router.travel_time(&leg).await?;
+        self.cache.insert((from, to), travel);
+        Ok(travel)
+    }
+}
+
+/// Distances in meters (PostGIS `ST_Distance`) between the positioned draft and
+/// scheduled cards of a trip, both ways
+async fn distances<C: ConnectionTrait>(
+    db: &C,
+    trip_id: Uuid,
+) -> Result<HashMap<(Uuid, Uuid), f64>, Error> {
+    let rows = db
+        .query_all_raw(Statement::from_sql_and_values(
+            DbBackend::Postgres,
+            r#"SELECT a.id AS "from", b.id AS "to", ST_Distance(a.position, b.position) AS distance
+               FROM trip_cards a
+               JOIN trip_cards b ON b.trip_id = a.trip_id AND a.id < b.id
+               WHERE a.trip_id = $1
+                 AND a.position IS NOT NULL AND b.position IS NOT NULL
+                 AND a.status IN ('draft', 'scheduled') AND b.status IN ('draft', 'scheduled')"#,
+            [trip_id.into()],
+        ))
+        .await?;
+
+    let mut distances = HashMap::new();
+    for row in rows {
+        let from: Uuid = row.try_get("", "from")?;
+        let to: Uuid = row.try_get("", "to")?;
+        let distance: f64 = row.try_get("", "distance")?;
+        distances.insert((from, to), distance);
+        distances.insert((to, from), distance);
+    }
+    Ok(distances)
+}
+
+fn local(date: NaiveDate, time: NaiveTime, offset: FixedOffset) -> DateTime<FixedOffset> {
+    let utc = date.and_time(time) - Duration::seconds(offset.local_minus_utc().into());
+    DateTime::from_naive_utc_and_offset(utc, offset)
+}
+
+/// Round up to the next slot boundary of local time
+fn round_up(time: DateTime<FixedOffset>) -> DateTime<FixedOffset> {
+    let slot = SLOT_MINUTES * 60;
+    let seconds = time.timestamp() + i64::from(time.offset().local_minus_utc());
+    let nanos = i64::from(time.timestamp_subsec_nanos());
+    let rest = seconds.rem_euclid(slot);
+    if rest == 0 && nanos == 0 {
+        return time;
+    }
+    time - Duration::nanoseconds(nanos) + Duration::seconds(slot - rest)
+}
+
+/// How long a card takes: its metadata duration, else its current length, else the default
+fn card_duration(card: &trip_cards::Model) -> Result<Duration, String> {
+    if let Some(minutes) = card
+        .metadata
+        .as_ref()
+        .and_then(|m| m.get(METADATA_DURATION))
+    {
+        return match minutes.as_i64() {
+            Some(minutes) if minutes > 0 && minutes <= 24 * 60 => Ok(Duration::minutes(minutes)),
+            _ => Err(format!(
+                "{METADATA_DURATION} must be a whole number of minutes up to a day"
+            )),
+        };
+    }
+    match (card.start_time, card.end_time) {
+        (Some(start), Some(end)) if end > start => Ok(end - start),
+        _ => Ok(Duration::minutes(DEFAULT_DURATION_MINUTES)),
+    }
+}
+
+/// A card's opening hours, or `None` if it has none
+fn opening_hours(card: &trip_cards::Model) -> Result<Option<Week>, String> {
+    let Some(value) = card
+        .metadata
+        .as_ref()
+        .and_then(|m| m.get(METADATA_OPENING_HOURS))
+    else {
+        return Ok(None);
+    };
+    let invalid = || {
+        format!(
+            "{METADATA_OPENING_HOURS} must be a list of {{ days, open, close }} with HH:MM times, close after open"
+        )
+    };
+    let entries: Vec<OpeningHours> =
+        serde_json::from_value(value.clone()).map_err(|_| invalid())?;
+
+    let mut week: Week = Default::default();
+    for entry in entries {
+        let open = NaiveTime::parse_from_str(&entry.open, "%H:%M").map_err(|_| invalid())?;
+        let close = NaiveTime::parse_from_str(&entry.close, "%H:%M").map_err(|_| invalid())?;
+        if close <= open || entry.days.iter().any(|day| !(1..=7).contains(day)) {
+            return Err(invalid());
+        }
+        let days = if entry.days.is_empty() {
+            (1..=7).collect()
+        } else {
+            entry.days
+        };
+        for day in days {
+            week[day as usize - 1].push((open, close));
+        }
+    }
+    Ok(Some(week))
+}
+
+/// The trip's first day and its number of days
+fn trip_days(trip: &trips::Model) -> Result<(NaiveDate, i64), Error> {
+    let (Some(first_day), Some(last_day)) = (trip.start_date, trip.end_date) else {
+        return Err(Error::FailedPrecondition(
+            "the trip has no start and end dates".to_string(),
+        ));
+    };
+    let days = (last_day - first_day).num_days() + 1;
+    if days < 1 {
+        return Err(Error::FailedPrecondition(
+            "the trip ends before it starts".to_string(),
+        ));
+    }
+    if days > MAX_DAYS {
+        return Err(Error::FailedPrecondition(format!(
+            "trips longer than {MAX_DAYS} days can't be planned"
+        )));
+    }
+    Ok((first_day, days))
+}
+
+/// Propose times for a trip's draft cards within the trip's dates, around its scheduled
+/// cards. Higher-voted cards are placed first, each in the free slot (inside the day
+/// window and its opening hours, with room to travel from the card before and on to the
+/// card after) that adds the least travel, earliest on ties. Nothing is written.
+pub async fn plan<C: ConnectionTrait>(
+    db: &C,
+    router: &dyn RoutingProvider,
+    trip: &trips::Model,
+    options: &Options,
+) -> Result<Plan, Error> {
+    let (first_day, days) = trip_days(trip)?;
+
+    let cards = trip_cards::Entity::find()
+        .filter(trip_cards::COLUMN.trip_id.eq(trip.id))
+        .filter(trip_cards::Column::Status.is_in(["draft", "scheduled"]))
+        .order_by_desc(trip_cards::Column::VoteCount)
+        .order_by_asc(trip_cards::Column::DisplayOrder)
+        .order_by_asc(trip_cards::Column::CreatedAt)
+        .all(db)
+        .await?;
+
+    let travel = TravelTimes {
+        router,
+        mode: options.mode,
+        positions: cards
+            .iter()
+            .filter_map(|card| card.position.map(|position| (card.id, position)))
+            .collect(),
+        distances: distances(db, trip.id).await?,
+        cache: HashMap::new(),
+    };
+    place(travel, cards, first_day, days, options).await
+}
+
+/// Place the draft `cards` (in placement order) on `days` days from `first_day`, around
+/// the scheduled ones
+async fn place(
+    mut travel: TravelTimes<'_>,
+    cards: Vec<trip_cards::Model>,
+    first_day: NaiveDate,
+    days: i64,
+    options: &Options,
+) -> Result<Plan, Error> {
+    let (drafts, scheduled): (Vec<_>, Vec<_>) =
+        cards.into_iter().partition(|card| card.status == "draft");
+    let mut timeline: Vec<Slot> = scheduled
+        .iter()
+        .filter_map(|card| {
+            let start = card.start_time?;
+            let end = card.end_time.filter(|end| *end >= start).unwrap_or(start);
+            Some(Slot {
+                id: card.id,
+                start,
+                end,
+            })
+        })
+        .collect();
+    timeline.sort_by_key(|slot| slot.start);
+
+    let mut placements = Vec::new();
+    let mut unplaced = Vec::new();
+    let mut added_travel = Duration::zero();
+
+    for card in drafts {
+        let constraints =
+            card_duration(&card).and_then(|duration| Ok((duration, opening_hours(&card)?)));
+        let (duration, hours) = match constraints {
+            Ok(constraints) => constraints,
+            Err(reason) => {
+                unplaced.push(Unplaced { card, reason });
+                continue;
+            }
+        };
+
+        // (added travel, start, index in the timeline)
+        let mut best: Option<(Duration, DateTime<FixedOffset>, usize)> = None;
+        for date in first_day.iter_days().take(days as usize) {
+            let window = (
+                local(date, options.day_start, options.utc_offset),
+                local(date, options.day_end, options.utc_offset),
+            );
+            let ranges: Vec<_> = match &hours {
+                None => vec![window],
+                Some(week) => week[date.weekday().num_days_from_monday() as usize]
+                    .iter()
+                    .map(|(open, close)| {
+                        (
+                            window.0.max(local(date, *open, options.utc_offset)),
+                            window.1.min(local(date, *close, options.utc_offset)),
+                        )
+                    })
+                    .filter(|(open, close)| open < close)
+                    .collect(),
+            };
+
+            for (open, close) in ranges {
+                // Slots may overlap, so the latest end so far bounds the gap
+                let mut busy_until: Option<DateTime<FixedOffset>> = None;
+                for index in 0..=timeline.len() {
+                    let previous = index.checked_sub(1).map(|i| &timeline[i]);
+                    if let Some(previous) = previous {
+                        busy_until = busy_until.max(Some(previous.end));
+                    }
+                    let next = timeline.get(index);
+                    if next.is_some_and(|next| next.start < open) {
+                        continue;
+                    }
+
+                    let to_card = match previous {
+                        Some(previous) => travel.between(previous.id, card.id).await?,
+                        None => Duration::zero(),
+                    };
+                    let earliest = busy_until.map_or(open, |busy_until| {
+                        open.max(busy_until + to_card + options.buffer)
+                    });
+                    let start = round_up(earliest);
+                    let end = start + duration;
+                    if end > close {
+                        // Later gaps start after this one, so none fits once it's closed
+                        if busy_until.is_some_and(|busy_until| busy_until >= close) {
+                            break;
+                        }
+                        continue;
+                    }
+
+                    let (from_card, direct) = match next {
+                        Some(next) => {
+                            let from_card = travel.between(card.id, next.id).await?;
+                            if end + from_card + options.buffer > next.start {
+                                continue;
+                            }
+                            let direct = match previous {
+                                Some(previous) => travel.between(previous.id, next.id).await?,
+                                None => Duration::zero(),
+                            };
+                            (from_card, direct)
+                        }
+                        None => (Duration::zero(), Duration::zero()),
+                    };
+                    let cost = to_card + from_card - direct;
+                    if best.is_none_or(|(best_cost, best_start, _)| {
+                        (cost, start) < (best_cost, best_start)
+                    }) {
+                        best = Some((cost, start, index));
+                    }
+                }
+            }
+        }
+
+        match best {
+            Some((cost, start, index)) => {
+                timeline.insert(
+                    index,
+                    Slot {
+                        id: card.id,
+                        start,
+                        end: start + duration,
+                    },
+                );
+                added_travel += cost;
+                placements.push(Placement {
+                    card,
+                    start_time: start,
+                    end_time: start + duration,
+                });
+            }
+            None => unplaced.push(Unplaced {
+                card,
+                reason: "no free slot fits the card".to_string(),
+            }),
+        }
+    }
+
+    placements.sort_by_key(|placement| placement.start_time);
+    Ok(Plan {
+        placements,
+        unplaced,
+        added_travel,
+    })
+}
+
+/// Other scheduled cards of the trip overlapping `start..end`
+pub async fn conflicts<C: ConnectionTrait>(
+    db: &C,
+    trip_id: Uuid,
+    trip_card_id: Uuid,
+    start: DateTime<FixedOffset>,
+    end: DateTime<FixedOffset>,
+) -> Result<Vec<trip_cards::Model>, Error> {
+    Ok(trip_cards::Entity::find()
+        .filter(trip_cards::COLUMN.trip_id.eq(trip_id))
+        .filter(trip_cards::COLUMN.status.eq("scheduled"))
+        .filter(trip_cards::Column::Id.ne(trip_card_id))
+        .filter(trip_cards::Column::StartTime.lt(end))
+        .filter(sea_orm::sea_query::Expr::cust_with_values(
+            "COALESCE(trip_cards.end_time, trip_cards.start_time) > $1",
+            [start],
+        ))
+        .all(db)
+        .await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    /// Every positioned leg takes the same time
+    struct FixedRouter(Duration);
+
+    #[async_trait::async_trait]
+    impl RoutingProvider for FixedRouter {
+        async fn travel_time(&self, _leg: &Leg) -> anyhow::Result<Duration> {
+            Ok(self.0)
+        }
+    }
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2025, 1, day).unwrap()
+    }
+
+    fn at(day: u32, hour: u32, minute: u32) -> DateTime<FixedOffset> {
+        local(
+            date(day),
+            NaiveTime::from_hms_opt(hour, minute, 0).unwrap(),
+            utc(),
+        )
+    }
+
+    fn utc() -> FixedOffset {
+        FixedOffset::east_opt(0).unwrap()
+    }
+
+    fn options() -> Options {
+        Options {
+            mode: TravelMode::Walking,
+            buffer: Duration::zero(),
+            utc_offset: utc(),
+            day_start: NaiveTime::from_hms_opt(9, 0, 0).unwrap(),
+            day_end: NaiveTime::from_hms_opt(18, 0, 0).unwrap(),
+        }
+    }
+
+    fn card(metadata: Option<serde_json::Value>) -> trip_cards::Model {
+        let now = Utc::now().into();
+        trip_cards::Model {
+            id: Uuid::now_v7(),
+            trip_id: Uuid::nil(),
+            created_by: Uuid::nil(),
+            title: "Card".to_owned(),
+            description: None,
+            category: None,
+            start_time: None,
+            end_time: None,
+            status: "draft".to_owned(),
+            display_order: None,
+            vote_count: 0,
+            vote_data: None,
+            created_at: now,
+            updated_at: now,
+            metadata,
+            version: 1,
+            position: None,
+        }
+    }
+
+    fn scheduled(start: DateTime<FixedOffset>, end: DateTime<FixedOffset>) -> trip_cards::Model {
+        trip_cards::Model {
+            status: "scheduled".to_owned(),
+            start_time: Some(start),
+            end_time: Some(end),
+            ..card(None)
+        }
+    }
+
+    fn trip(start_date: Option<NaiveDate>, end_date: Option<NaiveDate>) -> trips::Model {
+        let now = Utc::now().into();
+        trips::Model {
+            id: Uuid::nil(),
+            realm_id: Uuid::nil(),
+            created_by: Uuid::nil(),
+            title: "Trip".to_owned(),
+            description: None,
+            destination: None,
+            start_date,
+            end_date,
+            status: "planning".to_owned(),
+            created_at: now,
+            updated_at: now,
+            metadata: None,
+            version: 1,
+        }
+    }
+
+    /// Plan `cards` without travel between them
+    async fn place_without_travel(
+        cards: Vec<trip_cards::Model>,
+        first_day: NaiveDate,
+        days: i64,
+    ) -> Plan {
+        let router = FixedRouter(Duration::zero());
+        let travel = TravelTimes {
+            router: &router,
+            mode: TravelMode::Walking,
+            positions: HashMap::new(),
+            distances: HashMap::new(),
+            cache: HashMap::new(),
+        };
+        place(travel, cards, first_day, days, &options())
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn trip_days_needs_valid_dates() {
+        assert!(trip_days(&trip(None, Some(date(2)))).is_err());
+        assert!(trip_days(&trip(Some(date(3)), Some(date(2)))).is_err());
+        let too_long = date(1) + Duration::days(MAX_DAYS);
+        assert!(trip_days(&trip(Some(date(1)), Some(too_long))).is_err());
+        assert_eq!(
+            trip_days(&trip(Some(date(1)), Some(date(3)))).unwrap(),
+            (date(1), 3)
+        );
+    }
+
+    #[test]
+    fn opening_hours_are_indexed_by_iso_weekday() {
+        let monday_only = card(Some(serde_json::json!({
+            METADATA_OPENING_HOURS: [{ "days": [1], "open": "10:00", "close": "12:00" }]
+        })));
+        let week = opening_hours(&monday_only).unwrap().unwrap();
+        assert_eq!(week[0].len(), 1);
+        assert!(week[1..].iter().all(Vec::is_empty));
+
+        let every_day = card(Some(serde_json::json!({
+            METADATA_OPENING_HOURS: [{ "open": "10:00", "close": "12:00" }]
+        })));
+        let week = opening_hours(&every_day).unwrap().unwrap();
+        assert!(week.iter().all(|day| day.len() == 1));
+
+        assert!(opening_hours(&card(None)).unwrap().is_none());
+    }
+
+    #[test]
+    fn opening_hours_reject_invalid_days_and_times() {
+        for hours in [
+            serde_json::json!([{ "days": [0], "open": "10:00", "close": "12:00" }]),
+            serde_json::json!([{ "days": [8], "open": "10:00", "close": "12:00" }]),
+            serde_json::json!([{ "days": [1], "open": "12:00", "close": "10:00" }]),
+            serde_json::json!([{ "days": [1], "open": "10", "close": "12:00" }]),
+            serde_json::json!({ "open": "10:00", "close": "12:00" }),
+        ] {
+            let card = card(Some(serde_json::json!({ METADATA_OPENING_HOURS: hours })));
+            assert!(opening_hours(&card).is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn cards_go_to_the_days_they_are_open() {
+        // 2025-01-04 is a Saturday, so the trip runs Saturday to Monday
+        let monday_only = card(Some(serde_json::json!({
+            METADATA_OPENING_HOURS: [{ "days": [1], "open": "10:00", "close": "12:00" }]
+        })));
+        let plan = place_without_travel(vec![monday_only], date(4), 3).await;
+        assert_eq!(plan.placements.len(), 1);
+        assert_eq!(plan.placements[0].start_time, at(6, 10, 0));
+        assert_eq!(plan.placements[0].end_time, at(6, 11, 30));
+    }
+
+    #[tokio::test]
+    async fn cards_closed_on_every_trip_day_are_not_placed() {
+        let wednesday_only = card(Some(serde_json::json!({
+            METADATA_OPENING_HOURS: [{ "days": [3], "open": "10:00", "close": "12:00" }]
+        })));
+        let plan = place_without_travel(vec![wednesday_only], date(4), 3).await;
+        assert!(plan.placements.is_empty());
+        assert_eq!(plan.unplaced.len(), 1);
+        assert_eq!(plan.unplaced[0].reason, "no free slot fits the card");
+    }
+
+    #[tokio::test]
+    async fn cards_with_invalid_constraints_are_reported() {
+        let invalid = card(Some(serde_json::json!({ METADATA_DURATION: 0 })));
+        let plan = place_without_travel(vec![invalid], date(4), 1).await;
+        assert!(plan.placements.is_empty());
+        assert!(plan.unplaced[0].reason.contains(METADATA_DURATION));
+    }
+
+    #[tokio::test]
+    async fn cards_are_placed_between_scheduled_cards_with_travel() {
+        let position = GeoPoint::new(2.35, 48.85);
+        let mut morning = scheduled(at(4, 9, 0), at(4, 10, 0));
+        let mut afternoon = scheduled(at(4, 12, 30), at(4, 18, 0));
+        let mut draft = card(Some(serde_json::json!({ METADATA_DURATION: 60 })));
+        morning.position = position;
+        afternoon.position = position;
+        draft.position = position;
+
+        let ids = [morning.id, afternoon.id, draft.id];
+        let router = FixedRouter(Duration::minutes(20));
+        let travel = TravelTimes {
+            router: &router,
+            mode: TravelMode::Walking,
+            positions: ids.iter().map(|id| (*id, position.unwrap())).collect(),
+            distances: ids
+                .iter()
+                .flat_map(|from| ids.iter().map(move |to| ((*from, *to), 1000.0)))
+                .collect(),
+            cache: HashMap::new(),
+        };
+        let draft_id = draft.id;
+        let plan = place(
+            travel,
+            vec![morning, afternoon, draft],
+            date(4),
+            1,
+            &options(),
+        )
+        .await
+        .unwrap();
+
+        // 10:00 plus 20 minutes of travel, rounded up to the next slot
+        assert_eq!(plan.placements.len(), 1);
+        assert_eq!(plan.placements[0].card.id, draft_id);
+        assert_eq!(plan.placements[0].start_time, at(4, 10, 30));
+        assert_eq!(plan.placements[0].end_time, at(4, 11, 30));
+        // Both legs to and from the card, instead of the direct one
+        assert_eq!(plan.added_travel, Duration::minutes(20));
+    }
+
+    #[tokio::test]
+    async fn cards_that_fit_no_gap_are_not_placed() {
+        let morning = scheduled(at(4, 9, 0), at(4, 12, 0));
+        let afternoon = scheduled(at(4, 13, 0), at(4, 18, 0));
+        let draft = card(None);
+        let plan = place_without_travel(vec![morning, afternoon, draft], date(4), 1).await;
+        assert!(plan.placements.is_empty());
+        assert_eq!(plan.unplaced.len(), 1);
+    }
+}
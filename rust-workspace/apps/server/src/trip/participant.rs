@@ -0,0 +1,105 @@
+use sea_orm::sea_query::OnConflict;
+use sea_orm::{ColumnTrait, ConnectionTrait, EntityTrait, PaginatorTrait, QueryFilter, Set};
+use uuid::Uuid;
+use workspace_entity::{chat_participants, chats, trip_participants};
+
+// Values of trip_participants.role
+pub const ROLE_OWNER: &str = "owner";
+pub const ROLE_EDITOR: &str = "editor";
+pub const ROLE_VIEWER: &str = "viewer";
+
+pub const ROLES: &[&str] = &[ROLE_OWNER, ROLE_EDITOR, ROLE_VIEWER];
+
+// Values of chat_participants.role
+pub const CHAT_ROLE_OWNER: &str = "owner";
+const CHAT_ROLE_PARTICIPANT: &str = "participant";
+
+/// Trip owners own the trip's chat; everyone else takes part in it
+fn chat_role(trip_role: &str) -> &'static str {
+    if trip_role == ROLE_OWNER {
+        CHAT_ROLE_OWNER
+    } else {
+        CHAT_ROLE_PARTICIPANT
+    }
+}
+
+/// Role of a profile in a trip; `None` if it doesn't take part
+pub async fn role_of<C: ConnectionTrait>(
+    db: &C,
+    trip_id: Uuid,
+    profile_id: Uuid,
+) -> Result<Option<String>, sea_orm::DbErr> {
+    Ok(trip_participants::Entity::find_by_id((trip_id, profile_id))
+        .one(db)
+        .await?
+        .map(|p| p.role))
+}
+
+pub async fn count_owners<C: ConnectionTrait>(
+    db: &C,
+    trip_id: Uuid,
+) -> Result<u64, sea_orm::DbErr> {
+    trip_participants::Entity::find()
+        .filter(trip_participants::COLUMN.trip_id.eq(trip_id))
+        .filter(trip_participants::COLUMN.role.eq(ROLE_OWNER))
+        .count(db)
+        .await
+}
+
+async fn find_main_chat<C: ConnectionTrait>(
+    db: &C,
+    trip_id: Uuid,
+) -> Result<Option<chats::Model>, sea_orm::DbErr> {
+    chats::Entity::find()
+        .filter(chats::Column::TripId.eq(trip_id))
+        .filter(chats::COLUMN.is_main.eq(true))
+        .one(db)
+        .await
+}
+
+/// Add the participant to the trip's main chat (if the trip has one), or update
+/// their chat role to match `trip_role`
+pub async fn sync_main_chat<C: ConnectionTrait>(
+    db: &C,
+    trip_id: Uuid,
+    profile_id: Uuid,
+    trip_role: &str,
+) -> Result<(), sea_orm::DbErr> {
+    let Some(chat) = find_main_chat(db, trip_id).await? else {
+        return Ok(());
+    };
+
+    chat_participants::Entity::insert(chat_participants::ActiveModel {
+        chat_id: Set(chat.id),
+        profile_id: Set(profile_id),
+        role: Set(chat_role(trip_role).to_owned()),
+        joined_at: Set(chrono::Utc::now().into()),
+    })
+    .on_conflict(
+        OnConflict::columns([
+            chat_participants::Column::ChatId,
+            chat_participants::Column::ProfileId,
+        ])
+        .update_column(chat_participants::Column::Role)
+        .to_owned(),
+    )
+    .exec(db)
+    .await?;
+
+    Ok(())
+}
+
+/// Remove the participant from the trip's main chat; their messages stay
+pub async fn leave_main_chat<C: ConnectionTrait>(
+    db: &C,
+    trip_id: Uuid,
+    profile_id: Uuid,
+) -> Result<(), sea_orm::DbErr> {
+    if let Some(chat) = find_main_chat(db, trip_id).await? {
+        chat_participants::Entity::delete_by_id((chat.id, profile_id))
+            .exec(db)
+            .await?;
+    }
+
+    Ok(())
+}
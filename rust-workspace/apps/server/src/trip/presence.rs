@@ -0,0 +1,155 @@
+use chrono::{DateTime, Duration, Utc};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+/// Seconds after the last heartbeat at which a session drops out
+pub const TTL_SECS: i64 = 30;
+/// Seconds after which a session's membership of the trip is checked again
+pub const RECHECK_SECS: i64 = 60;
+pub const MAX_SESSION_ID_LEN: usize = 64;
+pub const MAX_CURSOR_BYTES: usize = 1024;
+const EXPIRY_INTERVAL_SECS: u64 = 5;
+/// Events buffered per trip for slow subscribers before they are sent a new snapshot
+const CHANNEL_CAPACITY: usize = 64;
+
+#[derive(Clone, Debug)]
+pub struct Session {
+    pub account_id: Uuid,
+    pub session_id: String,
+    pub profile_id: Uuid,
+    pub trip_card_id: Option<Uuid>,
+    pub cursor: Vec<u8>,
+    pub joined_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    /// Until when the account is known to take part in the trip
+    pub verified_until: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug)]
+pub enum Event {
+    Join(Session),
+    Update(Session),
+    Leave(Session),
+}
+
+struct TripPresence {
+    sender: broadcast::Sender<Event>,
+    sessions: HashMap<(Uuid, String), Session>,
+}
+
+impl TripPresence {
+    fn new() -> Self {
+        TripPresence {
+            sender: broadcast::channel(CHANNEL_CAPACITY).0,
+            sessions: HashMap::new(),
+        }
+    }
+
+    fn is_idle(&self) -> bool {
+        self.sessions.is_empty() && self.sender.receiver_count() == 0
+    }
+}
+
+/// Ephemeral presence of every trip, held in memory of this server process only
+#[derive(Default)]
+pub struct Hub {
+    trips: Mutex<HashMap<Uuid, TripPresence>>,
+}
+
+impl Hub {
+    /// The account's session in a trip, if present
+    pub fn session(&self, trip_id: Uuid, account_id: Uuid, session_id: &str) -> Option<Session> {
+        let trips = self.trips.lock().expect("presence hub poisoned");
+        trips
+            .get(&trip_id)?
+            .sessions
+            .get(&(account_id, session_id.to_owned()))
+            .cloned()
+    }
+
+    /// Add or refresh a session. Subscribers are told about joins, and about updates when
+    /// the focus or cursor changed; plain heartbeats only extend the session.
+    pub fn heartbeat(&self, trip_id: Uuid, mut session: Session) {
+        let mut trips = self.trips.lock().expect("presence hub poisoned");
+        let trip = trips.entry(trip_id).or_insert_with(TripPresence::new);
+        let key = (session.account_id, session.session_id.clone());
+
+        let event = match trip.sessions.get(&key) {
+            None => Some(Event::Join(session.clone())),
+            Some(existing) => {
+                session.joined_at = existing.joined_at;
+                (existing.trip_card_id != session.trip_card_id || existing.cursor != session.cursor)
+                    .then(|| Event::Update(session.clone()))
+            }
+        };
+        trip.sessions.insert(key, session);
+
+        if let Some(event) = event {
+            // Fails only without subscribers
+            let _ = trip.sender.send(event);
+        }
+    }
+
+    /// Remove a session; returns false if it wasn't present
+    pub fn leave(&self, trip_id: Uuid, account_id: Uuid, session_id: &str) -> bool {
+        let mut trips = self.trips.lock().expect("presence hub poisoned");
+        let Some(trip) = trips.get_mut(&trip_id) else {
+            return false;
+        };
+        let Some(session) = trip.sessions.remove(&(account_id, session_id.to_owned())) else {
+            return false;
+        };
+        let _ = trip.sender.send(Event::Leave(session));
+        if trip.is_idle() {
+            trips.remove(&trip_id);
+        }
+        true
+    }
+
+    /// The sessions present in a trip, and a receiver of everything that happens after
+    pub fn subscribe(&self, trip_id: Uuid) -> (Vec<Session>, broadcast::Receiver<Event>) {
+        let mut trips = self.trips.lock().expect("presence hub poisoned");
+        let trip = trips.entry(trip_id).or_insert_with(TripPresence::new);
+        let mut sessions: Vec<Session> = trip.sessions.values().cloned().collect();
+        sessions.sort_by_key(|s| s.joined_at);
+        (sessions, trip.sender.subscribe())
+    }
+
+    /// Drop the sessions whose TTL has passed, and trips nobody is present in or watching
+    pub fn expire(&self) {
+        let now = Utc::now();
+        let mut trips = self.trips.lock().expect("presence hub poisoned");
+        trips.retain(|_, trip| {
+            trip.sessions.retain(|_, session| {
+                if session.expires_at > now {
+                    return true;
+                }
+                let _ = trip.sender.send(Event::Leave(session.clone()));
+                false
+            });
+            !trip.is_idle()
+        });
+    }
+}
+
+pub fn expires_at(now: DateTime<Utc>) -> DateTime<Utc> {
+    now + Duration::seconds(TTL_SECS)
+}
+
+/// Ticks every `RECHECK_SECS`, starting one period from now; subscribers check their
+/// access again on each tick
+pub fn recheck_interval() -> tokio::time::Interval {
+    let period = std::time::Duration::from_secs(RECHECK_SECS as u64);
+    tokio::time::interval_at(tokio::time::Instant::now() + period, period)
+}
+
+/// Periodically expire sessions that stopped heartbeating
+pub async fn run_expiry(hub: Arc<Hub>) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(EXPIRY_INTERVAL_SECS));
+    loop {
+        interval.tick().await;
+        hub.expire();
+    }
+}
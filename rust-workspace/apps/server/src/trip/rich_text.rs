@@ -0,0 +1,414 @@
+use crate::error::Error;
+use chrono::Utc;
+use sea_orm::{
+    ActiveModelTrait, ConnectionTrait, DbErr, EntityTrait, QueryFilter, QueryOrder, QuerySelect,
+    Set,
+};
+use serde_json::{Map, Value, json};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tokio::sync::broadcast;
+use uuid::Uuid;
+use workspace_entity::{trip_card_rich_text, trip_card_rich_text_updates};
+use yrs::types::ToJson;
+use yrs::types::text::YChange;
+use yrs::types::xml::{XmlFragment, XmlFragmentRef, XmlOut};
+use yrs::updates::decoder::Decode;
+use yrs::{Any, Doc, Out, ReadTxn, StateVector, Text, Transact, Update, Xml};
+
+/// Largest Yjs update accepted in one push
+pub const MAX_UPDATE_BYTES: usize = 256 * 1024;
+/// Updates after the last snapshot from which they are compacted into a new snapshot
+const SNAPSHOT_THRESHOLD: u64 = 100;
+/// Updates buffered per card for slow subscribers before they have to catch up from the database
+const CHANNEL_CAPACITY: usize = 256;
+
+/// Relays new updates to the subscribers of a card. Only reaches subscribers connected to
+/// this server process; subscribers notice missed updates by their `seq` and reload them.
+#[derive(Default)]
+pub struct Hub {
+    channels: Mutex<HashMap<Uuid, broadcast::Sender<trip_card_rich_text_updates::Model>>>,
+}
+
+impl Hub {
+    pub fn subscribe(
+        &self,
+        trip_card_id: Uuid,
+    ) -> broadcast::Receiver<trip_card_rich_text_updates::Model> {
+        let mut channels = self.channels.lock().expect("rich text hub poisoned");
+        channels
+            .entry(trip_card_id)
+            .or_insert_with(|| broadcast::channel(CHANNEL_CAPACITY).0)
+            .subscribe()
+    }
+
+    pub fn publish(&self, update: trip_card_rich_text_updates::Model) {
+        let mut channels = self.channels.lock().expect("rich text hub poisoned");
+        if let Some(sender) = channels.get(&update.trip_card_id) {
+            let trip_card_id = update.trip_card_id;
+            // Fails once every subscriber is gone
+            if sender.send(update).is_err() {
+                channels.remove(&trip_card_id);
+            }
+        }
+    }
+}
+
+/// The card's document, created empty if the card has none yet, locked for the rest of
+/// the transaction so updates get consecutive `seq`s
+pub async fn lock_document<C: ConnectionTrait>(
+    db: &C,
+    trip_card_id: Uuid,
+) -> Result<trip_card_rich_text::Model, DbErr> {
+    let now = Utc::now();
+    trip_card_rich_text::Entity::insert(trip_card_rich_text::ActiveModel {
+        trip_card_id: Set(trip_card_id),
+        content: Set(serde_json::json!({})),
+        last_edited_by: Set(None),
+        created_at: Set(now.into()),
+        updated_at: Set(now.into()),
+        crdt_snapshot: Set(None),
+        snapshot_seq: Set(0),
+        crdt_seq: Set(0),
+        content_seq: Set(0),
+    })
+    .on_conflict_do_nothing()
+    .exec(db)
+    .await?;
+
+    trip_card_rich_text::Entity::find_by_id(trip_card_id)
+        .lock_exclusive()
+        .one(db)
+        .await?
+        .ok_or_else(|| DbErr::RecordNotFound("trip_card_rich_text".to_owned()))
+}
+
+/// Updates of a card after `since`, in order
+pub async fn updates_since<C: ConnectionTrait>(
+    db: &C,
+    trip_card_id: Uuid,
+    since: i64,
+) -> Result<Vec<trip_card_rich_text_updates::Model>, DbErr> {
+    trip_card_rich_text_updates::Entity::find()
+        .filter(
+            trip_card_rich_text_updates::COLUMN
+                .trip_card_id
+                .eq(trip_card_id),
+        )
+        .filter(trip_card_rich_text_updates::COLUMN.seq.gt(since))
+        .order_by_asc(trip_card_rich_text_updates::Column::Seq)
+        .all(db)
+        .await
+}
+
+/// Name of the shared type holding a card's note in the Yjs document: an XmlFragment, as
+/// edited through y-prosemirror or Tiptap's collaboration extension
+pub const FRAGMENT: &str = "default";
+
+/// Apply an update to a locked document: the update is merged into the document state on
+/// the server, which derives the JSON `content` from it. Updates are numbered
+/// consecutively; once enough have piled up after the last snapshot, they are compacted
+/// into a new one.
+pub async fn append_update<C: ConnectionTrait>(
+    db: &C,
+    document: trip_card_rich_text::Model,
+    payload: Vec<u8>,
+    profile_id: Option<Uuid>,
+) -> Result<trip_card_rich_text_updates::Model, Error> {
+    let pending = updates_since(db, document.trip_card_id, document.snapshot_seq).await?;
+    let compact = pending.len() as u64 + 1 >= SNAPSHOT_THRESHOLD;
+    let merged = merge(
+        document.crdt_snapshot.as_deref(),
+        pending.iter().map(|u| u.payload.as_slice()),
+        &payload,
+        compact,
+    )?;
+
+    let seq = document.crdt_seq + 1;
+    let now = Utc::now();
+    let update = trip_card_rich_text_updates::ActiveModel {
+        trip_card_id: Set(document.trip_card_id),
+        seq: Set(seq),
+        payload: Set(payload),
+        profile_id: Set(profile_id),
+        created_at: Set(now.into()),
+    }
+    .insert(db)
+    .await?;
+
+    let trip_card_id = document.trip_card_id;
+    let mut active: trip_card_rich_text::ActiveModel = document.into();
+    active.crdt_seq = Set(seq);
+    active.content = Set(merged.content);
+    active.content_seq = Set(seq);
+    if let Some(snapshot) = merged.snapshot {
+        active.crdt_snapshot = Set(Some(snapshot));
+        active.snapshot_seq = Set(seq);
+    }
+    active.last_edited_by = Set(profile_id);
+    active.updated_at = Set(now.into());
+    active.update(db).await?;
+
+    if compact {
+        trip_card_rich_text_updates::Entity::delete_many()
+            .filter(
+                trip_card_rich_text_updates::COLUMN
+                    .trip_card_id
+                    .eq(trip_card_id),
+            )
+            .filter(trip_card_rich_text_updates::COLUMN.seq.lte(seq))
+            .exec(db)
+            .await?;
+    }
+
+    Ok(update)
+}
+
+struct Merged {
+    content: Value,
+    /// The whole document as one update, if asked for
+    snapshot: Option<Vec<u8>>,
+}
+
+/// Merge `update` into the document made of `snapshot` and the `stored` updates after it
+fn merge<'a>(
+    snapshot: Option<&'a [u8]>,
+    stored: impl IntoIterator<Item = &'a [u8]>,
+    update: &[u8],
+    with_snapshot: bool,
+) -> Result<Merged, Error> {
+    let doc = Doc::new();
+    let fragment = doc.get_or_insert_xml_fragment(FRAGMENT);
+    let mut txn = doc.transact_mut();
+
+    // What is stored was accepted before, so failing to apply it is a server error
+    for stored in snapshot.into_iter().chain(stored) {
+        let stored = Update::decode_v1(stored).map_err(anyhow::Error::from)?;
+        txn.apply_update(stored).map_err(anyhow::Error::from)?;
+    }
+
+    let update = Update::decode_v1(update)
+        .map_err(|_| Error::invalid_field("update", "must be a Yjs update (v1 encoding)"))?;
+    txn.apply_update(update)
+        .map_err(|_| Error::invalid_field("update", "can't be applied to the document"))?;
+    // Stored documents never have missing updates, so these were missed by the author
+    if txn.has_missing_updates() {
+        return Err(Error::invalid_field(
+            "update",
+            "depends on updates the document doesn't have",
+        ));
+    }
+
+    Ok(Merged {
+        content: to_content(&txn, &fragment),
+        snapshot: with_snapshot.then(|| txn.encode_state_as_update_v1(&StateVector::default())),
+    })
+}
+
+/// The fragment as ProseMirror JSON: `{"type": "doc", "content": [...]}` with elements as
+/// nodes and formatted text as text nodes with marks
+fn to_content<T: ReadTxn>(txn: &T, fragment: &XmlFragmentRef) -> Value {
+    json!({ "type": "doc", "content": nodes(txn, fragment.children(txn)) })
+}
+
+fn nodes<T: ReadTxn>(txn: &T, children: impl Iterator<Item = XmlOut>) -> Vec<Value> {
+    let mut out = Vec::new();
+    for child in children {
+        match child {
+            XmlOut::Element(element) => {
+                let mut node = Map::new();
+                node.insert("type".to_owned(), Value::from(element.tag().as_ref()));
+                let attrs: Map<String, Value> = element
+                    .attributes(txn)
+                    .map(|(name, value)| (name.to_owned(), out_to_json(txn, value)))
+                    .collect();
+                if !attrs.is_empty() {
+                    node.insert("attrs".to_owned(), Value::Object(attrs));
+                }
+                let content = nodes(txn, element.children(txn));
+                if !content.is_empty() {
+                    node.insert("content".to_owned(), Value::Array(content));
+                }
+                out.push(Value::Object(node));
+            }
+            XmlOut::Fragment(fragment) => out.extend(nodes(txn, fragment.children(txn))),
+            XmlOut::Text(text) => {
+                for chunk in text.diff(txn, YChange::identity) {
+                    // Embedded values have no ProseMirror counterpart
+                    let Out::Any(Any::String(string)) = chunk.insert else {
+                        continue;
+                    };
+                    let mut node = json!({ "type": "text", "text": string.as_ref() });
+                    let mut marks: Vec<(&str, &Any)> = chunk
+                        .attributes
+                        .iter()
+                        .flat_map(|attrs| attrs.iter())
+                        .map(|(name, value)| (name.as_ref(), value))
+                        .collect();
+                    marks.sort_by_key(|(name, _)| *name);
+                    if !marks.is_empty() {
+                        node["marks"] = marks
+                            .into_iter()
+                            .map(|(name, value)| match any_to_json(value) {
+                                attrs @ Value::Object(_) => json!({ "type": name, "attrs": attrs }),
+                                _ => json!({ "type": name }),
+                            })
+                            .collect();
+                    }
+                    out.push(node);
+                }
+            }
+        }
+    }
+    out
+}
+
+fn out_to_json<T: ReadTxn>(txn: &T, value: Out) -> Value {
+    match value {
+        Out::Any(any) => any_to_json(&any),
+        other => any_to_json(&other.to_json(txn)),
+    }
+}
+
+fn any_to_json(any: &Any) -> Value {
+    serde_json::to_value(any).unwrap_or(Value::Null)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use yrs::types::Attrs;
+    use yrs::{XmlElementPrelim, XmlTextPrelim, XmlTextRef};
+
+    /// A client's copy of the document, with the given updates applied
+    fn client(id: u64, updates: &[&[u8]]) -> Doc {
+        let doc = Doc::with_client_id(id);
+        doc.get_or_insert_xml_fragment(FRAGMENT);
+        let mut txn = doc.transact_mut();
+        for update in updates {
+            txn.apply_update(Update::decode_v1(update).unwrap())
+                .unwrap();
+        }
+        drop(txn);
+        doc
+    }
+
+    /// Edit the client's document, returning the update of the edit
+    fn edit(doc: &Doc, f: impl FnOnce(&mut yrs::TransactionMut, XmlFragmentRef)) -> Vec<u8> {
+        let fragment = doc.get_or_insert_xml_fragment(FRAGMENT);
+        let mut txn = doc.transact_mut();
+        f(&mut txn, fragment);
+        txn.encode_update_v1()
+    }
+
+    fn paragraph_text(txn: &yrs::TransactionMut, fragment: &XmlFragmentRef, i: u32) -> XmlTextRef {
+        let Some(XmlOut::Element(paragraph)) = fragment.get(txn, i) else {
+            panic!("no paragraph {i}");
+        };
+        let Some(XmlOut::Text(text)) = paragraph.get(txn, 0) else {
+            panic!("no text in paragraph {i}");
+        };
+        text
+    }
+
+    fn paragraph(text: &str) -> Value {
+        json!({ "type": "paragraph", "content": [{ "type": "text", "text": text }] })
+    }
+
+    fn is_invalid_update(result: Result<Merged, Error>) -> bool {
+        matches!(result, Err(Error::InvalidArgument(fields)) if fields[0].field == "update")
+    }
+
+    fn initial() -> Vec<u8> {
+        edit(&client(1, &[]), |txn, fragment| {
+            let paragraph = fragment.push_back(txn, XmlElementPrelim::empty("paragraph"));
+            paragraph.push_back(txn, XmlTextPrelim::new("Hello"));
+        })
+    }
+
+    #[test]
+    fn concurrent_edits_are_merged() {
+        let initial = initial();
+
+        // Two clients edit the same state without seeing each other's change
+        let append = edit(&client(2, &[&initial]), |txn, fragment| {
+            paragraph_text(txn, &fragment, 0).push(txn, " world");
+        });
+        let add = edit(&client(3, &[&initial]), |txn, fragment| {
+            let paragraph = fragment.push_back(txn, XmlElementPrelim::empty("paragraph"));
+            paragraph.push_back(txn, XmlTextPrelim::new("Bye"));
+        });
+
+        let merged = merge(None, [initial.as_slice(), &append], &add, false).unwrap();
+        assert_eq!(
+            merged.content,
+            json!({ "type": "doc", "content": [paragraph("Hello world"), paragraph("Bye")] })
+        );
+        assert!(merged.snapshot.is_none());
+    }
+
+    #[test]
+    fn snapshots_hold_the_whole_document() {
+        let initial = initial();
+        let append = edit(&client(2, &[&initial]), |txn, fragment| {
+            paragraph_text(txn, &fragment, 0).push(txn, "!");
+        });
+
+        let merged = merge(None, [initial.as_slice()], &append, true).unwrap();
+        let snapshot = merged.snapshot.unwrap();
+
+        // Later updates apply on top of the snapshot alone
+        let more = edit(&client(3, &[&snapshot]), |txn, fragment| {
+            paragraph_text(txn, &fragment, 0).insert(txn, 0, ">");
+        });
+        let merged = merge(Some(&snapshot), [], &more, false).unwrap();
+        assert_eq!(merged.content["content"][0], paragraph(">Hello!"));
+    }
+
+    #[test]
+    fn formatting_becomes_marks() {
+        let initial = initial();
+        let format = edit(&client(2, &[&initial]), |txn, fragment| {
+            let text = paragraph_text(txn, &fragment, 0);
+            let attrs = Attrs::from([
+                ("bold".into(), Any::Bool(true)),
+                (
+                    "link".into(),
+                    Any::from(HashMap::from([(
+                        "href".to_owned(),
+                        Any::from("https://example.com"),
+                    )])),
+                ),
+            ]);
+            text.format(txn, 0, 2, attrs);
+        });
+
+        let merged = merge(None, [initial.as_slice()], &format, false).unwrap();
+        assert_eq!(
+            merged.content["content"][0]["content"],
+            json!([
+                {
+                    "type": "text",
+                    "text": "He",
+                    "marks": [
+                        { "type": "bold" },
+                        { "type": "link", "attrs": { "href": "https://example.com" } },
+                    ],
+                },
+                { "type": "text", "text": "llo" },
+            ])
+        );
+    }
+
+    #[test]
+    fn rejects_updates_that_cant_be_merged() {
+        assert!(is_invalid_update(merge(None, [], b"not an update", false)));
+
+        // An edit of a document the server never saw
+        let initial = initial();
+        let append = edit(&client(2, &[&initial]), |txn, fragment| {
+            paragraph_text(txn, &fragment, 0).push(txn, " world");
+        });
+        assert!(is_invalid_update(merge(None, [], &append, false)));
+    }
+}
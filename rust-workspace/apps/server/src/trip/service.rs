@@ -0,0 +1,2334 @@
+use crate::AppState;
+use crate::auth::api_token::{SCOPE_TRIPS_READ, SCOPE_TRIPS_WRITE};
+use crate::auth::audit;
+use crate::auth::context::AuthContext;
+use crate::auth::jwt;
+use crate::auth::realm;
+use crate::auth::service::ClientInfo;
+use crate::auth::throttle::{self, ThrottleScope};
+use crate::error::Error;
+use crate::profile;
+use crate::quota;
+use crate::trip::{
+    activity, copy, geo, ical, itinerary, optimizer, participant, presence, rich_text, share,
+};
+use async_stream::try_stream;
+use axum::extract::{Path, Query, State};
+use axum::http;
+use axum::response::{IntoResponse, Response};
+use axum_connect::futures::Stream;
+use chrono::Utc;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, ConnectionTrait, EntityTrait, ExprTrait, QueryFilter,
+    QueryOrder, QuerySelect, Set, TransactionTrait,
+};
+use tokio::sync::broadcast;
+use uuid::Uuid;
+use workspace_entity::geo_point::GeoPoint;
+use workspace_entity::{
+    account_realm_roles, accounts, activity_log, profiles, trip_card_rich_text,
+    trip_card_rich_text_updates, trip_cards, trip_participants, trip_share_links, trips,
+};
+
+use crate::proto::trip::*;
+
+/// Convert profiles::Model to protobuf Profile
+fn profile_to_proto(profile: profiles::Model) -> Profile {
+    Profile {
+        id: profile.id.to_string(),
+        realm_id: profile.realm_id.to_string(),
+        username: profile.username,
+        email: profile.email,
+        phone: profile.phone,
+        created_at: profile.created_at.to_rfc3339(),
+        account_id: profile
+            .account_id
+            .map(|id| id.to_string())
+            .unwrap_or_default(),
+    }
+}
+
+/// Convert trip_participants::Model to protobuf TripParticipant
+fn participant_to_proto(
+    participant: trip_participants::Model,
+    profile: Option<profiles::Model>,
+) -> TripParticipant {
+    TripParticipant {
+        trip_id: participant.trip_id.to_string(),
+        profile_id: participant.profile_id.to_string(),
+        role: participant.role,
+        joined_at: participant.joined_at.to_rfc3339(),
+        profile: profile.map(profile_to_proto),
+    }
+}
+
+pub fn parse_uuid(field: &str, value: &str) -> Result<Uuid, Error> {
+    Uuid::parse_str(value).map_err(|_| Error::invalid_field(field, "must be a UUID"))
+}
+
+fn validate_role(role: &str) -> Result<(), Error> {
+    if !participant::ROLES.contains(&role) {
+        return Err(Error::invalid_field(
+            "role",
+            "must be 'owner', 'editor' or 'viewer'",
+        ));
+    }
+    Ok(())
+}
+
+/// A trip as seen by the caller
+pub struct TripAccess {
+    pub trip: trips::Model,
+    /// The caller's profile in the trip's realm, if it has one
+    pub profile: Option<profiles::Model>,
+    /// The caller's role in the trip; realm admins are owners of every trip
+    pub role: Option<String>,
+}
+
+impl TripAccess {
+    pub fn is_owner(&self) -> bool {
+        self.role.as_deref() == Some(participant::ROLE_OWNER)
+    }
+
+    /// The caller's profile, recorded as the actor of their changes
+    pub fn profile_id(&self) -> Option<Uuid> {
+        self.profile.as_ref().map(|p| p.id)
+    }
+
+    pub fn can_edit(&self) -> bool {
+        matches!(
+            self.role.as_deref(),
+            Some(participant::ROLE_OWNER | participant::ROLE_EDITOR)
+        )
+    }
+}
+
+/// Load a trip of the selected realm with the caller's role in it. With `for_update`,
+/// the trip row is locked so concurrent membership changes can't race the last-owner check.
+pub async fn trip_access<C: ConnectionTrait>(
+    db: &C,
+    ctx: &AuthContext,
+    trip_id: Uuid,
+    for_update: bool,
+) -> Result<TripAccess, Error> {
+    let account_id = ctx.require_account()?;
+    let realm_id = ctx.require_realm()?;
+
+    let mut query = trips::Entity::find_by_id(trip_id);
+    if for_update {
+        query = query.lock_exclusive();
+    }
+    let trip = query.one(db).await?.ok_or(Error::NotFound)?;
+    if trip.realm_id != realm_id {
+        return Err(Error::Forbidden);
+    }
+    // Templates are only reachable through the template handlers
+    if trip.status == copy::STATUS_TEMPLATE {
+        return Err(Error::NotFound);
+    }
+
+    let profile = profile::find_for_account(db, realm_id, account_id).await?;
+
+    let role = if ctx.roles.iter().any(|r| r == "admin") {
+        Some(participant::ROLE_OWNER.to_owned())
+    } else if let Some(profile) = &profile {
+        participant::role_of(db, trip.id, profile.id).await?
+    } else {
+        None
+    };
+
+    Ok(TripAccess {
+        trip,
+        profile,
+        role,
+    })
+}
+
+/// Load a card of the selected realm with the caller's access to its trip. With
+/// `for_update`, the card row is locked for the rest of the transaction.
+async fn card_access<C: ConnectionTrait>(
+    db: &C,
+    ctx: &AuthContext,
+    trip_card_id: &str,
+    for_update: bool,
+) -> Result<(trip_cards::Model, TripAccess), Error> {
+    let trip_card_id = parse_uuid("trip_card_id", trip_card_id)?;
+    let mut query = trip_cards::Entity::find_by_id(trip_card_id);
+    if for_update {
+        query = query.lock_exclusive();
+    }
+    let card = query.one(db).await?.ok_or(Error::NotFound)?;
+    let access = trip_access(db, ctx, card.trip_id, false).await?;
+    Ok((card, access))
+}
+
+/// Check again that the caller of a long-lived stream can read the trip. Realm roles are
+/// reloaded too, so leaving the realm or losing the admin role also ends the stream.
+async fn recheck_read_access(
+    state: &AppState,
+    ctx: &mut AuthContext,
+    trip_id: Uuid,
+) -> Result<(), Error> {
+    ctx.roles = ctx.roles_in(state, ctx.require_realm()?).await?;
+    let access = trip_access(&state.conn, ctx, trip_id, false).await?;
+    if access.role.is_none() {
+        return Err(Error::Forbidden);
+    }
+    Ok(())
+}
+
+/// Participant of a trip together with their profile
+async fn find_participant<C: ConnectionTrait>(
+    db: &C,
+    trip_id: Uuid,
+    profile_id: Uuid,
+) -> Result<(trip_participants::Model, Option<profiles::Model>), Error> {
+    trip_participants::Entity::find_by_id((trip_id, profile_id))
+        .find_also_related(profiles::Entity)
+        .one(db)
+        .await?
+        .ok_or(Error::NotFound)
+}
+
+/// Get My Profile handler
+pub async fn get_my_profile(
+    State(state): State<AppState>,
+    ctx: AuthContext,
+    _request: GetMyProfileRequest,
+) -> Result<GetMyProfileResponse, Error> {
+    ctx.require_scope(SCOPE_TRIPS_READ)?;
+    let account_id = ctx.require_account()?;
+    // Membership of the realm was checked when the context was built
+    let realm_id = ctx.require_realm()?;
+
+    let account = accounts::Entity::find_by_id(account_id)
+        .one(&state.conn)
+        .await?
+        .ok_or(Error::Unauthenticated)?;
+    let profile = profile::ensure_for_account(&state.conn, realm_id, &account).await?;
+
+    Ok(GetMyProfileResponse {
+        profile: Some(profile_to_proto(profile)),
+    })
+}
+
+/// Merge Profiles handler
+pub async fn merge_profiles(
+    State(state): State<AppState>,
+    ctx: AuthContext,
+    client: ClientInfo,
+    request: MergeProfilesRequest,
+) -> Result<MergeProfilesResponse, Error> {
+    ctx.require_scope(SCOPE_TRIPS_WRITE)?;
+    let account_id = ctx.require_account()?;
+    let realm_id = ctx.require_realm()?;
+    if !ctx.roles.iter().any(|r| r == "admin") {
+        return Err(Error::Forbidden);
+    }
+
+    let source_id = parse_uuid("source_profile_id", &request.source_profile_id)?;
+    let target_id = parse_uuid("target_profile_id", &request.target_profile_id)?;
+    if source_id == target_id {
+        return Err(Error::invalid_field(
+            "target_profile_id",
+            "must differ from source_profile_id",
+        ));
+    }
+
+    let txn = state.conn.begin().await?;
+
+    let mut profiles = profiles::Entity::find()
+        .filter(profiles::COLUMN.id.is_in([source_id, target_id]))
+        .filter(profiles::COLUMN.realm_id.eq(realm_id))
+        .lock_exclusive()
+        .all(&txn)
+        .await?;
+    let Some(source) = profiles
+        .iter()
+        .position(|p| p.id == source_id)
+        .map(|i| profiles.remove(i))
+    else {
+        return Err(Error::invalid_field(
+            "source_profile_id",
+            "no such profile in this realm",
+        ));
+    };
+    let Some(target) = profiles.pop() else {
+        return Err(Error::invalid_field(
+            "target_profile_id",
+            "no such profile in this realm",
+        ));
+    };
+    if let (Some(a), Some(b)) = (source.account_id, target.account_id)
+        && a != b
+    {
+        return Err(Error::FailedPrecondition(
+            "both profiles are linked to different accounts".to_string(),
+        ));
+    }
+
+    let actor = profile::find_for_account(&txn, realm_id, account_id)
+        .await?
+        .map(|p| p.id);
+    let merged = profile::merge(&txn, source, target, actor).await?;
+
+    audit::record_event(
+        &txn,
+        audit::PROFILES_MERGED,
+        Some(account_id),
+        None,
+        &client,
+        Some(serde_json::json!({
+            "realm_id": realm_id,
+            "source_profile_id": source_id,
+            "target_profile_id": target_id,
+        })),
+    )
+    .await
+    .map_err(Error::Anyhow)?;
+
+    txn.commit().await?;
+
+    Ok(MergeProfilesResponse {
+        profile: Some(profile_to_proto(merged)),
+    })
+}
+
+/// Add Participant handler
+pub async fn add_participant(
+    State(state): State<AppState>,
+    ctx: AuthContext,
+    request: AddParticipantRequest,
+) -> Result<AddParticipantResponse, Error> {
+    ctx.require_scope(SCOPE_TRIPS_WRITE)?;
+
+    let role = if request.role.is_empty() {
+        participant::ROLE_VIEWER
+    } else {
+        request.role.as_str()
+    };
+    validate_role(role)?;
+
+    let account = request.account.trim();
+    if request.profile_id.is_empty() == account.is_empty() {
+        return Err(Error::InvalidArgument(
+            ["profile_id", "account"]
+                .into_iter()
+                .map(|field| crate::error::FieldViolation {
+                    field: field.to_string(),
+                    description: "exactly one of profile_id and account is required".to_string(),
+                })
+                .collect(),
+        ));
+    }
+
+    let txn = state.conn.begin().await?;
+
+    let access = trip_access(&txn, &ctx, parse_uuid("trip_id", &request.trip_id)?, true).await?;
+    if !access.is_owner() {
+        return Err(Error::Forbidden);
+    }
+    let realm_id = access.trip.realm_id;
+
+    let profile = if account.is_empty() {
+        let profile_id = parse_uuid("profile_id", &request.profile_id)?;
+        profiles::Entity::find_by_id(profile_id)
+            .one(&txn)
+            .await?
+            .filter(|p| p.realm_id == realm_id)
+            .ok_or_else(|| Error::invalid_field("profile_id", "no such profile in this realm"))?
+    } else {
+        let member = accounts::Entity::find()
+            .filter(
+                accounts::Column::Email
+                    .eq(account)
+                    .or(accounts::Column::Username.eq(account)),
+            )
+            .one(&txn)
+            .await?
+            .filter(|a| a.is_active);
+        let is_member = match &member {
+            Some(member) => account_realm_roles::Entity::find()
+                .filter(account_realm_roles::COLUMN.account_id.eq(member.id))
+                .filter(account_realm_roles::COLUMN.realm_id.eq(realm_id))
+                .one(&txn)
+                .await?
+                .is_some(),
+            None => false,
+        };
+        let Some(member) = member.filter(|_| is_member) else {
+            return Err(Error::invalid_field(
+                "account",
+                "no such account in this realm",
+            ));
+        };
+        profile::ensure_for_account(&txn, realm_id, &member).await?
+    };
+
+    if participant::role_of(&txn, access.trip.id, profile.id)
+        .await?
+        .is_some()
+    {
+        return Err(Error::AlreadyExists("trip participant".to_string()));
+    }
+
+    let created = trip_participants::ActiveModel {
+        trip_id: Set(access.trip.id),
+        profile_id: Set(profile.id),
+        role: Set(role.to_owned()),
+        joined_at: Set(Utc::now().into()),
+    }
+    .insert(&txn)
+    .await?;
+    participant::sync_main_chat(&txn, access.trip.id, profile.id, role).await?;
+    activity::record(
+        &txn,
+        access.trip.id,
+        access.profile_id(),
+        activity::ENTITY_TRIP_PARTICIPANT,
+        profile.id,
+        activity::ACTION_CREATE,
+        None,
+        Some(activity::snapshot(&created)?),
+    )
+    .await?;
+
+    txn.commit().await?;
+
+    Ok(AddParticipantResponse {
+        participant: Some(participant_to_proto(created, Some(profile))),
+    })
+}
+
+/// Remove Participant handler
+pub async fn remove_participant(
+    State(state): State<AppState>,
+    ctx: AuthContext,
+    request: RemoveParticipantRequest,
+) -> Result<RemoveParticipantResponse, Error> {
+    ctx.require_scope(SCOPE_TRIPS_WRITE)?;
+    let profile_id = parse_uuid("profile_id", &request.profile_id)?;
+
+    let txn = state.conn.begin().await?;
+
+    let access = trip_access(&txn, &ctx, parse_uuid("trip_id", &request.trip_id)?, true).await?;
+    // Anyone may leave a trip; removing others is up to its owners
+    let is_self = access.profile.as_ref().is_some_and(|p| p.id == profile_id);
+    if !access.is_owner() && !is_self {
+        return Err(Error::Forbidden);
+    }
+
+    let (removed, _) = find_participant(&txn, access.trip.id, profile_id).await?;
+    if removed.role == participant::ROLE_OWNER
+        && participant::count_owners(&txn, access.trip.id).await? <= 1
+    {
+        return Err(Error::FailedPrecondition(
+            "the last owner of a trip can't be removed".to_string(),
+        ));
+    }
+
+    trip_participants::Entity::delete_by_id((access.trip.id, profile_id))
+        .exec(&txn)
+        .await?;
+    participant::leave_main_chat(&txn, access.trip.id, profile_id).await?;
+    activity::record(
+        &txn,
+        access.trip.id,
+        access.profile_id(),
+        activity::ENTITY_TRIP_PARTICIPANT,
+        profile_id,
+        activity::ACTION_DELETE,
+        Some(activity::snapshot(&removed)?),
+        None,
+    )
+    .await?;
+
+    txn.commit().await?;
+
+    Ok(RemoveParticipantResponse { success: true })
+}
+
+/// Change Participant Role handler
+pub async fn change_participant_role(
+    State(state): State<AppState>,
+    ctx: AuthContext,
+    request: ChangeParticipantRoleRequest,
+) -> Result<ChangeParticipantRoleResponse, Error> {
+    ctx.require_scope(SCOPE_TRIPS_WRITE)?;
+    let profile_id = parse_uuid("profile_id", &request.profile_id)?;
+    validate_role(&request.role)?;
+
+    let txn = state.conn.begin().await?;
+
+    let access = trip_access(&txn, &ctx, parse_uuid("trip_id", &request.trip_id)?, true).await?;
+    if !access.is_owner() {
+        return Err(Error::Forbidden);
+    }
+
+    let (existing, profile) = find_participant(&txn, access.trip.id, profile_id).await?;
+    if existing.role == participant::ROLE_OWNER
+        && request.role != participant::ROLE_OWNER
+        && participant::count_owners(&txn, access.trip.id).await? <= 1
+    {
+        return Err(Error::FailedPrecondition(
+            "the last owner of a trip can't be demoted".to_string(),
+        ));
+    }
+
+    let mut active: trip_participants::ActiveModel = existing.clone().into();
+    active.role = Set(request.role.clone());
+    let updated = active.update(&txn).await?;
+    participant::sync_main_chat(&txn, access.trip.id, profile_id, &request.role).await?;
+    activity::record_update(
+        &txn,
+        access.trip.id,
+        access.profile_id(),
+        activity::ENTITY_TRIP_PARTICIPANT,
+        profile_id,
+        &existing,
+        &updated,
+    )
+    .await?;
+
+    txn.commit().await?;
+
+    Ok(ChangeParticipantRoleResponse {
+        participant: Some(participant_to_proto(updated, profile)),
+    })
+}
+
+/// List Participants handler
+pub async fn list_participants(
+    State(state): State<AppState>,
+    ctx: AuthContext,
+    request: ListParticipantsRequest,
+) -> Result<ListParticipantsResponse, Error> {
+    ctx.require_scope(SCOPE_TRIPS_READ)?;
+
+    let access = trip_access(
+        &state.conn,
+        &ctx,
+        parse_uuid("trip_id", &request.trip_id)?,
+        false,
+    )
+    .await?;
+    if access.role.is_none() {
+        return Err(Error::Forbidden);
+    }
+
+    let participants = trip_participants::Entity::find()
+        .filter(trip_participants::COLUMN.trip_id.eq(access.trip.id))
+        .find_also_related(profiles::Entity)
+        .order_by_asc(trip_participants::Column::JoinedAt)
+        .all(&state.conn)
+        .await?;
+
+    Ok(ListParticipantsResponse {
+        participants: participants
+            .into_iter()
+            .map(|(participant, profile)| participant_to_proto(participant, profile))
+            .collect(),
+    })
+}
+
+// ============================================================================
+// Trips and Cards
+// ============================================================================
+
+const TRIP_STATUSES: [&str; 5] = [
+    "planning",
+    "confirmed",
+    "in_progress",
+    "completed",
+    "cancelled",
+];
+const CARD_STATUSES: [&str; 4] = ["draft", "scheduled", "completed", "cancelled"];
+
+pub fn trip_to_proto(trip: &trips::Model) -> Trip {
+    Trip {
+        id: trip.id.to_string(),
+        realm_id: trip.realm_id.to_string(),
+        created_by: trip.created_by.to_string(),
+        title: trip.title.clone(),
+        description: trip.description.clone().unwrap_or_default(),
+        destination: trip.destination.clone().unwrap_or_default(),
+        start_date: trip.start_date.map(|d| d.to_string()).unwrap_or_default(),
+        end_date: trip.end_date.map(|d| d.to_string()).unwrap_or_default(),
+        status: trip.status.clone(),
+        created_at: trip.created_at.to_rfc3339(),
+        updated_at: trip.updated_at.to_rfc3339(),
+        version: trip.version,
+    }
+}
+
+fn trip_card_to_proto(card: &trip_cards::Model) -> TripCard {
+    TripCard {
+        id: card.id.to_string(),
+        trip_id: card.trip_id.to_string(),
+        created_by: card.created_by.to_string(),
+        title: card.title.clone(),
+        description: card.description.clone().unwrap_or_default(),
+        category: card.category.clone().unwrap_or_default(),
+        start_time: card.start_time.map(|t| t.to_rfc3339()).unwrap_or_default(),
+        end_time: card.end_time.map(|t| t.to_rfc3339()).unwrap_or_default(),
+        status: card.status.clone(),
+        display_order: card.display_order.unwrap_or_default(),
+        vote_count: card.vote_count,
+        created_at: card.created_at.to_rfc3339(),
+        updated_at: card.updated_at.to_rfc3339(),
+        version: card.version,
+        position: card.position.map(|p| Position {
+            latitude: p.latitude,
+            longitude: p.longitude,
+        }),
+    }
+}
+
+fn parse_position(field: &str, position: Option<&Position>) -> Result<GeoPoint, Error> {
+    let position = position.ok_or_else(|| Error::invalid_field(field, "is required"))?;
+    GeoPoint::new(position.longitude, position.latitude).ok_or_else(|| {
+        Error::invalid_field(
+            field,
+            "latitude must be within ±90 and longitude within ±180",
+        )
+    })
+}
+
+/// An optional text field of an update: unset keeps the value, empty clears it
+fn optional_text(value: Option<String>) -> Option<Option<String>> {
+    value.map(|v| if v.is_empty() { None } else { Some(v) })
+}
+
+fn parse_optional_date(
+    field: &str,
+    value: Option<String>,
+) -> Result<Option<Option<chrono::NaiveDate>>, Error> {
+    optional_text(value)
+        .map(|v| {
+            v.map(|v| chrono::NaiveDate::parse_from_str(&v, "%Y-%m-%d"))
+                .transpose()
+                .map_err(|_| Error::invalid_field(field, "must be a YYYY-MM-DD date"))
+        })
+        .transpose()
+}
+
+fn parse_optional_time(
+    field: &str,
+    value: Option<String>,
+) -> Result<Option<Option<chrono::DateTime<chrono::FixedOffset>>>, Error> {
+    optional_text(value)
+        .map(|v| {
+            v.map(|v| chrono::DateTime::parse_from_rfc3339(&v))
+                .transpose()
+                .map_err(|_| Error::invalid_field(field, "must be an ISO 8601 timestamp"))
+        })
+        .transpose()
+}
+
+fn validate_title(title: &Option<String>) -> Result<(), Error> {
+    if title.as_deref().is_some_and(|t| t.trim().is_empty()) {
+        return Err(Error::invalid_field("title", "must not be empty"));
+    }
+    Ok(())
+}
+
+fn validate_status(status: &Option<String>, statuses: &[&str]) -> Result<(), Error> {
+    if let Some(status) = status
+        && !statuses.contains(&status.as_str())
+    {
+        return Err(Error::invalid_field(
+            "status",
+            format!("must be one of {}", statuses.join(", ")),
+        ));
+    }
+    Ok(())
+}
+
+/// Update Trip handler
+pub async fn update_trip(
+    State(state): State<AppState>,
+    ctx: AuthContext,
+    request: UpdateTripRequest,
+) -> Result<UpdateTripResponse, Error> {
+    ctx.require_scope(SCOPE_TRIPS_WRITE)?;
+    let trip_id = parse_uuid("trip_id", &request.trip_id)?;
+    validate_title(&request.title)?;
+    validate_status(&request.status, &TRIP_STATUSES)?;
+    let start_date = parse_optional_date("start_date", request.start_date)?;
+    let end_date = parse_optional_date("end_date", request.end_date)?;
+
+    let txn = state.conn.begin().await?;
+
+    // Locks the trip, so the version check can't race another update
+    let access = trip_access(&txn, &ctx, trip_id, true).await?;
+    if !access.can_edit() {
+        return Err(Error::Forbidden);
+    }
+    let profile_id = access.profile_id();
+    let trip = access.trip;
+
+    if let Some(expected_version) = request.expected_version
+        && expected_version != trip.version
+    {
+        return Err(Error::version_mismatch(
+            expected_version,
+            trip.version,
+            "trip.Trip",
+            &trip_to_proto(&trip),
+        ));
+    }
+
+    let final_start = start_date.unwrap_or(trip.start_date);
+    let final_end = end_date.unwrap_or(trip.end_date);
+    if let (Some(start), Some(end)) = (final_start, final_end)
+        && end < start
+    {
+        return Err(Error::invalid_field(
+            "end_date",
+            "must not be before start_date",
+        ));
+    }
+
+    let version = trip.version;
+    let mut active: trips::ActiveModel = trip.clone().into();
+    if let Some(title) = request.title {
+        active.title = Set(title);
+    }
+    if let Some(description) = optional_text(request.description) {
+        active.description = Set(description);
+    }
+    if let Some(destination) = optional_text(request.destination) {
+        active.destination = Set(destination);
+    }
+    active.start_date = Set(final_start);
+    active.end_date = Set(final_end);
+    if let Some(status) = request.status {
+        active.status = Set(status);
+    }
+    active.updated_at = Set(Utc::now().into());
+    active.version = Set(version + 1);
+    let updated = active.update(&txn).await?;
+    activity::record_update(
+        &txn,
+        trip.id,
+        profile_id,
+        activity::ENTITY_TRIP,
+        trip.id,
+        &trip,
+        &updated,
+    )
+    .await?;
+
+    txn.commit().await?;
+
+    Ok(UpdateTripResponse {
+        trip: Some(trip_to_proto(&updated)),
+    })
+}
+
+/// Update Trip Card handler
+pub async fn update_trip_card(
+    State(state): State<AppState>,
+    ctx: AuthContext,
+    request: UpdateTripCardRequest,
+) -> Result<UpdateTripCardResponse, Error> {
+    ctx.require_scope(SCOPE_TRIPS_WRITE)?;
+    validate_title(&request.title)?;
+    validate_status(&request.status, &CARD_STATUSES)?;
+    let start_time = parse_optional_time("start_time", request.start_time)?;
+    let end_time = parse_optional_time("end_time", request.end_time)?;
+    let position = match (request.position.as_ref(), request.clear_position) {
+        (Some(_), true) => {
+            return Err(Error::invalid_field(
+                "position",
+                "can't be set together with clear_position",
+            ));
+        }
+        (Some(position), false) => Some(Some(parse_position("position", Some(position))?)),
+        (None, true) => Some(None),
+        (None, false) => None,
+    };
+
+    let txn = state.conn.begin().await?;
+
+    // Locks the card, so the version check can't race another update
+    let (card, access) = card_access(&txn, &ctx, &request.trip_card_id, true).await?;
+    if !access.can_edit() {
+        return Err(Error::Forbidden);
+    }
+
+    if let Some(expected_version) = request.expected_version
+        && expected_version != card.version
+    {
+        return Err(Error::version_mismatch(
+            expected_version,
+            card.version,
+            "trip.TripCard",
+            &trip_card_to_proto(&card),
+        ));
+    }
+
+    let final_start = start_time.unwrap_or(card.start_time);
+    let final_end = end_time.unwrap_or(card.end_time);
+    if let (Some(start), Some(end)) = (final_start, final_end)
+        && end < start
+    {
+        return Err(Error::invalid_field(
+            "end_time",
+            "must not be before start_time",
+        ));
+    }
+
+    let version = card.version;
+    let mut active: trip_cards::ActiveModel = card.clone().into();
+    if let Some(title) = request.title {
+        active.title = Set(title);
+    }
+    if let Some(description) = optional_text(request.description) {
+        active.description = Set(description);
+    }
+    if let Some(category) = optional_text(request.category) {
+        active.category = Set(category);
+    }
+    active.start_time = Set(final_start);
+    active.end_time = Set(final_end);
+    if let Some(status) = request.status {
+        active.status = Set(status);
+    }
+    if let Some(display_order) = request.display_order {
+        active.display_order = Set(Some(display_order));
+    }
+    if let Some(position) = position {
+        active.position = Set(position);
+    }
+    active.updated_at = Set(Utc::now().into());
+    active.version = Set(version + 1);
+    let updated = active.update(&txn).await?;
+    activity::record_update(
+        &txn,
+        card.trip_id,
+        access.profile_id(),
+        activity::ENTITY_TRIP_CARD,
+        card.id,
+        &card,
+        &updated,
+    )
+    .await?;
+
+    txn.commit().await?;
+
+    Ok(UpdateTripCardResponse {
+        card: Some(trip_card_to_proto(&updated)),
+    })
+}
+
+// ============================================================================
+// Copies and Templates
+// ============================================================================
+
+/// The caller's profile in a realm, created if the account has none yet
+pub async fn caller_profile<C: ConnectionTrait>(
+    db: &C,
+    ctx: &AuthContext,
+    realm_id: Uuid,
+) -> Result<profiles::Model, Error> {
+    let account = accounts::Entity::find_by_id(ctx.require_account()?)
+        .one(db)
+        .await?
+        .ok_or(Error::Unauthenticated)?;
+    Ok(profile::ensure_for_account(db, realm_id, &account).await?)
+}
+
+/// A template of the selected realm
+async fn find_template<C: ConnectionTrait>(
+    db: &C,
+    ctx: &AuthContext,
+    template_id: &str,
+) -> Result<trips::Model, Error> {
+    let realm_id = ctx.require_realm()?;
+    let template = trips::Entity::find_by_id(parse_uuid("template_id", template_id)?)
+        .one(db)
+        .await?
+        .filter(|t| t.status == copy::STATUS_TEMPLATE)
+        .ok_or(Error::NotFound)?;
+    if template.realm_id != realm_id {
+        return Err(Error::Forbidden);
+    }
+    Ok(template)
+}
+
+/// `title` if set, else `default`
+fn copy_title(title: &str, default: String) -> Result<String, Error> {
+    if title.is_empty() {
+        return Ok(default);
+    }
+    if title.trim().is_empty() {
+        return Err(Error::invalid_field("title", "must not be empty"));
+    }
+    Ok(title.to_owned())
+}
+
+fn parse_start_date(start_date: &str) -> Result<Option<chrono::NaiveDate>, Error> {
+    Ok(parse_optional_date("start_date", Some(start_date.to_owned()))?.flatten())
+}
+
+/// Clone Trip handler
+pub async fn clone_trip(
+    State(state): State<AppState>,
+    ctx: AuthContext,
+    request: CloneTripRequest,
+) -> Result<CloneTripResponse, Error> {
+    ctx.require_scope(SCOPE_TRIPS_WRITE)?;
+    let start_date = parse_start_date(&request.start_date)?;
+
+    let txn = state.conn.begin().await?;
+
+    let access = trip_access(&txn, &ctx, parse_uuid("trip_id", &request.trip_id)?, false).await?;
+    if access.role.is_none() || (request.include_participants && !access.is_owner()) {
+        return Err(Error::Forbidden);
+    }
+    let title = copy_title(&request.title, format!("{} (copy)", access.trip.title))?;
+    let profile = caller_profile(&txn, &ctx, access.trip.realm_id).await?;
+
+    // The caller owns the copy; other participants keep their roles if included
+    let mut participants = vec![(profile.id, participant::ROLE_OWNER.to_owned())];
+    if request.include_participants {
+        let others = trip_participants::Entity::find()
+            .filter(trip_participants::COLUMN.trip_id.eq(access.trip.id))
+            .filter(trip_participants::COLUMN.profile_id.ne(profile.id))
+            .all(&txn)
+            .await?;
+        participants.extend(others.into_iter().map(|p| (p.profile_id, p.role)));
+    }
+
+    let trip = copy::copy_trip(
+        &txn,
+        &access.trip,
+        copy::Options {
+            title,
+            status: copy::STATUS_PLANNING,
+            created_by: profile.id,
+            start_date,
+            participants,
+        },
+    )
+    .await?;
+
+    txn.commit().await?;
+
+    Ok(CloneTripResponse {
+        trip: Some(trip_to_proto(&trip)),
+    })
+}
+
+/// Save Trip As Template handler
+pub async fn save_trip_as_template(
+    State(state): State<AppState>,
+    ctx: AuthContext,
+    request: SaveTripAsTemplateRequest,
+) -> Result<SaveTripAsTemplateResponse, Error> {
+    ctx.require_scope(SCOPE_TRIPS_WRITE)?;
+
+    let txn = state.conn.begin().await?;
+
+    let access = trip_access(&txn, &ctx, parse_uuid("trip_id", &request.trip_id)?, false).await?;
+    // Realm admins count as owners of every trip
+    if !access.is_owner() {
+        return Err(Error::Forbidden);
+    }
+    let title = copy_title(&request.title, access.trip.title.clone())?;
+    let profile = caller_profile(&txn, &ctx, access.trip.realm_id).await?;
+
+    let template = copy::copy_trip(
+        &txn,
+        &access.trip,
+        copy::Options {
+            title,
+            status: copy::STATUS_TEMPLATE,
+            created_by: profile.id,
+            start_date: None,
+            participants: Vec::new(),
+        },
+    )
+    .await?;
+
+    txn.commit().await?;
+
+    Ok(SaveTripAsTemplateResponse {
+        template: Some(trip_to_proto(&template)),
+    })
+}
+
+/// List Trip Templates handler
+pub async fn list_trip_templates(
+    State(state): State<AppState>,
+    ctx: AuthContext,
+    _request: ListTripTemplatesRequest,
+) -> Result<ListTripTemplatesResponse, Error> {
+    ctx.require_scope(SCOPE_TRIPS_READ)?;
+    // Membership of the realm was checked when the context was built
+    let realm_id = ctx.require_realm()?;
+
+    let templates = trips::Entity::find()
+        .filter(trips::COLUMN.realm_id.eq(realm_id))
+        .filter(trips::COLUMN.status.eq(copy::STATUS_TEMPLATE))
+        .order_by_asc(trips::Column::Title)
+        .order_by_asc(trips::Column::Id)
+        .all(&state.conn)
+        .await?;
+
+    Ok(ListTripTemplatesResponse {
+        templates: templates.iter().map(trip_to_proto).collect(),
+    })
+}
+
+/// Create Trip From Template handler
+pub async fn create_trip_from_template(
+    State(state): State<AppState>,
+    ctx: AuthContext,
+    request: CreateTripFromTemplateRequest,
+) -> Result<CreateTripFromTemplateResponse, Error> {
+    ctx.require_scope(SCOPE_TRIPS_WRITE)?;
+    let start_date = parse_start_date(&request.start_date)?;
+
+    let txn = state.conn.begin().await?;
+
+    let template = find_template(&txn, &ctx, &request.template_id).await?;
+    let title = copy_title(&request.title, template.title.clone())?;
+    let profile = caller_profile(&txn, &ctx, template.realm_id).await?;
+
+    let trip = copy::copy_trip(
+        &txn,
+        &template,
+        copy::Options {
+            title,
+            status: copy::STATUS_PLANNING,
+            created_by: profile.id,
+            start_date,
+            participants: vec![(profile.id, participant::ROLE_OWNER.to_owned())],
+        },
+    )
+    .await?;
+
+    txn.commit().await?;
+
+    Ok(CreateTripFromTemplateResponse {
+        trip: Some(trip_to_proto(&trip)),
+    })
+}
+
+/// Delete Trip Template handler
+pub async fn delete_trip_template(
+    State(state): State<AppState>,
+    ctx: AuthContext,
+    request: DeleteTripTemplateRequest,
+) -> Result<DeleteTripTemplateResponse, Error> {
+    ctx.require_scope(SCOPE_TRIPS_WRITE)?;
+
+    let template = find_template(&state.conn, &ctx, &request.template_id).await?;
+    if !ctx.roles.iter().any(|r| r == "admin") {
+        let profile =
+            profile::find_for_account(&state.conn, template.realm_id, ctx.require_account()?)
+                .await?;
+        if profile.is_none_or(|p| p.id != template.created_by) {
+            return Err(Error::Forbidden);
+        }
+    }
+
+    trips::Entity::delete_by_id(template.id)
+        .exec(&state.conn)
+        .await?;
+
+    Ok(DeleteTripTemplateResponse {})
+}
+
+// ============================================================================
+// Geospatial Search
+// ============================================================================
+
+fn search_limit(limit: i32) -> Result<u64, Error> {
+    match limit {
+        0 => Ok(geo::DEFAULT_LIMIT),
+        limit if limit < 0 => Err(Error::invalid_field("limit", "must not be negative")),
+        limit => Ok((limit as u64).min(geo::MAX_LIMIT)),
+    }
+}
+
+/// The trip named by `trip_id` if set, otherwise every trip of the selected realm the
+/// caller takes part in (all of them for realm admins)
+async fn searchable_trip_ids<C: ConnectionTrait>(
+    db: &C,
+    ctx: &AuthContext,
+    trip_id: &str,
+) -> Result<Vec<Uuid>, Error> {
+    if !trip_id.is_empty() {
+        let access = trip_access(db, ctx, parse_uuid("trip_id", trip_id)?, false).await?;
+        if access.role.is_none() {
+            return Err(Error::Forbidden);
+        }
+        return Ok(vec![access.trip.id]);
+    }
+
+    let account_id = ctx.require_account()?;
+    let realm_id = ctx.require_realm()?;
+    if ctx.roles.iter().any(|r| r == "admin") {
+        let trips = trips::Entity::find()
+            .filter(trips::COLUMN.realm_id.eq(realm_id))
+            .filter(trips::COLUMN.status.ne(copy::STATUS_TEMPLATE))
+            .all(db)
+            .await?;
+        return Ok(trips.into_iter().map(|t| t.id).collect());
+    }
+
+    let Some(profile) = profile::find_for_account(db, realm_id, account_id).await? else {
+        return Ok(Vec::new());
+    };
+    let participations = trip_participants::Entity::find()
+        .filter(trip_participants::COLUMN.profile_id.eq(profile.id))
+        .all(db)
+        .await?;
+    Ok(participations.into_iter().map(|p| p.trip_id).collect())
+}
+
+fn search_results(cards: Vec<(trip_cards::Model, f64)>) -> SearchCardsResponse {
+    SearchCardsResponse {
+        results: cards
+            .into_iter()
+            .map(|(card, distance)| CardSearchResult {
+                card: Some(trip_card_to_proto(&card)),
+                distance_meters: distance,
+            })
+            .collect(),
+    }
+}
+
+/// Search Cards Nearby handler
+pub async fn search_cards_nearby(
+    State(state): State<AppState>,
+    ctx: AuthContext,
+    request: SearchCardsNearbyRequest,
+) -> Result<SearchCardsResponse, Error> {
+    ctx.require_scope(SCOPE_TRIPS_READ)?;
+    let center = parse_position("center", request.center.as_ref())?;
+    if !(request.radius_meters > 0.0 && request.radius_meters <= geo::MAX_RADIUS_METERS) {
+        return Err(Error::invalid_field(
+            "radius_meters",
+            format!("must be above 0 and at most {}", geo::MAX_RADIUS_METERS),
+        ));
+    }
+    let limit = search_limit(request.limit)?;
+
+    let trip_ids = searchable_trip_ids(&state.conn, &ctx, &request.trip_id).await?;
+    if trip_ids.is_empty() {
+        return Ok(SearchCardsResponse::default());
+    }
+    let cards =
+        geo::cards_nearby(&state.conn, &trip_ids, center, request.radius_meters, limit).await?;
+
+    Ok(search_results(cards))
+}
+
+/// Search Cards In Bounds handler
+pub async fn search_cards_in_bounds(
+    State(state): State<AppState>,
+    ctx: AuthContext,
+    request: SearchCardsInBoundsRequest,
+) -> Result<SearchCardsResponse, Error> {
+    ctx.require_scope(SCOPE_TRIPS_READ)?;
+    let south_west = parse_position("south_west", request.south_west.as_ref())?;
+    let north_east = parse_position("north_east", request.north_east.as_ref())?;
+    if north_east.latitude < south_west.latitude {
+        return Err(Error::invalid_field(
+            "north_east",
+            "must not be south of south_west",
+        ));
+    }
+    if north_east.longitude < south_west.longitude {
+        return Err(Error::invalid_field(
+            "north_east",
+            "must not be west of south_west; bounds can't cross the antimeridian",
+        ));
+    }
+    let limit = search_limit(request.limit)?;
+
+    let trip_ids = searchable_trip_ids(&state.conn, &ctx, &request.trip_id).await?;
+    if trip_ids.is_empty() {
+        return Ok(SearchCardsResponse::default());
+    }
+    let cards = geo::cards_in_bounds(&state.conn, &trip_ids, south_west, north_east, limit).await?;
+
+    Ok(search_results(cards))
+}
+
+// ============================================================================
+// Itinerary
+// ============================================================================
+
+/// 'transit' if empty
+fn parse_travel_mode(mode: &str) -> Result<itinerary::TravelMode, Error> {
+    if mode.is_empty() {
+        return Ok(itinerary::TravelMode::Transit);
+    }
+    itinerary::TravelMode::parse(mode)
+        .ok_or_else(|| Error::invalid_field("mode", "must be 'walking', 'transit' or 'driving'"))
+}
+
+fn parse_buffer(buffer_minutes: i32) -> Result<chrono::Duration, Error> {
+    if buffer_minutes < 0 {
+        return Err(Error::invalid_field(
+            "buffer_minutes",
+            "must not be negative",
+        ));
+    }
+    Ok(chrono::Duration::minutes(buffer_minutes.into()))
+}
+
+/// Validate Itinerary handler
+pub async fn validate_itinerary(
+    State(state): State<AppState>,
+    ctx: AuthContext,
+    request: ValidateItineraryRequest,
+) -> Result<ValidateItineraryResponse, Error> {
+    ctx.require_scope(SCOPE_TRIPS_READ)?;
+    let mode = parse_travel_mode(&request.mode)?;
+    let buffer = parse_buffer(request.buffer_minutes)?;
+
+    let access = trip_access(
+        &state.conn,
+        &ctx,
+        parse_uuid("trip_id", &request.trip_id)?,
+        false,
+    )
+    .await?;
+    if access.role.is_none() {
+        return Err(Error::Forbidden);
+    }
+
+    let report = itinerary::validate(
+        &state.conn,
+        state.routing_provider.as_ref(),
+        access.trip.id,
+        mode,
+        buffer,
+    )
+    .await?;
+
+    Ok(ValidateItineraryResponse {
+        feasible: report.transitions.iter().all(|t| t.feasible),
+        transitions: report
+            .transitions
+            .into_iter()
+            .map(|t| ItineraryTransition {
+                from_card_id: t.from.to_string(),
+                to_card_id: t.to.to_string(),
+                distance_meters: t.distance_meters,
+                travel_seconds: t.travel.num_seconds(),
+                available_seconds: t.available.num_seconds(),
+                feasible: t.feasible,
+            })
+            .collect(),
+        suggestions: report
+            .suggestions
+            .into_iter()
+            .map(|s| ItinerarySuggestion {
+                trip_card_id: s.trip_card_id.to_string(),
+                start_time: s.start_time.to_rfc3339(),
+                end_time: s.end_time.map(|t| t.to_rfc3339()).unwrap_or_default(),
+            })
+            .collect(),
+    })
+}
+
+/// UTC offsets range from -12:00 to +14:00; ±14:00 are allowed both ways
+fn parse_utc_offset(minutes: i32) -> Result<chrono::FixedOffset, Error> {
+    chrono::FixedOffset::east_opt(minutes.saturating_mul(60))
+        .filter(|_| minutes.abs() <= 14 * 60)
+        .ok_or_else(|| Error::invalid_field("utc_offset_minutes", "must be within -840 and 840"))
+}
+
+/// Parse an `HH:MM` time, `default` if empty
+fn parse_time_of_day(
+    field: &str,
+    value: &str,
+    default: chrono::NaiveTime,
+) -> Result<chrono::NaiveTime, Error> {
+    if value.is_empty() {
+        return Ok(default);
+    }
+    chrono::NaiveTime::parse_from_str(value, "%H:%M")
+        .map_err(|_| Error::invalid_field(field, "must be an HH:MM time"))
+}
+
+/// Optimize Itinerary handler
+pub async fn optimize_itinerary(
+    State(state): State<AppState>,
+    ctx: AuthContext,
+    request: OptimizeItineraryRequest,
+) -> Result<OptimizeItineraryResponse, Error> {
+    ctx.require_scope(SCOPE_TRIPS_READ)?;
+    let mode = parse_travel_mode(&request.mode)?;
+    let buffer = parse_buffer(request.buffer_minutes)?;
+    let utc_offset = parse_utc_offset(request.utc_offset_minutes)?;
+    let day_start = parse_time_of_day(
+        "day_start",
+        &request.day_start,
+        chrono::NaiveTime::from_hms_opt(9, 0, 0).unwrap_or_default(),
+    )?;
+    let day_end = parse_time_of_day(
+        "day_end",
+        &request.day_end,
+        chrono::NaiveTime::from_hms_opt(21, 0, 0).unwrap_or_default(),
+    )?;
+    if day_end <= day_start {
+        return Err(Error::invalid_field("day_end", "must be after day_start"));
+    }
+
+    let access = trip_access(
+        &state.conn,
+        &ctx,
+        parse_uuid("trip_id", &request.trip_id)?,
+        false,
+    )
+    .await?;
+    if access.role.is_none() {
+        return Err(Error::Forbidden);
+    }
+
+    let plan = optimizer::plan(
+        &state.conn,
+        state.routing_provider.as_ref(),
+        &access.trip,
+        &optimizer::Options {
+            mode,
+            buffer,
+            utc_offset,
+            day_start,
+            day_end,
+        },
+    )
+    .await?;
+
+    Ok(OptimizeItineraryResponse {
+        total_votes: plan.placements.iter().map(|p| p.card.vote_count).sum(),
+        added_travel_seconds: plan.added_travel.num_seconds(),
+        cards: plan
+            .placements
+            .into_iter()
+            .map(|p| PlannedCard {
+                trip_card_id: p.card.id.to_string(),
+                start_time: p.start_time.to_rfc3339(),
+                end_time: p.end_time.to_rfc3339(),
+                version: p.card.version,
+            })
+            .collect(),
+        unplaced: plan
+            .unplaced
+            .into_iter()
+            .map(|u| UnplacedCard {
+                trip_card_id: u.card.id.to_string(),
+                reason: u.reason,
+            })
+            .collect(),
+    })
+}
+
+/// Apply Itinerary Plan handler
+pub async fn apply_itinerary_plan(
+    State(state): State<AppState>,
+    ctx: AuthContext,
+    request: ApplyItineraryPlanRequest,
+) -> Result<ApplyItineraryPlanResponse, Error> {
+    ctx.require_scope(SCOPE_TRIPS_WRITE)?;
+    if request.cards.is_empty() {
+        return Err(Error::invalid_field("cards", "must not be empty"));
+    }
+    let mut planned: Vec<(Uuid, _, _, i64)> = Vec::with_capacity(request.cards.len());
+    for card in &request.cards {
+        let id = parse_uuid("cards.trip_card_id", &card.trip_card_id)?;
+        let parse = |field: &str, value: &str| {
+            chrono::DateTime::parse_from_rfc3339(value)
+                .map_err(|_| Error::invalid_field(field, "must be an ISO 8601 timestamp"))
+        };
+        let start = parse("cards.start_time", &card.start_time)?;
+        let end = parse("cards.end_time", &card.end_time)?;
+        if end < start {
+            return Err(Error::invalid_field(
+                "cards.end_time",
+                "must not be before start_time",
+            ));
+        }
+        if planned.iter().any(|&(planned_id, ..)| planned_id == id) {
+            return Err(Error::invalid_field("cards", "must not repeat a card"));
+        }
+        planned.push((id, start, end, card.version));
+    }
+
+    let txn = state.conn.begin().await?;
+
+    let access = trip_access(&txn, &ctx, parse_uuid("trip_id", &request.trip_id)?, false).await?;
+    if !access.can_edit() {
+        return Err(Error::Forbidden);
+    }
+
+    let mut cards = Vec::with_capacity(planned.len());
+    for (id, start, end, version) in planned {
+        let card = trip_cards::Entity::find_by_id(id)
+            .lock_exclusive()
+            .one(&txn)
+            .await?
+            .filter(|card| card.trip_id == access.trip.id)
+            .ok_or(Error::NotFound)?;
+        if card.version != version {
+            return Err(Error::version_mismatch(
+                version,
+                card.version,
+                "trip.TripCard",
+                &trip_card_to_proto(&card),
+            ));
+        }
+        if card.status != "draft" {
+            return Err(Error::FailedPrecondition(format!(
+                "card {id} is no longer a draft"
+            )));
+        }
+
+        let mut active: trip_cards::ActiveModel = card.clone().into();
+        active.start_time = Set(Some(start));
+        active.end_time = Set(Some(end));
+        active.status = Set("scheduled".to_string());
+        active.updated_at = Set(Utc::now().into());
+        active.version = Set(card.version + 1);
+        let updated = active.update(&txn).await?;
+        activity::record_update(
+            &txn,
+            card.trip_id,
+            access.profile_id(),
+            activity::ENTITY_TRIP_CARD,
+            card.id,
+            &card,
+            &updated,
+        )
+        .await?;
+        cards.push(updated);
+    }
+
+    // Checked once every planned card is in place, so they are checked against each other
+    for card in &cards {
+        if let (Some(start), Some(end)) = (card.start_time, card.end_time)
+            && let Some(conflict) = optimizer::conflicts(&txn, card.trip_id, card.id, start, end)
+                .await?
+                .first()
+        {
+            return Err(Error::FailedPrecondition(format!(
+                "card {} now overlaps card {}",
+                card.id, conflict.id
+            )));
+        }
+    }
+
+    txn.commit().await?;
+
+    Ok(ApplyItineraryPlanResponse {
+        cards: cards.iter().map(trip_card_to_proto).collect(),
+    })
+}
+
+// ============================================================================
+// Calendar
+// ============================================================================
+
+/// Lifetime of calendar feed links
+const CALENDAR_FEED_SECS: u64 = 365 * 24 * 60 * 60;
+
+/// Cards that appear in a trip's calendar, in start time order
+async fn calendar_cards<C: ConnectionTrait>(
+    db: &C,
+    trip_id: Uuid,
+) -> Result<Vec<trip_cards::Model>, Error> {
+    Ok(trip_cards::Entity::find()
+        .filter(trip_cards::COLUMN.trip_id.eq(trip_id))
+        .filter(trip_cards::COLUMN.status.eq("scheduled"))
+        .filter(trip_cards::Column::StartTime.is_not_null())
+        .order_by_asc(trip_cards::Column::StartTime)
+        .order_by_asc(trip_cards::Column::Id)
+        .all(db)
+        .await?)
+}
+
+/// Export Trip ICS handler
+pub async fn export_trip_ics(
+    State(state): State<AppState>,
+    ctx: AuthContext,
+    request: ExportTripIcsRequest,
+) -> Result<ExportTripIcsResponse, Error> {
+    ctx.require_scope(SCOPE_TRIPS_READ)?;
+    let access = trip_access(
+        &state.conn,
+        &ctx,
+        parse_uuid("trip_id", &request.trip_id)?,
+        false,
+    )
+    .await?;
+    if access.role.is_none() {
+        return Err(Error::Forbidden);
+    }
+
+    let cards = calendar_cards(&state.conn, access.trip.id).await?;
+
+    // Realm admins see every trip but only participants get a feed, which is bound
+    // to their participation
+    let participant = match access.profile_id() {
+        Some(profile_id) => participant::role_of(&state.conn, access.trip.id, profile_id)
+            .await?
+            .map(|_| profile_id),
+        None => None,
+    };
+    let feed_url = match participant {
+        Some(profile_id) => {
+            let token = jwt::sign_calendar_token(
+                &access.trip.id.to_string(),
+                &profile_id.to_string(),
+                &state.jwt_keys,
+                CALENDAR_FEED_SECS,
+            )
+            .map_err(Error::Anyhow)?;
+            format!("/trips/{}/calendar.ics?token={token}", access.trip.id)
+        }
+        None => String::new(),
+    };
+
+    Ok(ExportTripIcsResponse {
+        ics: ical::render(&access.trip, &cards),
+        feed_url,
+    })
+}
+
+#[derive(serde::Deserialize)]
+pub struct CalendarFeedQuery {
+    token: String,
+}
+
+/// `GET /trips/{id}/calendar.ics?token=...`: the feed link from `ExportTripIcsResponse`
+pub async fn trip_calendar_feed(
+    State(state): State<AppState>,
+    Path(trip_id): Path<String>,
+    Query(query): Query<CalendarFeedQuery>,
+) -> Result<Response, Error> {
+    let claims = jwt::verify_calendar_token(&query.token, &state.jwt_keys)
+        .map_err(|_| Error::Unauthenticated)?;
+    if claims.sub != trip_id {
+        return Err(Error::Unauthenticated);
+    }
+    let trip_id = Uuid::parse_str(&claims.sub).map_err(|_| Error::NotFound)?;
+    let profile_id =
+        Uuid::parse_str(&claims.extra.profile_id).map_err(|_| Error::Unauthenticated)?;
+
+    let trip = trips::Entity::find_by_id(trip_id)
+        .one(&state.conn)
+        .await?
+        .ok_or(Error::NotFound)?;
+    realm::ensure_usable(&state.conn, trip.realm_id).await?;
+    if participant::role_of(&state.conn, trip.id, profile_id)
+        .await?
+        .is_none()
+    {
+        return Err(Error::Forbidden);
+    }
+
+    let cards = calendar_cards(&state.conn, trip.id).await?;
+
+    Ok((
+        [
+            (http::header::CONTENT_TYPE, ical::CONTENT_TYPE.to_owned()),
+            (
+                http::header::CONTENT_DISPOSITION,
+                format!("inline; filename=\"trip-{trip_id}.ics\""),
+            ),
+            (http::header::CACHE_CONTROL, "private, no-cache".to_owned()),
+        ],
+        ical::render(&trip, &cards),
+    )
+        .into_response())
+}
+
+/// Largest .ics file accepted by ImportTripIcs
+const MAX_ICS_BYTES: usize = 1024 * 1024;
+
+/// Import Trip ICS handler
+pub async fn import_trip_ics(
+    State(state): State<AppState>,
+    ctx: AuthContext,
+    request: ImportTripIcsRequest,
+) -> Result<ImportTripIcsResponse, Error> {
+    ctx.require_scope(SCOPE_TRIPS_WRITE)?;
+    Error::require_fields(&[("trip_id", &request.trip_id), ("ics", &request.ics)])?;
+    if request.ics.len() > MAX_ICS_BYTES {
+        return Err(Error::invalid_field("ics", "must be at most 1 MiB"));
+    }
+    let utc_offset = parse_utc_offset(request.utc_offset_minutes)?;
+    let events = ical::parse(&request.ics).map_err(|err| Error::invalid_field("ics", &err))?;
+
+    let txn = state.conn.begin().await?;
+
+    // Locks the trip, so concurrent imports of the same file can't both create cards
+    let access = trip_access(&txn, &ctx, parse_uuid("trip_id", &request.trip_id)?, true).await?;
+    if !access.can_edit() {
+        return Err(Error::Forbidden);
+    }
+    let profile_id = access.profile_id().ok_or_else(|| {
+        Error::FailedPrecondition("you have no profile in this realm".to_string())
+    })?;
+
+    // Cards by the UID of the event they were imported from
+    let mut imported: std::collections::HashMap<String, Uuid> = trip_cards::Entity::find()
+        .filter(trip_cards::COLUMN.trip_id.eq(access.trip.id))
+        .filter(sea_orm::sea_query::Expr::cust_with_values(
+            "trip_cards.metadata ->> $1 IS NOT NULL",
+            [ical::METADATA_UID],
+        ))
+        .all(&txn)
+        .await?
+        .into_iter()
+        .filter_map(|card| {
+            let uid = card.metadata?.get(ical::METADATA_UID)?.as_str()?.to_owned();
+            Some((uid, card.id))
+        })
+        .collect();
+
+    let mut report = Vec::with_capacity(events.len());
+    let mut cards = Vec::new();
+    for event in &events {
+        let mut entry = ImportedEvent {
+            uid: event.uid.clone().unwrap_or_default(),
+            summary: event.summary.clone().unwrap_or_default(),
+            ..Default::default()
+        };
+        match event.uid.as_ref().and_then(|uid| imported.get(uid)) {
+            Some(trip_card_id) => {
+                entry.result = "duplicate".to_string();
+                entry.trip_card_id = trip_card_id.to_string();
+            }
+            None => match ical::card_from_event(event, &access.trip, profile_id, utc_offset) {
+                Ok(card) => {
+                    if let Some(uid) = &event.uid {
+                        imported.insert(uid.clone(), card.id);
+                    }
+                    entry.result = "created".to_string();
+                    entry.trip_card_id = card.id.to_string();
+                    cards.push(card);
+                }
+                Err(reason) => {
+                    entry.result = "skipped".to_string();
+                    entry.reason = reason;
+                }
+            },
+        }
+        report.push(entry);
+    }
+
+    quota::ensure_cards_available(
+        &txn,
+        access.trip.realm_id,
+        access.trip.id,
+        cards.len() as u64,
+    )
+    .await?;
+
+    for card in &cards {
+        let active = trip_cards::ActiveModel::from(card.clone()).reset_all();
+        let created = active.insert(&txn).await?;
+        activity::record(
+            &txn,
+            created.trip_id,
+            Some(profile_id),
+            activity::ENTITY_TRIP_CARD,
+            created.id,
+            activity::ACTION_CREATE,
+            None,
+            Some(activity::snapshot(&created)?),
+        )
+        .await?;
+    }
+
+    txn.commit().await?;
+
+    Ok(ImportTripIcsResponse {
+        events: report,
+        created: cards.len() as i32,
+    })
+}
+
+// ============================================================================
+// Share Links
+// ============================================================================
+
+fn share_link_to_proto(link: &trip_share_links::Model) -> ShareLink {
+    ShareLink {
+        id: link.id.to_string(),
+        trip_id: link.trip_id.to_string(),
+        token_prefix: link.token_prefix.clone(),
+        has_password: link.password_hash.is_some(),
+        expires_at: link.expires_at.map(|t| t.to_rfc3339()).unwrap_or_default(),
+        revoked_at: link.revoked_at.map(|t| t.to_rfc3339()).unwrap_or_default(),
+        last_used_at: link
+            .last_used_at
+            .map(|t| t.to_rfc3339())
+            .unwrap_or_default(),
+        created_by: link.created_by.to_string(),
+        created_at: link.created_at.to_rfc3339(),
+    }
+}
+
+/// Create Share Link handler
+pub async fn create_share_link(
+    State(state): State<AppState>,
+    ctx: AuthContext,
+    request: CreateShareLinkRequest,
+) -> Result<CreateShareLinkResponse, Error> {
+    ctx.require_scope(SCOPE_TRIPS_WRITE)?;
+    let access = trip_access(
+        &state.conn,
+        &ctx,
+        parse_uuid("trip_id", &request.trip_id)?,
+        false,
+    )
+    .await?;
+    if !access.is_owner() {
+        return Err(Error::Forbidden);
+    }
+    if !request.password.is_empty() && request.password.trim().is_empty() {
+        return Err(Error::invalid_field("password", "must not be blank"));
+    }
+
+    let profile = caller_profile(&state.conn, &ctx, access.trip.realm_id).await?;
+    let expires_at = (request.expires_in_days > 0)
+        .then(|| Utc::now() + chrono::Duration::days(request.expires_in_days.into()));
+    let created = share::create(
+        &state.conn,
+        access.trip.id,
+        profile.id,
+        expires_at.map(Into::into),
+        Some(request.password.as_str()).filter(|p| !p.is_empty()),
+    )
+    .await
+    .map_err(Error::Anyhow)?;
+
+    Ok(CreateShareLinkResponse {
+        token: created.token,
+        share_link: Some(share_link_to_proto(&created.link)),
+    })
+}
+
+/// List Share Links handler
+pub async fn list_share_links(
+    State(state): State<AppState>,
+    ctx: AuthContext,
+    request: ListShareLinksRequest,
+) -> Result<ListShareLinksResponse, Error> {
+    ctx.require_scope(SCOPE_TRIPS_READ)?;
+    let access = trip_access(
+        &state.conn,
+        &ctx,
+        parse_uuid("trip_id", &request.trip_id)?,
+        false,
+    )
+    .await?;
+    if !access.is_owner() {
+        return Err(Error::Forbidden);
+    }
+
+    let links = trip_share_links::Entity::find()
+        .filter(trip_share_links::COLUMN.trip_id.eq(access.trip.id))
+        .order_by_desc(trip_share_links::Column::CreatedAt)
+        .all(&state.conn)
+        .await?;
+
+    Ok(ListShareLinksResponse {
+        share_links: links.iter().map(share_link_to_proto).collect(),
+    })
+}
+
+/// Revoke Share Link handler
+pub async fn revoke_share_link(
+    State(state): State<AppState>,
+    ctx: AuthContext,
+    request: RevokeShareLinkRequest,
+) -> Result<RevokeShareLinkResponse, Error> {
+    ctx.require_scope(SCOPE_TRIPS_WRITE)?;
+    let link =
+        trip_share_links::Entity::find_by_id(parse_uuid("share_link_id", &request.share_link_id)?)
+            .one(&state.conn)
+            .await?
+            .ok_or(Error::NotFound)?;
+    let access = trip_access(&state.conn, &ctx, link.trip_id, false).await?;
+    if !access.is_owner() {
+        return Err(Error::Forbidden);
+    }
+
+    if link.revoked_at.is_none() {
+        let mut active: trip_share_links::ActiveModel = link.into();
+        active.revoked_at = Set(Some(Utc::now().into()));
+        active.update(&state.conn).await?;
+    }
+
+    Ok(RevokeShareLinkResponse { success: true })
+}
+
+/// Get Shared Trip handler; the share link's token is the only credential
+pub async fn get_shared_trip(
+    State(state): State<AppState>,
+    request: GetSharedTripRequest,
+) -> Result<GetSharedTripResponse, Error> {
+    Error::require_fields(&[("token", &request.token)])?;
+
+    let link = share::find_active(&state.conn, &request.token)
+        .await
+        .map_err(Error::Anyhow)?
+        .ok_or(Error::NotFound)?;
+
+    if link.password_hash.is_some() {
+        let key = link.id.to_string();
+        let locked = throttle::locked_until(&state.conn, ThrottleScope::ShareLink, &key)
+            .await
+            .map_err(Error::Anyhow)?;
+        if let Some(until) = locked {
+            return Err(Error::ResourceExhausted {
+                message: "Too many wrong passwords for this link".to_string(),
+                retry_after: until.signed_duration_since(Utc::now()).to_std().ok(),
+            });
+        }
+        if !share::check_password(&link, &request.password).map_err(Error::Anyhow)? {
+            throttle::record_failure(&state.conn, ThrottleScope::ShareLink, &key)
+                .await
+                .map_err(Error::Anyhow)?;
+            return Err(Error::Unauthenticated);
+        }
+        throttle::reset(&state.conn, ThrottleScope::ShareLink, &key)
+            .await
+            .map_err(Error::Anyhow)?;
+    }
+    share::touch(&state.conn, &link)
+        .await
+        .map_err(Error::Anyhow)?;
+
+    let trip = trips::Entity::find_by_id(link.trip_id)
+        .one(&state.conn)
+        .await?
+        .ok_or(Error::NotFound)?;
+    realm::ensure_usable(&state.conn, trip.realm_id).await?;
+    let cards = calendar_cards(&state.conn, trip.id).await?;
+    let mut contents: std::collections::HashMap<Uuid, serde_json::Value> =
+        trip_card_rich_text::Entity::find()
+            .filter(trip_card_rich_text::Column::TripCardId.is_in(cards.iter().map(|c| c.id)))
+            .all(&state.conn)
+            .await?
+            .into_iter()
+            .map(|document| (document.trip_card_id, document.content))
+            .collect();
+    let participants = trip_participants::Entity::find()
+        .filter(trip_participants::COLUMN.trip_id.eq(trip.id))
+        .find_also_related(profiles::Entity)
+        .order_by_asc(trip_participants::Column::JoinedAt)
+        .all(&state.conn)
+        .await?;
+
+    Ok(GetSharedTripResponse {
+        trip: Some(trip_to_proto(&trip)),
+        cards: cards
+            .iter()
+            .map(|card| SharedTripCard {
+                card: Some(trip_card_to_proto(card)),
+                content: contents
+                    .remove(&card.id)
+                    .map_or_else(|| "{}".to_string(), |content| content.to_string()),
+            })
+            .collect(),
+        participants: participants
+            .into_iter()
+            .map(|(participant, profile)| {
+                participant_to_proto(participant, profile.map(share::redact_profile))
+            })
+            .collect(),
+    })
+}
+
+// ============================================================================
+// Activity
+// ============================================================================
+
+const DEFAULT_ACTIVITY_PAGE_SIZE: u64 = 50;
+const MAX_ACTIVITY_PAGE_SIZE: u64 = 200;
+
+fn activity_to_proto(activity: activity_log::Model) -> Activity {
+    Activity {
+        id: activity.id.to_string(),
+        trip_id: activity.trip_id.to_string(),
+        profile_id: activity
+            .profile_id
+            .map(|id| id.to_string())
+            .unwrap_or_default(),
+        entity_type: activity.entity_type,
+        entity_id: activity.entity_id.to_string(),
+        action: activity.action,
+        before: activity.before.map(|v| v.to_string()).unwrap_or_default(),
+        after: activity.after.map(|v| v.to_string()).unwrap_or_default(),
+        undoes_activity_id: activity
+            .undoes_id
+            .map(|id| id.to_string())
+            .unwrap_or_default(),
+        undone_at: activity
+            .undone_at
+            .map(|t| t.to_rfc3339())
+            .unwrap_or_default(),
+        created_at: activity.created_at.to_rfc3339(),
+    }
+}
+
+/// List Trip Activity handler
+pub async fn list_trip_activity(
+    State(state): State<AppState>,
+    ctx: AuthContext,
+    request: ListTripActivityRequest,
+) -> Result<ListTripActivityResponse, Error> {
+    ctx.require_scope(SCOPE_TRIPS_READ)?;
+    let page_size = match request.page_size {
+        0 => DEFAULT_ACTIVITY_PAGE_SIZE,
+        size if size < 0 => {
+            return Err(Error::invalid_field("page_size", "must not be negative"));
+        }
+        size => (size as u64).min(MAX_ACTIVITY_PAGE_SIZE),
+    };
+
+    let access = trip_access(
+        &state.conn,
+        &ctx,
+        parse_uuid("trip_id", &request.trip_id)?,
+        false,
+    )
+    .await?;
+    if access.role.is_none() {
+        return Err(Error::Forbidden);
+    }
+
+    // Ids are UUIDv7, so they order the log by time
+    let mut query = activity_log::Entity::find()
+        .filter(activity_log::COLUMN.trip_id.eq(access.trip.id))
+        .order_by_desc(activity_log::Column::Id)
+        .limit(page_size + 1);
+    if !request.entity_id.is_empty() {
+        let entity_id = parse_uuid("entity_id", &request.entity_id)?;
+        query = query.filter(activity_log::COLUMN.entity_id.eq(entity_id));
+    }
+    if !request.before_id.is_empty() {
+        let before_id = parse_uuid("before_id", &request.before_id)?;
+        query = query.filter(activity_log::COLUMN.id.lt(before_id));
+    }
+
+    let mut activities = query.all(&state.conn).await?;
+    let next_before_id = if activities.len() as u64 > page_size {
+        activities.truncate(page_size as usize);
+        activities
+            .last()
+            .map(|a| a.id.to_string())
+            .unwrap_or_default()
+    } else {
+        String::new()
+    };
+
+    Ok(ListTripActivityResponse {
+        activities: activities.into_iter().map(activity_to_proto).collect(),
+        next_before_id,
+    })
+}
+
+/// Undo Activity handler
+pub async fn undo_activity(
+    State(state): State<AppState>,
+    ctx: AuthContext,
+    request: UndoActivityRequest,
+) -> Result<UndoActivityResponse, Error> {
+    ctx.require_scope(SCOPE_TRIPS_WRITE)?;
+    let activity_id = parse_uuid("activity_id", &request.activity_id)?;
+
+    let txn = state.conn.begin().await?;
+
+    // Locked, so the same change can't be undone twice at once
+    let activity = activity_log::Entity::find_by_id(activity_id)
+        .lock_exclusive()
+        .one(&txn)
+        .await?
+        .ok_or(Error::NotFound)?;
+    let access = trip_access(&txn, &ctx, activity.trip_id, false).await?;
+    if !access.can_edit() {
+        return Err(Error::Forbidden);
+    }
+
+    let undo = activity::undo(&txn, activity, access.profile_id()).await?;
+
+    txn.commit().await?;
+
+    Ok(UndoActivityResponse {
+        activity: Some(activity_to_proto(undo)),
+    })
+}
+
+// ============================================================================
+// Rich Text
+// ============================================================================
+
+fn rich_text_update_to_proto(update: trip_card_rich_text_updates::Model) -> RichTextUpdate {
+    RichTextUpdate {
+        seq: update.seq,
+        update: update.payload,
+        profile_id: update
+            .profile_id
+            .map(|id| id.to_string())
+            .unwrap_or_default(),
+        created_at: update.created_at.to_rfc3339(),
+    }
+}
+
+/// Get Rich Text handler
+pub async fn get_rich_text(
+    State(state): State<AppState>,
+    ctx: AuthContext,
+    request: GetRichTextRequest,
+) -> Result<GetRichTextResponse, Error> {
+    ctx.require_scope(SCOPE_TRIPS_READ)?;
+    let (card, access) = card_access(&state.conn, &ctx, &request.trip_card_id, false).await?;
+    if access.role.is_none() {
+        return Err(Error::Forbidden);
+    }
+
+    let Some(document) = trip_card_rich_text::Entity::find_by_id(card.id)
+        .one(&state.conn)
+        .await?
+    else {
+        return Ok(GetRichTextResponse {
+            content: "{}".to_string(),
+            ..Default::default()
+        });
+    };
+    let updates = rich_text::updates_since(&state.conn, card.id, document.snapshot_seq).await?;
+
+    Ok(GetRichTextResponse {
+        snapshot: document.crdt_snapshot.unwrap_or_default(),
+        snapshot_seq: document.snapshot_seq,
+        updates: updates.into_iter().map(rich_text_update_to_proto).collect(),
+        content: document.content.to_string(),
+        content_seq: document.content_seq,
+    })
+}
+
+/// Push Rich Text Update handler
+pub async fn push_rich_text_update(
+    State(state): State<AppState>,
+    ctx: AuthContext,
+    request: PushRichTextUpdateRequest,
+) -> Result<PushRichTextUpdateResponse, Error> {
+    ctx.require_scope(SCOPE_TRIPS_WRITE)?;
+    if request.update.is_empty() {
+        return Err(Error::invalid_field("update", "is required"));
+    }
+    if request.update.len() > rich_text::MAX_UPDATE_BYTES {
+        return Err(Error::invalid_field("update", "is too large"));
+    }
+
+    let txn = state.conn.begin().await?;
+
+    let (card, access) = card_access(&txn, &ctx, &request.trip_card_id, false).await?;
+    if !access.can_edit() {
+        return Err(Error::Forbidden);
+    }
+
+    let document = rich_text::lock_document(&txn, card.id).await?;
+    let update =
+        rich_text::append_update(&txn, document, request.update, access.profile.map(|p| p.id))
+            .await?;
+
+    txn.commit().await?;
+
+    let seq = update.seq;
+    state.rich_text_hub.publish(update);
+
+    Ok(PushRichTextUpdateResponse { seq })
+}
+
+/// Events bringing a subscriber from `since` to the latest stored update: the snapshot if
+/// the subscriber is behind it, then the updates after that
+async fn rich_text_catch_up<C: ConnectionTrait>(
+    db: &C,
+    trip_card_id: Uuid,
+    since: i64,
+) -> Result<(Vec<RichTextEvent>, i64), Error> {
+    let mut events = Vec::new();
+    let mut last = since;
+
+    let document = trip_card_rich_text::Entity::find_by_id(trip_card_id)
+        .one(db)
+        .await?;
+    if let Some(document) = document
+        && since < document.snapshot_seq
+    {
+        last = document.snapshot_seq;
+        events.push(RichTextEvent {
+            snapshot: document.crdt_snapshot.unwrap_or_default(),
+            snapshot_seq: document.snapshot_seq,
+            update: None,
+        });
+    }
+
+    for update in rich_text::updates_since(db, trip_card_id, last).await? {
+        last = update.seq;
+        events.push(RichTextEvent {
+            update: Some(rich_text_update_to_proto(update)),
+            ..Default::default()
+        });
+    }
+
+    Ok((events, last))
+}
+
+/// Subscribe Rich Text handler
+pub async fn subscribe_rich_text(
+    State(state): State<AppState>,
+    ctx: AuthContext,
+    request: SubscribeRichTextRequest,
+) -> impl Stream<Item = Result<RichTextEvent, Error>> {
+    try_stream! {
+        ctx.require_scope(SCOPE_TRIPS_READ)?;
+        let (card, access) = card_access(&state.conn, &ctx, &request.trip_card_id, false).await?;
+        if access.role.is_none() {
+            Err(Error::Forbidden)?;
+        }
+
+        // Subscribe before loading, so nothing pushed in between is lost
+        let mut live = state.rich_text_hub.subscribe(card.id);
+
+        let (events, mut last) = rich_text_catch_up(&state.conn, card.id, request.since_seq).await?;
+        for event in events {
+            yield event;
+        }
+
+        // Someone removed from the trip stops receiving the card's updates
+        let mut ctx = ctx;
+        let mut recheck = presence::recheck_interval();
+        loop {
+            let received = tokio::select! {
+                received = live.recv() => Some(received),
+                _ = recheck.tick() => None,
+            };
+            let Some(received) = received else {
+                recheck_read_access(&state, &mut ctx, card.trip_id).await?;
+                continue;
+            };
+            let update = match received {
+                Ok(update) if update.seq <= last => continue,
+                // Updates are consecutive, so a gap means one was missed (updates can be
+                // published out of order, or we lagged behind): reload from the database
+                Ok(update) if update.seq == last + 1 => update,
+                Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {
+                    let (events, caught_up) = rich_text_catch_up(&state.conn, card.id, last).await?;
+                    last = caught_up;
+                    for event in events {
+                        yield event;
+                    }
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            };
+
+            last = update.seq;
+            yield RichTextEvent {
+                update: Some(rich_text_update_to_proto(update)),
+                ..Default::default()
+            };
+        }
+    }
+}
+
+// ============================================================================
+// Presence
+// ============================================================================
+
+fn presence_session_to_proto(session: presence::Session) -> PresenceSession {
+    PresenceSession {
+        session_id: session.session_id,
+        profile_id: session.profile_id.to_string(),
+        trip_card_id: session
+            .trip_card_id
+            .map(|id| id.to_string())
+            .unwrap_or_default(),
+        cursor: session.cursor,
+        joined_at: session.joined_at.to_rfc3339(),
+    }
+}
+
+fn presence_event_to_proto(event: presence::Event) -> PresenceEvent {
+    let (kind, session) = match event {
+        presence::Event::Join(session) => ("join", session),
+        presence::Event::Update(session) => ("update", session),
+        presence::Event::Leave(session) => ("leave", session),
+    };
+    PresenceEvent {
+        kind: kind.to_string(),
+        session: Some(presence_session_to_proto(session)),
+        ..Default::default()
+    }
+}
+
+fn presence_snapshot_to_proto(sessions: Vec<presence::Session>) -> PresenceEvent {
+    PresenceEvent {
+        kind: "snapshot".to_string(),
+        sessions: sessions
+            .into_iter()
+            .map(presence_session_to_proto)
+            .collect(),
+        session: None,
+    }
+}
+
+fn validate_session_id(session_id: &str) -> Result<(), Error> {
+    if session_id.is_empty() {
+        return Err(Error::invalid_field("session_id", "is required"));
+    }
+    if session_id.len() > presence::MAX_SESSION_ID_LEN {
+        return Err(Error::invalid_field("session_id", "is too long"));
+    }
+    Ok(())
+}
+
+/// Update Presence handler. Heartbeats of a known session are handled in memory; the
+/// database is only read when a session joins, focuses another card, or is due for its
+/// membership check.
+pub async fn update_presence(
+    State(state): State<AppState>,
+    ctx: AuthContext,
+    request: UpdatePresenceRequest,
+) -> Result<UpdatePresenceResponse, Error> {
+    ctx.require_scope(SCOPE_TRIPS_READ)?;
+    let account_id = ctx.require_account()?;
+    let trip_id = parse_uuid("trip_id", &request.trip_id)?;
+    validate_session_id(&request.session_id)?;
+    let trip_card_id = if request.trip_card_id.is_empty() {
+        None
+    } else {
+        Some(parse_uuid("trip_card_id", &request.trip_card_id)?)
+    };
+    if request.cursor.len() > presence::MAX_CURSOR_BYTES {
+        return Err(Error::invalid_field("cursor", "is too large"));
+    }
+
+    let now = Utc::now();
+    let existing = state
+        .presence_hub
+        .session(trip_id, account_id, &request.session_id);
+
+    let (profile_id, verified_until) = match existing {
+        Some(session) if session.verified_until > now && session.trip_card_id == trip_card_id => {
+            (session.profile_id, session.verified_until)
+        }
+        _ => {
+            let access = trip_access(&state.conn, &ctx, trip_id, false).await?;
+            let Some(profile) = access.profile.filter(|_| access.role.is_some()) else {
+                // Someone removed from the trip disappears from it right away
+                state
+                    .presence_hub
+                    .leave(trip_id, account_id, &request.session_id);
+                return Err(Error::Forbidden);
+            };
+            if let Some(trip_card_id) = trip_card_id {
+                trip_cards::Entity::find_by_id(trip_card_id)
+                    .filter(trip_cards::COLUMN.trip_id.eq(trip_id))
+                    .one(&state.conn)
+                    .await?
+                    .ok_or_else(|| {
+                        Error::invalid_field("trip_card_id", "is not a card of the trip")
+                    })?;
+            }
+            (
+                profile.id,
+                now + chrono::Duration::seconds(presence::RECHECK_SECS),
+            )
+        }
+    };
+
+    state.presence_hub.heartbeat(
+        trip_id,
+        presence::Session {
+            account_id,
+            session_id: request.session_id,
+            profile_id,
+            trip_card_id,
+            cursor: request.cursor,
+            joined_at: now,
+            expires_at: presence::expires_at(now),
+            verified_until,
+        },
+    );
+
+    Ok(UpdatePresenceResponse {
+        ttl_seconds: presence::TTL_SECS as i32,
+    })
+}
+
+/// Leave Presence handler
+pub async fn leave_presence(
+    State(state): State<AppState>,
+    ctx: AuthContext,
+    request: LeavePresenceRequest,
+) -> Result<LeavePresenceResponse, Error> {
+    ctx.require_scope(SCOPE_TRIPS_READ)?;
+    let account_id = ctx.require_account()?;
+    let trip_id = parse_uuid("trip_id", &request.trip_id)?;
+    validate_session_id(&request.session_id)?;
+
+    let success = state
+        .presence_hub
+        .leave(trip_id, account_id, &request.session_id);
+
+    Ok(LeavePresenceResponse { success })
+}
+
+/// Subscribe Presence handler
+pub async fn subscribe_presence(
+    State(state): State<AppState>,
+    ctx: AuthContext,
+    request: SubscribePresenceRequest,
+) -> impl Stream<Item = Result<PresenceEvent, Error>> {
+    try_stream! {
+        ctx.require_scope(SCOPE_TRIPS_READ)?;
+        let trip_id = parse_uuid("trip_id", &request.trip_id)?;
+        let access = trip_access(&state.conn, &ctx, trip_id, false).await?;
+        if access.role.is_none() {
+            Err(Error::Forbidden)?;
+        }
+
+        let (sessions, mut live) = state.presence_hub.subscribe(trip_id);
+        yield presence_snapshot_to_proto(sessions);
+
+        // Someone removed from the trip stops receiving its presence
+        let mut ctx = ctx;
+        let mut recheck = presence::recheck_interval();
+        loop {
+            let received = tokio::select! {
+                received = live.recv() => Some(received),
+                _ = recheck.tick() => None,
+            };
+            let Some(received) = received else {
+                recheck_read_access(&state, &mut ctx, trip_id).await?;
+                continue;
+            };
+            match received {
+                Ok(event) => yield presence_event_to_proto(event),
+                // Events were missed; start over from the current state
+                Err(broadcast::error::RecvError::Lagged(_)) => {
+                    let (sessions, receiver) = state.presence_hub.subscribe(trip_id);
+                    live = receiver;
+                    yield presence_snapshot_to_proto(sessions);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    }
+}
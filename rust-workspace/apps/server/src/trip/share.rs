@@ -0,0 +1,155 @@
+use crate::auth::api_token;
+use crate::auth::password;
+use anyhow::Result;
+use chrono::{DateTime, Duration, FixedOffset, Utc};
+use sea_orm::{ActiveModelTrait, ConnectionTrait, EntityTrait, QueryFilter, Set};
+use uuid::Uuid;
+use workspace_entity::{profiles, trip_share_links};
+
+/// Prefix of share link tokens
+pub const SHARE_TOKEN_PREFIX: &str = "tvs_";
+
+/// `last_used_at` is only written when older than this, to avoid a write per view
+const LAST_USED_RESOLUTION_SECS: i64 = 60;
+
+/// A new link with its token, which is only available now
+pub struct CreatedLink {
+    pub link: trip_share_links::Model,
+    pub token: String,
+}
+
+/// Issue a share link for a trip. Only hashes of the token and the password are stored.
+pub async fn create<C: ConnectionTrait>(
+    db: &C,
+    trip_id: Uuid,
+    created_by: Uuid,
+    expires_at: Option<DateTime<FixedOffset>>,
+    password: Option<&str>,
+) -> Result<CreatedLink> {
+    let generated = api_token::generate_token(SHARE_TOKEN_PREFIX);
+    let password_hash = password.map(password::hash_password).transpose()?;
+
+    let link = trip_share_links::ActiveModel {
+        id: Set(Uuid::now_v7()),
+        trip_id: Set(trip_id),
+        created_by: Set(created_by),
+        token_prefix: Set(generated.token_prefix),
+        token_hash: Set(generated.token_hash),
+        password_hash: Set(password_hash),
+        expires_at: Set(expires_at),
+        revoked_at: Set(None),
+        last_used_at: Set(None),
+        created_at: Set(Utc::now().into()),
+    }
+    .insert(db)
+    .await?;
+
+    Ok(CreatedLink {
+        link,
+        token: generated.token,
+    })
+}
+
+/// Look up an active (not revoked, not expired) link by its token
+pub async fn find_active<C: ConnectionTrait>(
+    db: &C,
+    token: &str,
+) -> Result<Option<trip_share_links::Model>> {
+    if !token.starts_with(SHARE_TOKEN_PREFIX) {
+        return Ok(None);
+    }
+    let now: DateTime<FixedOffset> = Utc::now().into();
+
+    Ok(trip_share_links::Entity::find()
+        .filter(
+            trip_share_links::COLUMN
+                .token_hash
+                .eq(api_token::hash_token(token)),
+        )
+        .one(db)
+        .await?
+        .filter(|link| link.revoked_at.is_none() && link.expires_at.is_none_or(|at| at > now)))
+}
+
+/// Whether `password` opens the link; links without a password accept any
+pub fn check_password(link: &trip_share_links::Model, password: &str) -> Result<bool> {
+    match &link.password_hash {
+        Some(hash) => password::verify_password(password, hash),
+        None => Ok(true),
+    }
+}
+
+/// Record a view of the link
+pub async fn touch<C: ConnectionTrait>(db: &C, link: &trip_share_links::Model) -> Result<()> {
+    let now: DateTime<FixedOffset> = Utc::now().into();
+    let stale = link
+        .last_used_at
+        .is_none_or(|at| now - at > Duration::seconds(LAST_USED_RESOLUTION_SECS));
+    if stale {
+        let mut active: trip_share_links::ActiveModel = link.clone().into();
+        active.last_used_at = Set(Some(now));
+        active.update(db).await?;
+    }
+    Ok(())
+}
+
+/// A profile as shown to people outside the realm: only the id and username are kept,
+/// contact details and links to accounts and channels are left out
+pub fn redact_profile(profile: profiles::Model) -> profiles::Model {
+    profiles::Model {
+        email: String::new(),
+        phone: String::new(),
+        third_id: None,
+        third_provider_type: None,
+        channel_bridge_id: None,
+        metadata: None,
+        account_id: None,
+        ..profile
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{Value, json};
+
+    fn profile() -> profiles::Model {
+        profiles::Model {
+            id: Uuid::now_v7(),
+            realm_id: Uuid::now_v7(),
+            username: "alice".to_owned(),
+            email: "alice@example.com".to_owned(),
+            phone: "+33 6 12 34 56 78".to_owned(),
+            third_id: Some("123456789".to_owned()),
+            third_provider_type: Some("telegram".to_owned()),
+            channel_bridge_id: Some(Uuid::now_v7()),
+            created_at: Utc::now().into(),
+            metadata: Some(json!({ "avatar": "https://example.com/alice.png" })),
+            account_id: Some(Uuid::now_v7()),
+        }
+    }
+
+    #[test]
+    fn redaction_keeps_only_public_fields() {
+        let original = profile();
+        let redacted = redact_profile(original.clone());
+        assert_eq!(redacted.id, original.id);
+        assert_eq!(redacted.realm_id, original.realm_id);
+        assert_eq!(redacted.username, original.username);
+        assert_eq!(redacted.created_at, original.created_at);
+
+        // Checked on the serialized form, so fields added later must be redacted too
+        let Value::Object(fields) = serde_json::to_value(&redacted).unwrap() else {
+            panic!("profile did not serialize to an object");
+        };
+        for (field, value) in fields {
+            if ["id", "realmId", "username", "createdAt"].contains(&field.as_str()) {
+                continue;
+            }
+            assert!(
+                value.is_null() || value == "",
+                "{field} is not redacted: {value}"
+            );
+        }
+    }
+}
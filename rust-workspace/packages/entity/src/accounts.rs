@@ -23,6 +23,9 @@ pub struct Model {
     pub last_login_at: Option<DateTimeWithTimeZone>,
     #[sea_orm(column_type = "JsonBinary", nullable)]
     pub metadata: Option<Json>,
+    pub session_version: i32,
+    pub deactivated_at: Option<DateTimeWithTimeZone>,
+    pub last_realm_id: Option<Uuid>,
     #[sea_orm(has_many, via = "federated_identities")]
     pub identity_providers: HasMany<super::identity_providers::Entity>,
 }
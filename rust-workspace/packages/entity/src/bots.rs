@@ -28,6 +28,7 @@ pub struct Model {
     pub metadata: Option<Json>,
     #[sea_orm(nullable)]
     pub capabilities: Option<Vec<String>>,
+    pub version: i64,
     #[sea_orm(
         belongs_to,
         relation_enum = "ChannelBridge2",
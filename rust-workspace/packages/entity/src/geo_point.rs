@@ -0,0 +1,264 @@
+//! `GEOGRAPHY(POINT, 4326)` values (PostGIS)
+//!
+//! Points are read as hex-encoded EWKB (the text form of a geography, see the column's
+//! `select_as = "text"`) and written as EWKT cast to geography (`save_as = "geography"`).
+
+use sea_orm::sea_query::{ArrayType, ColumnType, Nullable, ValueType, ValueTypeErr};
+use sea_orm::{ColIdx, DbErr, QueryResult, TryGetError, TryGetable, Value};
+use serde::{Deserialize, Serialize};
+
+/// WGS 84, the only spatial reference system points are stored in
+pub const SRID: u32 = 4326;
+
+const EWKB_POINT: u32 = 1;
+const EWKB_SRID_FLAG: u32 = 0x2000_0000;
+
+/// A WGS 84 point. Coordinates are always finite and within range.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GeoPoint {
+    pub longitude: f64,
+    pub latitude: f64,
+}
+
+// Coordinates are never NaN, so equality is total
+impl Eq for GeoPoint {}
+
+impl GeoPoint {
+    /// A point, or `None` if the coordinates are out of range
+    pub fn new(longitude: f64, latitude: f64) -> Option<Self> {
+        ((-180.0..=180.0).contains(&longitude) && (-90.0..=90.0).contains(&latitude)).then_some(
+            GeoPoint {
+                longitude,
+                latitude,
+            },
+        )
+    }
+
+    /// `SRID=4326;POINT(<longitude> <latitude>)`
+    pub fn to_ewkt(&self) -> String {
+        format!("SRID={SRID};POINT({} {})", self.longitude, self.latitude)
+    }
+
+    /// Parse `POINT(<longitude> <latitude>)`, optionally prefixed by `SRID=4326;`
+    pub fn from_ewkt(ewkt: &str) -> Result<Self, String> {
+        let wkt = match ewkt.split_once(';') {
+            Some((srid, wkt)) if srid.trim() == format!("SRID={SRID}") => wkt,
+            Some((srid, _)) => return Err(format!("unsupported spatial reference {srid}")),
+            None => ewkt,
+        };
+        let coordinates = wkt
+            .trim()
+            .strip_prefix("POINT")
+            .map(str::trim)
+            .and_then(|c| c.strip_prefix('('))
+            .and_then(|c| c.strip_suffix(')'))
+            .ok_or_else(|| format!("not a point: {ewkt}"))?;
+        let mut parts = coordinates.split_whitespace().map(str::parse::<f64>);
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(Ok(longitude)), Some(Ok(latitude)), None) => Self::new(longitude, latitude)
+                .ok_or_else(|| format!("coordinates out of range: {ewkt}")),
+            _ => Err(format!("not a 2D point: {ewkt}")),
+        }
+    }
+
+    /// Parse hex-encoded (E)WKB of a 2D point
+    pub fn from_ewkb_hex(hex: &str) -> Result<Self, String> {
+        if !hex.is_ascii() || !hex.len().is_multiple_of(2) {
+            return Err("invalid hex".to_owned());
+        }
+        let bytes = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|_| "invalid hex".to_owned())?;
+        Self::from_ewkb(&bytes)
+    }
+
+    /// Parse (E)WKB of a 2D point
+    pub fn from_ewkb(bytes: &[u8]) -> Result<Self, String> {
+        let little_endian = match bytes.first() {
+            Some(0) => false,
+            Some(1) => true,
+            _ => return Err("invalid byte order".to_owned()),
+        };
+        let mut rest = &bytes[1..];
+        let mut take = |n: usize| -> Result<&[u8], String> {
+            if rest.len() < n {
+                return Err("truncated point".to_owned());
+            }
+            let (head, tail) = rest.split_at(n);
+            rest = tail;
+            Ok(head)
+        };
+        let read_u32 = |b: &[u8]| {
+            let b: [u8; 4] = b.try_into().expect("4 bytes");
+            if little_endian {
+                u32::from_le_bytes(b)
+            } else {
+                u32::from_be_bytes(b)
+            }
+        };
+        let read_f64 = |b: &[u8]| {
+            let b: [u8; 8] = b.try_into().expect("8 bytes");
+            if little_endian {
+                f64::from_le_bytes(b)
+            } else {
+                f64::from_be_bytes(b)
+            }
+        };
+
+        let geometry_type = read_u32(take(4)?);
+        if geometry_type & !EWKB_SRID_FLAG != EWKB_POINT {
+            return Err(format!("not a 2D point (type {geometry_type:#x})"));
+        }
+        if geometry_type & EWKB_SRID_FLAG != 0 {
+            let srid = read_u32(take(4)?);
+            if srid != SRID {
+                return Err(format!("unsupported spatial reference {srid}"));
+            }
+        }
+        let longitude = read_f64(take(8)?);
+        let latitude = read_f64(take(8)?);
+        Self::new(longitude, latitude).ok_or_else(|| "coordinates out of range".to_owned())
+    }
+}
+
+impl From<GeoPoint> for Value {
+    fn from(point: GeoPoint) -> Self {
+        Value::String(Some(point.to_ewkt()))
+    }
+}
+
+impl TryGetable for GeoPoint {
+    fn try_get_by<I: ColIdx>(res: &QueryResult, index: I) -> Result<Self, TryGetError> {
+        let hex = String::try_get_by(res, index)?;
+        Self::from_ewkb_hex(&hex).map_err(|err| TryGetError::DbErr(DbErr::Type(err)))
+    }
+}
+
+impl ValueType for GeoPoint {
+    fn try_from(v: Value) -> Result<Self, ValueTypeErr> {
+        match v {
+            Value::String(Some(ewkt)) => Self::from_ewkt(&ewkt).map_err(|_| ValueTypeErr),
+            _ => Err(ValueTypeErr),
+        }
+    }
+
+    fn type_name() -> String {
+        stringify!(GeoPoint).to_owned()
+    }
+
+    fn array_type() -> ArrayType {
+        ArrayType::String
+    }
+
+    fn column_type() -> ColumnType {
+        ColumnType::custom("geography")
+    }
+}
+
+impl Nullable for GeoPoint {
+    fn null() -> Value {
+        Value::String(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// EWKB of a point, as PostGIS writes it
+    fn ewkb(point: GeoPoint, little_endian: bool, srid: Option<u32>) -> Vec<u8> {
+        let u32_bytes = |v: u32| {
+            if little_endian {
+                v.to_le_bytes()
+            } else {
+                v.to_be_bytes()
+            }
+        };
+        let f64_bytes = |v: f64| {
+            if little_endian {
+                v.to_le_bytes()
+            } else {
+                v.to_be_bytes()
+            }
+        };
+
+        let mut bytes = vec![u8::from(little_endian)];
+        match srid {
+            Some(srid) => {
+                bytes.extend(u32_bytes(EWKB_POINT | EWKB_SRID_FLAG));
+                bytes.extend(u32_bytes(srid));
+            }
+            None => bytes.extend(u32_bytes(EWKB_POINT)),
+        }
+        bytes.extend(f64_bytes(point.longitude));
+        bytes.extend(f64_bytes(point.latitude));
+        bytes
+    }
+
+    fn louvre() -> GeoPoint {
+        GeoPoint::new(2.3376, 48.8606).unwrap()
+    }
+
+    #[test]
+    fn wkb_round_trips() {
+        let point = louvre();
+        for little_endian in [true, false] {
+            for srid in [Some(SRID), None] {
+                let bytes = ewkb(point, little_endian, srid);
+                assert_eq!(GeoPoint::from_ewkb(&bytes), Ok(point));
+            }
+        }
+
+        let hex: String = ewkb(point, true, Some(SRID))
+            .iter()
+            .map(|b| format!("{b:02X}"))
+            .collect();
+        assert!(hex.starts_with("0101000020E6100000"));
+        assert_eq!(GeoPoint::from_ewkb_hex(&hex), Ok(point));
+        assert_eq!(GeoPoint::from_ewkb_hex(&hex.to_lowercase()), Ok(point));
+    }
+
+    #[test]
+    fn unsupported_wkb_is_rejected() {
+        let bytes = ewkb(louvre(), true, Some(SRID));
+        assert!(GeoPoint::from_ewkb(&bytes[..bytes.len() - 1]).is_err());
+        assert!(GeoPoint::from_ewkb(&ewkb(louvre(), true, Some(3857))).is_err());
+        assert!(GeoPoint::from_ewkb(&[]).is_err());
+
+        let mut line = bytes.clone();
+        line[1] = 2;
+        assert!(GeoPoint::from_ewkb(&line).is_err());
+
+        let out_of_range = GeoPoint {
+            longitude: 200.0,
+            latitude: 0.0,
+        };
+        assert!(GeoPoint::from_ewkb(&ewkb(out_of_range, true, None)).is_err());
+
+        assert!(GeoPoint::from_ewkb_hex("0101").is_err());
+        assert!(GeoPoint::from_ewkb_hex("zz").is_err());
+        assert!(GeoPoint::from_ewkb_hex("010").is_err());
+    }
+
+    #[test]
+    fn ewkt_round_trips() {
+        let point = louvre();
+        assert_eq!(point.to_ewkt(), "SRID=4326;POINT(2.3376 48.8606)");
+        assert_eq!(GeoPoint::from_ewkt(&point.to_ewkt()), Ok(point));
+        assert_eq!(GeoPoint::from_ewkt("POINT (2.3376 48.8606)"), Ok(point));
+
+        let value: Value = point.into();
+        assert_eq!(<GeoPoint as ValueType>::try_from(value).ok(), Some(point));
+    }
+
+    #[test]
+    fn unsupported_ewkt_is_rejected() {
+        assert!(GeoPoint::from_ewkt("SRID=3857;POINT(2.3376 48.8606)").is_err());
+        assert!(GeoPoint::from_ewkt("LINESTRING(0 0, 1 1)").is_err());
+        assert!(GeoPoint::from_ewkt("POINT(2.3376 48.8606 35)").is_err());
+        assert!(GeoPoint::from_ewkt("POINT(2.3376)").is_err());
+        assert!(GeoPoint::from_ewkt("POINT(0 91)").is_err());
+    }
+}
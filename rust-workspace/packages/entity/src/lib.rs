@@ -2,22 +2,36 @@
 
 pub mod prelude;
 
+pub mod account_email_changes;
 pub mod account_realm_roles;
+pub mod account_recovery_codes;
+pub mod account_totp_secrets;
 pub mod accounts;
+pub mod activity_log;
+pub mod api_tokens;
+pub mod auth_audit_logs;
 pub mod bots;
 pub mod channel_bridge;
 pub mod chat_participants;
 pub mod chats;
+pub mod data_exports;
 pub mod federated_identities;
+pub mod geo_point;
 pub mod identity_providers;
+pub mod login_throttles;
 pub mod messages;
 pub mod permissions;
 pub mod profiles;
+pub mod realm_ai_usage;
 pub mod realms;
 pub mod roles;
 pub mod spatial_ref_sys;
 pub mod trip_card_rich_text;
+pub mod trip_card_rich_text_updates;
 pub mod trip_card_votes;
 pub mod trip_cards;
+pub mod trip_expense_shares;
+pub mod trip_expenses;
 pub mod trip_participants;
+pub mod trip_share_links;
 pub mod trips;
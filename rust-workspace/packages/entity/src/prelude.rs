@@ -1,21 +1,34 @@
 //! `SeaORM` Entity, @generated by sea-orm-codegen 2.0.0-rc.18
 
+pub use super::account_email_changes::Entity as AccountEmailChanges;
 pub use super::account_realm_roles::Entity as AccountRealmRoles;
+pub use super::account_recovery_codes::Entity as AccountRecoveryCodes;
+pub use super::account_totp_secrets::Entity as AccountTotpSecrets;
 pub use super::accounts::Entity as Accounts;
+pub use super::activity_log::Entity as ActivityLog;
+pub use super::api_tokens::Entity as ApiTokens;
+pub use super::auth_audit_logs::Entity as AuthAuditLogs;
 pub use super::bots::Entity as Bots;
 pub use super::channel_bridge::Entity as ChannelBridge;
 pub use super::chat_participants::Entity as ChatParticipants;
 pub use super::chats::Entity as Chats;
+pub use super::data_exports::Entity as DataExports;
 pub use super::federated_identities::Entity as FederatedIdentities;
 pub use super::identity_providers::Entity as IdentityProviders;
+pub use super::login_throttles::Entity as LoginThrottles;
 pub use super::messages::Entity as Messages;
 pub use super::permissions::Entity as Permissions;
 pub use super::profiles::Entity as Profiles;
+pub use super::realm_ai_usage::Entity as RealmAiUsage;
 pub use super::realms::Entity as Realms;
 pub use super::roles::Entity as Roles;
 pub use super::spatial_ref_sys::Entity as SpatialRefSys;
 pub use super::trip_card_rich_text::Entity as TripCardRichText;
+pub use super::trip_card_rich_text_updates::Entity as TripCardRichTextUpdates;
 pub use super::trip_card_votes::Entity as TripCardVotes;
 pub use super::trip_cards::Entity as TripCards;
+pub use super::trip_expense_shares::Entity as TripExpenseShares;
+pub use super::trip_expenses::Entity as TripExpenses;
 pub use super::trip_participants::Entity as TripParticipants;
+pub use super::trip_share_links::Entity as TripShareLinks;
 pub use super::trips::Entity as Trips;
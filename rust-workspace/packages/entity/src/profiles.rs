@@ -34,6 +34,15 @@ pub struct Model {
     pub created_at: DateTimeWithTimeZone,
     #[sea_orm(column_type = "JsonBinary", nullable)]
     pub metadata: Option<Json>,
+    pub account_id: Option<Uuid>,
+    #[sea_orm(
+        belongs_to,
+        from = "account_id",
+        to = "id",
+        on_update = "NoAction",
+        on_delete = "SetNull"
+    )]
+    pub accounts: HasOne<super::accounts::Entity>,
     #[sea_orm(
         belongs_to,
         from = "channel_bridge_id",
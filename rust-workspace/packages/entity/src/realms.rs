@@ -21,6 +21,8 @@ pub struct Model {
     pub updated_at: DateTimeWithTimeZone,
     #[sea_orm(column_type = "JsonBinary", nullable)]
     pub metadata: Option<Json>,
+    pub require_admin_mfa: bool,
+    pub deleted_at: Option<DateTimeWithTimeZone>,
     #[sea_orm(has_many)]
     pub account_realm_roles: HasMany<super::account_realm_roles::Entity>,
     #[sea_orm(has_many)]
@@ -15,6 +15,11 @@ pub struct Model {
     pub last_edited_by: Option<Uuid>,
     pub created_at: DateTimeWithTimeZone,
     pub updated_at: DateTimeWithTimeZone,
+    #[sea_orm(column_type = "VarBinary(StringLen::None)", nullable)]
+    pub crdt_snapshot: Option<Vec<u8>>,
+    pub snapshot_seq: i64,
+    pub crdt_seq: i64,
+    pub content_seq: i64,
     #[sea_orm(
         belongs_to,
         from = "last_edited_by",
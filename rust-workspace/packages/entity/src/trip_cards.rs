@@ -1,5 +1,6 @@
 //! `SeaORM` Entity, @generated by sea-orm-codegen 2.0.0-rc.18
 
+use super::geo_point::GeoPoint;
 use sea_orm::entity::prelude::*;
 use serde::{Deserialize, Serialize};
 
@@ -30,13 +31,14 @@ pub struct Model {
     pub updated_at: DateTimeWithTimeZone,
     #[sea_orm(column_type = "JsonBinary", nullable)]
     pub metadata: Option<Json>,
+    pub version: i64,
     #[sea_orm(
-        ignore,
         column_type = "custom(\"geography\")",
         select_as = "text",
+        save_as = "geography",
         nullable
     )]
-    pub position: Option<String>,
+    pub position: Option<GeoPoint>,
     #[sea_orm(has_one)]
     pub trip_card_rich_text: HasOne<super::trip_card_rich_text::Entity>,
     #[sea_orm(
@@ -26,6 +26,7 @@ pub struct Model {
     pub updated_at: DateTimeWithTimeZone,
     #[sea_orm(column_type = "JsonBinary", nullable)]
     pub metadata: Option<Json>,
+    pub version: i64,
     #[sea_orm(has_one)]
     pub chats: HasOne<super::chats::Entity>,
     #[sea_orm(
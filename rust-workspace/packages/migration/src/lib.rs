@@ -1,12 +1,52 @@
 pub use sea_orm_migration::prelude::*;
 
 mod m20251118_000001_mvp;
+mod m20251125_000001_login_throttling;
+mod m20251126_000001_row_level_security;
+mod m20251127_000001_mfa;
+mod m20251128_000001_api_tokens;
+mod m20251129_000001_account_self_service;
+mod m20251130_000001_data_exports;
+mod m20251201_000001_realm_lifecycle;
+mod m20251202_000001_realm_quotas;
+mod m20251203_000001_trip_participant_roles;
+mod m20251204_000001_profile_accounts;
+mod m20251205_000001_rich_text_crdt;
+mod m20251206_000001_row_versions;
+mod m20251207_000001_activity_log;
+mod m20251208_000001_trip_templates;
+mod m20251209_000001_trip_share_links;
+mod m20251210_000001_trip_expenses;
+mod m20251211_000001_account_email_changes;
+mod m20251212_000001_account_last_realm;
+mod m20251213_000001_activity_triggers;
 
 pub struct Migrator;
 
 #[async_trait::async_trait]
 impl MigratorTrait for Migrator {
     fn migrations() -> Vec<Box<dyn MigrationTrait>> {
-        vec![Box::new(m20251118_000001_mvp::Migration)]
+        vec![
+            Box::new(m20251118_000001_mvp::Migration),
+            Box::new(m20251125_000001_login_throttling::Migration),
+            Box::new(m20251126_000001_row_level_security::Migration),
+            Box::new(m20251127_000001_mfa::Migration),
+            Box::new(m20251128_000001_api_tokens::Migration),
+            Box::new(m20251129_000001_account_self_service::Migration),
+            Box::new(m20251130_000001_data_exports::Migration),
+            Box::new(m20251201_000001_realm_lifecycle::Migration),
+            Box::new(m20251202_000001_realm_quotas::Migration),
+            Box::new(m20251203_000001_trip_participant_roles::Migration),
+            Box::new(m20251204_000001_profile_accounts::Migration),
+            Box::new(m20251205_000001_rich_text_crdt::Migration),
+            Box::new(m20251206_000001_row_versions::Migration),
+            Box::new(m20251207_000001_activity_log::Migration),
+            Box::new(m20251208_000001_trip_templates::Migration),
+            Box::new(m20251209_000001_trip_share_links::Migration),
+            Box::new(m20251210_000001_trip_expenses::Migration),
+            Box::new(m20251211_000001_account_email_changes::Migration),
+            Box::new(m20251212_000001_account_last_realm::Migration),
+            Box::new(m20251213_000001_activity_triggers::Migration),
+        ]
     }
 }
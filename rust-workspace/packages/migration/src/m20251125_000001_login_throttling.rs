@@ -0,0 +1,150 @@
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_query::{Expr, ForeignKey, Index};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // ============================================================================
+        // Login Throttling and Auth Audit Log
+        // ============================================================================
+
+        // Create login_throttles table
+        // One row per (scope, key), e.g. ('account', '<account uuid>') or ('ip', '203.0.113.7')
+        manager
+            .create_table(
+                Table::create()
+                    .table(LoginThrottles::Table)
+                    .col(
+                        ColumnDef::new(LoginThrottles::Scope)
+                            .text()
+                            .not_null()
+                            .check(Expr::col(LoginThrottles::Scope).is_in(vec!["account", "ip"])),
+                    )
+                    .col(ColumnDef::new(LoginThrottles::Key).text().not_null())
+                    .col(
+                        ColumnDef::new(LoginThrottles::FailedCount)
+                            .integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .col(ColumnDef::new(LoginThrottles::LastFailedAt).timestamp_with_time_zone())
+                    .col(ColumnDef::new(LoginThrottles::LockedUntil).timestamp_with_time_zone())
+                    .primary_key(
+                        Index::create()
+                            .name("pk_login_throttles")
+                            .col(LoginThrottles::Scope)
+                            .col(LoginThrottles::Key),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // Create auth_audit_logs table
+        manager
+            .create_table(
+                Table::create()
+                    .table(AuthAuditLogs::Table)
+                    .col(
+                        ColumnDef::new(AuthAuditLogs::Id)
+                            .uuid()
+                            .not_null()
+                            .default(Expr::cust("uuidv7()"))
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(AuthAuditLogs::AccountId).uuid())
+                    .col(ColumnDef::new(AuthAuditLogs::EventType).text().not_null())
+                    .col(ColumnDef::new(AuthAuditLogs::Identifier).text())
+                    .col(ColumnDef::new(AuthAuditLogs::IpAddress).text())
+                    .col(ColumnDef::new(AuthAuditLogs::UserAgent).text())
+                    .col(
+                        ColumnDef::new(AuthAuditLogs::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::cust("now()")),
+                    )
+                    .col(ColumnDef::new(AuthAuditLogs::Metadata).json_binary())
+                    .to_owned(),
+            )
+            .await?;
+
+        // Keep audit entries when the account is deleted
+        manager
+            .create_foreign_key(
+                ForeignKey::create()
+                    .name("fk_auth_audit_logs_account")
+                    .from(AuthAuditLogs::Table, AuthAuditLogs::AccountId)
+                    .to(Accounts::Table, Accounts::Id)
+                    .on_delete(ForeignKeyAction::SetNull)
+                    .to_owned(),
+            )
+            .await?;
+
+        // Create indexes on auth_audit_logs
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_auth_audit_logs_account_created")
+                    .table(AuthAuditLogs::Table)
+                    .col(AuthAuditLogs::AccountId)
+                    .col(AuthAuditLogs::CreatedAt)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_auth_audit_logs_ip_created")
+                    .table(AuthAuditLogs::Table)
+                    .col(AuthAuditLogs::IpAddress)
+                    .col(AuthAuditLogs::CreatedAt)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(AuthAuditLogs::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(LoginThrottles::Table).to_owned())
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum Accounts {
+    Table,
+    Id,
+}
+
+#[derive(DeriveIden)]
+enum LoginThrottles {
+    Table,
+    Scope,
+    Key,
+    FailedCount,
+    LastFailedAt,
+    LockedUntil,
+}
+
+#[derive(DeriveIden)]
+enum AuthAuditLogs {
+    Table,
+    Id,
+    AccountId,
+    EventType,
+    Identifier,
+    IpAddress,
+    UserAgent,
+    CreatedAt,
+    Metadata,
+}
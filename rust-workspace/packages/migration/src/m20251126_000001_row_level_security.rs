@@ -0,0 +1,111 @@
+use sea_orm::ConnectionTrait;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+// Helper function to execute raw SQL statements
+// Roles, grants and policies have no StatementBuilder implementations
+async fn exec_raw_sql(manager: &SchemaManager<'_>, sql: &str) -> Result<(), DbErr> {
+    manager.get_connection().execute_unprepared(sql).await?;
+    Ok(())
+}
+
+/// Realm of the current request, taken from the `realm_id` JWT claim that
+/// PostGraphile exposes as `jwt.claims.realm_id`. NULL (no rows) when unset.
+const CURRENT_REALM_ID: &str = "NULLIF(current_setting('jwt.claims.realm_id', true), '')::uuid";
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // ============================================================================
+        // Row-Level Security for the PostGraphile read path
+        // ============================================================================
+
+        // Role named by the `role` JWT claim; PostGraphile switches to it per request.
+        // The table owner (used by the Rust server) is not subject to these policies.
+        exec_raw_sql(
+            manager,
+            r#"
+            DO $$
+            BEGIN
+                IF NOT EXISTS (SELECT 1 FROM pg_roles WHERE rolname = 'tripvota_user') THEN
+                    CREATE ROLE tripvota_user NOLOGIN;
+                END IF;
+                -- The connecting user must be a member to SET ROLE into it
+                IF NOT pg_has_role(current_user, 'tripvota_user', 'MEMBER') THEN
+                    GRANT tripvota_user TO CURRENT_USER;
+                END IF;
+            END
+            $$
+            "#,
+        )
+        .await?;
+        exec_raw_sql(manager, "GRANT USAGE ON SCHEMA public TO tripvota_user").await?;
+        exec_raw_sql(
+            manager,
+            "GRANT SELECT ON trips, trip_cards, bots, profiles, chats TO tripvota_user",
+        )
+        .await?;
+
+        // Tables carrying realm_id directly
+        for table in ["trips", "bots", "profiles"] {
+            exec_raw_sql(
+                manager,
+                &format!("ALTER TABLE {table} ENABLE ROW LEVEL SECURITY"),
+            )
+            .await?;
+            exec_raw_sql(
+                manager,
+                &format!(
+                    "CREATE POLICY {table}_realm_isolation ON {table} TO tripvota_user \
+                     USING (realm_id = {CURRENT_REALM_ID})"
+                ),
+            )
+            .await?;
+        }
+
+        // Tables scoped through their trip
+        for table in ["trip_cards", "chats"] {
+            exec_raw_sql(
+                manager,
+                &format!("ALTER TABLE {table} ENABLE ROW LEVEL SECURITY"),
+            )
+            .await?;
+            exec_raw_sql(
+                manager,
+                &format!(
+                    "CREATE POLICY {table}_realm_isolation ON {table} TO tripvota_user \
+                     USING (EXISTS (SELECT 1 FROM trips t WHERE t.id = {table}.trip_id AND t.realm_id = {CURRENT_REALM_ID}))"
+                ),
+            )
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        for table in ["trips", "bots", "profiles", "trip_cards", "chats"] {
+            exec_raw_sql(
+                manager,
+                &format!("DROP POLICY IF EXISTS {table}_realm_isolation ON {table}"),
+            )
+            .await?;
+            exec_raw_sql(
+                manager,
+                &format!("ALTER TABLE {table} DISABLE ROW LEVEL SECURITY"),
+            )
+            .await?;
+        }
+
+        exec_raw_sql(
+            manager,
+            "REVOKE ALL ON trips, trip_cards, bots, profiles, chats FROM tripvota_user",
+        )
+        .await?;
+        exec_raw_sql(manager, "REVOKE USAGE ON SCHEMA public FROM tripvota_user").await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,187 @@
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_query::{Expr, ForeignKey, Index};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // ============================================================================
+        // Multi-Factor Authentication (TOTP + Recovery Codes)
+        // ============================================================================
+
+        // Create account_totp_secrets table
+        // One row per account; the secret is AES-GCM encrypted by the server
+        manager
+            .create_table(
+                Table::create()
+                    .table(AccountTotpSecrets::Table)
+                    .col(
+                        ColumnDef::new(AccountTotpSecrets::AccountId)
+                            .uuid()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(AccountTotpSecrets::SecretCiphertext)
+                            .binary()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(AccountTotpSecrets::SecretNonce)
+                            .binary()
+                            .not_null(),
+                    )
+                    // NULL until the first code has been verified
+                    .col(ColumnDef::new(AccountTotpSecrets::ConfirmedAt).timestamp_with_time_zone())
+                    // Last accepted time step, so a code can't be replayed
+                    .col(ColumnDef::new(AccountTotpSecrets::LastUsedStep).big_integer())
+                    .col(
+                        ColumnDef::new(AccountTotpSecrets::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::cust("now()")),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_foreign_key(
+                ForeignKey::create()
+                    .name("fk_account_totp_secrets_account")
+                    .from(AccountTotpSecrets::Table, AccountTotpSecrets::AccountId)
+                    .to(Accounts::Table, Accounts::Id)
+                    .on_delete(ForeignKeyAction::Cascade)
+                    .to_owned(),
+            )
+            .await?;
+
+        // Create account_recovery_codes table
+        manager
+            .create_table(
+                Table::create()
+                    .table(AccountRecoveryCodes::Table)
+                    .col(
+                        ColumnDef::new(AccountRecoveryCodes::Id)
+                            .uuid()
+                            .not_null()
+                            .default(Expr::cust("uuidv7()"))
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(AccountRecoveryCodes::AccountId)
+                            .uuid()
+                            .not_null(),
+                    )
+                    // SHA-256 of the code; codes are random so a slow hash isn't needed
+                    .col(
+                        ColumnDef::new(AccountRecoveryCodes::CodeHash)
+                            .text()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(AccountRecoveryCodes::UsedAt).timestamp_with_time_zone())
+                    .col(
+                        ColumnDef::new(AccountRecoveryCodes::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::cust("now()")),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_foreign_key(
+                ForeignKey::create()
+                    .name("fk_account_recovery_codes_account")
+                    .from(AccountRecoveryCodes::Table, AccountRecoveryCodes::AccountId)
+                    .to(Accounts::Table, Accounts::Id)
+                    .on_delete(ForeignKeyAction::Cascade)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("uq_account_recovery_codes_account_hash")
+                    .table(AccountRecoveryCodes::Table)
+                    .col(AccountRecoveryCodes::AccountId)
+                    .col(AccountRecoveryCodes::CodeHash)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+
+        // Realm policy: members holding the `admin` role must use MFA
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Realms::Table)
+                    .add_column(
+                        ColumnDef::new(Realms::RequireAdminMfa)
+                            .boolean()
+                            .not_null()
+                            .default(false),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Realms::Table)
+                    .drop_column(Realms::RequireAdminMfa)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .drop_table(Table::drop().table(AccountRecoveryCodes::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(AccountTotpSecrets::Table).to_owned())
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum Accounts {
+    Table,
+    Id,
+}
+
+#[derive(DeriveIden)]
+enum Realms {
+    Table,
+    RequireAdminMfa,
+}
+
+#[derive(DeriveIden)]
+enum AccountTotpSecrets {
+    Table,
+    AccountId,
+    SecretCiphertext,
+    SecretNonce,
+    ConfirmedAt,
+    LastUsedStep,
+    CreatedAt,
+}
+
+#[derive(DeriveIden)]
+enum AccountRecoveryCodes {
+    Table,
+    Id,
+    AccountId,
+    CodeHash,
+    UsedAt,
+    CreatedAt,
+}
@@ -0,0 +1,198 @@
+use sea_orm::ConnectionTrait;
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_query::{Expr, ForeignKey, Index};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+// Helper function to execute raw SQL statements
+// Used for CHECK constraints spanning several columns
+async fn exec_raw_sql(manager: &SchemaManager<'_>, sql: &str) -> Result<(), DbErr> {
+    manager.get_connection().execute_unprepared(sql).await?;
+    Ok(())
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // ============================================================================
+        // API Tokens (personal access tokens and bot service tokens)
+        // ============================================================================
+
+        // Create api_tokens table
+        // Tokens belong to either an account or a bot; only a hash is stored
+        manager
+            .create_table(
+                Table::create()
+                    .table(ApiTokens::Table)
+                    .col(
+                        ColumnDef::new(ApiTokens::Id)
+                            .uuid()
+                            .not_null()
+                            .default(Expr::cust("uuidv7()"))
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(ApiTokens::Name).text().not_null())
+                    // First characters of the token, shown in listings to tell tokens apart
+                    .col(ColumnDef::new(ApiTokens::TokenPrefix).text().not_null())
+                    .col(
+                        ColumnDef::new(ApiTokens::TokenHash)
+                            .text()
+                            .not_null()
+                            .unique_key(),
+                    )
+                    .col(ColumnDef::new(ApiTokens::AccountId).uuid())
+                    .col(ColumnDef::new(ApiTokens::BotId).uuid())
+                    // Realm the token is limited to (always set for bot tokens)
+                    .col(ColumnDef::new(ApiTokens::RealmId).uuid())
+                    .col(
+                        ColumnDef::new(ApiTokens::Scopes)
+                            .array(ColumnType::Text)
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(ApiTokens::CreatedBy).uuid())
+                    .col(ColumnDef::new(ApiTokens::ExpiresAt).timestamp_with_time_zone())
+                    .col(ColumnDef::new(ApiTokens::LastUsedAt).timestamp_with_time_zone())
+                    .col(ColumnDef::new(ApiTokens::RevokedAt).timestamp_with_time_zone())
+                    .col(
+                        ColumnDef::new(ApiTokens::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::cust("now()")),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // Exactly one owner: an account or a bot
+        exec_raw_sql(
+            manager,
+            "ALTER TABLE api_tokens ADD CONSTRAINT api_tokens_owner_check CHECK ((account_id IS NULL) <> (bot_id IS NULL))",
+        )
+        .await?;
+
+        // Create foreign keys for api_tokens
+        manager
+            .create_foreign_key(
+                ForeignKey::create()
+                    .name("fk_api_tokens_account")
+                    .from(ApiTokens::Table, ApiTokens::AccountId)
+                    .to(Accounts::Table, Accounts::Id)
+                    .on_delete(ForeignKeyAction::Cascade)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_foreign_key(
+                ForeignKey::create()
+                    .name("fk_api_tokens_bot")
+                    .from(ApiTokens::Table, ApiTokens::BotId)
+                    .to(Bots::Table, Bots::Id)
+                    .on_delete(ForeignKeyAction::Cascade)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_foreign_key(
+                ForeignKey::create()
+                    .name("fk_api_tokens_realm")
+                    .from(ApiTokens::Table, ApiTokens::RealmId)
+                    .to(Realms::Table, Realms::Id)
+                    .on_delete(ForeignKeyAction::Cascade)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_foreign_key(
+                ForeignKey::create()
+                    .name("fk_api_tokens_created_by")
+                    .from(ApiTokens::Table, ApiTokens::CreatedBy)
+                    .to(Accounts::Table, Accounts::Id)
+                    .on_delete(ForeignKeyAction::SetNull)
+                    .to_owned(),
+            )
+            .await?;
+
+        // Distinct field names for the two account relations (PostGraphile)
+        exec_raw_sql(
+            manager,
+            "COMMENT ON CONSTRAINT fk_api_tokens_account ON api_tokens IS E'@fieldName account'",
+        )
+        .await?;
+        exec_raw_sql(
+            manager,
+            "COMMENT ON CONSTRAINT fk_api_tokens_created_by ON api_tokens IS E'@fieldName created_by_account'",
+        )
+        .await?;
+
+        // Create indexes on api_tokens
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_api_tokens_account")
+                    .table(ApiTokens::Table)
+                    .col(ApiTokens::AccountId)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_api_tokens_bot")
+                    .table(ApiTokens::Table)
+                    .col(ApiTokens::BotId)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(ApiTokens::Table).to_owned())
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum Accounts {
+    Table,
+    Id,
+}
+
+#[derive(DeriveIden)]
+enum Bots {
+    Table,
+    Id,
+}
+
+#[derive(DeriveIden)]
+enum Realms {
+    Table,
+    Id,
+}
+
+#[derive(DeriveIden)]
+enum ApiTokens {
+    Table,
+    Id,
+    Name,
+    TokenPrefix,
+    TokenHash,
+    AccountId,
+    BotId,
+    RealmId,
+    Scopes,
+    CreatedBy,
+    ExpiresAt,
+    LastUsedAt,
+    RevokedAt,
+    CreatedAt,
+}
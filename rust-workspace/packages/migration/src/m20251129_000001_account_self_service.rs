@@ -0,0 +1,100 @@
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_query::ForeignKey;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // ============================================================================
+        // Account Self-Service (password change, deactivation, deletion)
+        // ============================================================================
+
+        // Bumping session_version invalidates every access/refresh token issued before
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Accounts::Table)
+                    .add_column(
+                        ColumnDef::new(Accounts::SessionVersion)
+                            .integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .add_column(ColumnDef::new(Accounts::DeactivatedAt).timestamp_with_time_zone())
+                    .to_owned(),
+            )
+            .await?;
+
+        // Role grants outlive the account that granted them
+        manager
+            .drop_foreign_key(
+                ForeignKey::drop()
+                    .name("fk_account_realm_roles_granted_by")
+                    .table(AccountRealmRoles::Table)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_foreign_key(
+                ForeignKey::create()
+                    .name("fk_account_realm_roles_granted_by")
+                    .from(AccountRealmRoles::Table, AccountRealmRoles::GrantedBy)
+                    .to(Accounts::Table, Accounts::Id)
+                    .on_delete(ForeignKeyAction::SetNull)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_foreign_key(
+                ForeignKey::drop()
+                    .name("fk_account_realm_roles_granted_by")
+                    .table(AccountRealmRoles::Table)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_foreign_key(
+                ForeignKey::create()
+                    .name("fk_account_realm_roles_granted_by")
+                    .from(AccountRealmRoles::Table, AccountRealmRoles::GrantedBy)
+                    .to(Accounts::Table, Accounts::Id)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Accounts::Table)
+                    .drop_column(Accounts::DeactivatedAt)
+                    .drop_column(Accounts::SessionVersion)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum Accounts {
+    Table,
+    Id,
+    SessionVersion,
+    DeactivatedAt,
+}
+
+#[derive(DeriveIden)]
+enum AccountRealmRoles {
+    Table,
+    GrantedBy,
+}
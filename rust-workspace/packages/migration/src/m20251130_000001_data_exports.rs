@@ -0,0 +1,113 @@
+use sea_orm::ConnectionTrait;
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_query::{Expr, ForeignKey, Index};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+// Helper function to execute raw SQL statements
+// Used for CHECK constraints
+async fn exec_raw_sql(manager: &SchemaManager<'_>, sql: &str) -> Result<(), DbErr> {
+    manager.get_connection().execute_unprepared(sql).await?;
+    Ok(())
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // ============================================================================
+        // Personal Data Exports
+        // ============================================================================
+
+        // Create data_exports table
+        // One row per export job; the finished JSON archive is kept until expires_at
+        manager
+            .create_table(
+                Table::create()
+                    .table(DataExports::Table)
+                    .col(
+                        ColumnDef::new(DataExports::Id)
+                            .uuid()
+                            .not_null()
+                            .default(Expr::cust("uuidv7()"))
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(DataExports::AccountId).uuid().not_null())
+                    .col(
+                        ColumnDef::new(DataExports::Status)
+                            .text()
+                            .not_null()
+                            .default("pending"),
+                    )
+                    .col(ColumnDef::new(DataExports::Archive).binary())
+                    .col(ColumnDef::new(DataExports::Error).text())
+                    .col(
+                        ColumnDef::new(DataExports::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::cust("now()")),
+                    )
+                    .col(ColumnDef::new(DataExports::CompletedAt).timestamp_with_time_zone())
+                    // Set once the archive is ready; it can't be downloaded afterwards
+                    .col(ColumnDef::new(DataExports::ExpiresAt).timestamp_with_time_zone())
+                    .to_owned(),
+            )
+            .await?;
+
+        exec_raw_sql(
+            manager,
+            "ALTER TABLE data_exports ADD CONSTRAINT data_exports_status_check CHECK (status IN ('pending', 'running', 'ready', 'failed'))",
+        )
+        .await?;
+
+        manager
+            .create_foreign_key(
+                ForeignKey::create()
+                    .name("fk_data_exports_account")
+                    .from(DataExports::Table, DataExports::AccountId)
+                    .to(Accounts::Table, Accounts::Id)
+                    .on_delete(ForeignKeyAction::Cascade)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_data_exports_account")
+                    .table(DataExports::Table)
+                    .col(DataExports::AccountId)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(DataExports::Table).to_owned())
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum Accounts {
+    Table,
+    Id,
+}
+
+#[derive(DeriveIden)]
+enum DataExports {
+    Table,
+    Id,
+    AccountId,
+    Status,
+    Archive,
+    Error,
+    CreatedAt,
+    CompletedAt,
+    ExpiresAt,
+}
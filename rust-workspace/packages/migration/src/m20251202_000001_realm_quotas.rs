@@ -0,0 +1,82 @@
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_query::{Expr, ForeignKey, Index};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // ============================================================================
+        // Realm Quotas
+        // ============================================================================
+        // Limits live in realms.metadata.quotas; this table only counts AI usage,
+        // the other quotas are checked against row counts
+
+        // Create realm_ai_usage table
+        // One row per realm and calendar month (UTC)
+        manager
+            .create_table(
+                Table::create()
+                    .table(RealmAiUsage::Table)
+                    .col(ColumnDef::new(RealmAiUsage::RealmId).uuid().not_null())
+                    .col(ColumnDef::new(RealmAiUsage::PeriodStart).date().not_null())
+                    .col(
+                        ColumnDef::new(RealmAiUsage::TokensUsed)
+                            .big_integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .col(
+                        ColumnDef::new(RealmAiUsage::UpdatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::cust("now()")),
+                    )
+                    .primary_key(
+                        Index::create()
+                            .name("pk_realm_ai_usage")
+                            .col(RealmAiUsage::RealmId)
+                            .col(RealmAiUsage::PeriodStart),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_foreign_key(
+                ForeignKey::create()
+                    .name("fk_realm_ai_usage_realm")
+                    .from(RealmAiUsage::Table, RealmAiUsage::RealmId)
+                    .to(Realms::Table, Realms::Id)
+                    .on_delete(ForeignKeyAction::Cascade)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(RealmAiUsage::Table).to_owned())
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum Realms {
+    Table,
+    Id,
+}
+
+#[derive(DeriveIden)]
+enum RealmAiUsage {
+    Table,
+    RealmId,
+    PeriodStart,
+    TokensUsed,
+    UpdatedAt,
+}
@@ -0,0 +1,71 @@
+use sea_orm::ConnectionTrait;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+// Helper function to execute raw SQL statements
+// Used for CHECK constraints
+async fn exec_raw_sql(manager: &SchemaManager<'_>, sql: &str) -> Result<(), DbErr> {
+    manager.get_connection().execute_unprepared(sql).await?;
+    Ok(())
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // ============================================================================
+        // Trip Participant Roles
+        // ============================================================================
+
+        // owner/participant/invited becomes owner/editor/viewer:
+        // owners manage participants, editors change the trip, viewers only read it
+        exec_raw_sql(
+            manager,
+            "ALTER TABLE trip_participants DROP CONSTRAINT IF EXISTS trip_participants_role_check",
+        )
+        .await?;
+        exec_raw_sql(
+            manager,
+            "UPDATE trip_participants SET role = CASE role WHEN 'participant' THEN 'editor' WHEN 'invited' THEN 'viewer' ELSE role END",
+        )
+        .await?;
+        exec_raw_sql(
+            manager,
+            "ALTER TABLE trip_participants ALTER COLUMN role SET DEFAULT 'viewer'",
+        )
+        .await?;
+        exec_raw_sql(
+            manager,
+            "ALTER TABLE trip_participants ADD CONSTRAINT trip_participants_role_check CHECK (role IN ('owner', 'editor', 'viewer'))",
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        exec_raw_sql(
+            manager,
+            "ALTER TABLE trip_participants DROP CONSTRAINT IF EXISTS trip_participants_role_check",
+        )
+        .await?;
+        exec_raw_sql(
+            manager,
+            "UPDATE trip_participants SET role = CASE role WHEN 'editor' THEN 'participant' WHEN 'viewer' THEN 'invited' ELSE role END",
+        )
+        .await?;
+        exec_raw_sql(
+            manager,
+            "ALTER TABLE trip_participants ALTER COLUMN role SET DEFAULT 'participant'",
+        )
+        .await?;
+        exec_raw_sql(
+            manager,
+            "ALTER TABLE trip_participants ADD CONSTRAINT trip_participants_role_check CHECK (role IN ('owner', 'participant', 'invited'))",
+        )
+        .await?;
+
+        Ok(())
+    }
+}
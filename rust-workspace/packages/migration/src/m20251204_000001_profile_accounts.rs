@@ -0,0 +1,100 @@
+use sea_orm::ConnectionTrait;
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_query::ForeignKey;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+// Helper function to execute raw SQL statements
+// Used for partial indexes and the backfill
+async fn exec_raw_sql(manager: &SchemaManager<'_>, sql: &str) -> Result<(), DbErr> {
+    manager.get_connection().execute_unprepared(sql).await?;
+    Ok(())
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // ============================================================================
+        // Profile <-> Account Link
+        // ============================================================================
+
+        // The account a profile belongs to; NULL for people who only reach the realm
+        // through a channel (e.g. LINE) and have no web account
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Profiles::Table)
+                    .add_column(ColumnDef::new(Profiles::AccountId).uuid())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_foreign_key(
+                ForeignKey::create()
+                    .name("fk_profiles_account")
+                    .from(Profiles::Table, Profiles::AccountId)
+                    .to(Accounts::Table, Accounts::Id)
+                    .on_delete(ForeignKeyAction::SetNull)
+                    .to_owned(),
+            )
+            .await?;
+
+        // An account has at most one profile per realm
+        exec_raw_sql(
+            manager,
+            "CREATE UNIQUE INDEX idx_profiles_realm_account ON profiles (realm_id, account_id) WHERE account_id IS NOT NULL",
+        )
+        .await?;
+
+        // Link existing profiles by email, in realms the account is a member of; when a
+        // realm has several profiles with the account's email, the oldest one wins
+        exec_raw_sql(
+            manager,
+            r#"
+            UPDATE profiles p SET account_id = a.id
+            FROM accounts a
+            WHERE p.email = a.email
+              AND EXISTS (
+                SELECT 1 FROM account_realm_roles r
+                WHERE r.account_id = a.id AND r.realm_id = p.realm_id
+              )
+              AND p.id = (
+                SELECT p2.id FROM profiles p2
+                WHERE p2.realm_id = p.realm_id AND p2.email = p.email
+                ORDER BY p2.created_at, p2.id
+                LIMIT 1
+              )
+        "#,
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Profiles::Table)
+                    .drop_column(Profiles::AccountId)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum Accounts {
+    Table,
+    Id,
+}
+
+#[derive(DeriveIden)]
+enum Profiles {
+    Table,
+    AccountId,
+}
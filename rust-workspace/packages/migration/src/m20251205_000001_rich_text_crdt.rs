@@ -0,0 +1,168 @@
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_query::{Expr, ForeignKey, Index};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // ============================================================================
+        // Collaborative Rich Text (Yjs CRDT)
+        // ============================================================================
+
+        // Create trip_card_rich_text_updates table
+        // Incremental Yjs updates of a card, numbered 1, 2, 3... in the order they were
+        // received; clients resume from the last `seq` they have
+        manager
+            .create_table(
+                Table::create()
+                    .table(TripCardRichTextUpdates::Table)
+                    .col(
+                        ColumnDef::new(TripCardRichTextUpdates::TripCardId)
+                            .uuid()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(TripCardRichTextUpdates::Seq)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(TripCardRichTextUpdates::Payload)
+                            .binary()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(TripCardRichTextUpdates::ProfileId).uuid())
+                    .col(
+                        ColumnDef::new(TripCardRichTextUpdates::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::cust("now()")),
+                    )
+                    .primary_key(
+                        Index::create()
+                            .name("pk_trip_card_rich_text_updates")
+                            .col(TripCardRichTextUpdates::TripCardId)
+                            .col(TripCardRichTextUpdates::Seq),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_foreign_key(
+                ForeignKey::create()
+                    .name("fk_trip_card_rich_text_updates_trip_card")
+                    .from(
+                        TripCardRichTextUpdates::Table,
+                        TripCardRichTextUpdates::TripCardId,
+                    )
+                    .to(TripCards::Table, TripCards::Id)
+                    .on_delete(ForeignKeyAction::Cascade)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_foreign_key(
+                ForeignKey::create()
+                    .name("fk_trip_card_rich_text_updates_profile")
+                    .from(
+                        TripCardRichTextUpdates::Table,
+                        TripCardRichTextUpdates::ProfileId,
+                    )
+                    .to(Profiles::Table, Profiles::Id)
+                    .on_delete(ForeignKeyAction::SetNull)
+                    .to_owned(),
+            )
+            .await?;
+
+        // The document state: a snapshot (full Yjs update) covering all updates up to
+        // snapshot_seq, the last update received (crdt_seq), and the update the
+        // materialized `content` reflects (content_seq)
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(TripCardRichText::Table)
+                    .add_column(ColumnDef::new(TripCardRichText::CrdtSnapshot).binary())
+                    .add_column(
+                        ColumnDef::new(TripCardRichText::SnapshotSeq)
+                            .big_integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .add_column(
+                        ColumnDef::new(TripCardRichText::CrdtSeq)
+                            .big_integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .add_column(
+                        ColumnDef::new(TripCardRichText::ContentSeq)
+                            .big_integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(TripCardRichText::Table)
+                    .drop_column(TripCardRichText::CrdtSnapshot)
+                    .drop_column(TripCardRichText::SnapshotSeq)
+                    .drop_column(TripCardRichText::CrdtSeq)
+                    .drop_column(TripCardRichText::ContentSeq)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .drop_table(
+                Table::drop()
+                    .table(TripCardRichTextUpdates::Table)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum Profiles {
+    Table,
+    Id,
+}
+
+#[derive(DeriveIden)]
+enum TripCards {
+    Table,
+    Id,
+}
+
+#[derive(DeriveIden)]
+enum TripCardRichText {
+    Table,
+    CrdtSnapshot,
+    SnapshotSeq,
+    CrdtSeq,
+    ContentSeq,
+}
+
+#[derive(DeriveIden)]
+enum TripCardRichTextUpdates {
+    Table,
+    TripCardId,
+    Seq,
+    Payload,
+    ProfileId,
+    CreatedAt,
+}
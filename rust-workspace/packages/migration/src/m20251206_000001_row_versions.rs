@@ -0,0 +1,108 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // ============================================================================
+        // Row Versions (optimistic concurrency control)
+        // ============================================================================
+
+        // Incremented on every update; updates may name the version they were based on
+        // and are rejected if the row changed in the meantime
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Trips::Table)
+                    .add_column(
+                        ColumnDef::new(Trips::Version)
+                            .big_integer()
+                            .not_null()
+                            .default(1),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(TripCards::Table)
+                    .add_column(
+                        ColumnDef::new(TripCards::Version)
+                            .big_integer()
+                            .not_null()
+                            .default(1),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Bots::Table)
+                    .add_column(
+                        ColumnDef::new(Bots::Version)
+                            .big_integer()
+                            .not_null()
+                            .default(1),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Bots::Table)
+                    .drop_column(Bots::Version)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(TripCards::Table)
+                    .drop_column(TripCards::Version)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Trips::Table)
+                    .drop_column(Trips::Version)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum Trips {
+    Table,
+    Version,
+}
+
+#[derive(DeriveIden)]
+enum TripCards {
+    Table,
+    Version,
+}
+
+#[derive(DeriveIden)]
+enum Bots {
+    Table,
+    Version,
+}
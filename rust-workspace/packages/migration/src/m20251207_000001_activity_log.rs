@@ -0,0 +1,163 @@
+use sea_orm::ConnectionTrait;
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_query::{Expr, ForeignKey, Index};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+// Helper function to execute raw SQL statements
+// Used for CHECK constraints
+async fn exec_raw_sql(manager: &SchemaManager<'_>, sql: &str) -> Result<(), DbErr> {
+    manager.get_connection().execute_unprepared(sql).await?;
+    Ok(())
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // ============================================================================
+        // Trip Activity Log
+        // ============================================================================
+
+        // Create activity_log table
+        // Append-only history of changes to a trip, its cards and participants. `before` and
+        // `after` hold the changed fields only (the whole record for creations and deletions).
+        manager
+            .create_table(
+                Table::create()
+                    .table(ActivityLog::Table)
+                    .col(
+                        ColumnDef::new(ActivityLog::Id)
+                            .uuid()
+                            .not_null()
+                            .default(Expr::cust("uuidv7()"))
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(ActivityLog::TripId).uuid().not_null())
+                    .col(ColumnDef::new(ActivityLog::ProfileId).uuid())
+                    .col(ColumnDef::new(ActivityLog::EntityType).text().not_null())
+                    .col(ColumnDef::new(ActivityLog::EntityId).uuid().not_null())
+                    .col(ColumnDef::new(ActivityLog::Action).text().not_null())
+                    .col(ColumnDef::new(ActivityLog::Before).json_binary())
+                    .col(ColumnDef::new(ActivityLog::After).json_binary())
+                    // Set on an undo: the activity it reverted
+                    .col(ColumnDef::new(ActivityLog::UndoesId).uuid())
+                    // Set on an undone activity: when it was reverted
+                    .col(ColumnDef::new(ActivityLog::UndoneAt).timestamp_with_time_zone())
+                    .col(
+                        ColumnDef::new(ActivityLog::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::cust("now()")),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        exec_raw_sql(
+            manager,
+            "ALTER TABLE activity_log ADD CONSTRAINT activity_log_entity_type_check CHECK (entity_type IN ('trip', 'trip_card', 'trip_participant'))",
+        )
+        .await?;
+        exec_raw_sql(
+            manager,
+            "ALTER TABLE activity_log ADD CONSTRAINT activity_log_action_check CHECK (action IN ('create', 'update', 'delete'))",
+        )
+        .await?;
+
+        manager
+            .create_foreign_key(
+                ForeignKey::create()
+                    .name("fk_activity_log_trip")
+                    .from(ActivityLog::Table, ActivityLog::TripId)
+                    .to(Trips::Table, Trips::Id)
+                    .on_delete(ForeignKeyAction::Cascade)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_foreign_key(
+                ForeignKey::create()
+                    .name("fk_activity_log_profile")
+                    .from(ActivityLog::Table, ActivityLog::ProfileId)
+                    .to(Profiles::Table, Profiles::Id)
+                    .on_delete(ForeignKeyAction::SetNull)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_foreign_key(
+                ForeignKey::create()
+                    .name("fk_activity_log_undoes")
+                    .from(ActivityLog::Table, ActivityLog::UndoesId)
+                    .to(ActivityLog::Table, ActivityLog::Id)
+                    .on_delete(ForeignKeyAction::SetNull)
+                    .to_owned(),
+            )
+            .await?;
+
+        // The feed of a trip, newest first
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_activity_log_trip_id")
+                    .table(ActivityLog::Table)
+                    .col(ActivityLog::TripId)
+                    .col(ActivityLog::Id)
+                    .to_owned(),
+            )
+            .await?;
+
+        // Later changes of an entity, for undo conflict checks
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_activity_log_entity")
+                    .table(ActivityLog::Table)
+                    .col(ActivityLog::EntityType)
+                    .col(ActivityLog::EntityId)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(ActivityLog::Table).to_owned())
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum Profiles {
+    Table,
+    Id,
+}
+
+#[derive(DeriveIden)]
+enum Trips {
+    Table,
+    Id,
+}
+
+#[derive(DeriveIden)]
+enum ActivityLog {
+    Table,
+    Id,
+    TripId,
+    ProfileId,
+    EntityType,
+    EntityId,
+    Action,
+    Before,
+    After,
+    UndoesId,
+    UndoneAt,
+    CreatedAt,
+}
@@ -0,0 +1,59 @@
+use sea_orm::ConnectionTrait;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+// Helper function to execute raw SQL statements
+// Used for CHECK constraints
+async fn exec_raw_sql(manager: &SchemaManager<'_>, sql: &str) -> Result<(), DbErr> {
+    manager.get_connection().execute_unprepared(sql).await?;
+    Ok(())
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // ============================================================================
+        // Trip Templates
+        // ============================================================================
+
+        // Templates are trips with status 'template'. They have no participants, so
+        // row-level security keeps them out of every participant's trip list; the server
+        // lists them per realm.
+        exec_raw_sql(
+            manager,
+            "ALTER TABLE trips DROP CONSTRAINT IF EXISTS trips_status_check",
+        )
+        .await?;
+        exec_raw_sql(
+            manager,
+            "ALTER TABLE trips ADD CONSTRAINT trips_status_check CHECK (status IN ('planning', 'confirmed', 'in_progress', 'completed', 'cancelled', 'template'))",
+        )
+        .await?;
+        exec_raw_sql(
+            manager,
+            "CREATE INDEX idx_trips_realm_templates ON trips (realm_id) WHERE status = 'template'",
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        exec_raw_sql(manager, "DROP INDEX IF EXISTS idx_trips_realm_templates").await?;
+        exec_raw_sql(manager, "DELETE FROM trips WHERE status = 'template'").await?;
+        exec_raw_sql(
+            manager,
+            "ALTER TABLE trips DROP CONSTRAINT IF EXISTS trips_status_check",
+        )
+        .await?;
+        exec_raw_sql(
+            manager,
+            "ALTER TABLE trips ADD CONSTRAINT trips_status_check CHECK (status IN ('planning', 'confirmed', 'in_progress', 'completed', 'cancelled'))",
+        )
+        .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,160 @@
+use sea_orm::ConnectionTrait;
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_query::{Expr, ForeignKey, Index};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+// Helper function to execute raw SQL statements
+// Used for CHECK constraints
+async fn exec_raw_sql(manager: &SchemaManager<'_>, sql: &str) -> Result<(), DbErr> {
+    manager.get_connection().execute_unprepared(sql).await?;
+    Ok(())
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // ============================================================================
+        // Public Trip Share Links
+        // ============================================================================
+
+        // Create trip_share_links table
+        // Only a hash of the token is stored; the token itself is shown once on creation
+        manager
+            .create_table(
+                Table::create()
+                    .table(TripShareLinks::Table)
+                    .col(
+                        ColumnDef::new(TripShareLinks::Id)
+                            .uuid()
+                            .not_null()
+                            .default(Expr::cust("uuidv7()"))
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(TripShareLinks::TripId).uuid().not_null())
+                    .col(ColumnDef::new(TripShareLinks::CreatedBy).uuid().not_null())
+                    .col(
+                        ColumnDef::new(TripShareLinks::TokenPrefix)
+                            .text()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(TripShareLinks::TokenHash)
+                            .text()
+                            .not_null()
+                            .unique_key(),
+                    )
+                    // Argon2 hash; NULL if the link needs no password
+                    .col(ColumnDef::new(TripShareLinks::PasswordHash).text())
+                    .col(ColumnDef::new(TripShareLinks::ExpiresAt).timestamp_with_time_zone())
+                    .col(ColumnDef::new(TripShareLinks::RevokedAt).timestamp_with_time_zone())
+                    .col(ColumnDef::new(TripShareLinks::LastUsedAt).timestamp_with_time_zone())
+                    .col(
+                        ColumnDef::new(TripShareLinks::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::cust("now()")),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_foreign_key(
+                ForeignKey::create()
+                    .name("fk_trip_share_links_trip")
+                    .from(TripShareLinks::Table, TripShareLinks::TripId)
+                    .to(Trips::Table, Trips::Id)
+                    .on_delete(ForeignKeyAction::Cascade)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_foreign_key(
+                ForeignKey::create()
+                    .name("fk_trip_share_links_created_by")
+                    .from(TripShareLinks::Table, TripShareLinks::CreatedBy)
+                    .to(Profiles::Table, Profiles::Id)
+                    .on_delete(ForeignKeyAction::Cascade)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_trip_share_links_trip")
+                    .table(TripShareLinks::Table)
+                    .col(TripShareLinks::TripId)
+                    .to_owned(),
+            )
+            .await?;
+
+        // Failed share link passwords are throttled per link
+        exec_raw_sql(
+            manager,
+            "ALTER TABLE login_throttles DROP CONSTRAINT IF EXISTS login_throttles_scope_check",
+        )
+        .await?;
+        exec_raw_sql(
+            manager,
+            "ALTER TABLE login_throttles ADD CONSTRAINT login_throttles_scope_check CHECK (scope IN ('account', 'ip', 'share_link'))",
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        exec_raw_sql(
+            manager,
+            "DELETE FROM login_throttles WHERE scope = 'share_link'",
+        )
+        .await?;
+        exec_raw_sql(
+            manager,
+            "ALTER TABLE login_throttles DROP CONSTRAINT IF EXISTS login_throttles_scope_check",
+        )
+        .await?;
+        exec_raw_sql(
+            manager,
+            "ALTER TABLE login_throttles ADD CONSTRAINT login_throttles_scope_check CHECK (scope IN ('account', 'ip'))",
+        )
+        .await?;
+
+        manager
+            .drop_table(Table::drop().table(TripShareLinks::Table).to_owned())
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum Trips {
+    Table,
+    Id,
+}
+
+#[derive(DeriveIden)]
+enum Profiles {
+    Table,
+    Id,
+}
+
+#[derive(DeriveIden)]
+enum TripShareLinks {
+    Table,
+    Id,
+    TripId,
+    CreatedBy,
+    TokenPrefix,
+    TokenHash,
+    PasswordHash,
+    ExpiresAt,
+    RevokedAt,
+    LastUsedAt,
+    CreatedAt,
+}
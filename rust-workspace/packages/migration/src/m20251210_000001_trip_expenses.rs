@@ -0,0 +1,275 @@
+use sea_orm::ConnectionTrait;
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_query::{Expr, ForeignKey, Index};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+// Helper function to execute raw SQL statements
+// Used for CHECK constraints
+async fn exec_raw_sql(manager: &SchemaManager<'_>, sql: &str) -> Result<(), DbErr> {
+    manager.get_connection().execute_unprepared(sql).await?;
+    Ok(())
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // ============================================================================
+        // Trip Expenses
+        // ============================================================================
+
+        // Create trip_expenses table
+        // Amounts are in minor units of the currency (e.g. cents), never converted
+        manager
+            .create_table(
+                Table::create()
+                    .table(TripExpenses::Table)
+                    .col(
+                        ColumnDef::new(TripExpenses::Id)
+                            .uuid()
+                            .not_null()
+                            .default(Expr::cust("uuidv7()"))
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(TripExpenses::TripId).uuid().not_null())
+                    .col(ColumnDef::new(TripExpenses::TripCardId).uuid())
+                    .col(ColumnDef::new(TripExpenses::PaidBy).uuid().not_null())
+                    .col(ColumnDef::new(TripExpenses::CreatedBy).uuid().not_null())
+                    .col(ColumnDef::new(TripExpenses::Description).text().not_null())
+                    .col(ColumnDef::new(TripExpenses::Category).text())
+                    .col(
+                        ColumnDef::new(TripExpenses::Amount)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(TripExpenses::Currency).text().not_null())
+                    .col(
+                        ColumnDef::new(TripExpenses::SpentAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::cust("now()")),
+                    )
+                    .col(
+                        ColumnDef::new(TripExpenses::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::cust("now()")),
+                    )
+                    .col(
+                        ColumnDef::new(TripExpenses::UpdatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::cust("now()")),
+                    )
+                    .col(
+                        ColumnDef::new(TripExpenses::Version)
+                            .big_integer()
+                            .not_null()
+                            .default(1),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        exec_raw_sql(
+            manager,
+            "ALTER TABLE trip_expenses ADD CONSTRAINT trip_expenses_amount_check CHECK (amount > 0)",
+        )
+        .await?;
+        exec_raw_sql(
+            manager,
+            "ALTER TABLE trip_expenses ADD CONSTRAINT trip_expenses_currency_check CHECK (currency ~ '^[A-Z]{3}$')",
+        )
+        .await?;
+
+        manager
+            .create_foreign_key(
+                ForeignKey::create()
+                    .name("fk_trip_expenses_trip")
+                    .from(TripExpenses::Table, TripExpenses::TripId)
+                    .to(Trips::Table, Trips::Id)
+                    .on_delete(ForeignKeyAction::Cascade)
+                    .to_owned(),
+            )
+            .await?;
+
+        // The expense stays when its card is deleted
+        manager
+            .create_foreign_key(
+                ForeignKey::create()
+                    .name("fk_trip_expenses_trip_card")
+                    .from(TripExpenses::Table, TripExpenses::TripCardId)
+                    .to(TripCards::Table, TripCards::Id)
+                    .on_delete(ForeignKeyAction::SetNull)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_foreign_key(
+                ForeignKey::create()
+                    .name("fk_trip_expenses_paid_by")
+                    .from(TripExpenses::Table, TripExpenses::PaidBy)
+                    .to(Profiles::Table, Profiles::Id)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_foreign_key(
+                ForeignKey::create()
+                    .name("fk_trip_expenses_created_by")
+                    .from(TripExpenses::Table, TripExpenses::CreatedBy)
+                    .to(Profiles::Table, Profiles::Id)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_trip_expenses_trip")
+                    .table(TripExpenses::Table)
+                    .col(TripExpenses::TripId)
+                    .col(TripExpenses::SpentAt)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_trip_expenses_paid_by")
+                    .table(TripExpenses::Table)
+                    .col(TripExpenses::PaidBy)
+                    .to_owned(),
+            )
+            .await?;
+
+        // Create trip_expense_shares table
+        // How an expense is split: the shares of an expense add up to its amount
+        manager
+            .create_table(
+                Table::create()
+                    .table(TripExpenseShares::Table)
+                    .col(
+                        ColumnDef::new(TripExpenseShares::ExpenseId)
+                            .uuid()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(TripExpenseShares::ProfileId)
+                            .uuid()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(TripExpenseShares::Amount)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .primary_key(
+                        Index::create()
+                            .name("pk_trip_expense_shares")
+                            .col(TripExpenseShares::ExpenseId)
+                            .col(TripExpenseShares::ProfileId),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        exec_raw_sql(
+            manager,
+            "ALTER TABLE trip_expense_shares ADD CONSTRAINT trip_expense_shares_amount_check CHECK (amount >= 0)",
+        )
+        .await?;
+
+        manager
+            .create_foreign_key(
+                ForeignKey::create()
+                    .name("fk_trip_expense_shares_expense")
+                    .from(TripExpenseShares::Table, TripExpenseShares::ExpenseId)
+                    .to(TripExpenses::Table, TripExpenses::Id)
+                    .on_delete(ForeignKeyAction::Cascade)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_foreign_key(
+                ForeignKey::create()
+                    .name("fk_trip_expense_shares_profile")
+                    .from(TripExpenseShares::Table, TripExpenseShares::ProfileId)
+                    .to(Profiles::Table, Profiles::Id)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_trip_expense_shares_profile")
+                    .table(TripExpenseShares::Table)
+                    .col(TripExpenseShares::ProfileId)
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(TripExpenseShares::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(TripExpenses::Table).to_owned())
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum Trips {
+    Table,
+    Id,
+}
+
+#[derive(DeriveIden)]
+enum TripCards {
+    Table,
+    Id,
+}
+
+#[derive(DeriveIden)]
+enum Profiles {
+    Table,
+    Id,
+}
+
+#[derive(DeriveIden)]
+enum TripExpenses {
+    Table,
+    Id,
+    TripId,
+    TripCardId,
+    PaidBy,
+    CreatedBy,
+    Description,
+    Category,
+    Amount,
+    Currency,
+    SpentAt,
+    CreatedAt,
+    UpdatedAt,
+    Version,
+}
+
+#[derive(DeriveIden)]
+enum TripExpenseShares {
+    Table,
+    ExpenseId,
+    ProfileId,
+    Amount,
+}
@@ -0,0 +1,187 @@
+use sea_orm::ConnectionTrait;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+// Helper function to execute raw SQL statements
+// Used for CHECK constraints, functions and triggers
+async fn exec_raw_sql(manager: &SchemaManager<'_>, sql: &str) -> Result<(), DbErr> {
+    manager.get_connection().execute_unprepared(sql).await?;
+    Ok(())
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // ============================================================================
+        // Activity Log Triggers
+        // ============================================================================
+
+        // Card deletions and votes mostly happen through GraphQL, so they are logged by
+        // triggers rather than by the server. The server sets
+        // `tripvota.activity_recorded` for transactions that log their changes themselves.
+        exec_raw_sql(
+            manager,
+            "ALTER TABLE activity_log DROP CONSTRAINT activity_log_entity_type_check",
+        )
+        .await?;
+        exec_raw_sql(
+            manager,
+            "ALTER TABLE activity_log ADD CONSTRAINT activity_log_entity_type_check CHECK (entity_type IN ('trip', 'trip_card', 'trip_participant', 'trip_card_vote'))",
+        )
+        .await?;
+
+        // Profile of the GraphQL caller in the trip's realm; NULL for the server
+        exec_raw_sql(
+            manager,
+            r#"
+            CREATE FUNCTION activity_actor(trip uuid) RETURNS uuid
+            LANGUAGE sql STABLE SECURITY DEFINER SET search_path = public AS $$
+                SELECT p.id FROM profiles p JOIN trips t ON t.realm_id = p.realm_id
+                WHERE t.id = trip
+                  AND p.account_id = NULLIF(current_setting('jwt.claims.account_id', true), '')::uuid
+            $$
+            "#,
+        )
+        .await?;
+
+        // Deleted cards, as serialized by the server, so the deletion can be undone. Cards
+        // deleted along with their trip are not logged: the trip's log goes with it.
+        exec_raw_sql(
+            manager,
+            r#"
+            CREATE FUNCTION log_trip_card_delete() RETURNS trigger
+            LANGUAGE plpgsql SECURITY DEFINER SET search_path = public AS $$
+            BEGIN
+                IF current_setting('tripvota.activity_recorded', true) = 'on'
+                    OR NOT EXISTS (SELECT 1 FROM trips WHERE id = OLD.trip_id) THEN
+                    RETURN NULL;
+                END IF;
+
+                INSERT INTO activity_log (trip_id, profile_id, entity_type, entity_id, action, before)
+                VALUES (
+                    OLD.trip_id,
+                    activity_actor(OLD.trip_id),
+                    'trip_card',
+                    OLD.id,
+                    'delete',
+                    jsonb_build_object(
+                        'id', OLD.id,
+                        'tripId', OLD.trip_id,
+                        'createdBy', OLD.created_by,
+                        'title', OLD.title,
+                        'description', OLD.description,
+                        'category', OLD.category,
+                        'startTime', OLD.start_time,
+                        'endTime', OLD.end_time,
+                        'status', OLD.status,
+                        'displayOrder', OLD.display_order,
+                        'voteCount', OLD.vote_count,
+                        'voteData', OLD.vote_data,
+                        'createdAt', OLD.created_at,
+                        'updatedAt', OLD.updated_at,
+                        'metadata', OLD.metadata,
+                        'version', OLD.version,
+                        'position', CASE WHEN OLD.position IS NULL THEN NULL ELSE jsonb_build_object(
+                            'longitude', ST_X(OLD.position::geometry),
+                            'latitude', ST_Y(OLD.position::geometry)
+                        ) END
+                    )
+                );
+                RETURN NULL;
+            END
+            $$
+            "#,
+        )
+        .await?;
+        exec_raw_sql(
+            manager,
+            "CREATE TRIGGER trip_cards_activity AFTER DELETE ON trip_cards FOR EACH ROW EXECUTE FUNCTION log_trip_card_delete()",
+        )
+        .await?;
+
+        // Votes, logged against their card. The voter counts as the actor when the change
+        // doesn't come from a GraphQL caller (e.g. profile merges). Votes going away with
+        // their card or profile are not logged.
+        exec_raw_sql(
+            manager,
+            r#"
+            CREATE FUNCTION log_trip_card_vote() RETURNS trigger
+            LANGUAGE plpgsql SECURITY DEFINER SET search_path = public AS $$
+            DECLARE
+                vote trip_card_votes := COALESCE(NEW, OLD);
+                trip uuid;
+            BEGIN
+                SELECT trip_id INTO trip FROM trip_cards WHERE id = vote.trip_card_id;
+                IF current_setting('tripvota.activity_recorded', true) = 'on'
+                    OR trip IS NULL
+                    OR NOT EXISTS (SELECT 1 FROM profiles WHERE id = vote.profile_id) THEN
+                    RETURN NULL;
+                END IF;
+
+                INSERT INTO activity_log (trip_id, profile_id, entity_type, entity_id, action, before, after)
+                VALUES (
+                    trip,
+                    COALESCE(activity_actor(trip), vote.profile_id),
+                    'trip_card_vote',
+                    vote.trip_card_id,
+                    CASE TG_OP WHEN 'INSERT' THEN 'create' WHEN 'UPDATE' THEN 'update' ELSE 'delete' END,
+                    CASE WHEN TG_OP = 'INSERT' THEN NULL ELSE jsonb_build_object(
+                        'tripCardId', OLD.trip_card_id,
+                        'profileId', OLD.profile_id,
+                        'voteType', OLD.vote_type,
+                        'createdAt', OLD.created_at
+                    ) END,
+                    CASE WHEN TG_OP = 'DELETE' THEN NULL ELSE jsonb_build_object(
+                        'tripCardId', NEW.trip_card_id,
+                        'profileId', NEW.profile_id,
+                        'voteType', NEW.vote_type,
+                        'createdAt', NEW.created_at
+                    ) END
+                );
+                RETURN NULL;
+            END
+            $$
+            "#,
+        )
+        .await?;
+        exec_raw_sql(
+            manager,
+            "CREATE TRIGGER trip_card_votes_activity AFTER INSERT OR UPDATE OR DELETE ON trip_card_votes FOR EACH ROW EXECUTE FUNCTION log_trip_card_vote()",
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        exec_raw_sql(
+            manager,
+            "DROP TRIGGER trip_card_votes_activity ON trip_card_votes",
+        )
+        .await?;
+        exec_raw_sql(manager, "DROP TRIGGER trip_cards_activity ON trip_cards").await?;
+        exec_raw_sql(manager, "DROP FUNCTION log_trip_card_vote()").await?;
+        exec_raw_sql(manager, "DROP FUNCTION log_trip_card_delete()").await?;
+        exec_raw_sql(manager, "DROP FUNCTION activity_actor(uuid)").await?;
+
+        exec_raw_sql(
+            manager,
+            "DELETE FROM activity_log WHERE entity_type = 'trip_card_vote'",
+        )
+        .await?;
+        exec_raw_sql(
+            manager,
+            "ALTER TABLE activity_log DROP CONSTRAINT activity_log_entity_type_check",
+        )
+        .await?;
+        exec_raw_sql(
+            manager,
+            "ALTER TABLE activity_log ADD CONSTRAINT activity_log_entity_type_check CHECK (entity_type IN ('trip', 'trip_card', 'trip_participant'))",
+        )
+        .await?;
+
+        Ok(())
+    }
+}